@@ -27,6 +27,7 @@ let
         "languages"
         "topiary"
         "topiary-cli"
+        "topiary-ffi"
         "topiary-playground"
         "tests"
       ];
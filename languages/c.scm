@@ -0,0 +1,266 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (char_literal)
+  (comment)
+  (string_literal)
+  (system_lib_string)
+] @leaf
+
+; Preprocessor directives are not C code proper: they are passed through as
+; they are, including their continuation lines, on lines of their own.
+[
+  (preproc_call)
+  (preproc_def)
+  (preproc_function_def)
+  (preproc_include)
+] @leaf @prepend_hardline @append_hardline
+
+; The lines of conditional directives are kept as they are, but the code
+; between them is formatted like the code around them. The line break that ends
+; a condition is part of the tree, and is replaced by our own.
+[
+  (preproc_elif
+    condition: (_) @leaf @append_hardline
+    "\n" @delete
+  )
+  (preproc_if
+    condition: (_) @leaf @append_hardline
+    "\n" @delete
+  )
+]
+
+(preproc_ifdef
+  name: (_) @append_hardline
+)
+
+[
+  "#elif"
+  "#if"
+  "#ifdef"
+  "#ifndef"
+] @prepend_hardline @append_space
+
+"#else" @prepend_hardline @append_hardline
+
+"#endif" @prepend_hardline
+
+; Allow blank line before
+[
+  (compound_statement
+    (_) @allow_blank_line_before
+  )
+  (preproc_else
+    (_) @allow_blank_line_before
+  )
+  (preproc_if
+    (_) @allow_blank_line_before
+  )
+  (preproc_ifdef
+    (_) @allow_blank_line_before
+  )
+  (translation_unit
+    (_) @allow_blank_line_before
+  )
+]
+
+; Input softlines before and after all comments. This means that the input
+; decides if a comment should have line breaks before or after. A line comment
+; is always followed by a line break in the input, so it keeps it.
+(comment) @prepend_input_softline @append_input_softline
+
+; Every declaration and statement is on a line of its own. If there is a
+; comment following, we don't add anything, because the input softlines above
+; will already have sorted out the formatting.
+[
+  (compound_statement
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (field_declaration_list
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (preproc_else
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (preproc_if
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (preproc_ifdef
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (translation_unit
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+]
+
+; The semicolon that ends the declaration of a type, e.g.,
+; "struct point { ... };", is on the line of its closing brace.
+(translation_unit
+  ";" @prepend_antispace
+  (#priority! 1)
+)
+
+(translation_unit
+  ";" @append_hardline
+)
+
+; Blocks are indented, on the lines after their opening brace. Empty blocks are
+; written "{}".
+[
+  (compound_statement
+    .
+    "{" @append_hardline @append_indent_start
+    (_)
+    "}" @prepend_hardline @prepend_indent_end
+    .
+  )
+  (enumerator_list
+    .
+    "{" @append_hardline @append_indent_start
+    (_)
+    "}" @prepend_hardline @prepend_indent_end
+    .
+  )
+  (field_declaration_list
+    .
+    "{" @append_hardline @append_indent_start
+    (_)
+    "}" @prepend_hardline @prepend_indent_end
+    .
+  )
+]
+
+(enumerator_list
+  "," @append_hardline
+)
+
+; As in K&R style, the opening braces of functions are on the line after their
+; heads. Those of control structures and types are on the same line.
+(_
+  body: (_) @prepend_space
+)
+
+(if_statement
+  consequence: (_) @prepend_space
+)
+
+(function_definition
+  body: (_) @prepend_hardline
+)
+
+; The statements of a case are indented under its label.
+(case_statement
+  ":" @append_hardline @append_indent_start
+) @append_indent_end
+
+(case_statement
+  ":"
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+"else" @prepend_space @append_space
+
+(do_statement
+  "while" @prepend_space
+)
+
+; Append spaces
+[
+  "case"
+  "do"
+  "enum"
+  "for"
+  "if"
+  "struct"
+  "switch"
+  "typedef"
+  "union"
+  "while"
+  (storage_class_specifier)
+  (type_qualifier)
+] @append_space
+
+(return_statement
+  "return" @append_space
+  (_)
+)
+
+; The type of a declaration is separated from its declarator, which holds the
+; pointer stars, e.g., "char *name".
+[
+  (declaration
+    type: (_) @append_space
+  )
+  (field_declaration
+    type: (_) @append_space
+  )
+  (function_definition
+    type: (_) @append_space
+  )
+  (parameter_declaration
+    type: (_) @append_space
+    declarator: (_)
+  )
+  (type_definition
+    type: (_) @append_space
+  )
+  (type_descriptor
+    type: (_) @append_space
+    declarator: (_)
+  )
+]
+
+(sized_type_specifier
+  _ @append_space
+  .
+  _
+)
+
+; Surround spaces
+[
+  (assignment_expression
+    operator: _ @prepend_space @append_space
+  )
+  (binary_expression
+    operator: _ @prepend_space @append_space
+  )
+  (conditional_expression
+    [
+      "?"
+      ":"
+    ] @prepend_space @append_space
+  )
+  (init_declarator
+    "=" @prepend_space @append_space
+  )
+  (initializer_pair
+    "=" @prepend_space @append_space
+  )
+]
+
+(for_statement
+  ";" @append_space
+  .
+  (_)
+)
+
+(for_statement
+  initializer: (declaration) @append_space
+)
+
+"," @append_space
@@ -0,0 +1,53 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this. Shell commands are not formatted, but their continuation lines
+; are indented with the rest of the instruction.
+[
+  (comment)
+  (double_quoted_string)
+  (json_string)
+  (shell_command)
+] @leaf
+
+(shell_command) @reindent
+
+; Every instruction is on a line of its own.
+(source_file
+  (_) @allow_blank_line_before @append_hardline
+)
+
+; The arguments of instructions are separated by a single space where the input
+; had whitespace.
+(
+  _ @_node @prepend_space
+  (#whitespace-before? @_node)
+)
+
+; Instructions are written in upper case. Their continuation lines are
+; indented.
+(_
+  [
+    "ADD"
+    "ARG"
+    "CMD"
+    "COPY"
+    "ENTRYPOINT"
+    "ENV"
+    "EXPOSE"
+    "FROM"
+    "HEALTHCHECK"
+    "LABEL"
+    "MAINTAINER"
+    "ONBUILD"
+    "RUN"
+    "SHELL"
+    "STOPSIGNAL"
+    "USER"
+    "VOLUME"
+    "WORKDIR"
+  ] @upper_case @append_space @append_indent_start
+) @append_indent_end
+
+"AS" @upper_case
+
+(line_continuation) @prepend_space @append_hardline
@@ -0,0 +1,201 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this. Keywords, e.g., "do: ", hold the space that follows their colon.
+[
+  (charlist)
+  (comment)
+  (keyword)
+  (quoted_atom)
+  (quoted_keyword)
+  (sigil)
+  (string)
+] @leaf
+
+; Allow blank line before
+[
+  (after_block
+    (_) @allow_blank_line_before
+  )
+  (catch_block
+    (_) @allow_blank_line_before
+  )
+  (do_block
+    (_) @allow_blank_line_before
+  )
+  (else_block
+    (_) @allow_blank_line_before
+  )
+  (rescue_block
+    (_) @allow_blank_line_before
+  )
+  (source
+    (_) @allow_blank_line_before
+  )
+]
+
+; Input softlines before and after all comments. This means that the input
+; decides if a comment should have line breaks before or after. A line comment
+; is always followed by a line break in the input, so it keeps it.
+(comment) @prepend_input_softline @append_input_softline
+
+; Every expression of a block is on a line of its own. If there is a comment
+; following, we don't add anything, because the input softlines above will
+; already have sorted out the formatting.
+[
+  (after_block
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (catch_block
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (do_block
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (else_block
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (rescue_block
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+  (source
+    (_) @append_hardline
+    .
+    (comment)* @do_nothing
+  )
+]
+
+; The content of do/end blocks is indented, on the lines after "do". The
+; blocks that follow, e.g., "else", are indented likewise.
+(do_block
+  "do" @prepend_space @append_hardline @append_indent_start
+  "end" @prepend_hardline @prepend_indent_end
+)
+
+[
+  "after"
+  "catch"
+  "else"
+  "rescue"
+] @prepend_hardline @prepend_indent_end @append_hardline @append_indent_start
+
+; The body of a clause is on the line of its arrow if it fits there. Otherwise,
+; it is indented, on the lines after it.
+(stab_clause
+  "->" @prepend_space
+  right: (body) @prepend_spaced_softline @prepend_indent_start @append_indent_end
+)
+
+(body
+  (_) @append_hardline
+  .
+  (_)
+)
+
+(anonymous_function
+  "fn" @append_space
+  "end" @prepend_spaced_softline
+)
+
+; Calls without parentheses keep the space before their arguments, e.g.,
+; "defmodule Example do" or "@doc "text"".
+(call
+  (arguments) @_arguments @prepend_space
+  (#whitespace-before? @_arguments)
+)
+
+; Arguments and lists that span several lines have one item per line,
+; indented.
+(arguments
+  .
+  "(" @append_empty_softline @append_indent_start
+  (_)
+  ")" @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+(list
+  .
+  "[" @append_empty_softline @append_indent_start
+  (_)
+  "]" @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+[
+  (arguments
+    "," @append_spaced_softline
+  )
+  (keywords
+    "," @append_spaced_softline
+  )
+  (list
+    "," @append_spaced_softline
+  )
+]
+
+"," @append_space
+
+; Surround spaces. Ranges, e.g., "1..10", and function captures, e.g.,
+; "&length/1", are written without.
+(binary_operator
+  operator: _ @_operator @prepend_space @append_space
+  (#not-any-of? @_operator ".." "//")
+)
+
+(unary_operator
+  operator: "&"
+  operand: (binary_operator
+    operator: "/" @prepend_antispace @append_antispace
+  )
+  (#priority! 1)
+)
+
+(unary_operator
+  operator: "not" @append_space
+)
+
+; A pipeline is on a single line if it fits in the line width. Otherwise, every
+; step of it is on a line of its own. Since pipelines nest to the left, only
+; the outermost one opens a scope.
+(
+  [
+    (binary_operator
+      operator: "="
+      right: (binary_operator
+        operator: "|>"
+      ) @begin_scope @end_scope
+    )
+    (body
+      (binary_operator
+        operator: "|>"
+      ) @begin_scope @end_scope
+    )
+    (do_block
+      (binary_operator
+        operator: "|>"
+      ) @begin_scope @end_scope
+    )
+    (source
+      (binary_operator
+        operator: "|>"
+      ) @begin_scope @end_scope
+    )
+  ]
+  (#scope_id! "pipeline")
+  (#forced_by_contents!)
+)
+
+(binary_operator
+  operator: "|>" @prepend_spaced_scoped_softline
+  (#scope_id! "pipeline")
+)
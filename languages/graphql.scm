@@ -0,0 +1,145 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (comment)
+  (string_value)
+] @leaf
+
+; Every definition is on a line of its own, preceded by its description.
+(document
+  (_) @allow_blank_line_before @append_hardline
+)
+
+(description) @append_hardline
+
+; Input softlines before and after all comments. This means that the input
+; decides if a comment should have line breaks before or after. A comment is
+; always followed by a line break in the input, so it keeps it.
+(comment) @prepend_input_softline @append_input_softline
+
+; The content of braces is indented, one item per line.
+[
+  (enum_values_definition)
+  (fields_definition)
+  (input_fields_definition)
+  (selection_set)
+] @prepend_space
+
+[
+  (enum_values_definition
+    "{" @append_hardline @append_indent_start
+    "}" @prepend_hardline @prepend_indent_end
+  )
+  (fields_definition
+    "{" @append_hardline @append_indent_start
+    "}" @prepend_hardline @prepend_indent_end
+  )
+  (input_fields_definition
+    "{" @append_hardline @append_indent_start
+    "}" @prepend_hardline @prepend_indent_end
+  )
+  (selection_set
+    "{" @append_hardline @append_indent_start
+    "}" @prepend_hardline @prepend_indent_end
+  )
+]
+
+[
+  (enum_value_definition)
+  (field_definition)
+  (input_value_definition)
+  (selection)
+] @allow_blank_line_before @prepend_hardline
+
+; Argument lists that span several lines have one argument per line, indented.
+; GraphQL does not need commas between arguments, so we separate them whether
+; there is one or not.
+[
+  (arguments
+    "(" @append_empty_softline @append_indent_start
+    ")" @prepend_empty_softline @prepend_indent_end
+  )
+  (arguments_definition
+    "(" @append_empty_softline @append_indent_start
+    ")" @prepend_empty_softline @prepend_indent_end
+  )
+  (variable_definitions
+    "(" @append_empty_softline @append_indent_start
+    ")" @prepend_empty_softline @prepend_indent_end
+  )
+]
+
+[
+  (arguments
+    (argument)
+    .
+    (argument) @prepend_spaced_softline
+  )
+  (arguments_definition
+    (input_value_definition)
+    .
+    (input_value_definition) @prepend_spaced_softline
+  )
+  (variable_definitions
+    (variable_definition)
+    .
+    (variable_definition) @prepend_spaced_softline
+  )
+]
+
+; Input values are on a single line, padded with spaces in braces.
+(object_value
+  "{" @append_space
+  (_)
+  "}" @prepend_space
+)
+
+(object_value
+  (object_field)
+  .
+  (object_field) @prepend_space
+)
+
+(list_value
+  (_)
+  .
+  (_) @prepend_space
+)
+
+; Directives follow what they apply to, on the same line.
+(directive) @prepend_space
+
+; Surround spaces
+[
+  "="
+  "|"
+] @prepend_space @append_space
+
+; Append spaces
+[
+  ","
+  ":"
+  "enum"
+  "fragment"
+  "implements"
+  "input"
+  "interface"
+  "mutation"
+  "on"
+  "query"
+  "scalar"
+  "schema"
+  "subscription"
+  "type"
+  "union"
+] @append_space
+
+[
+  (implements_interfaces)
+  (type_condition)
+] @prepend_space
+
+(inline_fragment
+  "..." @append_space
+)
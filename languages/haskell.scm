@@ -0,0 +1,64 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (char)
+  (comment)
+  (pragma)
+  (string)
+] @leaf
+
+; Haskell is sensitive to layout: the items of a do block, or of let and where
+; bindings, must start in the same column, and their continuation lines must
+; be indented further. We therefore keep the line breaks of the input, and put
+; a single space where the input had whitespace, rather than lay out
+; expressions ourselves. Only the indentation is recomputed.
+(
+  _ @_node @allow_blank_line_before @prepend_input_softline
+  (#whitespace-before? @_node)
+)
+
+[
+  "="
+  "->"
+  "<-"
+  "::"
+  "=>"
+] @prepend_space @append_space
+
+; The continuation lines of a declaration are indented under its name.
+(function
+  .
+  (_) @append_indent_start
+) @append_indent_end
+
+(signature
+  .
+  (_) @append_indent_start
+) @append_indent_end
+
+; The items of a block are indented under the keyword that opens it. If the
+; block spans several lines, its first item goes on the line after that
+; keyword, so that all items are aligned, whatever the width of what precedes
+; them.
+(_
+  [
+    "do"
+    "let"
+    "of"
+  ] @append_indent_start
+) @append_indent_end
+
+(_
+  [
+    "do"
+    "let"
+    "of"
+  ]
+  .
+  (_) @prepend_spaced_softline
+)
+
+(function
+  (where) @append_indent_start @append_spaced_softline
+) @append_indent_end
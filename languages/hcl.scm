@@ -0,0 +1,106 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (comment)
+  (heredoc_template)
+  (quoted_template)
+  (string_lit)
+] @leaf
+
+; Allow blank line before
+(body
+  (_) @allow_blank_line_before
+)
+
+; Input softlines before and after all comments. This means that the input
+; decides if a comment should have line breaks before or after. A line comment
+; is always followed by a line break in the input, so it keeps it.
+(comment) @prepend_input_softline @append_input_softline
+
+; Every attribute and block is on a line of its own. If there is a comment
+; following, we don't add anything, because the input softlines above will
+; already have sorted out the formatting.
+(body
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+; The type and labels of a block are separated by spaces.
+(block
+  [
+    (identifier)
+    (string_lit)
+  ] @append_space
+)
+
+; The bodies of blocks are indented, on the lines after their opening brace.
+; Empty blocks are written "{}".
+(block
+  (block_start) @append_hardline @append_indent_start
+  (body)
+  (block_end) @prepend_hardline @prepend_indent_end
+)
+
+; As in "terraform fmt", the equal signs of consecutive attributes are aligned.
+(attribute
+  "=" @prepend_space @append_space @align
+)
+
+; Objects are on a single line if they were in the input. Otherwise, they are
+; exploded: every element is on a line of its own, indented, and their equal
+; signs are aligned.
+(object
+  .
+  (object_start) @append_spaced_softline @append_indent_start
+  (object_elem)
+  (object_end) @prepend_spaced_softline @prepend_indent_end
+  .
+)
+
+(object
+  (object_elem) @prepend_spaced_softline
+)
+
+(object_elem
+  [
+    "="
+    ":"
+  ] @prepend_space @append_space @align
+)
+
+(object
+  "," @append_space
+)
+
+; Tuples that span several lines have one item per line, indented.
+(tuple
+  .
+  (tuple_start) @append_empty_softline @append_indent_start
+  (expression)
+  (tuple_end) @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+(tuple
+  "," @append_spaced_softline
+)
+
+(function_arguments
+  "," @append_space
+)
+
+(conditional
+  [
+    "?"
+    ":"
+  ] @prepend_space @append_space
+)
+
+(binary_operation
+  .
+  (_) @append_space
+  (_) @prepend_space
+  .
+)
@@ -0,0 +1,107 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (doctype)
+  (quoted_attribute_value)
+] @leaf
+
+; The whitespace of preformatted elements is significant, so they are kept as
+; they are.
+(
+  (element
+    (start_tag
+      (tag_name) @_tag
+    )
+  ) @leaf
+  (#any-of? @_tag "pre" "textarea")
+)
+
+; Multi-line text is indented with the rest of the code.
+(text) @reindent
+
+; In HTML, adding whitespace between two nodes may change the rendered page,
+; e.g., between two inline elements. We therefore only put a space or a line
+; break between the children of elements where there was whitespace in the
+; input, and the input decides which one it is.
+(document
+  (_) @_child @allow_blank_line_before @prepend_input_softline
+  (#whitespace-before? @_child)
+)
+
+(element
+  (_) @_child @allow_blank_line_before @prepend_input_softline
+  (#whitespace-before? @_child)
+)
+
+; The content of elements is indented. Void elements, like <br>, have no end
+; tag, hence no content.
+(element
+  (start_tag) @append_indent_start
+  (end_tag) @prepend_indent_end
+)
+
+; Tags that do not fit on a line have one attribute per line.
+(
+  [
+    (start_tag)
+    (self_closing_tag)
+  ] @begin_scope @end_scope
+  (#scope_id! "tag")
+  (#forced_by_contents!)
+)
+
+(_
+  (attribute) @prepend_spaced_scoped_softline
+  (#scope_id! "tag")
+)
+
+(_
+  (tag_name) @append_indent_start
+  [
+    ">"
+    "/>"
+  ] @prepend_indent_end
+)
+
+(start_tag
+  ">" @prepend_empty_scoped_softline
+  (#scope_id! "tag")
+)
+
+(self_closing_tag
+  "/>" @prepend_spaced_scoped_softline
+  (#scope_id! "tag")
+)
+
+; Multi-line scripts and stylesheets are indented under their tags.
+(
+  (_
+    (start_tag) @append_indent_start
+    (raw_text) @reindent
+    (end_tag) @prepend_hardline @prepend_indent_end
+  ) @_element
+  (#not-single_line? @_element)
+)
+
+; Stylesheets are formatted as CSS.
+(style_element
+  (raw_text) @injection
+  (#injection_language! "css")
+)
+
+; Scripts that hold JSON data are formatted as JSON.
+(script_element
+  (start_tag
+    (attribute
+      (attribute_name) @_name
+      (quoted_attribute_value
+        (attribute_value) @_type
+      )
+    )
+  )
+  (raw_text) @injection
+  (#eq? @_name "type")
+  (#any-of? @_type "application/json" "application/ld+json" "importmap")
+  (#injection_language! "json")
+)
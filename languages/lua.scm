@@ -0,0 +1,124 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this. This keeps long strings and long comments, e.g., "[[...]]", as
+; they are.
+[
+  (comment)
+  (string)
+] @leaf
+
+; Allow blank line before
+(chunk
+  (_) @allow_blank_line_before
+)
+
+(block
+  (_) @allow_blank_line_before
+)
+
+; Input softlines before and after all comments. This means that the input
+; decides if a comment should have line breaks before or after. A line comment
+; is always followed by a line break in the input, so it keeps it.
+(comment) @prepend_input_softline @append_input_softline
+
+; Every statement is on a line of its own. If there is a comment following, we
+; don't add anything, because the input softlines above will already have
+; sorted out the formatting.
+(chunk
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+(block
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+; The bodies of functions and control structures are indented, on the lines
+; after their heads. Their closing keyword is on a line of its own.
+(block) @prepend_hardline @prepend_indent_start @append_indent_end
+
+[
+  "end"
+  (else_statement)
+  (elseif_statement)
+  "until"
+] @prepend_hardline
+
+; Surround spaces
+[
+  "and"
+  "in"
+  "or"
+  "="
+] @prepend_space @append_space
+
+[
+  "elseif"
+  "for"
+  "if"
+  "local"
+  "until"
+  "while"
+] @append_space
+
+[
+  "do"
+  "then"
+] @prepend_space
+
+(function_declaration
+  "function" @append_space
+)
+
+(return_statement
+  "return" @append_space
+  (_)
+)
+
+(unary_expression
+  "not" @append_space
+)
+
+(binary_expression
+  .
+  (_) @append_space
+  (_) @prepend_space
+  .
+)
+
+"," @append_space
+
+; Tables are on a single line if they were in the input. Otherwise, they are
+; exploded: every field is on a line of its own, indented. We do not want to
+; add spaces or newlines in empty tables, so we only do this if there is a
+; field in the table.
+(table_constructor
+  .
+  "{" @append_spaced_softline @append_indent_start
+  (field)
+  "}" @prepend_spaced_softline @prepend_indent_end
+  .
+)
+
+(table_constructor
+  [
+    ","
+    ";"
+  ] @append_spaced_softline
+)
+
+; Method call chains that span several lines have one call per line, indented
+; under the receiver.
+(method_index_expression
+  ":" @prepend_empty_softline @prepend_indent_start
+) @append_indent_end
+
+; Calls with a single string or table argument keep the space that they had
+; before it, e.g., 'require "module"'.
+(function_call
+  (arguments) @_arguments @prepend_space
+  (#whitespace-before? @_arguments)
+)
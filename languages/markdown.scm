@@ -0,0 +1,128 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this. Prose is not reflowed, and raw blocks are kept as they are.
+[
+  (block_quote)
+  (code_fence_content)
+  (html_block)
+  (indented_code_block)
+  (info_string)
+  (inline)
+  (link_reference_definition)
+  (paragraph)
+  (pipe_table_cell)
+  (setext_heading)
+  (thematic_break)
+] @leaf
+
+; Markdown is sensitive to indentation: the continuation lines of a block in a
+; list item must be indented under it. They are therefore indented with the
+; rest of the code, rather than left where they were in the input.
+[
+  (block_quote)
+  (paragraph)
+  (setext_heading)
+] @reindent
+
+; The indentation of the blocks of list items is that of the output, rather
+; than that of the input.
+(
+  (block_continuation) @delete
+  (#deletable! "block_continuation")
+)
+
+; Headings have a single space after their marker.
+(atx_heading
+  [
+    (atx_h1_marker)
+    (atx_h2_marker)
+    (atx_h3_marker)
+    (atx_h4_marker)
+    (atx_h5_marker)
+    (atx_h6_marker)
+  ] @append_space
+)
+
+; Every block is on a line of its own. Blank lines are kept, as they separate
+; paragraphs, but several of them are merged into one.
+(document
+  (_) @allow_blank_line_before @append_hardline
+)
+
+(section
+  (_) @allow_blank_line_before @append_hardline
+)
+
+; Every list item is on a line of its own. Loose lists keep the blank lines
+; between their items. List markers include the whitespace after them, which is
+; kept, as it sets the column of the content of the item.
+(list
+  (list_item) @allow_blank_line_before @append_hardline
+)
+
+; The blocks of a list item are indented under its marker, each on a line of
+; its own.
+(list_item
+  .
+  [
+    (list_marker_dot)
+    (list_marker_minus)
+    (list_marker_parenthesis)
+    (list_marker_plus)
+    (list_marker_star)
+  ] @append_indent_start
+)
+
+(list_item) @append_indent_end
+
+(list_item
+  [
+    (block_quote)
+    (fenced_code_block)
+    (html_block)
+    (indented_code_block)
+    (link_reference_definition)
+    (list)
+    (paragraph)
+    (pipe_table)
+    (setext_heading)
+    (thematic_break)
+  ] @allow_blank_line_before @append_hardline
+)
+
+[
+  (task_list_marker_checked)
+  (task_list_marker_unchecked)
+] @append_space
+
+; The content of a code block, and its closing fence, are on lines of their
+; own.
+(code_fence_content) @prepend_hardline
+
+(fenced_code_block
+  (fenced_code_block_delimiter)
+  (fenced_code_block_delimiter) @prepend_hardline
+)
+
+; Code blocks are formatted in the language that they declare, if Topiary knows
+; it. Otherwise, they are left as they are.
+(fenced_code_block
+  (info_string
+    (language) @_language
+  )
+  (code_fence_content) @injection
+  (#injection_language! @_language)
+)
+
+; Table rows are on lines of their own, and their cells are separated from the
+; pipes by a single space.
+[
+  (pipe_table_delimiter_row)
+  (pipe_table_header)
+  (pipe_table_row)
+] @append_hardline
+
+[
+  (pipe_table_cell)
+  (pipe_table_delimiter_cell)
+] @prepend_space @append_space
@@ -0,0 +1,213 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (comment)
+  (encapsed_string)
+  (heredoc)
+  (string)
+] @leaf
+
+; The HTML around the PHP code is formatted as HTML. If it cannot be, e.g.,
+; because it only holds the start of an element, it is left as is.
+(
+  (text) @injection
+  (#injection_language! "html")
+)
+
+; A PHP tag that follows HTML is on a line of its own if it was in the input.
+; The code after it always starts on a new line.
+(
+  (php_tag) @_tag @prepend_input_softline
+  (#whitespace-before? @_tag)
+)
+
+(php_tag) @append_hardline
+
+; Allow blank line before
+(program
+  (_) @allow_blank_line_before
+)
+
+(compound_statement
+  (_) @allow_blank_line_before
+)
+
+(declaration_list
+  (_) @allow_blank_line_before
+)
+
+; Input softlines before and after all comments. This means that the input
+; decides if a comment should have line breaks before or after. A line comment
+; is always followed by a line break in the input, so it keeps it.
+(comment) @prepend_input_softline @append_input_softline
+
+; Every statement and member declaration is on a line of its own. If there is
+; a comment following, we don't add anything, because the input softlines
+; above will already have sorted out the formatting.
+(program
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+(compound_statement
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+(declaration_list
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+; Blocks are indented, on the lines after their opening brace. Empty blocks are
+; written "{}".
+(compound_statement
+  .
+  "{" @append_hardline @append_indent_start
+  (_)
+  "}" @prepend_hardline @prepend_indent_end
+  .
+)
+
+(declaration_list
+  .
+  "{" @append_hardline @append_indent_start
+  (_)
+  "}" @prepend_hardline @prepend_indent_end
+  .
+)
+
+; As in PSR-12, the opening braces of classes, functions and methods are on the
+; line after their heads, unless the parameters span several lines. Those of
+; control structures and closures are on the same line.
+(_
+  body: (compound_statement) @prepend_space
+)
+
+(_
+  body: (declaration_list) @prepend_hardline
+)
+
+(
+  [
+    (function_definition
+      parameters: (_) @_parameters
+      body: (_) @prepend_hardline
+    )
+    (method_declaration
+      parameters: (_) @_parameters
+      body: (_) @prepend_hardline
+    )
+  ]
+  (#single_line? @_parameters)
+)
+
+[
+  (else_clause)
+  (else_if_clause)
+] @prepend_space
+
+; Surround spaces
+[
+  "as"
+  "="
+  "=>"
+] @prepend_space @append_space
+
+; Append spaces
+[
+  "class"
+  "echo"
+  "elseif"
+  "extends"
+  "for"
+  "foreach"
+  "function"
+  "if"
+  "implements"
+  "namespace"
+  "new"
+  "use"
+  "while"
+  (visibility_modifier)
+] @append_space
+
+[
+  (base_clause)
+  (class_interface_clause)
+] @prepend_space
+
+(return_statement
+  "return" @append_space
+  (_)
+)
+
+[
+  (function_definition
+    ":" @append_space
+  )
+  (method_declaration
+    ":" @append_space
+  )
+]
+
+(simple_parameter
+  (_) @append_space
+  .
+  (variable_name)
+)
+
+(binary_expression
+  .
+  (_) @append_space
+  (_) @prepend_space
+  .
+)
+
+(for_statement
+  ";" @append_space
+)
+
+"," @append_space
+
+; Arguments, parameters and arrays that span several lines have one item per
+; line, indented.
+(arguments
+  .
+  "(" @append_empty_softline @append_indent_start
+  (_)
+  ")" @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+(formal_parameters
+  .
+  "(" @append_empty_softline @append_indent_start
+  (_)
+  ")" @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+(array_creation_expression
+  .
+  "[" @append_empty_softline @append_indent_start
+  (_)
+  "]" @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+[
+  (arguments
+    "," @append_spaced_softline
+  )
+  (array_creation_expression
+    "," @append_spaced_softline
+  )
+  (formal_parameters
+    "," @append_spaced_softline
+  )
+]
@@ -0,0 +1,115 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (comment)
+  (string)
+] @leaf
+
+; Allow blank line before
+[
+  (comment)
+  (enum)
+  (enum_field)
+  (field)
+  (import)
+  (map_field)
+  (message)
+  (oneof)
+  (oneof_field)
+  (option)
+  (package)
+  (reserved)
+  (rpc)
+  (service)
+  (syntax)
+] @allow_blank_line_before
+
+; Input softlines before and after all comments. This means that the input
+; decides if a comment should have line breaks before or after. A line comment
+; is always followed by a line break in the input, so it keeps it.
+(comment) @prepend_input_softline @append_input_softline
+
+; Every definition, field and option is on a line of its own, and so is every
+; entry of an option block.
+[
+  (enum)
+  (enum_field)
+  (field)
+  (import)
+  (map_field)
+  (message)
+  (oneof)
+  (oneof_field)
+  (option)
+  (package)
+  (reserved)
+  (rpc)
+  (service)
+  (syntax)
+] @prepend_hardline
+
+(block_lit
+  (identifier) @prepend_hardline
+)
+
+; The content of braces is indented. Empty braces are written "{}".
+"{" @prepend_space
+
+(_
+  "{" @append_indent_start
+  "}" @prepend_indent_end
+)
+
+(_
+  (_)
+  .
+  "}" @prepend_hardline
+)
+
+; Surround spaces
+[
+  "="
+  "returns"
+] @prepend_space @append_space
+
+; Append spaces
+[
+  "enum"
+  "import"
+  "message"
+  "oneof"
+  "option"
+  "optional"
+  "package"
+  "repeated"
+  "required"
+  "reserved"
+  "rpc"
+  "service"
+  "stream"
+  "syntax"
+] @append_space
+
+"," @append_space
+
+(block_lit
+  ":" @append_space
+)
+
+[
+  (field
+    (type) @append_space
+  )
+  (oneof_field
+    (type) @append_space
+  )
+]
+
+(field
+  "[" @prepend_space
+)
+
+(map_field
+  ">" @append_space
+)
@@ -0,0 +1,219 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+(string) @leaf
+
+; Python is sensitive to line breaks: a statement may only span several lines
+; inside brackets. We therefore never add a line break outside of a block or
+; brackets, and we never remove one between statements.
+
+; Allow blank line before
+(module
+  (_) @allow_blank_line_before
+)
+
+(block
+  (_) @allow_blank_line_before
+)
+
+; Surround spaces
+[
+  "and"
+  "as"
+  "else"
+  "for"
+  "from"
+  "if"
+  "import"
+  "in"
+  "is"
+  "or"
+  "->"
+  ":="
+  "+="
+  "-="
+  "*="
+  "/="
+  "//="
+  "%="
+  "**="
+  "@="
+  "&="
+  "|="
+  "^="
+  "<<="
+  ">>="
+] @prepend_space @append_space
+
+; Append spaces
+[
+  "assert"
+  "async"
+  "await"
+  "class"
+  "def"
+  "del"
+  "elif"
+  "except"
+  "global"
+  "lambda"
+  "nonlocal"
+  "not"
+  "raise"
+  "return"
+  "while"
+  "with"
+  "yield"
+  ","
+] @append_space
+
+; Colons are always put directly after what precedes them, e.g. "else:".
+":" @prepend_antispace
+
+(assignment
+  "=" @prepend_space @append_space
+)
+
+(typed_default_parameter
+  "=" @prepend_space @append_space
+)
+
+; Type annotations, lambdas and dictionary entries have a space after their
+; colon. Slices do not.
+(assignment
+  ":" @append_space
+)
+
+(lambda
+  ":" @append_space
+)
+
+(pair
+  ":" @append_space
+)
+
+(typed_default_parameter
+  ":" @append_space
+)
+
+(typed_parameter
+  ":" @append_space
+)
+
+(binary_operator
+  [
+    "+"
+    "-"
+    "*"
+    "/"
+    "//"
+    "%"
+    "**"
+    "@"
+    "&"
+    "|"
+    "^"
+    "<<"
+    ">>"
+  ] @prepend_space @append_space
+)
+
+; Comparison operators can be made of several keywords, e.g. "is not", so we
+; space their operands rather than the operators themselves.
+(comparison_operator
+  (_) @append_space
+  .
+  (_) @prepend_space
+)
+
+; Adjacent strings are concatenated.
+(concatenated_string
+  (string) @append_space
+  .
+  (string)
+)
+
+; Input softlines before all comments. This means that the input decides if a
+; comment should have a line break before it. A comment always ends with a line
+; break.
+(comment) @prepend_input_softline @append_hardline
+
+; Every statement is on a line of its own. If there is a comment following, we
+; don't add anything, because the input softlines above will already have
+; sorted out the formatting.
+(module
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+(block
+  (_) @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+; Statements separated by semicolons are put on lines of their own instead.
+";" @delete
+
+; The body of a compound statement is indented on the lines after its colon.
+; Its end is marked by the end of the indentation block, so that the rest of
+; the code is dedented.
+(block) @prepend_hardline @prepend_indent_start @append_indent_end
+
+(decorator) @append_hardline
+
+; Brackets: if the bracketed node spans several lines, its elements are put on
+; lines of their own, in an indented block.
+(_
+  .
+  [
+    "("
+    "["
+    "{"
+  ] @append_empty_softline @append_indent_start
+  [
+    ")"
+    "]"
+    "}"
+  ] @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+(_
+  .
+  [
+    "("
+    "["
+    "{"
+  ]
+  ","
+  .
+  (_) @prepend_spaced_softline
+  [
+    ")"
+    "]"
+    "}"
+  ]
+  .
+)
+
+; A trailing comma is put directly before the closing bracket, e.g. "(1,)",
+; unless the elements are on lines of their own.
+(_
+  .
+  [
+    "("
+    "["
+    "{"
+  ]
+  "," @append_antispace
+  .
+  [
+    ")"
+    "]"
+    "}"
+  ]
+  .
+  (#single_line_only!)
+)
@@ -0,0 +1,190 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (comment)
+  (single_line_comment)
+  (string_value)
+] @leaf
+
+; Rules, at-rules and declarations are kept in the order of the input: their
+; order matters, as later declarations override earlier ones.
+
+; Allow blank line before
+(stylesheet
+  (_) @allow_blank_line_before
+)
+
+(block
+  (_) @allow_blank_line_before
+)
+
+; Input softlines before all comments. This means that the input decides if a
+; comment should have a line break before it.
+[
+  (comment)
+  (single_line_comment)
+] @prepend_input_softline
+
+; Single-line comments run until the end of the line.
+(single_line_comment) @append_hardline
+
+; Every rule, declaration and statement is on a line of its own. If there is a comment
+; following, we don't add anything, because the input softlines above will
+; already have sorted out the formatting.
+(stylesheet
+  (_) @append_hardline
+  .
+  [
+    (comment)
+    (single_line_comment)
+  ]* @do_nothing
+)
+
+(block
+  (_) @append_hardline
+  .
+  [
+    (comment)
+    (single_line_comment)
+  ]* @do_nothing
+)
+
+(keyframe_block_list
+  (keyframe_block) @allow_blank_line_before @append_hardline
+)
+
+; Declarations always end with a semicolon, even the last one of a block.
+(
+  (declaration) @append_missing_delimiter
+  (#delimiter! ";")
+)
+
+; Blocks are indented, on the lines after their opening brace.
+(_
+  .
+  "{" @append_hardline @append_indent_start
+  (_)
+  "}" @prepend_hardline @prepend_indent_end
+  .
+)
+
+[
+  (block)
+  (keyframe_block_list)
+] @prepend_space
+
+; Selector lists are put on several lines, one selector per line, if they span
+; several lines.
+(selectors
+  "," @append_spaced_softline
+)
+
+(descendant_selector
+  (_) @append_space
+  .
+  (_)
+)
+
+(child_selector
+  ">" @prepend_space @append_space
+)
+
+(sibling_selector
+  "~" @prepend_space @append_space
+)
+
+(adjacent_sibling_selector
+  "+" @prepend_space @append_space
+)
+
+; Declarations have a space after their colon, and between their values.
+(declaration
+  ":" @append_space
+)
+
+(declaration
+  ":"
+  .
+  (_)
+  (_) @prepend_space
+)
+
+"," @append_space
+
+(arguments
+  (_)
+  .
+  (_) @_value @prepend_space
+  (#whitespace-before? @_value)
+)
+
+(binary_expression
+  [
+    "+"
+    "-"
+  ] @prepend_space @append_space
+)
+
+; Multiplications and divisions are spaced as in the input, as a slash also
+; separates the values of shorthand properties, e.g., "font: 12px/1.5 serif".
+(binary_expression
+  [
+    "*"
+    "/"
+  ] @_operator @prepend_space @append_space
+  (#whitespace-before? @_operator)
+)
+
+; At-rules
+[
+  "@charset"
+  "@import"
+  "@keyframes"
+  "@media"
+  "@namespace"
+  "@supports"
+  "@each"
+  "@extend"
+  "@for"
+  "@forward"
+  "@function"
+  "@include"
+  "@mixin"
+  "@return"
+  "@use"
+  (at_keyword)
+] @append_space
+
+(each_statement
+  "in" @prepend_space @append_space
+)
+
+(for_statement
+  [
+    "from"
+    "through"
+  ] @prepend_space @append_space
+)
+
+(import_statement
+  (_) @prepend_space
+)
+
+(feature_query
+  ":" @append_space
+)
+
+(binary_query
+  [
+    "and"
+    "or"
+  ] @prepend_space @append_space
+)
+
+(unary_query
+  [
+    "not"
+    "only"
+  ] @append_space
+)
@@ -0,0 +1,99 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (comment)
+  (literal)
+  (marginalia)
+] @leaf
+
+; Keywords are written in upper case, and surrounded by spaces.
+[
+  (keyword_all)
+  (keyword_and)
+  (keyword_as)
+  (keyword_asc)
+  (keyword_between)
+  (keyword_by)
+  (keyword_case)
+  (keyword_delete)
+  (keyword_desc)
+  (keyword_distinct)
+  (keyword_else)
+  (keyword_end)
+  (keyword_exists)
+  (keyword_from)
+  (keyword_group)
+  (keyword_having)
+  (keyword_in)
+  (keyword_inner)
+  (keyword_insert)
+  (keyword_into)
+  (keyword_is)
+  (keyword_join)
+  (keyword_left)
+  (keyword_like)
+  (keyword_limit)
+  (keyword_not)
+  (keyword_null)
+  (keyword_offset)
+  (keyword_on)
+  (keyword_or)
+  (keyword_order)
+  (keyword_outer)
+  (keyword_select)
+  (keyword_set)
+  (keyword_then)
+  (keyword_union)
+  (keyword_update)
+  (keyword_values)
+  (keyword_when)
+  (keyword_where)
+] @upper_case @prepend_space @append_space
+
+; Allow blank line before
+(program
+  (statement) @allow_blank_line_before
+)
+
+; Input softlines before all comments. This means that the input decides if a
+; comment should have a line break before it. A comment always ends with a line
+; break.
+[
+  (comment)
+  (marginalia)
+] @prepend_input_softline @append_hardline
+
+; Every statement ends with a line break.
+";" @append_hardline
+
+; Every clause is on a line of its own.
+[
+  (from)
+  (group_by)
+  (join)
+  (limit)
+  (order_by)
+  (where)
+] @prepend_hardline
+
+"," @append_space
+
+(binary_expression
+  operator: _ @prepend_space @append_space
+)
+
+; Aliases without the AS keyword
+(relation
+  (_) @append_space
+  .
+  (identifier)
+)
+
+; Subqueries are indented, on lines of their own.
+(subquery
+  .
+  "(" @append_hardline @append_indent_start
+  ")" @prepend_hardline @prepend_indent_end
+  .
+)
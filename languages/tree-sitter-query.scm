@@ -54,6 +54,20 @@
   (parameters) @prepend_space
 )
 
+; Predicates whose parameters span several lines have their first parameter
+; on the line of their name, and each of the others on a line of its own,
+; indented.
+(predicate
+  (parameters) @prepend_indent_start @append_indent_end
+  ")" @prepend_empty_softline
+)
+
+(parameters
+  (_)
+  .
+  (_) @prepend_spaced_softline
+)
+
 ; Line breaks and indentation in multi-line lists and nodes
 (list
   "[" @append_indent_start @append_empty_softline
@@ -0,0 +1,96 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this. This keeps CDATA sections exactly as they are.
+[
+  (AttValue)
+  (CDSect)
+  (Comment)
+  (doctypedecl)
+  (PI)
+  (XMLDecl)
+] @leaf
+
+; The whitespace of elements that ask for it to be preserved is significant, so
+; they are kept as they are.
+(
+  (element
+    (STag
+      (Attribute
+        (Name) @_name
+        (AttValue) @_value
+      )
+    )
+  ) @leaf
+  (#eq? @_name "xml:space")
+  (#any-of? @_value "\"preserve\"" "'preserve'")
+)
+
+; Multi-line text is indented with the rest of the document.
+(CharData) @reindent
+
+; Adding whitespace to the content of an element may change its meaning, e.g.,
+; in mixed content. We therefore only put a space or a line break between the
+; children of elements where there was whitespace in the input, and the input
+; decides which one it is.
+(document
+  (_) @_child @allow_blank_line_before @prepend_input_softline
+  (#whitespace-before? @_child)
+)
+
+(prolog
+  (_) @_child @allow_blank_line_before @prepend_input_softline
+  (#whitespace-before? @_child)
+)
+
+(content
+  (_) @_child @allow_blank_line_before @prepend_input_softline
+  (#whitespace-before? @_child)
+)
+
+(element
+  (ETag) @_tag @prepend_input_softline
+  (#whitespace-before? @_tag)
+)
+
+; The content of elements is indented.
+(element
+  (STag) @append_indent_start
+  (ETag) @prepend_indent_end
+)
+
+; Tags that do not fit in the configured line width have one attribute per
+; line.
+(
+  [
+    (EmptyElemTag)
+    (STag)
+  ] @begin_scope @end_scope
+  (#scope_id! "tag")
+  (#forced_by_contents!)
+)
+
+(_
+  (Attribute) @prepend_spaced_scoped_softline
+  (#scope_id! "tag")
+)
+
+[
+  (EmptyElemTag
+    (Name) @append_indent_start
+    "/>" @prepend_indent_end
+  )
+  (STag
+    (Name) @append_indent_start
+    ">" @prepend_indent_end
+  )
+]
+
+(STag
+  ">" @prepend_empty_scoped_softline
+  (#scope_id! "tag")
+)
+
+(EmptyElemTag
+  "/>" @prepend_spaced_scoped_softline
+  (#scope_id! "tag")
+)
@@ -0,0 +1,123 @@
+; Sometimes we want to indicate that certain parts of our source text should
+; not be formatted, but taken as is. We use the leaf capture name to inform the
+; tool of this.
+[
+  (block_scalar)
+  (double_quote_scalar)
+  (reserved_directive)
+  (single_quote_scalar)
+  (tag_directive)
+  (yaml_directive)
+] @leaf
+
+; YAML is sensitive to indentation: the continuation lines of a multi-line
+; scalar must be indented under its key. They are therefore indented with the
+; rest of the code, keeping their relative indentation, rather than left where
+; they were in the input.
+[
+  (double_quote_scalar)
+  (single_quote_scalar)
+  (string_scalar)
+] @reindent
+
+; Block scalars whose indentation or trailing line breaks are explicit, like
+; "|2" or "|+", are kept verbatim.
+(
+  (block_scalar) @reindent
+  (#match? @reindent "^[|>]-?\\s")
+)
+
+; Append spaces
+[
+  "-"
+  ":"
+  "?"
+] @append_space
+
+(_
+  [
+    (anchor)
+    (tag)
+  ] @append_space
+)
+
+; Input softlines before all comments. This means that the input decides if a
+; comment should have a line break before it. A comment always ends with a line
+; break.
+(comment) @prepend_input_softline @append_hardline
+
+; Allow blank line before
+[
+  (block_mapping_pair)
+  (block_sequence_item)
+  (comment)
+] @allow_blank_line_before
+
+; Every entry of a block collection is on a line of its own. If there is a
+; comment following, we don't add anything, because the input softlines above
+; will already have sorted out the formatting.
+(
+  [
+    (block_mapping_pair)
+    (block_sequence_item)
+  ] @append_hardline
+  .
+  (comment)* @do_nothing
+)
+
+[
+  (document)
+  (reserved_directive)
+  (tag_directive)
+  (yaml_directive)
+] @append_hardline
+
+"---" @append_input_softline
+
+; The values of entries are indented, so that anything on the lines after
+; their key or dash is indented under it.
+(block_mapping_pair
+  value: (_) @prepend_indent_start @append_indent_end
+)
+
+(block_sequence_item
+  (_) @prepend_indent_start @append_indent_end
+)
+
+; Block collections start on a new line under their key.
+(block_mapping_pair
+  value: (block_node
+    [
+      (block_mapping)
+      (block_sequence)
+    ] @prepend_hardline
+  )
+)
+
+; The value of an explicit key is on the line after it.
+(block_mapping_pair
+  "?"
+  ":" @prepend_hardline
+)
+
+; Flow collections are put on several lines, one entry per line, if they span
+; several lines.
+(flow_mapping
+  .
+  "{" @append_empty_softline @append_indent_start
+  "}" @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+(flow_sequence
+  .
+  "[" @append_empty_softline @append_indent_start
+  "]" @prepend_empty_softline @prepend_indent_end
+  .
+)
+
+(
+  ","
+  .
+  (_) @prepend_spaced_softline
+)
@@ -0,0 +1,54 @@
+; Zig code is laid out as in the input: we keep its line breaks, and put a
+; single space where the input had whitespace. The indentation is recomputed
+; from the brackets, and spaces are added around binary operators.
+(
+  _ @_node @allow_blank_line_before @prepend_input_softline
+  (#whitespace-before? @_node)
+)
+
+[
+  "="
+  "=="
+  "!="
+  "<"
+  ">"
+  "<="
+  ">="
+  "=>"
+  "+="
+  "-="
+  "+"
+  "and"
+  "or"
+  "orelse"
+] @prepend_space @append_space
+
+"," @append_space
+
+; The contents of blocks, container declarations, struct literals and switch
+; expressions are indented, as are the arguments of calls that span several
+; lines.
+[
+  "{"
+  "("
+  "["
+] @append_indent_start
+
+[
+  "}"
+  ")"
+  "]"
+] @prepend_indent_end
+
+; Non-empty braces are padded with spaces, e.g., ".{ .x = 1 }".
+(_
+  "{" @append_space
+  .
+  (_)
+)
+
+(_
+  (_)
+  .
+  "}" @prepend_space
+)
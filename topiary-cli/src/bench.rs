@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    io::{sink, stdout, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use topiary::{format_with_metrics, Configuration, FormatMetrics, FormatterOptions, Language};
+
+use crate::{
+    error::{CLIError, CLIResult, TopiaryError},
+    query::language_query,
+};
+
+/// The phases of formatting that `bench` measures, along with how to read them from the metrics
+/// of a run.
+const BENCHMARK_PHASES: &[(&str, fn(&FormatMetrics) -> Duration)] = &[
+    ("parse", |m| m.parse_time),
+    ("query", |m| m.query_time),
+    ("post_processing", |m| m.post_processing_time),
+    ("render", |m| m.render_time),
+];
+
+/// Format each of the input files several times, without the idempotence check, and report the
+/// mean time of each phase of formatting, either as a table or as JSON, to stdout. The query and
+/// grammar of each file are loaded once, and a first run, which is not measured, warms the caches
+/// up.
+pub async fn run_benchmarks(
+    configuration: &Configuration,
+    files: &[PathBuf],
+    iterations: u32,
+    json: bool,
+) -> CLIResult<()> {
+    if iterations == 0 {
+        return Err(TopiaryError::Bin(
+            "The number of iterations must be at least 1".into(),
+            None,
+        ));
+    }
+
+    let mut results = Vec::new();
+
+    for file in files {
+        let language = Language::detect(file, configuration)?;
+
+        let content = fs::read_to_string(file).map_err(|e| {
+            TopiaryError::Bin(
+                format!("Could not open input file {}", file.display()),
+                Some(CLIError::IOError(e)),
+            )
+        })?;
+
+        let (_, query) = language_query(language, None)?;
+
+        let grammar = language.grammar().await?;
+        let options = FormatterOptions::new(&query, language, &grammar).skip_idempotence(true);
+
+        let mut runs = Vec::new();
+        for _ in 0..=iterations {
+            runs.push(format_with_metrics(
+                &mut content.as_bytes(),
+                &mut sink(),
+                &options,
+            )?);
+        }
+
+        // The first run only warms the caches up
+        let means: Vec<Duration> = BENCHMARK_PHASES
+            .iter()
+            .map(|(_, phase)| runs[1..].iter().map(phase).sum::<Duration>() / iterations)
+            .collect();
+
+        results.push((file.display().to_string(), language.name.clone(), means));
+    }
+
+    let milliseconds = |duration: &Duration| duration.as_secs_f64() * 1000.0;
+
+    if json {
+        let results: Vec<serde_json::Value> = results
+            .iter()
+            .map(|(file, language, means)| {
+                let mut phases: serde_json::Map<String, serde_json::Value> = BENCHMARK_PHASES
+                    .iter()
+                    .zip(means)
+                    .map(|((name, _), mean)| (name.to_string(), milliseconds(mean).into()))
+                    .collect();
+                phases.insert(
+                    "total".into(),
+                    milliseconds(&means.iter().sum::<Duration>()).into(),
+                );
+
+                serde_json::json!({
+                    "file": file,
+                    "language": language,
+                    "iterations": iterations,
+                    "mean_milliseconds": phases,
+                })
+            })
+            .collect();
+
+        let mut stdout = stdout();
+        serde_json::to_writer_pretty(&mut stdout, &results).map_err(|e| {
+            TopiaryError::Bin(
+                "Could not write the benchmark results".into(),
+                Some(CLIError::Generic(Box::new(e))),
+            )
+        })?;
+        writeln!(stdout)?;
+
+        return Ok(());
+    }
+
+    let width = results
+        .iter()
+        .map(|(file, _, _)| file.len())
+        .chain(["file".len()])
+        .max()
+        .unwrap_or_default();
+
+    print!("{:width$}  {:10}", "file", "language");
+    for (name, _) in BENCHMARK_PHASES {
+        print!("  {name:>15}");
+    }
+    println!("  {:>15}", "total");
+
+    for (file, language, means) in &results {
+        print!("{file:width$}  {language:10}");
+        for mean in means {
+            print!("  {:>12.3} ms", milliseconds(mean));
+        }
+        println!(
+            "  {:>12.3} ms",
+            milliseconds(&means.iter().sum::<Duration>())
+        );
+    }
+
+    println!("Mean times over {iterations} iteration(s), without the idempotence check");
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use crate::error::CLIResult;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+use topiary::Language;
+
+/// A directory that records the inputs that are known to be formatted, so that `--check`,
+/// `--in-place` and `--documents` can skip them on later runs. An input is recorded by an empty
+/// file, named after the hash of its content, of the query, of the grammar, of the configuration
+/// of the language, and of the options that it was formatted with; a change to any of them gives
+/// another name, so that the input is formatted anew.
+// NOTE The hashes are not cryptographic, nor stable across builds of Topiary, which only costs
+// the inputs that were recorded by another build to be formatted again.
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    // The hash of everything but the content of the inputs
+    context: u64,
+}
+
+impl Cache {
+    pub fn new(
+        dir: &Path,
+        query: &str,
+        language: &Language,
+        grammar: &tree_sitter_facade::Language,
+        options: &str,
+    ) -> Self {
+        let mut hasher = DefaultHasher::new();
+
+        // Grammars are built into Topiary, so their revision changes with its version, and the
+        // node kinds of a grammar tell it apart from another revision
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        for id in 0..grammar.node_kind_count() {
+            let id = id as u16;
+            grammar.node_kind_for_id(id).hash(&mut hasher);
+            grammar.node_kind_is_named(id).hash(&mut hasher);
+        }
+
+        query.hash(&mut hasher);
+        language.name.hash(&mut hasher);
+        language.indent.hash(&mut hasher);
+        language.line_width.hash(&mut hasher);
+        language
+            .settings
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(&mut hasher);
+        options.hash(&mut hasher);
+
+        Self {
+            dir: dir.to_path_buf(),
+            context: hasher.finish(),
+        }
+    }
+
+    /// Whether the content was recorded as formatted.
+    pub fn contains(&self, content: &[u8]) -> bool {
+        self.entry(content).exists()
+    }
+
+    /// Record that the content is formatted, creating the directory if needed.
+    pub fn insert(&self, content: &[u8]) -> CLIResult<()> {
+        fs::create_dir_all(&self.dir)?;
+        File::create(self.entry(content))?;
+
+        Ok(())
+    }
+
+    fn entry(&self, content: &[u8]) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        self.context.hash(&mut hasher);
+        content.hash(&mut hasher);
+
+        self.dir.join(format!("{:016x}", hasher.finish()))
+    }
+}
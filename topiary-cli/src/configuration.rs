@@ -1,6 +1,6 @@
 use directories::ProjectDirs;
-use std::{env::current_dir, path::PathBuf};
-use topiary::{default_configuration_toml, Configuration};
+use std::{collections::HashMap, env::current_dir, path::PathBuf};
+use topiary::{default_configuration_toml, Configuration, FileProvider, OsFileProvider};
 
 use crate::error::{CLIResult, TopiaryError};
 
@@ -10,13 +10,32 @@ pub fn parse_configuration() -> CLIResult<Configuration> {
         .map_err(TopiaryError::from)
 }
 
+/// The exit codes that the user configuration gives to errors, by their code, in its `exit_codes`
+/// table, e.g. `E010_PARSE = 65`, instead of those of Topiary.
+pub fn exit_codes() -> CLIResult<HashMap<String, u8>> {
+    let exit_codes: HashMap<String, u8> = match user_configuration_toml()?.get("exit_codes") {
+        Some(table) => table.clone().try_into()?,
+        None => HashMap::new(),
+    };
+
+    // Failures must not look like successes
+    match exit_codes.iter().find(|(_, exit_code)| **exit_code == 0) {
+        Some((code, _)) => Err(TopiaryError::Bin(
+            format!("The exit code of {code} cannot be 0"),
+            None,
+        )),
+        None => Ok(exit_codes),
+    }
+}
+
 /// User configured languages.toml file, merged with the default config.
 fn user_configuration_toml() -> CLIResult<toml::Value> {
     let config = [find_configuration_dir(), find_workspace().join(".topiary")]
         .into_iter()
         .map(|path| path.join("languages.toml"))
         .filter_map(|file| {
-            std::fs::read_to_string(file)
+            OsFileProvider
+                .read_to_string(&file)
                 .map(|config| toml::from_str(&config))
                 .ok()
         })
@@ -0,0 +1,57 @@
+use std::{fs, path::PathBuf};
+
+use topiary::Language;
+
+use crate::{
+    error::{CLIError, CLIResult, TopiaryError},
+    query::language_query,
+};
+
+/// Report how well the query of the language, or the given one, covers the input files: the
+/// patterns that match none of them, and the node kinds in them that no pattern captures.
+pub async fn report_coverage(
+    language: &Language,
+    query_path: Option<PathBuf>,
+    files: &[PathBuf],
+) -> CLIResult<()> {
+    let (query_path, query) = language_query(language, query_path.as_ref())?;
+
+    let inputs = files
+        .iter()
+        .map(|file| {
+            fs::read_to_string(file).map_err(|e| {
+                TopiaryError::Bin(
+                    format!("Could not open input file {}", file.display()),
+                    Some(CLIError::IOError(e)),
+                )
+            })
+        })
+        .collect::<CLIResult<Vec<String>>>()?;
+    let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+
+    let grammar = language.grammar().await?;
+    let coverage = topiary::query_coverage(&query, &grammar, &inputs)?;
+
+    let mut unmatched = 0;
+    for pattern in coverage.unmatched_patterns() {
+        println!(
+            "{}:{}: pattern matches none of the input files",
+            query_path.display(),
+            pattern.line
+        );
+        unmatched += 1;
+    }
+
+    for kind in &coverage.untouched_kinds {
+        println!("{kind}: node kind is never captured");
+    }
+
+    println!(
+        "{} of {} pattern(s) matched; {} node kind(s) never captured",
+        coverage.patterns.len() - unmatched,
+        coverage.patterns.len(),
+        coverage.untouched_kinds.len()
+    );
+
+    Ok(())
+}
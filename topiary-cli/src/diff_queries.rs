@@ -0,0 +1,64 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use topiary::{FormatterOptions, Language};
+
+use crate::{
+    error::{CLIError, CLIResult, TopiaryError},
+    query::read_query_file,
+};
+
+/// Format the input files with the old and the new versions of the query, and report, to stdout,
+/// the files whose output changes, each with the diff from its old output to its new one.
+/// Formatting failures are reported for each file, and make the command fail once every file has
+/// been compared.
+pub async fn report_query_diff(
+    language: &Language,
+    old_path: &Path,
+    new_path: &Path,
+    files: &[PathBuf],
+) -> CLIResult<()> {
+    let old_query = read_query_file(old_path)?.content;
+    let new_query = read_query_file(new_path)?.content;
+
+    let grammar = language.grammar().await?;
+    let old = FormatterOptions::new(&old_query, language, &grammar);
+    let new = FormatterOptions::new(&new_query, language, &grammar);
+
+    let mut changed = 0;
+    let mut failures = 0;
+
+    for file in files {
+        let input = fs::read_to_string(file).map_err(|e| {
+            TopiaryError::Bin(
+                format!("Could not open input file {}", file.display()),
+                Some(CLIError::IOError(e)),
+            )
+        })?;
+
+        match topiary::diff_queries(&input, &old, &new) {
+            Ok(None) => {}
+            Ok(Some(diff)) => {
+                println!("{}: the output changes\n{diff}", file.display());
+                changed += 1;
+            }
+            Err(e) => {
+                println!("{}: could not be formatted: {e}", file.display());
+                failures += 1;
+            }
+        }
+    }
+
+    println!("{changed} of {} file(s) changed", files.len());
+
+    if failures == 0 {
+        Ok(())
+    } else {
+        Err(TopiaryError::Bin(
+            format!("{failures} file(s) could not be formatted"),
+            None,
+        ))
+    }
+}
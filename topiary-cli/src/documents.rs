@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    io::{stdin, stdout, BufRead, Read, Write},
+    path::PathBuf,
+    sync::atomic::Ordering,
+};
+
+use topiary::{
+    format, Configuration, FormatterOptions, Language, LanguageRegistry, OsFileProvider,
+    OverlayFileProvider,
+};
+
+use crate::{
+    cache::Cache,
+    error::{CLIResult, TopiaryError},
+    formatting::{cache_options, formatting_options, record_formatted, WARNINGS},
+    query::language_query,
+    Args,
+};
+
+/// Format the documents framed on standard input, writing the output of each, framed likewise, to
+/// standard output as soon as it is formatted, so that many small files can be formatted by a
+/// single process. A document that cannot be formatted is reported in its frame, and the next
+/// ones are still formatted. Once the input is exhausted, the errors of all the documents are
+/// returned together, as a `TopiaryError::Batch`.
+pub async fn format_documents(configuration: &Configuration, args: &Args) -> CLIResult<()> {
+    let mut input = stdin().lock();
+    let mut output = stdout().lock();
+    // The queries that have been read, by path and overlays
+    let mut queries = HashMap::new();
+    // The injected languages are loaded once for all the documents
+    let injections = LanguageRegistry::new(configuration.clone());
+    let mut errors = Vec::new();
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            break;
+        }
+
+        let (length, name) = parse_document_header(&header)?;
+        let mut content = vec![0; length];
+        input.read_exact(&mut content)?;
+
+        // The document is left as it is, whatever its language
+        if topiary::skips_file(&String::from_utf8_lossy(&content)) {
+            writeln!(output, "skipped {} {name}", content.len())?;
+            output.write_all(&content)?;
+            output.flush()?;
+            continue;
+        }
+
+        let mut formatted = Vec::new();
+        match format_document(
+            configuration,
+            args,
+            name,
+            &content,
+            &mut formatted,
+            &mut queries,
+            &injections,
+        )
+        .await
+        {
+            Ok(()) => {
+                writeln!(output, "ok {} {name}", formatted.len())?;
+                output.write_all(&formatted)?;
+            }
+            Err(e) => {
+                let message = e.to_string();
+                writeln!(output, "{} {} {name}", e.code(), message.len())?;
+                output.write_all(message.as_bytes())?;
+                errors.push((name.to_string(), e));
+            }
+        }
+
+        output.flush()?;
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(TopiaryError::Batch(errors))
+    }
+}
+
+/// Parse the header of a framed document: its length in bytes, then its file name, which may
+/// have spaces, separated by a space.
+fn parse_document_header(header: &str) -> CLIResult<(usize, &str)> {
+    let header = header.trim_end_matches(|c| c == '\n' || c == '\r');
+
+    header
+        .split_once(' ')
+        .and_then(|(length, name)| Some((length.parse().ok()?, name)))
+        .ok_or_else(|| TopiaryError::Bin(format!("Invalid document header: {header:?}"), None))
+}
+
+/// Format a framed document, in the language given by the arguments, or else detected from its
+/// modelines, its file name, or its shebang line.
+async fn format_document(
+    configuration: &Configuration,
+    args: &Args,
+    name: &str,
+    content: &[u8],
+    output: &mut Vec<u8>,
+    queries: &mut HashMap<(PathBuf, Vec<String>), String>,
+    injections: &LanguageRegistry,
+) -> CLIResult<()> {
+    let language = match args.language {
+        Some(language) => language.to_language(configuration),
+        None => {
+            // The modelines and the shebang line are read from the document, rather than from
+            // the file of its name
+            let mut files = OverlayFileProvider::new(OsFileProvider);
+            files.insert(name, String::from_utf8_lossy(content));
+
+            Language::detect_in(name, &files, configuration)?
+        }
+    };
+
+    // Languages that share a query file may layer different overlays over it
+    let key = match &args.query {
+        Some(query) => (query.clone(), Vec::new()),
+        None => (language.query_file()?, language.overlays.clone()),
+    };
+    if !queries.contains_key(&key) {
+        let (_, query) = language_query(language, args.query.as_ref())?;
+        queries.insert(key.clone(), query);
+    }
+
+    let query = &queries[&key];
+    let grammar = language.grammar().await?;
+    let options = formatting_options(
+        FormatterOptions::new(query, language, &grammar),
+        args,
+        injections,
+    );
+    let cache = args
+        .cache
+        .as_deref()
+        .map(|dir| Cache::new(dir, query, language, &grammar, &cache_options(args)));
+
+    if cache
+        .as_ref()
+        .map_or(false, |cache| cache.contains(content))
+    {
+        output.extend_from_slice(content);
+        return Ok(());
+    }
+
+    let warnings = WARNINGS.load(Ordering::Relaxed);
+    format(&mut &content[..], output, &options)?;
+
+    record_formatted(cache.as_ref(), content, output, warnings)
+}
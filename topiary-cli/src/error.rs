@@ -1,5 +1,6 @@
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use std::{error, fmt, io, process::ExitCode, result};
-use topiary::FormatterError;
+use topiary::{source_chain, ErrorCategory, FormatterError};
 
 /// A convenience wrapper around `std::result::Result<T, TopiaryError>`.
 pub type CLIResult<T> = result::Result<T, TopiaryError>;
@@ -11,6 +12,14 @@ pub type CLIResult<T> = result::Result<T, TopiaryError>;
 pub enum TopiaryError {
     Lib(FormatterError),
     Bin(String, Option<CLIError>),
+    /// The errors of the documents of a batch that could not be formatted, along with their names,
+    /// in the order of the documents
+    Batch(Vec<(String, TopiaryError)>),
+    /// The input, of the given name, is not formatted, as `--check` found
+    Unformatted(String),
+    /// Formatting succeeded, but gave the given number of warnings, which `--deny-warnings` turns
+    /// into an error
+    Warnings(usize),
 }
 
 /// A subtype of `TopiaryError::Bin`
@@ -20,11 +29,81 @@ pub enum CLIError {
     Generic(Box<dyn error::Error>),
 }
 
+impl TopiaryError {
+    /// A stable code that identifies the kind of the error. Library errors keep their own code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Lib(error) => error.code(),
+            Self::Bin(_, Some(CLIError::IOError(_))) => "E050_IO",
+            Self::Bin(_, _) => "E070_CLI",
+            Self::Batch(errors) => errors.first().map_or("E070_CLI", |(_, error)| error.code()),
+            Self::Unformatted(_) => "E071_UNFORMATTED",
+            Self::Warnings(_) => "E072_WARNINGS",
+        }
+    }
+
+    /// The category of the error.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Lib(error) => error.category(),
+            Self::Bin(_, Some(CLIError::IOError(_))) => ErrorCategory::Io,
+            Self::Bin(_, _) => ErrorCategory::Usage,
+            Self::Batch(errors) => errors
+                .first()
+                .map_or(ErrorCategory::Usage, |(_, error)| error.category()),
+            Self::Unformatted(_) | Self::Warnings(_) => ErrorCategory::Formatting,
+        }
+    }
+
+    /// The exit code of the process that fails with the error, unless the configuration gives
+    /// another one to its code. A batch exits with the code of its first error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            // Warnings, with `--deny-warnings`: Exit 11
+            Self::Warnings(_) => 11,
+
+            // The input is not formatted: Exit 10
+            Self::Unformatted(_) => 10,
+
+            // The errors of the library: Exits 3 to 9, or else 1
+            Self::Lib(error) => error.exit_code(),
+
+            // I/O errors: Exit 3
+            Self::Bin(_, Some(CLIError::IOError(_))) => 3,
+
+            // Bad arguments: Exit 2
+            // (Handled by clap: https://github.com/clap-rs/clap/issues/3426)
+
+            // The errors of a batch
+            Self::Batch(errors) => errors.first().map_or(1, |(_, error)| error.exit_code()),
+
+            // Anything else: Exit 1
+            _ => 1,
+        }
+    }
+}
+
 impl fmt::Display for TopiaryError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::Lib(error) => write!(f, "{error}"),
             Self::Bin(message, _) => write!(f, "{message}"),
+            Self::Unformatted(name) => write!(f, "{name} is not formatted"),
+            Self::Warnings(count) => write!(
+                f,
+                "Formatting gave {count} warning(s), which --deny-warnings turns into errors"
+            ),
+            Self::Batch(errors) => {
+                write!(f, "{} document(s) could not be formatted", errors.len())?;
+                for (name, e) in errors {
+                    write!(f, "\n{name}: [{}] {e}", e.code())?;
+                    if let Some(source) = error::Error::source(e) {
+                        write!(f, "\n  Cause: {source}")?;
+                    }
+                }
+
+                Ok(())
+            }
         }
     }
 }
@@ -35,41 +114,47 @@ impl error::Error for TopiaryError {
             Self::Lib(error) => error.source(),
             Self::Bin(_, Some(CLIError::IOError(error))) => Some(error),
             Self::Bin(_, Some(CLIError::Generic(error))) => error.source(),
-            Self::Bin(_, None) => None,
+            Self::Bin(_, None) | Self::Unformatted(_) | Self::Warnings(_) => None,
+            // The causes of the errors of a batch are given along with them
+            Self::Batch(_) => None,
         }
     }
 }
 
-impl From<TopiaryError> for ExitCode {
-    fn from(e: TopiaryError) -> Self {
-        let exit_code = match e {
-            // Formatting errors: Exit 8
-            TopiaryError::Lib(FormatterError::Formatting(_)) => 8,
-
-            // Idempotency errors: Exit 7
-            TopiaryError::Lib(FormatterError::Idempotence) => 7,
-
-            // Language detection errors: Exit 6
-            TopiaryError::Lib(FormatterError::LanguageDetection(_, _)) => 6,
-
-            // Parsing errors: Exit 5
-            TopiaryError::Lib(FormatterError::Parsing { .. }) => 5,
-
-            // Query errors: Exit 4
-            TopiaryError::Lib(FormatterError::Query(_, _)) => 4,
-
-            // I/O errors: Exit 3
-            TopiaryError::Lib(FormatterError::Io(_))
-            | TopiaryError::Bin(_, Some(CLIError::IOError(_))) => 3,
+/// CLI errors are serialised as library errors are, with the same fields. The errors of a batch
+/// have an additional `documents` field, which lists the name and the error of each document.
+impl Serialize for TopiaryError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Document<'a> {
+            name: &'a str,
+            error: &'a TopiaryError,
+        }
 
-            // Bad arguments: Exit 2
-            // (Handled by clap: https://github.com/clap-rs/clap/issues/3426)
+        if let Self::Lib(e) = self {
+            return e.serialize(serializer);
+        }
 
-            // Anything else: Exit 1
-            _ => 1,
-        };
+        let mut error = serializer.serialize_struct("TopiaryError", 6)?;
+        error.serialize_field("kind", &self.category())?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.serialize_field("span", &None::<()>)?;
+        error.serialize_field("sources", &source_chain(self))?;
+        if let Self::Batch(errors) = self {
+            let documents: Vec<Document> = errors
+                .iter()
+                .map(|(name, error)| Document { name, error })
+                .collect();
+            error.serialize_field("documents", &documents)?;
+        }
+        error.end()
+    }
+}
 
-        ExitCode::from(exit_code)
+impl From<TopiaryError> for ExitCode {
+    fn from(e: TopiaryError) -> Self {
+        ExitCode::from(e.exit_code())
     }
 }
 
@@ -0,0 +1,344 @@
+use std::{
+    fs::{self, File},
+    io::{stdin, BufReader, BufWriter, Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use memmap2::Mmap;
+use topiary::{
+    dump_atoms, format, format_with_source_map, formatter, ByteOrderMark, Configuration, Encoding,
+    FormatterOptions, IntermediateRepresentation, Language, LanguageRegistry, Operation, Warning,
+    SKIP_FILE_PRAGMA,
+};
+
+use crate::{
+    cache::Cache,
+    error::{CLIError, CLIResult, TopiaryError},
+    output::OutputFile,
+    query::{in_query_file, language_query, report_query_profile},
+    report::{checkstyle_log, report_check, sarif_log, Violation},
+    Args, ErrorFormat, OutputFormat,
+};
+
+/// The number of warnings that formatting gave, for `--deny-warnings`
+pub static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// The size from which input files are mapped in memory, rather than read, so that they are not
+/// copied before being formatted. Smaller files are cheaper to read.
+const MAPPED_INPUT_BYTES: u64 = 1 << 20;
+
+/// Map the input file in memory, if it is large enough, and it can be mapped: standard input,
+/// pipes and other special files are read as usual.
+fn map_input(path: &str) -> Option<Mmap> {
+    let file = File::open(path).ok()?;
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() < MAPPED_INPUT_BYTES {
+        return None;
+    }
+
+    // SAFETY: The mapping is only read, and Topiary never writes to its input: in-place
+    // formatting replaces the file with a new one. As with any tool that maps its inputs, another
+    // process that modifies or truncates the file while it is formatted can make Topiary crash.
+    unsafe { Mmap::map(&file) }.ok()
+}
+
+/// Format the input file, or standard input, to the output file, standard output, or the input
+/// file itself, as the arguments say.
+pub async fn format_input(configuration: &Configuration, args: Args) -> CLIResult<()> {
+    // A file that opts out of formatting is left untouched, rather than rewritten as it is
+    if let Some(file) = args.input_file.as_deref().filter(|file| *file != "-") {
+        let skipped = (args.in_place || args.check)
+            && fs::read(file).map_or(false, |content| {
+                topiary::skips_file(&String::from_utf8_lossy(&content))
+            });
+        if skipped {
+            eprintln!("Skipping {file}, which has a `{SKIP_FILE_PRAGMA}` pragma");
+            return Ok(());
+        }
+    }
+
+    // The as_deref() gives us an Option<&str>, which we can match against
+    // string literals
+    let mut input: Box<(dyn Read)> = match args.input_file.as_deref() {
+        Some("-") | None => Box::new(stdin()),
+        Some(file) => Box::new(BufReader::new(File::open(file)?)),
+    };
+
+    // NOTE If --in-place is specified, it overrides --output-file
+    let mut output = BufWriter::new(if args.in_place {
+        // NOTE Clap handles the case when no input file is specified. If the input file is
+        // explicitly set to stdin (i.e., -), then --in-place will set the output to stdout; which
+        // is not completely weird.
+        OutputFile::new(args.input_file.as_deref(), args.backup_suffix.as_deref())?
+    } else {
+        OutputFile::new(args.output_file.as_deref(), None)?
+    });
+
+    let language = if let Some(language) = args.language {
+        language.to_language(configuration)
+    } else if let Some(filename) = args.input_file.as_deref() {
+        Language::detect(filename, configuration)?
+    } else {
+        // Clap ensures we won't get here
+        unreachable!();
+    };
+
+    let (query_path, query) = language_query(language, args.query.as_ref())?;
+    // Injected languages are formatted as configured too
+    let injections = LanguageRegistry::new(configuration.clone());
+
+    // Errors of the query are reported in its file
+    let result = async {
+        let grammar = language.grammar().await?;
+
+        // Without a range of lines, the whole input is traced
+        let trace_lines = args
+            .trace_queries
+            .clone()
+            .map(|lines| lines.unwrap_or(1..=usize::MAX));
+
+        if args.dump_atoms {
+            let mut options = FormatterOptions::new(&query, language, &grammar)
+                .tolerate_parsing_errors(args.tolerate_parsing_errors);
+            if let Some(lines) = trace_lines {
+                options = options.trace_queries(lines);
+            }
+
+            dump_atoms(&mut input, &mut output, &options)?;
+        } else if args.output_format == OutputFormat::Ir {
+            let mut options = FormatterOptions::new(&query, language, &grammar)
+                .tolerate_parsing_errors(args.tolerate_parsing_errors);
+            if let Some(lines) = trace_lines {
+                options = options.trace_queries(lines);
+            }
+
+            IntermediateRepresentation::new(&mut input, &options)?.write_json(&mut output)?;
+        } else if let Some(source_map_path) = args.source_map {
+            let mut content = String::new();
+            input.read_to_string(&mut content)?;
+
+            let (formatted, source_map) = format_with_source_map(
+                &content,
+                &query,
+                language,
+                &grammar,
+                args.skip_idempotence,
+                args.tolerate_parsing_errors,
+            )?;
+            write!(output, "{formatted}")?;
+
+            let mut source_map_file = BufWriter::new(File::create(source_map_path)?);
+            source_map.write_json(&mut source_map_file)?;
+            source_map_file.flush()?;
+        } else if let Some(visualisation) = args.visualise {
+            formatter(
+                &mut input,
+                &mut output,
+                &query,
+                language,
+                &grammar,
+                Operation::Visualise {
+                    output_format: visualisation.into(),
+                },
+            )?;
+        } else {
+            let mut options = formatting_options(
+                FormatterOptions::new(&query, language, &grammar),
+                &args,
+                &injections,
+            );
+            if let Some(lines) = trace_lines {
+                options = options.trace_queries(lines);
+            }
+            let cache = args
+                .cache
+                .as_deref()
+                .filter(|_| args.check || args.in_place)
+                .map(|dir| Cache::new(dir, &query, language, &grammar, &cache_options(&args)));
+
+            if matches!(
+                args.output_format,
+                OutputFormat::Sarif | OutputFormat::Checkstyle
+            ) {
+                let mut content = Vec::new();
+                input.read_to_end(&mut content)?;
+
+                let mut formatted = Vec::new();
+                let result = format(&mut content.as_slice(), &mut formatted, &options);
+                let violation = Violation::new(&content, &formatted, &result);
+                let input_file = args.input_file.as_deref();
+
+                if args.output_format == OutputFormat::Sarif {
+                    let log = sarif_log(input_file, violation.as_ref());
+                    serde_json::to_writer_pretty(&mut output, &log).map_err(|e| {
+                        TopiaryError::Bin(
+                            "Could not write the SARIF log".into(),
+                            Some(CLIError::Generic(Box::new(e))),
+                        )
+                    })?;
+                    writeln!(output)?;
+                } else {
+                    write!(output, "{}", checkstyle_log(input_file, violation.as_ref()))?;
+                }
+                output.into_inner()?.persist()?;
+
+                // The log holds the error, but the exit code still tells of it
+                result?;
+                return Ok(());
+            } else if args.check {
+                let mut content = Vec::new();
+                input.read_to_end(&mut content)?;
+
+                if cache
+                    .as_ref()
+                    .map_or(false, |cache| cache.contains(&content))
+                {
+                    let input_file = args.input_file.as_deref();
+                    report_check(args.output_format, input_file, &content, &content, &Ok(()));
+                    return Ok(());
+                }
+
+                let warnings = WARNINGS.load(Ordering::Relaxed);
+                let mut formatted = Vec::new();
+                let result = format(&mut content.as_slice(), &mut formatted, &options);
+                report_check(
+                    args.output_format,
+                    args.input_file.as_deref(),
+                    &content,
+                    &formatted,
+                    &result,
+                );
+                result?;
+
+                if formatted != content {
+                    let name = match args.input_file.as_deref() {
+                        Some(file) if file != "-" => file,
+                        _ => "The standard input",
+                    };
+
+                    return Err(TopiaryError::Unformatted(name.to_string()));
+                }
+
+                return record_formatted(cache.as_ref(), &content, &formatted, warnings);
+            } else if let Some(cache) = &cache {
+                // Formatting in place
+                let mut content = Vec::new();
+                input.read_to_end(&mut content)?;
+
+                // The file is left untouched, as the staged output is not persisted
+                if cache.contains(&content) {
+                    return Ok(());
+                }
+
+                let warnings = WARNINGS.load(Ordering::Relaxed);
+                let mut formatted = Vec::new();
+                format(&mut content.as_slice(), &mut formatted, &options)?;
+                output.write_all(&formatted)?;
+
+                record_formatted(Some(cache), &content, &formatted, warnings)?;
+            } else if args.profile_queries {
+                let mut content = String::new();
+                input.read_to_string(&mut content)?;
+
+                format(&mut content.as_bytes(), &mut output, &options)?;
+                let profile = topiary::profile_query(&query, &grammar, &[content.as_str()])?;
+                report_query_profile(&profile, &query_path);
+            } else if let Some(mapped) = args
+                .input_file
+                .as_deref()
+                .filter(|path| *path != "-")
+                .and_then(map_input)
+            {
+                topiary::format_bytes(&mapped, &mut output, &options)?;
+            } else {
+                format(&mut input, &mut output, &options)?;
+            }
+        }
+
+        output.into_inner()?.persist()?;
+
+        Ok::<(), TopiaryError>(())
+    }
+    .await;
+
+    result.map_err(|e| in_query_file(e, &query_path))
+}
+
+/// Set the options that the arguments give for formatting, other than the lines to trace.
+pub fn formatting_options<'a>(
+    options: FormatterOptions<'a>,
+    args: &Args,
+    injections: &'a LanguageRegistry,
+) -> FormatterOptions<'a> {
+    let mut options = options
+        .injections(injections)
+        .skip_idempotence(args.skip_idempotence)
+        .tolerate_parsing_errors(args.tolerate_parsing_errors)
+        .verify_tokens(args.verify_tokens)
+        .verify_comments(args.verify_comments);
+    if args.strip_bom {
+        options = options.byte_order_mark(ByteOrderMark::Strip);
+    }
+    if let Some(encoding) = args.encoding {
+        options = options.encoding(encoding);
+    }
+    if let Some(budget) = args.memory_budget {
+        options = options.memory_budget(budget);
+    }
+    if let Some(timeout) = args.timeout {
+        options = options.timeout(timeout);
+    }
+    let error_format = args.error_format;
+
+    options.on_warning(move |warning| report_warning(warning, error_format))
+}
+
+/// The options of the arguments that change the output, or what is checked of it, which the
+/// entries of the cache are keyed by.
+pub fn cache_options(args: &Args) -> String {
+    format!(
+        "{:?}",
+        (
+            args.skip_idempotence,
+            args.tolerate_parsing_errors,
+            args.verify_tokens,
+            args.verify_comments,
+            args.strip_bom,
+            args.encoding.map(Encoding::name),
+            args.memory_budget,
+        )
+    )
+}
+
+/// Record the content in the cache, if there is one, if formatting left it as it is, without
+/// warnings since there were the given number of them, so that it is skipped the next time.
+pub fn record_formatted(
+    cache: Option<&Cache>,
+    content: &[u8],
+    formatted: &[u8],
+    warnings: usize,
+) -> CLIResult<()> {
+    match cache {
+        Some(cache) if formatted == content && WARNINGS.load(Ordering::Relaxed) == warnings => {
+            cache.insert(content)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Report the warning on standard error, in the format of errors, and count it.
+fn report_warning(warning: &Warning, error_format: ErrorFormat) {
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+
+    if error_format == ErrorFormat::Json {
+        match serde_json::to_string(warning) {
+            Ok(json) => {
+                eprintln!("{json}");
+                return;
+            }
+            Err(error) => log::error!("Could not serialise the warning: {error}"),
+        }
+    }
+
+    eprintln!("Warning: [{}] {warning}", warning.code());
+}
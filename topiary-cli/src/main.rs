@@ -1,32 +1,218 @@
+mod bench;
+mod cache;
 mod configuration;
+mod coverage;
+mod diff_queries;
+mod documents;
 mod error;
+mod formatting;
 mod output;
+mod query;
+mod report;
+mod snapshot;
 mod visualise;
 
 use std::{
-    eprintln,
-    error::Error,
-    fs::File,
-    io::{stdin, BufReader, BufWriter, Read},
-    path::PathBuf,
-    process::ExitCode,
+    eprintln, error::Error, ops::RangeInclusive, path::PathBuf, process::ExitCode,
+    sync::atomic::Ordering, time::Duration,
 };
 
-use clap::{ArgGroup, Parser};
-use configuration::parse_configuration;
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use configuration::{exit_codes, parse_configuration};
 
 use crate::{
-    error::{CLIError, CLIResult, TopiaryError},
-    output::OutputFile,
+    bench::run_benchmarks,
+    coverage::report_coverage,
+    diff_queries::report_query_diff,
+    documents::format_documents,
+    error::{CLIResult, TopiaryError},
+    formatting::{format_input, WARNINGS},
+    query::{lint_query_file, validate_query_file},
+    snapshot::{check_corpus, run_snapshot_tests},
     visualise::Visualisation,
 };
-use topiary::{formatter, Language, Operation, SupportedLanguage};
+use topiary::{Encoding, SupportedLanguage, Symlinks};
+
+/// What to write to the output, when formatting
+// NOTE The variants are not documented with doc comments, as clap would then switch to its long
+// help format, which is not what the README documents.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum OutputFormat {
+    // The formatted input
+    #[default]
+    Formatted,
+
+    // The syntax tree and the atoms produced by the query, as JSON
+    Ir,
+
+    // A SARIF log of whether the input is formatted, or of why it cannot be, for code scanning
+    Sarif,
+
+    // The same, as checkstyle XML, for the CI plugins that read it
+    Checkstyle,
+
+    // With --check, GitHub Actions workflow commands, which annotate the input in pull requests
+    Github,
+
+    // With --check, the Test Anything Protocol, with an ok or not ok line for the input
+    Tap,
+}
+
+/// How the error that Topiary fails with is reported
+// NOTE The variants are not documented with doc comments, for the same reason as OutputFormat
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum ErrorFormat {
+    // Text, logged on stderr
+    #[default]
+    Human,
+
+    // A line of JSON, on stderr, with the kind, the code, the message, the span in the input and
+    // the causes of the error, for editors and other tools
+    Json,
+}
+
+/// How the failures of the corpus are reported
+// NOTE The variants are not documented with doc comments, for the same reason as OutputFormat
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum MessageFormat {
+    // Text, on stderr
+    #[default]
+    Human,
+
+    // GitHub Actions workflow commands, on stdout, which annotate the inputs in pull requests
+    Github,
+
+    // The Test Anything Protocol, on stdout, with a line for every test, for TAP harnesses
+    Tap,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that a query file compiles, and only uses capture names that Topiary understands
+    ValidateQuery {
+        /// Which language the query is for
+        #[arg(short, long, value_enum)]
+        language: SupportedLanguage,
+
+        /// Path to the query file
+        query: PathBuf,
+    },
+
+    /// Check a query file for likely mistakes: unknown capture names, captures of the same node
+    /// that undo each other, and duplicate patterns
+    LintQuery {
+        /// Which language the query is for
+        #[arg(short, long, value_enum)]
+        language: SupportedLanguage,
+
+        /// Path to the query file
+        query: PathBuf,
+    },
+
+    /// Write a starter query file for a language, generated from the node kinds of its grammar,
+    /// to standard output
+    InitQuery {
+        /// Which language the query is for
+        #[arg(short, long, value_enum)]
+        language: SupportedLanguage,
+    },
+
+    /// Format the inputs in the input subdirectory of a directory, and compare them with the
+    /// outputs of the same names in its expected subdirectory
+    Test {
+        /// Overwrite the expected outputs that are missing or different with the actual ones
+        #[arg(long)]
+        bless: bool,
+
+        /// Whether to follow the symbolic links among the inputs, or to skip them
+        #[arg(long, value_enum, default_value_t = Symlinks::Follow)]
+        symlinks: Symlinks,
+
+        /// Path to the directory of the tests
+        dir: PathBuf,
+    },
+
+    /// Format the inputs of every input subdirectory under a directory, compare them with the
+    /// outputs of the same names in the expected subdirectory next to it, and fail with the diffs
+    /// of all the outputs that differ
+    CheckCorpus {
+        /// Whether to follow the symbolic links in the corpus, or to skip them. Every directory
+        /// is walked once, whichever links lead to it
+        #[arg(long, value_enum, default_value_t = Symlinks::Follow)]
+        symlinks: Symlinks,
+
+        /// How to report the failures: as text, as annotations of the inputs for GitHub Actions, or
+        /// in the Test Anything Protocol, along with the tests that pass
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+
+        /// Path to the directory of the corpus
+        dir: PathBuf,
+    },
+
+    /// Report the patterns of a query file that match none of the input files, and the kinds of
+    /// the nodes of the input files that the query never captures
+    Coverage {
+        /// Which language the query and input files are written in
+        #[arg(short, long, value_enum)]
+        language: SupportedLanguage,
+
+        /// Which query file to use, instead of that of the language
+        #[arg(short, long)]
+        query: Option<PathBuf>,
+
+        /// Paths to the input files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    /// Format the input files with two versions of a query file, and report the files whose
+    /// output changes, and how
+    DiffQueries {
+        /// Which language the query and input files are written in
+        #[arg(short, long, value_enum)]
+        language: SupportedLanguage,
+
+        /// Path to the old version of the query file
+        #[arg(long)]
+        old: PathBuf,
+
+        /// Path to the new version of the query file
+        #[arg(long)]
+        new: PathBuf,
+
+        /// Paths to the input files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    /// Measure how long each phase of formatting the input files takes, over several iterations,
+    /// and report the mean time of each phase
+    Bench {
+        /// How many times to format each input file, after a first run that warms the caches up
+        #[arg(short = 'n', long, default_value_t = 10)]
+        iterations: u32,
+
+        /// Write the results as JSON, rather than as a table
+        #[arg(long)]
+        json: bool,
+
+        /// Paths to the input files, whose languages are detected from their names
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-// Require at least one of --language or --input-file (n.b., language > input)
-#[command(group(ArgGroup::new("rule").multiple(true).required(true).args(&["language", "input_file"]),))]
+// Require at least one of --language, --input-file or --documents (n.b., language > input)
+#[command(group(ArgGroup::new("rule").multiple(true).required(true).args(&["language", "input_file", "documents"]),))]
+// Subcommands take their own arguments
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Which language to parse and format
     #[arg(short, long, value_enum, display_order = 1)]
     language: Option<SupportedLanguage>,
@@ -42,7 +228,7 @@ struct Args {
 
     /// Path to an output file. If omitted, or equal to "-", write to standard
     /// output.
-    #[arg(short, long, display_order = 4)]
+    #[arg(short, long, visible_alias = "output", display_order = 4)]
     output_file: Option<String>,
 
     /// Format the input file in place.
@@ -75,21 +261,243 @@ struct Args {
     /// Format as much as possible even if some of the input causes parsing errors
     #[arg(short, long, display_order = 9)]
     tolerate_parsing_errors: bool,
+
+    /// Write a JSON source map, relating the output to the input, to the given file
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "visualise",
+        display_order = 10
+    )]
+    source_map: Option<PathBuf>,
+
+    /// Write the formatted input, the intermediate representation for debugging queries, a SARIF
+    /// or checkstyle log of the formatting violations and errors, or, with --check, GitHub Actions
+    /// annotations or a TAP report of them
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = &["visualise", "source_map", "in_place"],
+        display_order = 11
+    )]
+    output_format: OutputFormat,
+
+    /// Log the captures applied to every node, with their lines in the query and the atoms they
+    /// produce, optionally only for the nodes on the given lines of the input (e.g., 10-20)
+    #[arg(
+        long,
+        value_name = "LINES",
+        value_parser = parse_lines,
+        conflicts_with_all = &["visualise", "source_map"],
+        require_equals = true,
+        num_args = 0..=1,
+        display_order = 12
+    )]
+    trace_queries: Option<Option<RangeInclusive<usize>>>,
+
+    /// Write the atoms produced by the query, before and after post-processing, next to the text
+    /// of the input that they come from, rather than format
+    #[arg(
+        long,
+        conflicts_with_all = &["visualise", "source_map", "output_format", "in_place"],
+        display_order = 13
+    )]
+    dump_atoms: bool,
+
+    /// Check that the output has the same tokens as the input, in the same order
+    #[arg(long, conflicts_with_all = &["visualise", "source_map"], display_order = 14)]
+    verify_tokens: bool,
+
+    /// Check that every comment of the input appears exactly once in the output
+    #[arg(long, conflicts_with_all = &["visualise", "source_map"], display_order = 15)]
+    verify_comments: bool,
+
+    /// After formatting, report the patterns of the query that take the longest to match the
+    /// input, with their numbers of matches, to stderr
+    #[arg(
+        long,
+        conflicts_with_all = &["visualise", "source_map", "output_format", "dump_atoms"],
+        display_order = 16
+    )]
+    profile_queries: bool,
+
+    /// Format inputs larger than this size, in bytes, or with a K, M or G suffix, without holding
+    /// their output in memory, skipping the idempotence check and the token and comment checks
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        conflicts_with_all = &["visualise", "source_map"],
+        display_order = 17
+    )]
+    memory_budget: Option<usize>,
+
+    /// Abort formatting, with exit code 9, once it has taken longer than this number of seconds
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_timeout,
+        conflicts_with_all = &["visualise", "source_map"],
+        display_order = 18
+    )]
+    timeout: Option<Duration>,
+
+    /// Leave the byte order mark that starts the input, if any, out of the output
+    #[arg(long, conflicts_with_all = &["visualise", "source_map"], display_order = 19)]
+    strip_bom: bool,
+
+    /// Read the input in this encoding (e.g., latin1 or shift_jis), unless it starts with a byte
+    /// order mark, and write the output in the same encoding [default: utf-8]
+    #[arg(
+        long,
+        value_name = "ENCODING",
+        value_parser = parse_encoding,
+        conflicts_with_all = &["visualise", "source_map", "dump_atoms", "profile_queries"],
+        display_order = 20
+    )]
+    encoding: Option<&'static Encoding>,
+
+    /// When formatting in place, keep a copy of the input file, named after it with this suffix
+    /// (e.g., .bak)
+    #[arg(long, value_name = "SUFFIX", requires = "in_place", display_order = 21)]
+    backup_suffix: Option<String>,
+
+    /// Format several documents read from standard input, each preceded by a line with its length
+    /// in bytes and its file name, and write their outputs, each preceded by a line with its
+    /// status, its length and the file name
+    #[arg(
+        long,
+        conflicts_with_all = &[
+            "input_file", "output_file", "in_place", "visualise", "source_map", "output_format",
+            "trace_queries", "dump_atoms", "profile_queries",
+        ],
+        display_order = 22
+    )]
+    documents: bool,
+
+    /// How to report the error that Topiary fails with, if it does
+    #[arg(long, value_enum, default_value_t, display_order = 23)]
+    error_format: ErrorFormat,
+
+    /// Check that the input is formatted, without writing the output, and fail with exit code 10
+    /// if it is not
+    #[arg(
+        long,
+        conflicts_with_all = &[
+            "output_file", "in_place", "visualise", "source_map", "dump_atoms", "profile_queries",
+            "documents",
+        ],
+        display_order = 24
+    )]
+    check: bool,
+
+    /// Fail, with exit code 11, if formatting gives warnings, e.g. because injected code could not
+    /// be formatted, or because the scopes of the query do not match up
+    #[arg(long, display_order = 25)]
+    deny_warnings: bool,
+
+    /// Record the inputs that are found to be formatted in this directory, so that --check,
+    /// --in-place and --documents skip them while they, the query, the grammar, the configuration
+    /// and the options stay the same
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = &[
+            "visualise", "source_map", "output_format", "trace_queries", "dump_atoms",
+            "profile_queries",
+        ],
+        display_order = 26
+    )]
+    cache: Option<PathBuf>,
+}
+
+/// Parse a range of lines, starting from 1, written as "START-END", or as a single line.
+fn parse_lines(lines: &str) -> Result<RangeInclusive<usize>, String> {
+    let error = || format!("{lines} is not a line, or a range of lines such as 10-20");
+
+    let (start, end) = lines.split_once('-').unwrap_or((lines, lines));
+    let start: usize = start.trim().parse().map_err(|_| error())?;
+    let end: usize = end.trim().parse().map_err(|_| error())?;
+
+    if start == 0 || end < start {
+        return Err(error());
+    }
+
+    Ok(start..=end)
+}
+
+/// Parse a size in bytes, written as a number, optionally followed by K, M or G for kibibytes,
+/// mebibytes or gibibytes.
+fn parse_size(size: &str) -> Result<usize, String> {
+    let error = || format!("{size} is not a size, such as 512, 64K, 100M or 2G");
+
+    let trimmed = size.trim();
+    let (number, multiplier) = match trimmed.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&trimmed[..trimmed.len() - 1], 1 << 10),
+        Some('M') => (&trimmed[..trimmed.len() - 1], 1 << 20),
+        Some('G') => (&trimmed[..trimmed.len() - 1], 1 << 30),
+        _ => (trimmed, 1),
+    };
+    let number: usize = number.parse().map_err(|_| error())?;
+
+    number.checked_mul(multiplier).ok_or_else(error)
+}
+
+/// Parse a positive number of seconds, which can have a fractional part.
+fn parse_timeout(seconds: &str) -> Result<Duration, String> {
+    let error = || format!("{seconds} is not a positive number of seconds, such as 30 or 0.5");
+
+    let seconds: f64 = seconds.trim().parse().map_err(|_| error())?;
+    if seconds <= 0.0 {
+        return Err(error());
+    }
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| error())
+}
+
+/// Parse the label of an encoding, as known to the Encoding Standard.
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("{label} is not an encoding"))
 }
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    if let Err(e) = run().await {
-        print_error(&e);
-        return e.into();
+    let args = Args::parse();
+    let error_format = args.error_format;
+    let deny_warnings = args.deny_warnings;
+
+    let result = run(args)
+        .await
+        .and_then(|()| match WARNINGS.load(Ordering::Relaxed) {
+            count if deny_warnings && count > 0 => Err(TopiaryError::Warnings(count)),
+            _ => Ok(()),
+        });
+
+    if let Err(e) = result {
+        print_error(&e, error_format);
+
+        // A configuration that cannot be read already failed the run, with the default exit codes
+        let exit_codes = exit_codes().unwrap_or_default();
+        let exit_code = match exit_codes.get(e.code()) {
+            Some(exit_code) => *exit_code,
+            None => e.exit_code(),
+        };
+
+        return ExitCode::from(exit_code);
     }
 
     ExitCode::SUCCESS
 }
 
-async fn run() -> CLIResult<()> {
-    env_logger::init();
-    let args = Args::parse();
+async fn run(args: Args) -> CLIResult<()> {
+    // Traces are logged along with whatever else RUST_LOG asks for
+    let mut logger = env_logger::Builder::from_default_env();
+    if args.trace_queries.is_some() {
+        logger.filter_module("topiary::trace", log::LevelFilter::Info);
+    }
+    logger.init();
 
     let configuration = parse_configuration()?;
 
@@ -97,82 +505,96 @@ async fn run() -> CLIResult<()> {
         eprintln!("{:#?}", configuration);
     }
 
-    // The as_deref() gives us an Option<&str>, which we can match against
-    // string literals
-    let mut input: Box<(dyn Read)> = match args.input_file.as_deref() {
-        Some("-") | None => Box::new(stdin()),
-        Some(file) => Box::new(BufReader::new(File::open(file)?)),
-    };
-
-    // NOTE If --in-place is specified, it overrides --output-file
-    let mut output = BufWriter::new(if args.in_place {
-        // NOTE Clap handles the case when no input file is specified. If the input file is
-        // explicitly set to stdin (i.e., -), then --in-place will set the output to stdout; which
-        // is not completely weird.
-        OutputFile::new(args.input_file.as_deref())?
-    } else {
-        OutputFile::new(args.output_file.as_deref())?
-    });
-
-    let language = if let Some(language) = args.language {
-        language.to_language(&configuration)
-    } else if let Some(filename) = args.input_file.as_deref() {
-        Language::detect(filename, &configuration)?
-    } else {
-        // Clap ensures we won't get here
-        unreachable!();
-    };
-
-    let query_path = if let Some(query) = args.query {
-        query
-    } else {
-        // Deduce the query file from the language, if the argument is missing
-        language.query_file()?
-    };
+    match args.command {
+        Some(Command::ValidateQuery { language, query }) => {
+            return validate_query_file(language.to_language(&configuration), &query).await;
+        }
+        Some(Command::LintQuery { language, query }) => {
+            return lint_query_file(language.to_language(&configuration), &query).await;
+        }
+        Some(Command::InitQuery { language }) => {
+            let language = language.to_language(&configuration);
+            let grammar = language.grammar().await?;
+            print!("{}", topiary::skeleton_query(&language.name, &grammar));
 
-    let query = (|| {
-        let mut reader = BufReader::new(File::open(&query_path)?);
-        let mut contents = String::new();
-        reader.read_to_string(&mut contents)?;
-
-        Ok(contents)
-    })()
-    .map_err(|e| {
-        TopiaryError::Bin(
-            "Could not open query file".into(),
-            Some(CLIError::IOError(e)),
-        )
-    })?;
-
-    let grammar = language.grammar().await?;
-
-    let operation = if let Some(visualisation) = args.visualise {
-        Operation::Visualise {
-            output_format: visualisation.into(),
+            return Ok(());
         }
-    } else {
-        Operation::Format {
-            skip_idempotence: args.skip_idempotence,
-            tolerate_parsing_errors: args.tolerate_parsing_errors,
+        Some(Command::Test {
+            bless,
+            symlinks,
+            dir,
+        }) => {
+            return run_snapshot_tests(&configuration, &dir, bless, symlinks).await;
         }
-    };
+        Some(Command::CheckCorpus {
+            symlinks,
+            message_format,
+            dir,
+        }) => {
+            return check_corpus(&configuration, &dir, symlinks, message_format).await;
+        }
+        Some(Command::Coverage {
+            language,
+            query,
+            files,
+        }) => {
+            return report_coverage(language.to_language(&configuration), query, &files).await;
+        }
+        Some(Command::DiffQueries {
+            language,
+            old,
+            new,
+            files,
+        }) => {
+            return report_query_diff(language.to_language(&configuration), &old, &new, &files)
+                .await;
+        }
+        Some(Command::Bench {
+            iterations,
+            json,
+            files,
+        }) => {
+            return run_benchmarks(&configuration, &files, iterations, json).await;
+        }
+        None => {}
+    }
 
-    formatter(
-        &mut input,
-        &mut output,
-        &query,
-        language,
-        &grammar,
-        operation,
-    )?;
+    // The reports of --check are only written with --check, which writes no other output
+    match (args.check, args.output_format) {
+        (false, OutputFormat::Github | OutputFormat::Tap) => {
+            return Err(TopiaryError::Bin(
+                "--output-format github and tap require --check".into(),
+                None,
+            ));
+        }
+        (true, OutputFormat::Ir | OutputFormat::Sarif | OutputFormat::Checkstyle) => {
+            return Err(TopiaryError::Bin(
+                "--check cannot write the ir, sarif or checkstyle output formats".into(),
+                None,
+            ));
+        }
+        _ => {}
+    }
 
-    output.into_inner()?.persist()?;
+    if args.documents {
+        return format_documents(&configuration, &args).await;
+    }
 
-    Ok(())
+    format_input(&configuration, args).await
 }
 
-fn print_error(e: &dyn Error) {
-    log::error!("{e}");
+fn print_error(e: &TopiaryError, error_format: ErrorFormat) {
+    if error_format == ErrorFormat::Json {
+        match serde_json::to_string(e) {
+            Ok(json) => {
+                eprintln!("{json}");
+                return;
+            }
+            Err(error) => log::error!("Could not serialise the error: {error}"),
+        }
+    }
+
+    log::error!("[{}] {e}", e.code());
     if let Some(source) = e.source() {
         log::error!("Cause: {source}");
     }
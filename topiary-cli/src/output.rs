@@ -1,6 +1,7 @@
-use crate::error::CLIResult;
+use crate::error::{CLIResult, TopiaryError};
 use std::{
     ffi::OsString,
+    fs,
     io::{stdout, Write},
     path::Path,
 };
@@ -14,19 +15,60 @@ pub enum OutputFile {
         // to memory (e.g., Vec<u8>), to ensure atomicity
         staged: NamedTempFile,
         output: OsString,
+        // Where to copy the file before it is written to, if it is kept
+        backup: Option<OsString>,
     },
 }
 
 impl OutputFile {
-    pub fn new(path: Option<&str>) -> CLIResult<Self> {
+    // With a backup suffix, the file is copied to its path followed by the suffix, if it exists,
+    // before it is written to. The backup of a symbolic link is that of its target, next to it.
+    pub fn new(path: Option<&str>, backup_suffix: Option<&str>) -> CLIResult<Self> {
         match path {
             Some("-") | None => Ok(Self::Stdout),
             Some(file) => {
-                let path = Path::new(file).canonicalize()?;
-                let parent = path.parent().unwrap();
+                // A symbolic link is left in place, and its target is written to. A file that does
+                // not exist yet is created in its directory, which must exist.
+                let path = match Path::new(file).canonicalize() {
+                    Ok(path) => path,
+                    Err(_) => {
+                        let parent = match Path::new(file).parent() {
+                            Some(parent) if !parent.as_os_str().is_empty() => parent,
+                            _ => Path::new("."),
+                        };
+
+                        let file_name = match Path::new(file).file_name() {
+                            Some(file_name) => file_name,
+                            None => {
+                                return Err(TopiaryError::Bin(
+                                    format!("{file:?} is not a file path"),
+                                    None,
+                                ))
+                            }
+                        };
+
+                        parent.canonicalize()?.join(file_name)
+                    }
+                };
+                let parent = match path.parent() {
+                    Some(parent) => parent,
+                    None => {
+                        return Err(TopiaryError::Bin(
+                            format!("{file:?} is not a file path"),
+                            None,
+                        ))
+                    }
+                };
+                let backup = backup_suffix.map(|suffix| {
+                    let mut backup = path.clone().into_os_string();
+                    backup.push(suffix);
+                    backup
+                });
+
                 Ok(Self::Disk {
                     staged: NamedTempFile::new_in(parent)?,
-                    output: file.into(),
+                    output: path.into(),
+                    backup,
                 })
             }
         }
@@ -34,7 +76,16 @@ impl OutputFile {
 
     // This function must be called to persist the output to disk
     pub fn persist(self) -> CLIResult<()> {
-        if let Self::Disk { staged, output } = self {
+        if let Self::Disk {
+            staged,
+            output,
+            backup,
+        } = self
+        {
+            if let Some(backup) = backup.filter(|_| Path::new(&output).exists()) {
+                fs::copy(&output, backup)?;
+            }
+
             staged.persist(output)?;
         }
 
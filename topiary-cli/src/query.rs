@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+
+use topiary::{ExpandedQuery, FormatterError, Language, OsFileProvider, QueryProfile};
+
+use crate::error::{CLIResult, TopiaryError};
+
+/// The query file of the arguments, or else that of the language, deduced from it, with its
+/// overlays layered over it, along with the path of the query file, in which its errors are
+/// reported.
+pub fn language_query(
+    language: &Language,
+    query: Option<&PathBuf>,
+) -> CLIResult<(PathBuf, String)> {
+    match query {
+        Some(query_path) => Ok((query_path.clone(), read_query_file(query_path)?.content)),
+        None => Ok((
+            language.query_file()?,
+            language.expand_query(&OsFileProvider)?.content,
+        )),
+    }
+}
+
+/// Read the query file, with the files that it includes expanded in place.
+pub fn read_query_file(query_path: &Path) -> CLIResult<ExpandedQuery> {
+    Ok(topiary::expand_query(query_path, &OsFileProvider)?)
+}
+
+/// Name the query file in the message of an error of the query, as the lines and columns that the
+/// message gives are those of the file.
+pub fn in_query_file(e: TopiaryError, query_path: &Path) -> TopiaryError {
+    match e {
+        TopiaryError::Lib(FormatterError::Query(message, source)) => TopiaryError::Lib(
+            FormatterError::Query(format!("{}: {message}", query_path.display()), source),
+        ),
+        e => e,
+    }
+}
+
+/// Validate the query file for the language, reporting each capture name that Topiary does not
+/// understand, along with its position.
+pub async fn validate_query_file(language: &Language, query_path: &Path) -> CLIResult<()> {
+    let query = read_query_file(query_path)?;
+
+    let grammar = language.grammar().await?;
+    let unknown = topiary::validate_query(&query.content, &grammar)?;
+
+    for capture in &unknown {
+        let (path, line) = query
+            .origin(capture.line)
+            .unwrap_or((query_path, capture.line));
+        let suggestion = match topiary::closest_capture(&capture.name) {
+            Some(closest) => format!("; did you mean @{}?", closest.name),
+            None => String::new(),
+        };
+        eprintln!(
+            "{}:{}:{}: @{} is not a valid capture name{suggestion}",
+            path.display(),
+            line,
+            capture.column,
+            capture.name
+        );
+    }
+
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(TopiaryError::Lib(FormatterError::Query(
+            format!(
+                "Found {} unknown capture name(s) in {}",
+                unknown.len(),
+                query_path.display()
+            ),
+            None,
+        )))
+    }
+}
+
+/// Lint the query file for the language, reporting each likely mistake along with its position.
+pub async fn lint_query_file(language: &Language, query_path: &Path) -> CLIResult<()> {
+    let query = read_query_file(query_path)?;
+
+    let grammar = language.grammar().await?;
+    let lints = topiary::lint_query(&query.content, &grammar)?;
+
+    for lint in &lints {
+        let (path, line) = query.origin(lint.line).unwrap_or((query_path, lint.line));
+        eprintln!("{}:{}:{}: {}", path.display(), line, lint.column, lint.kind);
+    }
+
+    if lints.is_empty() {
+        Ok(())
+    } else {
+        Err(TopiaryError::Lib(FormatterError::Query(
+            format!(
+                "Found {} likely mistake(s) in {}",
+                lints.len(),
+                query_path.display()
+            ),
+            None,
+        )))
+    }
+}
+
+/// The number of patterns that `--profile-queries` reports.
+const PROFILED_PATTERNS: usize = 10;
+
+/// Report, to stderr, the patterns of the query that take the longest to match.
+pub fn report_query_profile(profile: &QueryProfile, query_path: &Path) {
+    eprintln!("Slowest patterns of the query:");
+    for pattern in profile.slowest().into_iter().take(PROFILED_PATTERNS) {
+        eprintln!(
+            "{}:{}: {:.3} ms, {} match(es)",
+            query_path.display(),
+            pattern.line,
+            pattern.time.as_secs_f64() * 1000.0,
+            pattern.matches
+        );
+    }
+}
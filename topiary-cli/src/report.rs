@@ -0,0 +1,274 @@
+use std::{path::Path, slice};
+
+use topiary::FormatterError;
+
+use crate::OutputFormat;
+
+/// A failed test of a corpus: its input, the line and column of the input at which it failed, if
+/// known, and why it failed.
+pub struct CorpusFailure<'a> {
+    pub input: &'a Path,
+    pub position: Option<(u32, u32)>,
+    pub message: String,
+}
+
+/// Report the result of `--check` on the input, for the output formats that report it, on
+/// standard output.
+pub fn report_check(
+    output_format: OutputFormat,
+    input_file: Option<&str>,
+    content: &[u8],
+    formatted: &[u8],
+    result: &Result<(), FormatterError>,
+) {
+    let input = Path::new(input_file.unwrap_or("-"));
+    let failure = Violation::new(content, formatted, result).map(|violation| CorpusFailure {
+        input,
+        // Inputs that are not formatted are reported at the start of their first changed line
+        position: violation
+            .start
+            .map(|(line, column)| (line, column.unwrap_or(1))),
+        message: violation.message,
+    });
+
+    match (output_format, &failure) {
+        (OutputFormat::Github, Some(failure)) => println!("{}", github_annotation(failure)),
+        (OutputFormat::Tap, _) => print!("{}", tap_report(&[input], slice::from_ref(&failure))),
+        _ => {}
+    }
+}
+
+/// The failure as a GitHub Actions workflow command, which annotates its input, at its position if
+/// it is known, in the pull requests and the summary of the workflow run.
+pub fn github_annotation(failure: &CorpusFailure) -> String {
+    // Workflow commands are a single line, whose properties cannot hold separators either
+    let escape = |text: &str| {
+        text.replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let escape_property = |text: &str| escape(text).replace(':', "%3A").replace(',', "%2C");
+
+    // The standard input has no file to annotate
+    let mut properties = String::new();
+    if failure.input != Path::new("-") {
+        properties.push_str(&format!(
+            "file={},",
+            escape_property(&failure.input.to_string_lossy())
+        ));
+    }
+    if let Some((line, column)) = failure.position {
+        properties.push_str(&format!("line={line},col={column},"));
+    }
+
+    format!(
+        "::error {properties}title=Topiary::{}",
+        escape(&failure.message)
+    )
+}
+
+/// The rule of inputs that formatting changes, which, unlike errors, have no code.
+const UNFORMATTED_RULE: &str = "unformatted";
+
+/// What is wrong with the input, as reported in SARIF and checkstyle logs: the input is not
+/// formatted, or it cannot be.
+pub struct Violation {
+    /// The code of the error, or `UNFORMATTED_RULE`
+    rule: &'static str,
+    message: String,
+    /// The line and the column where the violation starts, if they are known
+    start: Option<(u32, Option<u32>)>,
+    /// The line and the column where the violation ends, if they are known
+    end: Option<(u32, u32)>,
+    /// The error, serialised for tools, if the input cannot be formatted
+    error: Option<serde_json::Value>,
+}
+
+impl Violation {
+    /// The violation of formatting the input, if there is one: the error, or else the first line
+    /// that formatting changes.
+    pub fn new(
+        content: &[u8],
+        formatted: &[u8],
+        result: &Result<(), FormatterError>,
+    ) -> Option<Self> {
+        match result {
+            Err(e) => {
+                let (start, end) = match e {
+                    FormatterError::Parsing {
+                        start_line,
+                        start_column,
+                        end_line,
+                        end_column,
+                        ..
+                    } => (
+                        Some((*start_line, Some(*start_column))),
+                        Some((*end_line, *end_column)),
+                    ),
+                    _ => (None, None),
+                };
+
+                Some(Self {
+                    rule: e.code(),
+                    message: e.to_string(),
+                    start,
+                    end,
+                    error: serde_json::to_value(e).ok(),
+                })
+            }
+            Ok(()) if content == formatted => None,
+            Ok(()) => {
+                let lines = || content.split(|&b| b == b'\n');
+                let unchanged = lines()
+                    .zip(formatted.split(|&b| b == b'\n'))
+                    .take_while(|(line, formatted_line)| line == formatted_line)
+                    .count();
+
+                // If formatting only adds lines at the end, the last line of the input is reported
+                let line = (unchanged + 1).min(lines().count());
+
+                Some(Self {
+                    rule: UNFORMATTED_RULE,
+                    message: "The input is not formatted".into(),
+                    start: Some((line as u32, None)),
+                    end: None,
+                    error: None,
+                })
+            }
+        }
+    }
+
+    /// Inputs that are not formatted are warnings, as they only need formatting, unlike errors.
+    fn severity(&self) -> &'static str {
+        if self.rule == UNFORMATTED_RULE {
+            "warning"
+        } else {
+            "error"
+        }
+    }
+}
+
+/// The SARIF log of the violation of the input, whose rule is the code of its error, if it is
+/// one. The log has no results if there is no violation.
+pub fn sarif_log(input_file: Option<&str>, violation: Option<&Violation>) -> serde_json::Value {
+    let violation = match violation {
+        Some(violation) => violation,
+        None => return sarif_run(Vec::new(), Vec::new()),
+    };
+
+    let mut result = serde_json::json!({
+        "ruleId": violation.rule,
+        "level": violation.severity(),
+        "message": { "text": violation.message },
+    });
+    if let Some(error) = &violation.error {
+        result["properties"] = serde_json::json!({ "error": error });
+    }
+
+    // Input read from stdin has no location
+    if let Some(file) = input_file.filter(|file| *file != "-") {
+        let mut location = serde_json::json!({
+            "artifactLocation": { "uri": file.replace('\\', "/") },
+        });
+        if let Some((line, column)) = violation.start {
+            location["region"] = serde_json::json!({ "startLine": line });
+            if let Some(column) = column {
+                location["region"]["startColumn"] = column.into();
+            }
+            if let Some((end_line, end_column)) = violation.end {
+                location["region"]["endLine"] = end_line.into();
+                location["region"]["endColumn"] = end_column.into();
+            }
+        }
+
+        result["locations"] = serde_json::json!([{ "physicalLocation": location }]);
+    }
+
+    sarif_run(
+        vec![serde_json::json!({ "id": violation.rule })],
+        vec![result],
+    )
+}
+
+/// A SARIF log of a single run of Topiary, with the rules of its results.
+fn sarif_run(rules: Vec<serde_json::Value>, results: Vec<serde_json::Value>) -> serde_json::Value {
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "Topiary",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://topiary.tweag.io",
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// The checkstyle XML log of the violation of the input, as CI plugins such as those of Jenkins,
+/// GitLab or reviewdog read it. Its source is the code of its error, if it is one. The log lists
+/// the input with no errors if there is no violation.
+pub fn checkstyle_log(input_file: Option<&str>, violation: Option<&Violation>) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\n', "&#10;")
+    };
+
+    let mut log = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    log.push_str("<checkstyle version=\"4.3\">\n");
+    log.push_str(&format!(
+        "  <file name=\"{}\">\n",
+        escape(input_file.unwrap_or("-"))
+    ));
+
+    if let Some(violation) = violation {
+        let mut attributes = String::new();
+        if let Some((line, column)) = violation.start {
+            attributes.push_str(&format!(" line=\"{line}\""));
+            if let Some(column) = column {
+                attributes.push_str(&format!(" column=\"{column}\""));
+            }
+        }
+
+        log.push_str(&format!(
+            "    <error{attributes} severity=\"{}\" message=\"{}\" source=\"topiary.{}\"/>\n",
+            violation.severity(),
+            escape(&violation.message),
+            violation.rule
+        ));
+    }
+
+    log.push_str("  </file>\n</checkstyle>\n");
+
+    log
+}
+
+/// The results of the inputs of a corpus, or of --check, in the Test Anything Protocol: a line per
+/// input, which tells whether it passed, followed by the reason why it failed as comments.
+pub fn tap_report(inputs: &[&Path], results: &[Option<CorpusFailure>]) -> String {
+    let mut report = format!("1..{}\n", inputs.len());
+
+    for (number, (input, failure)) in (1..).zip(inputs.iter().zip(results)) {
+        // A # in the description would start a directive, such as SKIP
+        let description = input.display().to_string().replace('#', "\\#");
+
+        match failure {
+            None => report.push_str(&format!("ok {number} - {description}\n")),
+            Some(failure) => {
+                report.push_str(&format!("not ok {number} - {description}\n"));
+                for line in failure.message.lines() {
+                    report.push_str(&format!("# {line}\n"));
+                }
+            }
+        }
+    }
+
+    report
+}
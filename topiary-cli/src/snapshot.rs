@@ -0,0 +1,133 @@
+use std::path::Path;
+
+use topiary::{Configuration, FormatterError, SnapshotOutcome, Symlinks};
+
+use crate::{
+    error::{CLIResult, TopiaryError},
+    report::{github_annotation, tap_report, CorpusFailure},
+    MessageFormat,
+};
+
+/// Run the snapshot tests in the directory, reporting the failures along with their diffs.
+pub async fn run_snapshot_tests(
+    configuration: &Configuration,
+    dir: &Path,
+    bless: bool,
+    symlinks: Symlinks,
+) -> CLIResult<()> {
+    let tests = topiary::snapshot_tests(dir, configuration, symlinks)?;
+    let mut failures = 0;
+
+    for test in &tests {
+        let input = test.input.display();
+
+        match test.run(bless).await {
+            Ok(SnapshotOutcome::Passed) => eprintln!("ok: {input}"),
+            Ok(SnapshotOutcome::Blessed) => eprintln!("blessed: {input}"),
+            Ok(SnapshotOutcome::Missing) => {
+                eprintln!("FAILED: {input}: {} is missing", test.expected.display());
+                failures += 1;
+            }
+            Ok(SnapshotOutcome::Mismatch(diff)) => {
+                eprintln!("FAILED: {input}: the output differs from the expected one\n{diff}");
+                failures += 1;
+            }
+            Err(e) => {
+                eprintln!("FAILED: {input}: {e}");
+                failures += 1;
+            }
+        }
+    }
+
+    eprintln!("{} test(s), {failures} failure(s)", tests.len());
+
+    if failures == 0 {
+        Ok(())
+    } else {
+        Err(TopiaryError::Bin(
+            format!("{failures} snapshot test(s) failed in {}", dir.display()),
+            None,
+        ))
+    }
+}
+
+/// Run the snapshot tests of every directory of the corpus, reporting the failures along with
+/// their diffs once all the tests have run, so that they can be read in one place.
+pub async fn check_corpus(
+    configuration: &Configuration,
+    dir: &Path,
+    symlinks: Symlinks,
+    message_format: MessageFormat,
+) -> CLIResult<()> {
+    let tests = topiary::corpus_tests(dir, configuration, symlinks)?;
+    // The failure of each test, if it failed
+    let mut results = Vec::new();
+
+    for test in &tests {
+        let failure = |position, message| CorpusFailure {
+            input: &test.input,
+            position,
+            message,
+        };
+
+        let result = match test.run(false).await {
+            Ok(SnapshotOutcome::Passed | SnapshotOutcome::Blessed) => None,
+            Ok(SnapshotOutcome::Missing) => Some(failure(
+                None,
+                format!("{} is missing", test.expected.display()),
+            )),
+            Ok(SnapshotOutcome::Mismatch(diff)) => Some(failure(
+                None,
+                format!("the output differs from the expected one\n{diff}"),
+            )),
+            Err(e) => {
+                let position = match e {
+                    FormatterError::Parsing {
+                        start_line,
+                        start_column,
+                        ..
+                    } => Some((start_line, start_column)),
+                    _ => None,
+                };
+
+                Some(failure(position, e.to_string()))
+            }
+        };
+
+        results.push(result);
+    }
+
+    let failures: Vec<&CorpusFailure> = results.iter().flatten().collect();
+
+    match message_format {
+        MessageFormat::Human => {
+            for failure in &failures {
+                eprintln!("FAILED: {}: {}", failure.input.display(), failure.message);
+            }
+        }
+        MessageFormat::Github => {
+            for failure in &failures {
+                println!("{}", github_annotation(failure));
+            }
+        }
+        MessageFormat::Tap => {
+            let inputs: Vec<&Path> = tests.iter().map(|test| test.input.as_path()).collect();
+            print!("{}", tap_report(&inputs, &results));
+        }
+    }
+
+    eprintln!("{} test(s), {} failure(s)", tests.len(), failures.len());
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(TopiaryError::Bin(
+            format!(
+                "{} corpus test(s) failed in {}",
+                failures.len(),
+                dir.display()
+            ),
+            None,
+        ))
+    }
+}
@@ -1,6 +1,6 @@
 use assert_cmd::Command;
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{Read, Write},
     path::Path,
 };
@@ -108,3 +108,537 @@ fn test_in_place_no_input() {
         .assert()
         .failure();
 }
+
+#[test]
+fn test_in_place_backup() {
+    let json = State::new(JSON_INPUT);
+    let mut backup_path = json.path().as_os_str().to_owned();
+    backup_path.push(".bak");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--input-file")
+        .arg(json.path())
+        .arg("--in-place")
+        .arg("--backup-suffix")
+        .arg(".bak")
+        .assert()
+        .success();
+
+    assert_eq!(json.read().trim(), JSON_EXPECTED);
+    assert_eq!(fs::read_to_string(&backup_path).unwrap(), JSON_INPUT);
+    fs::remove_file(backup_path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_in_place_backup_of_symlink() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target.json");
+    let link = dir.path().join("link.json");
+    fs::write(&target, JSON_INPUT).unwrap();
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--input-file")
+        .arg(&link)
+        .arg("--in-place")
+        .arg("--backup-suffix")
+        .arg(".bak")
+        .assert()
+        .success();
+
+    // The target is formatted, and copied next to it, while the link is left in place
+    assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+    assert_eq!(fs::read_to_string(&target).unwrap().trim(), JSON_EXPECTED);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("target.json.bak")).unwrap(),
+        JSON_INPUT
+    );
+    assert!(!dir.path().join("link.json.bak").exists());
+}
+
+#[test]
+fn test_empty_output_path() {
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--output")
+        .arg("")
+        .write_stdin(JSON_INPUT)
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("\"\" is not a file path"));
+}
+
+#[test]
+fn test_new_output_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let output_path = dir.path().join("formatted.json");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--output")
+        .arg(&output_path)
+        .write_stdin(JSON_INPUT)
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(output_path).unwrap().trim(),
+        JSON_EXPECTED
+    );
+}
+
+#[test]
+fn test_source_map() {
+    let source_map = State::new("");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--source-map")
+        .arg(source_map.path())
+        .write_stdin(JSON_INPUT)
+        .assert()
+        .success()
+        .stdout(format!("{JSON_EXPECTED}\n"));
+
+    // The first leaf is the opening brace, which stays in place
+    assert!(source_map
+        .read()
+        .starts_with(r#"{"leaves":[{"input":{"start":0,"end":1},"output":{"start":0,"end":1}}"#));
+}
+
+#[test]
+fn test_output_format_ir() {
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--output-format")
+        .arg("ir")
+        .write_stdin(JSON_INPUT)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let ir = String::from_utf8(output).unwrap();
+    assert!(ir.starts_with(r#"{"tree":{"kind":"document""#));
+    assert!(ir.contains(r#""atoms":["#));
+}
+
+#[test]
+fn test_output_format_sarif() {
+    let json = State::new(JSON_INPUT);
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--output-format")
+        .arg("sarif")
+        .arg("--input-file")
+        .arg(json.path())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let log: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let result = &log["runs"][0]["results"][0];
+    assert_eq!(result["ruleId"], "unformatted");
+    assert_eq!(
+        result["locations"][0]["physicalLocation"]["region"]["startLine"],
+        1
+    );
+}
+
+#[test]
+fn test_error_format_json() {
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--error-format")
+        .arg("json")
+        .write_stdin("[1")
+        .assert()
+        .code(5)
+        .get_output()
+        .stderr
+        .clone();
+
+    let error: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(error["kind"], "parsing");
+    assert_eq!(error["code"], "E010_PARSE");
+    assert_eq!(error["span"]["start"]["line"], 1);
+}
+
+#[test]
+fn test_output_format_checkstyle() {
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--output-format")
+        .arg("checkstyle")
+        .write_stdin("{")
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+
+    let log = String::from_utf8(output).unwrap();
+    assert!(log.contains(r#"<file name="-">"#));
+    assert!(log.contains(r#"<error line="1" column="#));
+    assert!(log.contains(r#"source="topiary.E010_PARSE"/>"#));
+}
+
+#[test]
+fn test_documents() {
+    let input = format!("{} a.json\n{JSON_INPUT}2 b c.json\n[1", JSON_INPUT.len());
+    let formatted = format!("{JSON_EXPECTED}\n");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--documents")
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+
+    let output = String::from_utf8(output).unwrap();
+    let expected = format!("ok {} a.json\n{formatted}E010_PARSE ", formatted.len());
+    assert!(output.starts_with(&expected));
+    assert!(output.lines().nth(2).unwrap().ends_with(" b c.json"));
+}
+
+#[test]
+fn test_documents_skip_file() {
+    // The generated document is neither parsed nor detected, as it has the pragma
+    let generated = "// topiary: skip-file\n[1,\n";
+    let input = format!("{} gen/a.out\n{generated}", generated.len());
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--documents")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(format!(
+            "skipped {} gen/a.out\n{generated}",
+            generated.len()
+        ));
+}
+
+#[test]
+fn test_documents_errors() {
+    let input = format!(
+        "2 a.json\n[1{} b.json\n{JSON_INPUT}2 c.json\n{{1",
+        JSON_INPUT.len()
+    );
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--documents")
+        .write_stdin(input)
+        .assert()
+        .code(5)
+        .get_output()
+        .stderr
+        .clone();
+
+    // Every document that could not be formatted is reported, with its name
+    let errors = String::from_utf8(output).unwrap();
+    assert!(errors.contains("2 document(s) could not be formatted"));
+    assert!(errors.contains("\na.json: [E010_PARSE]"));
+    assert!(errors.contains("\nc.json: [E010_PARSE]"));
+    assert!(!errors.contains("b.json"));
+}
+
+#[test]
+fn test_validate_query() {
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("validate-query")
+        .arg("--language")
+        .arg("json")
+        .arg("../languages/json.scm")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_validate_query_unknown_capture() {
+    let query = State::new("(object) @append_spaces\n");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("validate-query")
+        .arg("--language")
+        .arg("json")
+        .arg(query.path())
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn test_query_error_location() {
+    let query = State::new("(array \"[\" @append_space)\n(number) @prepend_delimiter\n");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--query")
+        .arg(query.path())
+        .write_stdin("[1]")
+        .assert()
+        .code(4)
+        .get_output()
+        .stderr
+        .clone();
+
+    let errors = String::from_utf8(output).unwrap();
+    assert!(errors.contains(&format!("{}: ", query.path().display())));
+    assert!(errors.contains("line 2, column 10 of the query"));
+    assert!(errors.contains("2 | (number) @prepend_delimiter"));
+}
+
+#[test]
+fn test_check() {
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--check")
+        .write_stdin(JSON_INPUT)
+        .assert()
+        .code(10)
+        .stdout("");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--check")
+        .write_stdin(format!("{JSON_EXPECTED}\n"))
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn test_check_github() {
+    let json = State::new("{\n\"test\":123}");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--input-file")
+        .arg(json.path())
+        .arg("--check")
+        .arg("--output-format")
+        .arg("github")
+        .assert()
+        .code(10)
+        .stdout(format!(
+            "::error file={},line=1,col=1,title=Topiary::The input is not formatted\n",
+            json.path().display()
+        ));
+
+    // Formatted inputs have no annotation
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--check")
+        .arg("--output-format")
+        .arg("github")
+        .write_stdin(format!("{JSON_EXPECTED}\n"))
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn test_check_tap() {
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--check")
+        .arg("--output-format")
+        .arg("tap")
+        .write_stdin(JSON_INPUT)
+        .assert()
+        .code(10)
+        .stdout("1..1\nnot ok 1 - -\n# The input is not formatted\n");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--check")
+        .arg("--output-format")
+        .arg("tap")
+        .write_stdin(format!("{JSON_EXPECTED}\n"))
+        .assert()
+        .success()
+        .stdout("1..1\nok 1 - -\n");
+}
+
+#[test]
+fn test_check_cache() {
+    let cache = tempfile::tempdir().unwrap();
+    let entries = || fs::read_dir(cache.path()).map_or(0, |entries| entries.count());
+
+    // Inputs that are not formatted are not recorded
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--check")
+        .arg("--cache")
+        .arg(cache.path())
+        .write_stdin(JSON_INPUT)
+        .assert()
+        .code(10);
+
+    assert_eq!(entries(), 0);
+
+    for _ in 0..2 {
+        let mut topiary = Command::cargo_bin("topiary").unwrap();
+        topiary
+            .env("TOPIARY_LANGUAGE_DIR", "../languages")
+            .arg("--language")
+            .arg("json")
+            .arg("--check")
+            .arg("--cache")
+            .arg(cache.path())
+            .write_stdin(format!("{JSON_EXPECTED}\n"))
+            .assert()
+            .success();
+
+        assert_eq!(entries(), 1);
+    }
+
+    // Other options give other entries
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--check")
+        .arg("--skip-idempotence")
+        .arg("--cache")
+        .arg(cache.path())
+        .write_stdin(format!("{JSON_EXPECTED}\n"))
+        .assert()
+        .success();
+
+    assert_eq!(entries(), 2);
+}
+
+#[test]
+fn test_deny_warnings() {
+    let query = State::new("(array \"[\" @begin_scope (#scope_id! \"array\"))\n");
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    let output = topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--query")
+        .arg(query.path())
+        .write_stdin("[1]")
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+
+    let warnings = String::from_utf8(output).unwrap();
+    assert!(
+        warnings.contains("Warning: [W030_UNBALANCED_SCOPES] Some scopes have been left opened")
+    );
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .env("TOPIARY_LANGUAGE_DIR", "../languages")
+        .arg("--language")
+        .arg("json")
+        .arg("--query")
+        .arg(query.path())
+        .arg("--deny-warnings")
+        .write_stdin("[1]")
+        .assert()
+        .code(11);
+}
+
+#[test]
+fn test_configured_exit_codes() {
+    let workspace = tempfile::tempdir().unwrap();
+    fs::create_dir(workspace.path().join(".topiary")).unwrap();
+    fs::write(
+        workspace.path().join(".topiary/languages.toml"),
+        "[exit_codes]\nE071_UNFORMATTED = 1\nE010_PARSE = 65\n",
+    )
+    .unwrap();
+
+    let mut topiary = Command::cargo_bin("topiary").unwrap();
+    topiary
+        .current_dir(workspace.path())
+        .env(
+            "TOPIARY_LANGUAGE_DIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../languages"),
+        )
+        .arg("--language")
+        .arg("json")
+        .write_stdin("[1")
+        .assert()
+        .code(65);
+}
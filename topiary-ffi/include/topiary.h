@@ -0,0 +1,44 @@
+/* C bindings for Topiary, the universal code formatter. */
+
+#ifndef TOPIARY_H
+#define TOPIARY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The status codes returned by topiary_format. They match the exit codes of the CLI. */
+enum TopiaryStatus {
+  TOPIARY_OK = 0,
+  TOPIARY_ERROR = 1,
+  TOPIARY_INVALID_ARGUMENT = 2,
+  TOPIARY_IO = 3,
+  TOPIARY_QUERY = 4,
+  TOPIARY_PARSING = 5,
+  TOPIARY_LANGUAGE_DETECTION = 6,
+  TOPIARY_IDEMPOTENCE = 7,
+  TOPIARY_FORMATTING = 8,
+  TOPIARY_TIMEOUT = 9,
+};
+
+/*
+ * Format the len bytes of UTF-8 input as the given language (e.g., "json").
+ *
+ * On success, *out is set to the formatted output; otherwise, *err is set to an error message,
+ * unless err is NULL. Error messages start with the stable code of the error in brackets, e.g.
+ * "[E010_PARSE]". Either string must be released with topiary_free.
+ */
+int topiary_format(const char *language, const uint8_t *input, size_t len, char **out,
+                   char **err);
+
+/* Release a string returned by topiary_format. NULL is ignored. */
+void topiary_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TOPIARY_H */
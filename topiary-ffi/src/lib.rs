@@ -0,0 +1,248 @@
+//! C bindings for Topiary, so that editors and runtimes written in other languages can embed the
+//! formatter without shelling out to the CLI. See `include/topiary.h` for the C declarations.
+
+use std::{
+    error::Error,
+    ffi::{c_char, c_int, CStr, CString},
+    panic, ptr, slice, str,
+};
+
+use topiary::{
+    formatter, Configuration, FormatterError, FormatterResult, IoError, Operation, OsFileProvider,
+};
+
+/// The status codes returned by the bindings. They are stable, and match the exit codes of the
+/// CLI for the same errors.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TopiaryStatus {
+    Ok = 0,
+    /// Any other error, including internal errors and panics
+    Error = 1,
+    /// A null pointer, or a language name or input that is not valid UTF-8
+    InvalidArgument = 2,
+    Io = 3,
+    Query = 4,
+    Parsing = 5,
+    LanguageDetection = 6,
+    Idempotence = 7,
+    /// The output could not be formatted again, or lost tokens or comments of the input
+    Formatting = 8,
+    Timeout = 9,
+}
+
+impl From<&FormatterError> for TopiaryStatus {
+    fn from(e: &FormatterError) -> Self {
+        match e.exit_code() {
+            3 => Self::Io,
+            4 => Self::Query,
+            5 => Self::Parsing,
+            6 => Self::LanguageDetection,
+            7 => Self::Idempotence,
+            8 => Self::Formatting,
+            9 => Self::Timeout,
+            _ => Self::Error,
+        }
+    }
+}
+
+/// Format the `len` bytes of UTF-8 `input` as the given language, whose name is a
+/// null-terminated string (e.g., "json" or "ocaml-interface"). Its query file is found as by the
+/// CLI, honouring `TOPIARY_LANGUAGE_DIR`.
+///
+/// On success, `*out` is set to the null-terminated formatted output. Otherwise, `*err` is set
+/// to a null-terminated error message, which starts with the stable code of the error in
+/// brackets (e.g., "[E010_PARSE]"). Either string must be released with `topiary_free`.
+/// Pointers that are not set are left untouched; `err` may be null, if the message is not needed.
+///
+/// # Safety
+///
+/// `language` must be a valid null-terminated string, `input` must point to `len` readable bytes,
+/// `out` must be valid for writes and `err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn topiary_format(
+    language: *const c_char,
+    input: *const u8,
+    len: usize,
+    out: *mut *mut c_char,
+    err: *mut *mut c_char,
+) -> c_int {
+    let result = panic::catch_unwind(|| {
+        if language.is_null() || input.is_null() || out.is_null() {
+            return Err((
+                TopiaryStatus::InvalidArgument,
+                "Null pointer argument".into(),
+            ));
+        }
+
+        match (
+            CStr::from_ptr(language).to_str(),
+            str::from_utf8(slice::from_raw_parts(input, len)),
+        ) {
+            (Ok(language), Ok(input)) => {
+                format(language, input).map_err(|e| (TopiaryStatus::from(&e), error_message(&e)))
+            }
+            _ => Err((
+                TopiaryStatus::InvalidArgument,
+                "Argument is not valid UTF-8".into(),
+            )),
+        }
+    });
+
+    let result = result.unwrap_or_else(|_| Err((TopiaryStatus::Error, "Topiary panicked".into())));
+
+    match result.and_then(|output| {
+        CString::new(output).map_err(|_| {
+            (
+                TopiaryStatus::Error,
+                "The output contains a null character".into(),
+            )
+        })
+    }) {
+        Ok(output) => {
+            *out = output.into_raw();
+            TopiaryStatus::Ok as c_int
+        }
+        Err((status, message)) => {
+            if !err.is_null() {
+                // Error messages should not contain null characters; if one does, it is dropped
+                *err = CString::new(message).map_or(ptr::null_mut(), CString::into_raw);
+            }
+            status as c_int
+        }
+    }
+}
+
+/// Release a string returned by `topiary_format`. Null pointers are ignored.
+///
+/// # Safety
+///
+/// `string` must be null, or have been returned by `topiary_format` and not yet been released.
+#[no_mangle]
+pub unsafe extern "C" fn topiary_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+fn format(language_name: &str, input: &str) -> FormatterResult<String> {
+    let configuration = Configuration::parse_default_configuration()?;
+    let language = configuration.get_language(&language_name.replace('-', "_"))?;
+
+    let query = language.read_query(&OsFileProvider)?;
+
+    // Grammars are loaded asynchronously, although statically linked grammars are ready at once
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .map_err(|e| {
+            FormatterError::Io(IoError::Filesystem("Could not start a runtime".into(), e))
+        })?;
+    let grammar = runtime.block_on(language.grammar())?;
+
+    let mut output = Vec::new();
+    formatter(
+        &mut input.as_bytes(),
+        &mut output,
+        &query,
+        language,
+        &grammar,
+        Operation::Format {
+            skip_idempotence: false,
+            tolerate_parsing_errors: false,
+        },
+    )?;
+
+    Ok(String::from_utf8(output)?)
+}
+
+/// The code and message of the error, followed by the messages of its causes.
+fn error_message(e: &FormatterError) -> String {
+    let mut message = format!("[{}] {e}", e.code());
+    let mut inner: &dyn Error = e;
+
+    while let Some(source) = inner.source() {
+        message += &format!("\nCause: {source}");
+        inner = source;
+    }
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CStr, ptr};
+
+    use super::{topiary_format, topiary_free, TopiaryStatus};
+
+    #[test]
+    fn format_json() {
+        let input = r#"{"a":1}"#;
+        let mut out = ptr::null_mut();
+        let mut err = ptr::null_mut();
+
+        let status = unsafe {
+            topiary_format(
+                b"json\0".as_ptr().cast(),
+                input.as_ptr(),
+                input.len(),
+                &mut out,
+                &mut err,
+            )
+        };
+
+        assert_eq!(status, TopiaryStatus::Ok as i32);
+        assert!(err.is_null());
+        assert_eq!(
+            unsafe { CStr::from_ptr(out) }.to_str(),
+            Ok("{ \"a\": 1 }\n")
+        );
+
+        unsafe { topiary_free(out) };
+    }
+
+    #[test]
+    fn parsing_error() {
+        let input = r#"{"a"}"#;
+        let mut out = ptr::null_mut();
+        let mut err = ptr::null_mut();
+
+        let status = unsafe {
+            topiary_format(
+                b"json\0".as_ptr().cast(),
+                input.as_ptr(),
+                input.len(),
+                &mut out,
+                &mut err,
+            )
+        };
+
+        assert_eq!(status, TopiaryStatus::Parsing as i32);
+        assert!(out.is_null());
+        assert!(!err.is_null());
+
+        unsafe { topiary_free(err) };
+    }
+
+    #[test]
+    fn unsupported_language() {
+        let input = "{}";
+        let mut out = ptr::null_mut();
+        let mut err = ptr::null_mut();
+
+        let status = unsafe {
+            topiary_format(
+                b"cobol\0".as_ptr().cast(),
+                input.as_ptr(),
+                input.len(),
+                &mut out,
+                &mut err,
+            )
+        };
+
+        // As the CLI does, with exit code 6
+        assert_eq!(status, TopiaryStatus::LanguageDetection as i32);
+        assert!(out.is_null());
+
+        unsafe { topiary_free(err) };
+    }
+}
@@ -0,0 +1,126 @@
+//! Node.js bindings for Topiary, built with napi-rs, so that JavaScript tooling (e.g., editor
+//! extensions) can format code in-process rather than by running the CLI for every buffer.
+//!
+//! ```js
+//! const { format } = require("@tweag/topiary");
+//!
+//! const formatted = await format('{"a":1}', "json"); // '{ "a": 1 }\n'
+//! ```
+//!
+//! Formatting runs on the libuv thread pool, so it does not block the event loop. The grammar and
+//! query of each language are loaded once, and reused by later calls.
+
+use std::sync::OnceLock;
+
+use napi::{bindgen_prelude::AsyncTask, Env, Error, Status, Task};
+use napi_derive::napi;
+use topiary::{Configuration, FormatterError, FormatterResult, IoError, LanguageRegistry};
+
+/// The languages loaded so far, shared by all calls.
+fn registry() -> FormatterResult<&'static LanguageRegistry> {
+    static REGISTRY: OnceLock<LanguageRegistry> = OnceLock::new();
+
+    if let Some(registry) = REGISTRY.get() {
+        return Ok(registry);
+    }
+
+    let configuration = Configuration::parse_default_configuration()?;
+    Ok(REGISTRY.get_or_init(|| LanguageRegistry::new(configuration)))
+}
+
+fn format_inner(
+    input: &str,
+    language: &str,
+    query: Option<&str>,
+    check_idempotence: bool,
+    tolerate_parsing_errors: bool,
+) -> FormatterResult<String> {
+    // Grammars are loaded asynchronously, although statically linked grammars are ready at once
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .map_err(|e| {
+            FormatterError::Io(IoError::Filesystem("Could not start a runtime".into(), e))
+        })?;
+    let loaded = runtime.block_on(registry()?.get(&language.replace('-', "_")))?;
+
+    let mut options = loaded
+        .options()
+        .skip_idempotence(!check_idempotence)
+        .tolerate_parsing_errors(tolerate_parsing_errors);
+    if let Some(query) = query {
+        options = options.query(query);
+    }
+
+    let mut output = Vec::new();
+    topiary::format(&mut input.as_bytes(), &mut output, &options)?;
+
+    Ok(String::from_utf8(output)?)
+}
+
+/// Formatting options, in addition to the language.
+#[napi(object)]
+#[derive(Default)]
+pub struct FormatOptions {
+    /// The query to format with, rather than that of the language.
+    pub query: Option<String>,
+    /// Whether to check that formatting the output again leaves it unchanged. Defaults to true.
+    pub check_idempotence: Option<bool>,
+    /// Whether to format as much as possible, despite parsing errors. Defaults to false.
+    pub tolerate_parsing_errors: Option<bool>,
+}
+
+/// The formatting of an input, run on the libuv thread pool.
+pub struct FormatTask {
+    input: String,
+    language: String,
+    options: FormatOptions,
+}
+
+impl Task for FormatTask {
+    type Output = String;
+    type JsValue = String;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        format_inner(
+            &self.input,
+            &self.language,
+            self.options.query.as_deref(),
+            self.options.check_idempotence.unwrap_or(true),
+            self.options.tolerate_parsing_errors.unwrap_or(false),
+        )
+        .map_err(|e| to_js_error(&e))
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Format the input as the given language (e.g., "json" or "ocaml-interface"), resolving to the
+/// formatted output. Unless a query is given, that of the language is found as by the CLI,
+/// honouring `TOPIARY_LANGUAGE_DIR`.
+#[napi(ts_return_type = "Promise<string>")]
+pub fn format(
+    input: String,
+    language: String,
+    options: Option<FormatOptions>,
+) -> AsyncTask<FormatTask> {
+    AsyncTask::new(FormatTask {
+        input,
+        language,
+        options: options.unwrap_or_default(),
+    })
+}
+
+/// Reject with the message of the error, followed by those of its causes.
+fn to_js_error(e: &FormatterError) -> Error {
+    let mut message = format!("{e}");
+    let mut inner: &dyn std::error::Error = e;
+
+    while let Some(source) = inner.source() {
+        message += &format!("\nCause: {source}");
+        inner = source;
+    }
+
+    Error::new(Status::GenericFailure, message)
+}
@@ -0,0 +1,173 @@
+//! Python bindings for Topiary, built with PyO3 and packaged with maturin, so that Python tooling
+//! (e.g., pre-commit hooks) can format code in-process rather than by running the CLI.
+//!
+//! ```python
+//! import topiary
+//!
+//! assert topiary.format('{"a":1}', language="json") == '{ "a": 1 }\n'
+//! ```
+
+use pyo3::{create_exception, exceptions::PyException, prelude::*};
+use topiary::{
+    formatter, Configuration, FormatterError, FormatterResult, IoError, Operation, OsFileProvider,
+};
+
+create_exception!(
+    topiary,
+    TopiaryError,
+    PyException,
+    "Base class of all Topiary errors."
+);
+create_exception!(
+    topiary,
+    FormattingError,
+    TopiaryError,
+    "The output of the formatter could not be formatted again."
+);
+create_exception!(
+    topiary,
+    IdempotenceError,
+    TopiaryError,
+    "Formatting the output of the formatter made further changes."
+);
+create_exception!(
+    topiary,
+    InternalError,
+    TopiaryError,
+    "An internal error occurred in Topiary."
+);
+create_exception!(
+    topiary,
+    InvalidRangeError,
+    TopiaryError,
+    "A byte range is out of bounds of the input."
+);
+create_exception!(
+    topiary,
+    ParsingError,
+    TopiaryError,
+    "The input could not be parsed without errors."
+);
+create_exception!(
+    topiary,
+    QueryError,
+    TopiaryError,
+    "There is an error in the query."
+);
+create_exception!(
+    topiary,
+    LanguageError,
+    TopiaryError,
+    "The language is not supported."
+);
+create_exception!(
+    topiary,
+    TopiaryIoError,
+    TopiaryError,
+    "An I/O error occurred, e.g. when reading the query file."
+);
+
+/// Format the code as the given language (e.g., "json" or "ocaml-interface"). Unless the query is
+/// given, that of the language is found as by the CLI, honouring `TOPIARY_LANGUAGE_DIR`.
+#[pyfunction]
+#[pyo3(signature = (code, language, query = None, check_idempotence = true, tolerate_parsing_errors = false))]
+fn format(
+    code: &str,
+    language: &str,
+    query: Option<&str>,
+    check_idempotence: bool,
+    tolerate_parsing_errors: bool,
+) -> PyResult<String> {
+    format_inner(
+        code,
+        language,
+        query,
+        check_idempotence,
+        tolerate_parsing_errors,
+    )
+    .map_err(|e| to_exception(&e))
+}
+
+fn format_inner(
+    code: &str,
+    language_name: &str,
+    query: Option<&str>,
+    check_idempotence: bool,
+    tolerate_parsing_errors: bool,
+) -> FormatterResult<String> {
+    let configuration = Configuration::parse_default_configuration()?;
+    let language = configuration.get_language(language_name.replace('-', "_"))?;
+
+    let query = match query {
+        Some(query) => query.to_string(),
+        None => language.read_query(&OsFileProvider)?,
+    };
+
+    // Grammars are loaded asynchronously, although statically linked grammars are ready at once
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .map_err(|e| {
+            FormatterError::Io(IoError::Filesystem("Could not start a runtime".into(), e))
+        })?;
+    let grammar = runtime.block_on(language.grammar())?;
+
+    let mut output = Vec::new();
+    formatter(
+        &mut code.as_bytes(),
+        &mut output,
+        &query,
+        language,
+        &grammar,
+        Operation::Format {
+            skip_idempotence: !check_idempotence,
+            tolerate_parsing_errors,
+        },
+    )?;
+
+    Ok(String::from_utf8(output)?)
+}
+
+/// Raise the exception corresponding to the variant of the error.
+fn to_exception(e: &FormatterError) -> PyErr {
+    let message = format!("[{}] {e}", e.code());
+
+    match e {
+        FormatterError::Formatting(_) | FormatterError::Tokens(_) | FormatterError::Comments(_) => {
+            FormattingError::new_err(message)
+        }
+        FormatterError::Idempotence(_) => IdempotenceError::new_err(message),
+        FormatterError::Internal(_, _) | FormatterError::PatternDoesNotMatch(_) => {
+            InternalError::new_err(message)
+        }
+        FormatterError::InvalidRange(_) => InvalidRangeError::new_err(message),
+        FormatterError::Parsing { .. } => ParsingError::new_err(message),
+        FormatterError::Query(_, _) => QueryError::new_err(message),
+        FormatterError::LanguageDetection(_, _)
+        | FormatterError::AmbiguousLanguage(_, _)
+        | FormatterError::UnsupportedLanguage(_)
+        | FormatterError::IncompatibleGrammar(_) => LanguageError::new_err(message),
+        FormatterError::Io(_) => TopiaryIoError::new_err(message),
+        // Hooks cannot be registered, formatting cancelled, nor a timeout set, from Python
+        FormatterError::Hook(_, _) | FormatterError::Cancelled | FormatterError::Timeout(_) => {
+            TopiaryError::new_err(message)
+        }
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "topiary")]
+fn topiary_module(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(format, m)?)?;
+
+    m.add("TopiaryError", py.get_type::<TopiaryError>())?;
+    m.add("FormattingError", py.get_type::<FormattingError>())?;
+    m.add("IdempotenceError", py.get_type::<IdempotenceError>())?;
+    m.add("InternalError", py.get_type::<InternalError>())?;
+    m.add("InvalidRangeError", py.get_type::<InvalidRangeError>())?;
+    m.add("ParsingError", py.get_type::<ParsingError>())?;
+    m.add("QueryError", py.get_type::<QueryError>())?;
+    m.add("LanguageError", py.get_type::<LanguageError>())?;
+    m.add("TopiaryIoError", py.get_type::<TopiaryIoError>())?;
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! The code shared by the fuzz targets, which splice the inputs of one of the languages of the
+//! default configuration from its samples, as the data they are given tells, format them with its
+//! query, and check the invariants of formatting.
+
+use std::{cell::RefCell, collections::HashMap, fs, path::Path};
+
+use topiary::{fuzz_input, Configuration, FormatterOptions, Language, SpliceSeeds};
+
+const SAMPLES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/samples/input");
+
+type Target = (FormatterOptions<'static>, SpliceSeeds);
+
+thread_local! {
+    // The options and seeds are loaded once, rather than on every run of the fuzz target
+    static TARGETS: RefCell<HashMap<&'static str, Target>> = RefCell::new(HashMap::new());
+}
+
+/// Format an input of the language of the given name, spliced from its samples as the data tells,
+/// panicking if an invariant of formatting does not hold.
+pub fn fuzz_language(name: &'static str, data: &[u8]) {
+    TARGETS.with(|targets| {
+        let mut targets = targets.borrow_mut();
+        let (options, seeds) = targets.entry(name).or_insert_with(|| load_target(name));
+
+        fuzz_input(data, seeds, options);
+    });
+}
+
+fn load_target(name: &str) -> Target {
+    let configuration = Configuration::parse_default_configuration().expect("configuration");
+    let configuration: &'static Configuration = Box::leak(Box::new(configuration));
+    let language = configuration.get_language(name).expect("language");
+
+    let options = load_options(language);
+
+    let seeds = fs::read_dir(SAMPLES)
+        .expect("samples")
+        .map(|entry| entry.expect("sample").path())
+        .filter(|path| is_sample_of(path, language))
+        .map(|path| fs::read_to_string(path).expect("sample"))
+        .collect();
+    let seeds = SpliceSeeds::new(seeds, &options);
+
+    (options, seeds)
+}
+
+fn is_sample_of(path: &Path, language: &Language) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| language.extensions.contains(extension))
+}
+
+// The configuration, query and grammar live as long as the fuzzer
+fn load_options(language: &'static Language) -> FormatterOptions<'static> {
+    let query = fs::read_to_string(language.query_file().expect("query file")).expect("query");
+    let grammar = futures::executor::block_on(language.grammar()).expect("grammar");
+
+    FormatterOptions::new(
+        Box::leak(query.into_boxed_str()),
+        language,
+        Box::leak(Box::new(grammar)),
+    )
+}
@@ -0,0 +1,91 @@
+//! An asynchronous variant of the formatter, for services built on Tokio that must not block
+//! their executor threads while formatting (e.g., a web service behind the playground).
+
+use std::panic;
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    task,
+};
+
+use crate::{formatter, FormatterError, FormatterResult, IoError, Language, Operation};
+
+/// The asynchronous counterpart of `formatter`: the input is read and the output is written
+/// asynchronously, while formatting itself runs with `tokio::task::spawn_blocking`, on the
+/// blocking threads of the runtime, so that it does not hold up the other tasks of the executor.
+/// The query, language and grammar are copied for that thread. Grammars can be loaded with
+/// `Language::grammar`, which is already asynchronous.
+///
+/// # Errors
+///
+/// If formatting fails for any reason, a `FormatterError` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{formatter_async, Configuration, Operation};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let mut input = "[1,2]".as_bytes();
+/// let mut output = Vec::new();
+/// let operation = Operation::Format {
+///     skip_idempotence: false,
+///     tolerate_parsing_errors: false,
+/// };
+///
+/// formatter_async(&mut input, &mut output, &query, language, &grammar, operation)
+///     .await
+///     .unwrap();
+///
+/// assert_eq!(String::from_utf8(output).unwrap(), "[ 1, 2 ]\n");
+/// # }) // end tokio_test
+/// ```
+pub async fn formatter_async(
+    input: &mut (impl AsyncRead + Unpin),
+    output: &mut (impl AsyncWrite + Unpin),
+    query: &str,
+    language: &Language,
+    grammar: &tree_sitter_facade::Language,
+    operation: Operation,
+) -> FormatterResult<()> {
+    let mut content = Vec::new();
+    input.read_to_end(&mut content).await.map_err(|e| {
+        FormatterError::Io(IoError::Filesystem(
+            "Failed to read input contents".into(),
+            e,
+        ))
+    })?;
+
+    let query = query.to_owned();
+    let language = language.clone();
+    let grammar = grammar.clone();
+    let formatting = task::spawn_blocking(move || {
+        let mut formatted = Vec::new();
+        formatter(
+            &mut content.as_slice(),
+            &mut formatted,
+            &query,
+            &language,
+            &grammar,
+            operation,
+        )
+        .map(|()| formatted)
+    });
+
+    let formatted = match formatting.await {
+        Ok(formatted) => formatted?,
+        // Panics while formatting are passed on, as they would be without a separate thread
+        Err(error) => panic::resume_unwind(error.into_panic()),
+    };
+
+    output.write_all(&formatted).await?;
+    output.flush().await?;
+
+    Ok(())
+}
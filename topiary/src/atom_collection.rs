@@ -2,12 +2,13 @@ use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
     mem,
-    ops::Deref,
+    ops::{Deref, Range},
 };
 
 use tree_sitter_facade::Node;
+use unicode_width::UnicodeWidthStr;
 
-use crate::{Atom, FormatterError, FormatterResult, ScopeCondition};
+use crate::{captures, Atom, FormatterError, FormatterResult, ScopeCondition, Warning};
 
 /// A struct that holds sets of node IDs that have line breaks before or after them.
 ///
@@ -28,13 +29,15 @@ pub struct AtomCollection {
     /// directives, but most require some more complexity.
     atoms: Vec<Atom>,
     /// Whenever a formatting directive instructs tree-sitter to prepend
-    /// something to a node, a new Atom is added to this HashMap.
+    /// something to a node, a new Atom is added to this HashMap, along with
+    /// the priority of the pattern it comes from.
     /// The key of the hashmap is the identifier of the node.
-    prepend: HashMap<usize, Vec<Atom>>,
+    prepend: HashMap<usize, Vec<(i32, Atom)>>,
     /// Whenever a formatting directive instructs tree-sitter to append
-    /// something to a node, a new Atom is added to this HashMap.
+    /// something to a node, a new Atom is added to this HashMap, along with
+    /// the priority of the pattern it comes from.
     /// The key of the hashmap is the identifier of the node.
-    append: HashMap<usize, Vec<Atom>>,
+    append: HashMap<usize, Vec<(i32, Atom)>>,
     /// A query file can define custom leaf nodes (nodes that Topiary should not
     /// touch during formatting). When such a node is encountered, its id is stored in
     /// this HashSet.
@@ -62,11 +65,46 @@ pub struct AtomCollection {
     /// is lost at post-processing time.
     scope_begin: HashMap<usize, (u32, Vec<String>)>,
     scope_end: HashMap<usize, (u32, Vec<String>)>,
+    /// The IDs of the scopes that their contents can force to be multi-line, marked by
+    /// `#forced_by_contents!`.
+    forced_scopes: HashSet<String>,
+    /// Leaf nodes that are written in another language, captured by `@injection`,
+    /// along with the name of that language.
+    injections: HashMap<usize, String>,
+    /// The byte range in the input of every leaf node, keyed by node ID.
+    leaf_input_ranges: HashMap<usize, Range<usize>>,
+    /// The index in `atoms` of every leaf node, keyed by node ID, so that captures can rewrite
+    /// leaves in place. It is cleared once the leaves are moved, by `sort` or by
+    /// `apply_prepends_and_appends`.
+    leaf_indices: HashMap<usize, usize>,
+    /// The IDs of the nodes captured by `@sort`, which are sorted among their
+    /// consecutive siblings that are captured too.
+    sort_items: HashSet<usize>,
+    /// The names of the captures that were applied to each node, keyed by node ID, so that
+    /// the visualisation can show why a node was formatted the way it was.
+    captures: HashMap<usize, Vec<String>>,
+    /// While a capture is traced, by `trace_capture`, the atoms that it prepends and appends.
+    traced_atoms: Option<Vec<Atom>>,
     /// Used to generate unique IDs
     counter: usize,
+    /// The number of matches of the query that produced the atoms
+    pub(crate) match_count: usize,
+    /// The problems that post-processing worked around, to be reported by the formatter
+    pub(crate) warnings: Vec<Warning>,
 }
 
 impl AtomCollection {
+    /// Consume the collection, returning its atoms.
+    pub fn into_atoms(self) -> Vec<Atom> {
+        self.atoms
+    }
+
+    /// The names of the captures that were applied to the node with the given ID, in the order
+    /// in which they were applied.
+    pub(crate) fn captures_of(&self, id: usize) -> &[String] {
+        self.captures.get(&id).map_or(&[], Vec::as_slice)
+    }
+
     /// Use this to create an initial `AtomCollection`
     pub fn collect_leafs(
         root: &Node,
@@ -93,7 +131,16 @@ impl AtomCollection {
             line_break_after: line_break_nodes.after,
             scope_begin: HashMap::new(),
             scope_end: HashMap::new(),
+            forced_scopes: HashSet::new(),
+            injections: HashMap::new(),
+            leaf_input_ranges: HashMap::new(),
+            leaf_indices: HashMap::new(),
+            sort_items: HashSet::new(),
+            captures: HashMap::new(),
+            traced_atoms: None,
             counter: 0,
+            match_count: 0,
+            warnings: Vec::new(),
         };
 
         atoms.collect_leafs_inner(root, source, &Vec::new(), 0)?;
@@ -132,6 +179,7 @@ impl AtomCollection {
     /// * `name` - The name of the capture, starting with `@`.
     /// * `node` - The node that matches the capture in the syntax tree.
     /// * `predicates` - The query predicates that modify the formatting behavior for the capture.
+    /// * `source` - The full source code as a byte slice.
     ///
     /// # Errors
     ///
@@ -148,6 +196,7 @@ impl AtomCollection {
         name: &str,
         node: &Node,
         predicates: &QueryPredicates,
+        source: &[u8],
     ) -> FormatterResult<()> {
         log::debug!("Resolving {name}");
 
@@ -161,6 +210,14 @@ impl AtomCollection {
                 FormatterError::Query(format!("@{name} requires a #scope_id! predicate"), None)
             })
         };
+        let requires_injection_language = || {
+            predicates.injection_language.as_deref().ok_or_else(|| {
+                FormatterError::Query(
+                    format!("@{name} requires a #injection_language! predicate"),
+                    None,
+                )
+            })
+        };
 
         let mut is_multi_line = false;
         if let Some(parent) = node.parent() {
@@ -184,6 +241,11 @@ impl AtomCollection {
             }
         }
 
+        let node_captures = self.captures.entry(node.id()).or_default();
+        if !node_captures.iter().any(|capture| capture == name) {
+            node_captures.push(name.to_string());
+        }
+
         match name {
             "allow_blank_line_before" => {
                 if self.blank_lines_before.contains(&node.id()) {
@@ -195,6 +257,12 @@ impl AtomCollection {
                 node,
                 predicates,
             ),
+            "append_missing_delimiter" => {
+                let delimiter = requires_delimiter()?;
+                if !delimiter_follows(source, node, delimiter) {
+                    self.append(Atom::Literal(delimiter.to_string()), node, predicates);
+                }
+            }
             "append_empty_softline" => {
                 self.append(Atom::Softline { spaced: false }, node, predicates);
             }
@@ -220,6 +288,12 @@ impl AtomCollection {
                 node,
                 predicates,
             ),
+            "prepend_missing_delimiter" => {
+                let delimiter = requires_delimiter()?;
+                if !delimiter_precedes(source, node, delimiter) {
+                    self.prepend(Atom::Literal(delimiter.to_string()), node, predicates);
+                }
+            }
             "prepend_empty_softline" => {
                 self.prepend(Atom::Softline { spaced: false }, node, predicates);
             }
@@ -240,15 +314,38 @@ impl AtomCollection {
             "prepend_spaced_softline" => {
                 self.prepend(Atom::Softline { spaced: true }, node, predicates);
             }
+            // Alignment is resolved once the layout is known, in `post_process_alignment`
+            "align" => self.prepend(Atom::Align, node, predicates),
             // Skip over leafs
             "leaf" => {}
+            // Injected languages are leafs, which are formatted separately
+            "injection" => {
+                self.injections
+                    .insert(node.id(), requires_injection_language()?.to_string());
+            }
             // Deletion
             "delete" => {
+                // Named nodes carry the content of the program, so they can
+                // only be deleted if their kind is explicitly allowed
+                let kind = node.kind();
+                if node.is_named() && !predicates.deletable.iter().any(|k| k.as_str() == kind) {
+                    return Err(FormatterError::Query(
+                        format!("@{name} can only delete named {kind} nodes listed by #deletable!"),
+                        None,
+                    ));
+                }
+
                 self.prepend(Atom::DeleteBegin, node, predicates);
                 self.append(Atom::DeleteEnd, node, predicates);
             }
             // Scope manipulation
-            "begin_scope" => self.begin_scope_before(node, requires_scope_id()?),
+            "begin_scope" => {
+                let scope_id = requires_scope_id()?;
+                if predicates.forced_by_contents {
+                    self.forced_scopes.insert(scope_id.to_string());
+                }
+                self.begin_scope_before(node, scope_id);
+            }
             "end_scope" => self.end_scope_after(node, requires_scope_id()?),
             // Scoped softlines
             "append_empty_scoped_softline" => {
@@ -316,10 +413,22 @@ impl AtomCollection {
 
                 self.append(Atom::Hardline, node, predicates);
             }
+            // Indent the lines of a multi-line leaf like the surrounding code
+            "reindent" => self.reindent_leaf(node),
+            // Sorting is done once all captures are resolved, in `sort`
+            "sort" => {
+                self.sort_items.insert(node.id());
+            }
+            // Normalise the case of leaves
+            "upper_case" => self.transform_leaf(node, str::to_uppercase),
+            "lower_case" => self.transform_leaf(node, str::to_lowercase),
             // Return a query parsing error on unknown capture names
             unknown => {
                 return Err(FormatterError::Query(
-                    format!("@{unknown} is not a valid capture name"),
+                    format!(
+                        "@{unknown} is not a valid capture name{}",
+                        captures::did_you_mean(unknown)
+                    ),
                     None,
                 ))
             }
@@ -328,9 +437,32 @@ impl AtomCollection {
         Ok(())
     }
 
+    /// Like `resolve_capture`, but returns the atoms that the capture prepended and appended to
+    /// the leaves of the node, so that they can be traced.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error in the same cases as `resolve_capture`.
+    pub(crate) fn trace_capture(
+        &mut self,
+        name: &str,
+        node: &Node,
+        predicates: &QueryPredicates,
+        source: &[u8],
+    ) -> FormatterResult<Vec<Atom>> {
+        self.traced_atoms = Some(Vec::new());
+        let resolved = self.resolve_capture(name, node, predicates, source);
+        let traced = self.traced_atoms.take().unwrap_or_default();
+
+        resolved.map(|()| traced)
+    }
+
     /// After query processing is done, a flattened/expanded vector of atoms can be created.
     pub fn apply_prepends_and_appends(&mut self) {
         let mut expanded: Vec<Atom> = Vec::new();
+        // The atoms appended to the previous leaf, which share their gap with
+        // the atoms prepended to the next one
+        let mut gap: Vec<(i32, Atom)> = Vec::new();
 
         for atom in &mut self.atoms {
             if let Atom::Leaf { id, .. } = atom {
@@ -343,18 +475,245 @@ impl AtomCollection {
                 let swapped_atom = mem::take(atom);
 
                 log::debug!("Applying prepend of {prepends:?} to {atom:?}.");
-                expanded.append(prepends);
+                gap.append(prepends);
+                expanded.extend(resolve_priorities(mem::take(&mut gap)));
                 expanded.push(swapped_atom);
 
                 log::debug!("Applying append of {appends:?} to {atom:?}.");
-                expanded.append(appends);
+                gap.append(appends);
             } else {
                 log::debug!("Not a leaf: {atom:?}");
+                expanded.extend(resolve_priorities(mem::take(&mut gap)));
                 expanded.push(mem::take(atom));
             }
         }
 
+        expanded.extend(resolve_priorities(gap));
         self.atoms = expanded;
+        // The leaves have moved
+        self.leaf_indices.clear();
+    }
+
+    /// Formats the content of the leaves captured by `@injection` in their
+    /// respective languages, using the provided function. Should the injected
+    /// code fail to format (e.g., because it is a template that does not parse),
+    /// it is left as is.
+    pub fn format_injections<F>(&mut self, format: F)
+    where
+        F: Fn(&str, &str) -> FormatterResult<String>,
+    {
+        if self.injections.is_empty() {
+            return;
+        }
+
+        for atom in &mut self.atoms {
+            if let Atom::Leaf {
+                content,
+                id,
+                injected,
+                ..
+            } = atom
+            {
+                if let Some(language) = self.injections.get(id) {
+                    log::debug!("Formatting injected {language} code: {content:?}");
+
+                    match format(language, content.as_str()) {
+                        Ok(formatted) => {
+                            // Injected code that starts on a line of its own in the
+                            // input still does.
+                            let on_own_line = content
+                                .trim_start_matches([' ', '\t', '\r'])
+                                .starts_with('\n');
+                            let formatted = formatted.trim_end_matches('\n');
+                            *content = if on_own_line {
+                                format!("\n{formatted}")
+                            } else {
+                                formatted.to_string()
+                            };
+                            *injected = true;
+                        }
+                        Err(e) => {
+                            let warning = Warning::InjectionNotFormatted {
+                                language: language.clone(),
+                                reason: e.to_string(),
+                            };
+                            log::warn!("{warning}");
+                            self.warnings.push(warning);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sorts the runs of consecutive sibling nodes captured by `@sort` by their
+    /// text. Named nodes that are not captured end a run, while anonymous nodes,
+    /// such as separators, stay in place. Comments move along with the node that
+    /// follows them, unless they are on the same line as the end of the node that
+    /// precedes them.
+    ///
+    /// This must be called before `apply_prepends_and_appends`, so that the atoms
+    /// that are added to the sorted nodes move along with them.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if it fails to convert the source code belonging to a
+    /// node to UTF-8.
+    pub fn sort(&mut self, root: &Node, source: &[u8]) -> FormatterResult<()> {
+        if self.sort_items.is_empty() {
+            return Ok(());
+        }
+
+        let mut runs = Vec::new();
+        for node in dfs_flatten(root) {
+            self.collect_sort_runs(&node, source, &mut runs)?;
+        }
+
+        for run in runs {
+            self.sort_run(&run);
+        }
+
+        Ok(())
+    }
+
+    /// Collects the runs of nodes to sort among the children of the given node.
+    fn collect_sort_runs(
+        &self,
+        parent: &Node,
+        source: &[u8],
+        runs: &mut Vec<Vec<SortItem>>,
+    ) -> FormatterResult<()> {
+        let mut run: Vec<SortItem> = Vec::new();
+        // The first leaf of the comments that precede the next node to sort
+        let mut comments: Option<usize> = None;
+        // The row at which the previous child ended, if it was a node to sort
+        let mut previous_item_end: Option<u32> = None;
+
+        for child in parent.children(&mut parent.walk()) {
+            if self.sort_items.contains(&child.id()) {
+                let first = comments
+                    .take()
+                    .unwrap_or_else(|| self.first_leaf(&child).id());
+
+                run.push(SortItem {
+                    key: String::from(child.utf8_text(source)?),
+                    first,
+                    last: self.last_leaf(&child).id(),
+                });
+                previous_item_end = Some(child.end_position().row());
+            } else if child.kind().contains("comment") {
+                match (run.last_mut(), previous_item_end) {
+                    // A comment on the same line as the previous node belongs to it
+                    (Some(item), Some(row))
+                        if comments.is_none() && child.start_position().row() == row =>
+                    {
+                        item.last = self.last_leaf(&child).id();
+                    }
+                    _ => {
+                        comments.get_or_insert_with(|| self.first_leaf(&child).id());
+                    }
+                }
+                previous_item_end = None;
+            } else {
+                previous_item_end = None;
+
+                // Any other named node ends the run
+                if child.is_named() {
+                    if run.len() > 1 {
+                        runs.push(mem::take(&mut run));
+                    } else {
+                        run.clear();
+                    }
+                    comments = None;
+                }
+            }
+        }
+
+        if run.len() > 1 {
+            runs.push(run);
+        }
+
+        Ok(())
+    }
+
+    /// Reorders the atoms of a run of nodes, so that the nodes are sorted by their text.
+    fn sort_run(&mut self, run: &[SortItem]) {
+        let mut order: Vec<usize> = (0..run.len()).collect();
+        order.sort_by(|a, b| run[*a].key.cmp(&run[*b].key));
+
+        if order.iter().enumerate().all(|(slot, item)| slot == *item) {
+            return;
+        }
+
+        let positions: HashMap<usize, usize> = self
+            .atoms
+            .iter()
+            .enumerate()
+            .filter_map(|(position, atom)| match atom {
+                Atom::Leaf { id, .. } => Some((*id, position)),
+                _ => None,
+            })
+            .collect();
+
+        // The range of atoms that each node spans, along with its comments
+        let ranges: Option<Vec<Range<usize>>> = run
+            .iter()
+            .map(|item| Some(*positions.get(&item.first)?..*positions.get(&item.last)? + 1))
+            .collect();
+        let ranges = match ranges {
+            Some(ranges) => ranges,
+            None => {
+                log::warn!("Could not find all the nodes to sort, leaving them as they are");
+                return;
+            }
+        };
+
+        let mut sorted = Vec::with_capacity(self.atoms.len());
+        let mut next = 0;
+        for (slot, item) in ranges.iter().zip(order) {
+            sorted.extend_from_slice(&self.atoms[next..slot.start]);
+            sorted.extend_from_slice(&self.atoms[ranges[item].clone()]);
+            next = slot.end;
+        }
+        sorted.extend_from_slice(&self.atoms[next..]);
+
+        self.atoms = sorted;
+        // The leaves have moved
+        self.leaf_indices.clear();
+    }
+
+    /// Rewrites the content of the given leaf node with the provided function.
+    fn transform_leaf<F>(&mut self, node: &Node, transform: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        if let Some(Atom::Leaf { content, .. }) = self.leaf_mut(node) {
+            *content = transform(content);
+        }
+    }
+
+    /// Strips the common indentation of the lines of the given leaf node after
+    /// the first, and marks them to be indented to the surrounding indentation
+    /// level, like the code of injected languages.
+    fn reindent_leaf(&mut self, node: &Node) {
+        if let Some(Atom::Leaf {
+            content, injected, ..
+        }) = self.leaf_mut(node)
+        {
+            *content = dedent_continuation_lines(content);
+            *injected = true;
+        }
+    }
+
+    /// The atom of the given leaf node, if it is one, while captures are being resolved.
+    fn leaf_mut(&mut self, node: &Node) -> Option<&mut Atom> {
+        let index = *self.leaf_indices.get(&node.id())?;
+        self.atoms.get_mut(index)
+    }
+
+    /// Returns the byte range in the input of the leaf node with the given ID.
+    pub fn leaf_input_range(&self, id: usize) -> Option<Range<usize>> {
+        self.leaf_input_ranges.get(&id).cloned()
     }
 
     /// Marks the provided node as the parent of all its child nodes by adding
@@ -408,11 +767,31 @@ impl AtomCollection {
             // and as such the check below would be redundant.
             || node.kind() == "ERROR"
         {
+            let start = node.start_byte() as usize;
+            let end = node.end_byte() as usize;
+
+            // The content of error nodes is copied verbatim, so we keep it separated from its
+            // neighbours as it was in the input.
+            let is_error = node.kind() == "ERROR";
+            if is_error {
+                log::warn!("Leaving erroneous input untouched: {node:?}");
+                self.atoms
+                    .extend(whitespace_atom(source[..start].iter().rev()));
+            }
+
+            self.leaf_indices.insert(id, self.atoms.len());
             self.atoms.push(Atom::Leaf {
                 content: String::from(node.utf8_text(source)?),
                 id,
                 single_line_no_indent: false,
+                injected: false,
             });
+            self.leaf_input_ranges.insert(id, start..end);
+
+            if is_error {
+                self.atoms.extend(whitespace_atom(source[end..].iter()));
+            }
+
             // Mark all sub-nodes as having this node as a "leaf parent"
             self.mark_leaf_parent(node, node.id());
         } else {
@@ -439,7 +818,14 @@ impl AtomCollection {
 
         log::debug!("Prepending {atom:?} to node {:?}", target_node,);
 
-        self.prepend.entry(target_node.id()).or_default().push(atom);
+        if let Some(traced) = &mut self.traced_atoms {
+            traced.push(atom.clone());
+        }
+
+        self.prepend
+            .entry(target_node.id())
+            .or_default()
+            .push((predicates.priority, atom));
     }
 
     /// Append an atom to the last leaf node in the subtree of a given node.
@@ -456,7 +842,14 @@ impl AtomCollection {
 
         log::debug!("Appending {atom:?} to node {:?}", target_node,);
 
-        self.append.entry(target_node.id()).or_default().push(atom);
+        if let Some(traced) = &mut self.traced_atoms {
+            traced.push(atom.clone());
+        }
+
+        self.append
+            .entry(target_node.id())
+            .or_default()
+            .push((predicates.priority, atom));
     }
 
     /// Begins a scope with the given `scope_id` before the first leaf node of the given `Node`'s subtree.
@@ -556,18 +949,46 @@ impl AtomCollection {
     }
 
     /// This function expands `ScopedSoftline` atoms depending on whether the context
-    /// containing them is multiline.
+    /// containing them is multiline. If a line width is given, scopes that would not fit
+    /// in it on a single line are multiline too; the indentation of lines is estimated
+    /// with the given width of a single level of indentation.
     /// It does two passes over the atom collection: the first one associates each `ScopedSoftline`
     /// to its scope, and decides what to replace them with when the scope ends.
     /// The second pass applies the modifications to the atoms.
-    fn post_process_scopes(&mut self) {
+    fn post_process_scopes(&mut self, line_width: Option<usize>, indent_width: usize) {
         type ScopeId = String;
         type LineIndex = u32;
         type ScopedNodeId = usize;
+        type OpenedScopes<'a> = HashMap<&'a ScopeId, Vec<(LineIndex, Vec<&'a Atom>, bool)>>;
         // `opened_scopes` maintains stacks of opened scopes,
         // the line at which they started,
-        // and the list of `ScopedSoftline` they contain.
-        let mut opened_scopes: HashMap<&ScopeId, Vec<(LineIndex, Vec<&Atom>)>> = HashMap::new();
+        // the list of `ScopedSoftline` they contain,
+        // and whether they are forced to be multi-line by their content.
+        let mut opened_scopes: OpenedScopes = HashMap::new();
+        // The problems with the scopes, reported once the atoms are no longer borrowed
+        let mut unbalanced = Vec::new();
+        // Scopes nest, but each one decides whether it is multi-line independently. A scope is
+        // multi-line if it spans several lines of the input or, if its `scope_id` is marked by
+        // `#forced_by_contents!`, if it contains a line break that will be printed regardless of
+        // scopes, or a multi-line scope (of any `scope_id`). Hence, inner scopes can force outer
+        // ones to be multi-line, but not vice versa. Every scope that is open contains the line
+        // break or the multi-line scope that just ended, whether or not it nests with it, so all
+        // the marked ones are forced.
+        fn force_multiline(opened_scopes: &mut OpenedScopes, forced_scopes: &HashSet<String>) {
+            for (scope_id, scopes) in opened_scopes.iter_mut() {
+                if forced_scopes.contains(*scope_id) {
+                    for (_, _, forced) in scopes {
+                        *forced = true;
+                    }
+                }
+            }
+        }
+        // Line breaks in deleted segments are not printed
+        let mut delete_level = 0;
+        // The column at which the current atom ends, assuming that the scopes that are
+        // not decided yet are single-line, along with the current indentation level
+        let mut column = 0;
+        let mut indent_level = 0;
         // We can't process `ScopedSoftline` in-place as we encounter them in the list of
         // atoms: we need to know when their encompassing scope ends to decide what to
         // replace them with. Instead of in-place modifications, we associate a replacement
@@ -582,24 +1003,43 @@ impl AtomCollection {
         let mut force_apply_modifications = false;
 
         for atom in &self.atoms {
-            if let Atom::Leaf { id, .. } = atom {
+            if delete_level == 0 {
+                match atom {
+                    Atom::Leaf { content, .. } => column = end_column(column, content),
+                    Atom::Literal(literal) => column = end_column(column, literal),
+                    Atom::Space | Atom::ScopedSoftline { spaced: true, .. } => column += 1,
+                    Atom::Hardline | Atom::Blankline => column = indent_level * indent_width,
+                    Atom::IndentStart => indent_level += 1,
+                    Atom::IndentEnd => indent_level = indent_level.saturating_sub(1),
+                    _ => {}
+                }
+            }
+
+            if let Atom::Leaf { id, content, .. } = atom {
                 // Begin a new scope
                 if let Some((line_start, scope_ids)) = self.scope_begin.get(id) {
                     for scope_id in scope_ids {
                         opened_scopes
                             .entry(scope_id)
                             .or_insert_with(Vec::new)
-                            .push((*line_start, Vec::new()));
+                            .push((*line_start, Vec::new(), false));
                     }
                 }
                 // End a scope, and register the ScopedSoftline transformations
                 // in `modifications`
                 if let Some((line_end, scope_ids)) = self.scope_end.get(id) {
+                    let too_wide = line_width.map_or(false, |width| column > width);
+                    let mut closed_multiline = false;
+
                     for scope_id in scope_ids {
-                        if let Some((line_start, atoms)) =
+                        if let Some((line_start, atoms, forced)) =
                             opened_scopes.get_mut(scope_id).and_then(Vec::pop)
                         {
-                            let multiline = line_start != *line_end;
+                            let multiline = forced || too_wide || line_start != *line_end;
+                            if multiline {
+                                force_multiline(&mut opened_scopes, &self.forced_scopes);
+                                closed_multiline = true;
+                            }
                             for atom in atoms {
                                 if let Atom::ScopedSoftline { id, spaced, .. } = atom {
                                     let new_atom = if multiline {
@@ -628,27 +1068,46 @@ impl AtomCollection {
                                 }
                             }
                         } else {
-                            log::warn!("Closing unopened scope {scope_id:?}");
+                            unbalanced.push(format!("Closing unopened scope {scope_id:?}"));
                             force_apply_modifications = true;
                         }
                     }
+
+                    // The end of a multi-line scope is on a line of its own
+                    if closed_multiline {
+                        column = end_column(indent_level * indent_width, content);
+                    }
                 }
+            // Keep track of deleted segments
+            } else if *atom == Atom::DeleteBegin {
+                delete_level += 1;
+            } else if *atom == Atom::DeleteEnd {
+                delete_level -= 1;
+            // Line breaks force the opened scopes that are marked to be multi-line
+            } else if matches!(atom, Atom::Hardline | Atom::Blankline) && delete_level == 0 {
+                force_multiline(&mut opened_scopes, &self.forced_scopes);
             // Register the ScopedSoftline in the correct scope
             } else if let Atom::ScopedSoftline { scope_id, .. } = atom {
-                if let Some((_, vec)) = opened_scopes.get_mut(&scope_id).and_then(|v| v.last_mut())
+                if let Some((_, vec, _)) =
+                    opened_scopes.get_mut(&scope_id).and_then(|v| v.last_mut())
                 {
                     vec.push(atom);
                 } else {
-                    log::warn!("Found scoped softline {:?} outside of its scope", atom);
+                    unbalanced.push(format!(
+                        "Found scoped softline {atom:?} outside of its scope"
+                    ));
                     force_apply_modifications = true;
                 }
             // Register the ScopedConditional in the correct scope
             } else if let Atom::ScopedConditional { scope_id, .. } = atom {
-                if let Some((_, vec)) = opened_scopes.get_mut(&scope_id).and_then(|v| v.last_mut())
+                if let Some((_, vec, _)) =
+                    opened_scopes.get_mut(&scope_id).and_then(|v| v.last_mut())
                 {
                     vec.push(atom);
                 } else {
-                    log::warn!("Found scoped conditional {:?} outside of its scope", atom);
+                    unbalanced.push(format!(
+                        "Found scoped conditional {atom:?} outside of its scope"
+                    ));
                     force_apply_modifications = true;
                 }
             }
@@ -658,9 +1117,15 @@ impl AtomCollection {
             .filter_map(|(scope_id, vec)| if vec.is_empty() { None } else { Some(scope_id) })
             .collect();
         if !still_opened.is_empty() {
-            log::warn!("Some scopes have been left opened: {:?}", still_opened);
+            unbalanced.push(format!(
+                "Some scopes have been left opened: {still_opened:?}"
+            ));
             force_apply_modifications = true;
         }
+        for message in unbalanced {
+            log::warn!("{message}");
+            self.warnings.push(Warning::UnbalancedScopes(message));
+        }
 
         // Apply modifications.
         // For performance reasons, skip this step if there are no modifications to make
@@ -720,8 +1185,8 @@ impl AtomCollection {
     /// If there are several tokens of different kind one after the other,
     /// the blank line is kept over the new line which itself is kept over the space.
     /// Furthermore, this function put the indentation delimiters before any space/line atom.
-    pub fn post_process(&mut self) {
-        self.post_process_scopes();
+    pub fn post_process(&mut self, line_width: Option<usize>, indent_width: usize) {
+        self.post_process_scopes(line_width, indent_width);
         self.post_process_deletes();
         let mut prev: Option<&mut Atom> = None;
         for next in &mut self.atoms {
@@ -792,9 +1257,97 @@ impl AtomCollection {
         // preceding spaces.
         collapse_spaces_before_antispace(&mut self.atoms);
 
+        self.post_process_alignment(indent_width);
+
         log::debug!("List of atoms after post-processing: {:?}", self.atoms);
     }
 
+    /// Replaces the `Align` atoms by the spaces that align what follows them. The `Align` atoms
+    /// on consecutive lines, at the same indentation level, form a group, which is aligned on its
+    /// rightmost atom. Only the first `Align` atom of a line is part of a group.
+    fn post_process_alignment(&mut self, indent_width: usize) {
+        // The index, line, column and indentation level of every `Align` atom
+        let mut aligns: Vec<(usize, usize, usize, usize)> = Vec::new();
+        let mut line = 0;
+        let mut column = 0;
+        let mut indent_level = 0;
+
+        for (index, atom) in self.atoms.iter().enumerate() {
+            match atom {
+                Atom::Align => aligns.push((index, line, column, indent_level)),
+                Atom::Leaf {
+                    content,
+                    single_line_no_indent,
+                    injected,
+                    ..
+                } => {
+                    if *single_line_no_indent {
+                        line += 1;
+                        column = 0;
+                    }
+                    let content = content.trim_end_matches('\n');
+                    let line_breaks = content.matches('\n').count();
+                    line += line_breaks;
+                    // The lines of injected and reindented leaves are indented
+                    column = if line_breaks > 0 && *injected {
+                        indent_level * indent_width + end_column(0, content)
+                    } else {
+                        end_column(column, content)
+                    };
+                }
+                Atom::Literal(literal) => column = end_column(column, literal),
+                Atom::Space => column += 1,
+                Atom::Hardline => {
+                    line += 1;
+                    column = indent_level * indent_width;
+                }
+                Atom::Blankline => {
+                    line += 2;
+                    column = indent_level * indent_width;
+                }
+                Atom::IndentStart => indent_level += 1,
+                Atom::IndentEnd => indent_level = indent_level.saturating_sub(1),
+                _ => {}
+            }
+        }
+
+        if aligns.is_empty() {
+            return;
+        }
+
+        // Keep the first atom of every line, and group those on consecutive lines
+        let mut groups: Vec<Vec<(usize, usize)>> = Vec::new();
+        let mut previous: Option<(usize, usize)> = None;
+        for (index, line, column, indent_level) in aligns {
+            match previous {
+                Some((previous_line, _)) if previous_line == line => {
+                    self.atoms[index] = Atom::Empty;
+                    continue;
+                }
+                Some((previous_line, previous_level))
+                    if previous_line + 1 == line && previous_level == indent_level =>
+                {
+                    if let Some(group) = groups.last_mut() {
+                        group.push((index, column));
+                    }
+                }
+                _ => groups.push(vec![(index, column)]),
+            }
+            previous = Some((line, indent_level));
+        }
+
+        for group in groups {
+            let target = group.iter().map(|(_, column)| *column).max().unwrap_or(0);
+            for (index, column) in group {
+                self.atoms[index] = if column < target {
+                    Atom::Literal(" ".repeat(target - column))
+                } else {
+                    Atom::Empty
+                };
+            }
+        }
+    }
+
     fn next_id(&mut self) -> usize {
         self.counter += 1;
         self.counter
@@ -870,6 +1423,19 @@ impl AtomCollection {
     }
 }
 
+/// A node to sort, among its consecutive siblings.
+#[derive(Debug)]
+struct SortItem {
+    /// The text of the node, by which it is sorted.
+    key: String,
+    /// The ID of the first leaf that moves along with the node, which may be
+    /// that of a preceding comment.
+    first: usize,
+    /// The ID of the last leaf that moves along with the node, which may be
+    /// that of a comment on the same line.
+    last: usize,
+}
+
 #[derive(Clone, Debug, Default)]
 /// A struct that represents a set of predicates for a query that are relevant for Topiary.
 pub struct QueryPredicates {
@@ -878,10 +1444,21 @@ pub struct QueryPredicates {
     pub delimiter: Option<String>,
     /// The predicate used to name a scope indicated by `@begin_scope` and `@end_scope`.
     pub scope_id: Option<String>,
+    /// The predicate used to name the language of a node captured by `@injection`.
+    pub injection_language: Option<String>,
+    /// The kinds of named nodes that `@delete` is allowed to delete, set by
+    /// `#deletable!`. Anonymous nodes can always be deleted.
+    pub deletable: Vec<String>,
+    /// The priority of the query, set by `#priority!`. When queries add conflicting
+    /// whitespace between two leaves, only that of the highest priority is kept.
+    pub priority: i32,
     /// The flag that indicates whether the query only matches single-line nodes.
     pub single_line_only: bool,
     /// The flag that indicates whether the query only matches multi-line nodes.
     pub multi_line_only: bool,
+    /// The flag, set by `#forced_by_contents!` along with `@begin_scope`, that indicates that
+    /// the scope is multi-line if it contains a line break or a multi-line scope.
+    pub forced_by_contents: bool,
     /// The flag that indicates that the query only triggers if the associated
     /// custom scope containing the matched nodes is single-line.
     pub single_line_scope_only: Option<String>,
@@ -890,6 +1467,33 @@ pub struct QueryPredicates {
     pub multi_line_scope_only: Option<String>,
 }
 
+/// Strips the indentation that the lines of the text after the first have in
+/// common. Blank lines are ignored, and emptied.
+fn dedent_continuation_lines(text: &str) -> String {
+    let indentation = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+
+    let mut lines = text.split('\n');
+    let first = lines.next().unwrap_or_default();
+    let rest: Vec<&str> = lines.collect();
+    let common = rest
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| indentation(line))
+        .min()
+        .unwrap_or(0);
+
+    let mut dedented = vec![first];
+    dedented.extend(rest.iter().map(|line| {
+        if line.trim().is_empty() {
+            ""
+        } else {
+            &line[common..]
+        }
+    }));
+
+    dedented.join("\n")
+}
+
 /// Collapses spaces before antispace atoms in a vector of atoms.
 ///
 /// This function modifies the given vector of atoms in place, replacing any
@@ -926,6 +1530,124 @@ fn is_dominant(next: &Atom, prev: &Atom) -> bool {
     }
 }
 
+/// Resolves the conflicts between the atoms that queries added between two leaves,
+/// given along with the priority of their query. If there are whitespace atoms of
+/// different priorities, only those of the highest priority are kept; conflicts
+/// between these are then resolved by dominance, in `post_process`. All other atoms
+/// are kept, in the order in which they were added.
+fn resolve_priorities(atoms: Vec<(i32, Atom)>) -> impl Iterator<Item = Atom> {
+    let max_priority = atoms
+        .iter()
+        .filter(|(_, atom)| is_whitespace(atom))
+        .map(|(priority, _)| *priority)
+        .max();
+
+    atoms
+        .into_iter()
+        .filter(move |(priority, atom)| Some(*priority) == max_priority || !is_whitespace(atom))
+        .map(|(_, atom)| atom)
+}
+
+/// Whether the atom only decides the whitespace between two leaves.
+fn is_whitespace(atom: &Atom) -> bool {
+    match atom {
+        Atom::Antispace
+        | Atom::Blankline
+        | Atom::Empty
+        | Atom::Hardline
+        | Atom::Softline { .. }
+        | Atom::Space
+        | Atom::ScopedSoftline { .. } => true,
+        Atom::ScopedConditional { atom, .. } => is_whitespace(atom),
+        _ => false,
+    }
+}
+
+/// The column at which the given text ends, if it starts at the given column.
+fn end_column(column: usize, text: &str) -> usize {
+    match text.rfind('\n') {
+        Some(newline) => text[newline + 1..].width(),
+        None => column + text.width(),
+    }
+}
+
+/// Whether the input already has the given delimiter at the end of the node, or after
+/// it, save for whitespace and comments. The whitespace around the delimiter itself is
+/// ignored.
+fn delimiter_follows(source: &[u8], node: &Node, delimiter: &str) -> bool {
+    let delimiter = delimiter.trim().as_bytes();
+    let start = node.start_byte() as usize;
+    let end = node.end_byte() as usize;
+
+    let after = &source[end..];
+    let after = &after[after
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(after.len())..];
+
+    source[start..end].ends_with(delimiter)
+        || after.starts_with(delimiter)
+        || following_node(node).map_or(false, |next| {
+            source[next.start_byte() as usize..].starts_with(delimiter)
+        })
+}
+
+/// Whether the input already has the given delimiter at the start of the node, or
+/// before it, save for whitespace and comments. The whitespace around the delimiter
+/// itself is ignored.
+fn delimiter_precedes(source: &[u8], node: &Node, delimiter: &str) -> bool {
+    let delimiter = delimiter.trim().as_bytes();
+    let start = node.start_byte() as usize;
+    let end = node.end_byte() as usize;
+
+    let before = &source[..start];
+    let before = &before[..before
+        .iter()
+        .rposition(|byte| !byte.is_ascii_whitespace())
+        .map_or(0, |last| last + 1)];
+
+    source[start..end].starts_with(delimiter)
+        || before.ends_with(delimiter)
+        || preceding_node(node).map_or(false, |previous| {
+            source[..previous.end_byte() as usize].ends_with(delimiter)
+        })
+}
+
+/// The node that follows the given one in the input, i.e., its next sibling, or that of its
+/// closest ancestor that has one, skipping the extra nodes, such as comments, in between.
+fn following_node<'tree>(node: &Node<'tree>) -> Option<Node<'tree>> {
+    match node.next_sibling() {
+        Some(sibling) if sibling.is_extra() => following_node(&sibling),
+        Some(sibling) => Some(sibling),
+        None => following_node(&node.parent()?),
+    }
+}
+
+/// The node that precedes the given one in the input, i.e., its previous sibling, or that of its
+/// closest ancestor that has one, skipping the extra nodes, such as comments, in between.
+fn preceding_node<'tree>(node: &Node<'tree>) -> Option<Node<'tree>> {
+    match node.prev_sibling() {
+        Some(sibling) if sibling.is_extra() => preceding_node(&sibling),
+        Some(sibling) => Some(sibling),
+        None => preceding_node(&node.parent()?),
+    }
+}
+
+/// The atom corresponding to the run of whitespace at the start of the given bytes: a hardline
+/// if it contains a newline, a space if it doesn't, and nothing if there is no whitespace.
+fn whitespace_atom<'a>(bytes: impl Iterator<Item = &'a u8>) -> Option<Atom> {
+    let mut atom = None;
+
+    for byte in bytes.take_while(|byte| byte.is_ascii_whitespace()) {
+        if *byte == b'\n' {
+            return Some(Atom::Hardline);
+        }
+        atom = Some(Atom::Space);
+    }
+
+    atom
+}
+
 /// Flatten the tree, depth-first, into a vector of nodes.
 ///
 /// This function takes a reference to a node and returns a vector of references
@@ -1010,6 +1732,27 @@ fn detect_multi_line_nodes(dfs_nodes: &[Node]) -> HashSet<usize> {
 ///
 /// A `NodesWithLinebreaks` struct that contains two sets of node IDs: one for the nodes that have a line break
 /// before them, and one for the nodes that have a line break after them.
+/// The kinds of the nodes that include the line break that ends them, which are the blocks of the
+/// Markdown grammar, and some of their parts.
+const LINE_BREAK_TERMINATED_KINDS: &[&str] = &[
+    "atx_heading",
+    "block_quote",
+    "code_fence_content",
+    "fenced_code_block",
+    "html_block",
+    "indented_code_block",
+    "link_reference_definition",
+    "list",
+    "list_item",
+    "paragraph",
+    "pipe_table",
+    "section",
+    "setext_h1_underline",
+    "setext_h2_underline",
+    "setext_heading",
+    "thematic_break",
+];
+
 fn detect_line_breaks(dfs_nodes: &[Node], minimum_line_breaks: u32) -> NodesWithLinebreaks {
     // Zip the flattened vector with its own tail => Iterator of pairs of adjacent nodes
     // Filter this by the threshold distance between pair components
@@ -1018,7 +1761,19 @@ fn detect_line_breaks(dfs_nodes: &[Node], minimum_line_breaks: u32) -> NodesWith
         .iter()
         .zip(dfs_nodes[1..].iter())
         .filter_map(|(left, right)| {
-            let last = left.end_position().row();
+            // A Markdown block that ends at the start of a line includes the line
+            // break before it: it ends on the line before.
+            let end = left.end_position();
+            let last = if end.column() == 0
+                && end.row() > left.start_position().row()
+                && LINE_BREAK_TERMINATED_KINDS
+                    .iter()
+                    .any(|kind| left.kind() == *kind)
+            {
+                end.row() - 1
+            } else {
+                end.row()
+            };
             let next = right.start_position().row();
 
             if next >= last + minimum_line_breaks {
@@ -0,0 +1,91 @@
+//! Cancellation of formatting, so that editor integrations can abort requests that have been
+//! superseded, e.g. on huge or pathological inputs, and timeouts, so that such inputs cannot hang
+//! a formatting job.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{FormatterError, FormatterResult};
+
+/// A token that aborts formatting when cancelled, from any thread. Its clones share its state, so
+/// one can be given to `FormatterOptions::cancellation` and another kept to cancel with.
+///
+/// Parsing cannot be interrupted; the token is checked between the stages of formatting, and
+/// while the query is applied. Cancelled formatting fails with `FormatterError::Cancelled`.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{format, CancellationToken, Configuration, FormatterError, FormatterOptions};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let token = CancellationToken::new();
+/// let options = FormatterOptions::new(&query, language, &grammar).cancellation(token.clone());
+/// token.cancel();
+///
+/// let mut output = Vec::new();
+/// let result = format(&mut "[1,2]".as_bytes(), &mut output, &options);
+/// assert!(matches!(result, Err(FormatterError::Cancelled)));
+/// # }) // end tokio_test
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    /// The instant after which formatting times out, along with the timeout that it was set from
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl CancellationToken {
+    /// Create a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the formatting that uses this token. This cannot be undone.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// A clone of the token that also times out once the given time has elapsed from now.
+    pub(crate) fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            cancelled: self.cancelled.clone(),
+            deadline: Some((Instant::now() + timeout, timeout)),
+        }
+    }
+}
+
+/// Fail with `FormatterError::Cancelled` if the token, if any, has been cancelled, or with
+/// `FormatterError::Timeout` if it has timed out.
+pub(crate) fn check(token: Option<&CancellationToken>) -> FormatterResult<()> {
+    match token {
+        Some(token) if token.is_cancelled() => {
+            log::info!("Formatting was cancelled");
+            Err(FormatterError::Cancelled)
+        }
+        Some(CancellationToken {
+            deadline: Some((deadline, timeout)),
+            ..
+        }) if Instant::now() >= *deadline => {
+            log::info!("Formatting timed out");
+            Err(FormatterError::Timeout(*timeout))
+        }
+        _ => Ok(()),
+    }
+}
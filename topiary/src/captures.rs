@@ -0,0 +1,388 @@
+//! The capture names that Topiary understands in query files, as data, for query linters,
+//! editor completion and documentation generators.
+
+use std::fmt;
+
+use tree_sitter_facade::Query;
+
+use crate::{FormatterError, FormatterResult};
+
+/// A capture name that Topiary understands, such as `@append_space`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Capture {
+    /// The name of the capture, without the leading `@`
+    pub name: &'static str,
+    /// The predicates that must accompany the capture in its pattern, without the leading `#`,
+    /// e.g. `delimiter!` for `@append_delimiter`
+    pub required_predicates: &'static [&'static str],
+    /// A one-sentence description of the effect of the capture
+    pub description: &'static str,
+}
+
+impl Capture {
+    /// The number of arguments the capture takes, which are given by its required predicates.
+    pub fn arity(&self) -> usize {
+        self.required_predicates.len()
+    }
+}
+
+const fn capture(name: &'static str, description: &'static str) -> Capture {
+    Capture {
+        name,
+        required_predicates: &[],
+        description,
+    }
+}
+
+const fn capture_with(
+    name: &'static str,
+    predicates: &'static [&'static str],
+    description: &'static str,
+) -> Capture {
+    Capture {
+        name,
+        required_predicates: predicates,
+        description,
+    }
+}
+
+/// All the capture names that Topiary understands, in alphabetical order. Besides these, captures
+/// whose name starts with an underscore are allowed: they only serve as arguments to predicates.
+pub const CAPTURES: &[Capture] = &[
+    capture(
+        "align",
+        "Pad the line before the node, so that it lines up with those of the surrounding lines.",
+    ),
+    capture(
+        "allow_blank_line_before",
+        "Keep a blank line before the node, if there is one in the input.",
+    ),
+    capture(
+        "append_antispace",
+        "Remove any space appended to the node by other captures.",
+    ),
+    capture_with(
+        "append_delimiter",
+        &["delimiter!"],
+        "Append the delimiter to the node.",
+    ),
+    capture_with(
+        "append_empty_scoped_softline",
+        &["scope_id!"],
+        "Append a line break if the scope is multi-line, and nothing otherwise.",
+    ),
+    capture(
+        "append_empty_softline",
+        "Append a line break if the parent node is multi-line, and nothing otherwise.",
+    ),
+    capture("append_hardline", "Append a line break to the node."),
+    capture(
+        "append_indent_end",
+        "End the indentation block after the node.",
+    ),
+    capture(
+        "append_indent_start",
+        "Start an indentation block after the node.",
+    ),
+    capture(
+        "append_input_softline",
+        "Append a line break if the node is followed by one in the input, and a space otherwise.",
+    ),
+    capture_with(
+        "append_missing_delimiter",
+        &["delimiter!"],
+        "Append the delimiter to the node, unless it already follows the node in the input.",
+    ),
+    capture("append_space", "Append a space to the node."),
+    capture_with(
+        "append_spaced_scoped_softline",
+        &["scope_id!"],
+        "Append a line break if the scope is multi-line, and a space otherwise.",
+    ),
+    capture(
+        "append_spaced_softline",
+        "Append a line break if the parent node is multi-line, and a space otherwise.",
+    ),
+    capture_with(
+        "begin_scope",
+        &["scope_id!"],
+        "Begin the scope before the node.",
+    ),
+    capture(
+        "delete",
+        "Remove the node from the output; named nodes must be listed by #deletable!.",
+    ),
+    capture(
+        "do_nothing",
+        "Disable all the other captures of the match when this capture matches.",
+    ),
+    capture_with("end_scope", &["scope_id!"], "End the scope after the node."),
+    capture_with(
+        "injection",
+        &["injection_language!"],
+        "Format the content of the node as the injected language.",
+    ),
+    capture(
+        "leaf",
+        "Print the node as it is in the input, without formatting its children.",
+    ),
+    capture("lower_case", "Print the leaf node in lower case."),
+    capture(
+        "prepend_antispace",
+        "Remove any space prepended to the node by other captures.",
+    ),
+    capture_with(
+        "prepend_delimiter",
+        &["delimiter!"],
+        "Prepend the delimiter to the node.",
+    ),
+    capture_with(
+        "prepend_empty_scoped_softline",
+        &["scope_id!"],
+        "Prepend a line break if the scope is multi-line, and nothing otherwise.",
+    ),
+    capture(
+        "prepend_empty_softline",
+        "Prepend a line break if the parent node is multi-line, and nothing otherwise.",
+    ),
+    capture("prepend_hardline", "Prepend a line break to the node."),
+    capture(
+        "prepend_indent_end",
+        "End the indentation block before the node.",
+    ),
+    capture(
+        "prepend_indent_start",
+        "Start an indentation block before the node.",
+    ),
+    capture(
+        "prepend_input_softline",
+        "Prepend a line break if the node is preceded by one in the input, and a space otherwise.",
+    ),
+    capture_with(
+        "prepend_missing_delimiter",
+        &["delimiter!"],
+        "Prepend the delimiter to the node, unless it already precedes the node in the input.",
+    ),
+    capture("prepend_space", "Prepend a space to the node."),
+    capture_with(
+        "prepend_spaced_scoped_softline",
+        &["scope_id!"],
+        "Prepend a line break if the scope is multi-line, and a space otherwise.",
+    ),
+    capture(
+        "prepend_spaced_softline",
+        "Prepend a line break if the parent node is multi-line, and a space otherwise.",
+    ),
+    capture(
+        "reindent",
+        "Indent the lines of the multi-line leaf node after the first like the surrounding code.",
+    ),
+    capture(
+        "single_line_no_indent",
+        "Print the leaf node on a line of its own, without indentation.",
+    ),
+    capture(
+        "sort",
+        "Sort the node among its siblings also captured by @sort, by their text.",
+    ),
+    capture("upper_case", "Print the leaf node in upper case."),
+];
+
+/// The capture of the given name, without the leading `@`, if Topiary understands it.
+pub fn find_capture(name: &str) -> Option<&'static Capture> {
+    CAPTURES
+        .binary_search_by(|capture| capture.name.cmp(name))
+        .ok()
+        .map(|index| &CAPTURES[index])
+}
+
+/// The capture that Topiary understands whose name is the closest to the given one, without the
+/// leading `@`, if it is close enough to be a likely misspelling of it, e.g. `append_space` for
+/// `apend_space`. Names are compared by their edit distance, which must be at most a third of the
+/// length of the given name.
+pub fn closest_capture(name: &str) -> Option<&'static Capture> {
+    let max_distance = name.chars().count().max(3) / 3;
+
+    CAPTURES
+        .iter()
+        .map(|capture| (edit_distance(name, capture.name), capture))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, capture)| capture)
+}
+
+/// The suggestion that ends the message of an unknown capture name, if it has a close one.
+pub(crate) fn did_you_mean(name: &str) -> String {
+    match closest_capture(name) {
+        Some(capture) => format!("; did you mean @{}?", capture.name),
+        None => String::new(),
+    }
+}
+
+// The Levenshtein distance between the two strings, i.e., the number of characters to insert,
+// delete or substitute to turn one into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j + 1] + 1).min(row[j] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Whether a capture of the given name is allowed in a query: either Topiary understands it, or
+/// it starts with an underscore.
+pub(crate) fn is_valid_capture_name(name: &str) -> bool {
+    name.starts_with('_') || find_capture(name).is_some()
+}
+
+/// A capture of a query whose name Topiary does not understand.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnknownCapture {
+    /// The name of the capture, without the leading `@`
+    pub name: String,
+    /// The line of the capture in the query, starting from 1
+    pub line: usize,
+    /// The column of the `@` of the capture, in characters, starting from 1
+    pub column: usize,
+}
+
+impl fmt::Display for UnknownCapture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "@{} is not a valid capture name, at line {}, column {}{}",
+            self.name,
+            self.line,
+            self.column,
+            did_you_mean(&self.name)
+        )
+    }
+}
+
+/// Validate the query content for the given grammar, without formatting anything. Returns the
+/// captures whose name Topiary does not understand, in the order in which they appear in the
+/// query; the query is valid if there are none.
+///
+/// # Errors
+///
+/// If the query content cannot be parsed by the grammar, a `FormatterError::Query` will be
+/// returned.
+pub fn validate_query(
+    query_content: &str,
+    grammar: &tree_sitter_facade::Language,
+) -> FormatterResult<Vec<UnknownCapture>> {
+    let query = Query::new(grammar, query_content)
+        .map_err(|e| FormatterError::Query("Error parsing query file".into(), Some(e)))?;
+
+    Ok(unknown_captures(query_content, &query.capture_names()))
+}
+
+/// Locate the captures of the query content whose name is among the given capture names of its
+/// compiled query, but that Topiary does not understand. Comments and strings are skipped.
+pub(crate) fn unknown_captures(
+    query_content: &str,
+    capture_names: &[String],
+) -> Vec<UnknownCapture> {
+    let invalid: Vec<&str> = capture_names
+        .iter()
+        .map(String::as_str)
+        .filter(|name| !is_valid_capture_name(name))
+        .collect();
+
+    let mut unknown = Vec::new();
+    if invalid.is_empty() {
+        return unknown;
+    }
+
+    // Strings can span several lines
+    let mut in_string = false;
+
+    for (line_index, line) in query_content.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if in_string => i += 1,
+                '"' => in_string = !in_string,
+                ';' if !in_string => break,
+                '@' if !in_string => {
+                    let name: String = chars[i + 1..]
+                        .iter()
+                        .take_while(|c| c.is_alphanumeric() || "_-.?!".contains(**c))
+                        .collect();
+                    let length = name.chars().count();
+
+                    if invalid.contains(&name.as_str()) {
+                        unknown.push(UnknownCapture {
+                            name,
+                            line: line_index + 1,
+                            column: i + 1,
+                        });
+                    }
+
+                    i += length;
+                }
+                _ => {}
+            }
+
+            i += 1;
+        }
+    }
+
+    unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{closest_capture, find_capture, unknown_captures, UnknownCapture, CAPTURES};
+
+    #[test]
+    fn captures_are_sorted() {
+        assert!(CAPTURES.windows(2).all(|w| w[0].name < w[1].name));
+    }
+
+    #[test]
+    fn find_captures() {
+        assert_eq!(find_capture("append_delimiter").map(|c| c.arity()), Some(1));
+        assert_eq!(find_capture("append_space").map(|c| c.arity()), Some(0));
+        assert_eq!(find_capture("append_spaces"), None);
+    }
+
+    #[test]
+    fn suggest_captures() {
+        let closest = |name| closest_capture(name).map(|c| c.name);
+
+        assert_eq!(closest("apend_space"), Some("append_space"));
+        assert_eq!(closest("append_spaces"), Some("append_space"));
+        assert_eq!(closest("prepend_hardlin"), Some("prepend_hardline"));
+        assert_eq!(closest("keyword"), None);
+        assert_eq!(closest("spaces"), None);
+    }
+
+    #[test]
+    fn locate_unknown_captures() {
+        let query =
+            "; @append_spaces in a comment\n(\"@\" @append_space)\n\n  (x) @append_spaces\n";
+        let names = ["append_space".to_string(), "append_spaces".to_string()];
+
+        assert_eq!(
+            unknown_captures(query, &names),
+            vec![UnknownCapture {
+                name: "append_spaces".into(),
+                line: 4,
+                column: 7,
+            }]
+        );
+    }
+}
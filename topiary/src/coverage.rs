@@ -0,0 +1,125 @@
+//! Measuring how much of a query a corpus of inputs exercises, so that query maintainers can find
+//! the patterns that are dead and the constructs of the language that no pattern formats.
+
+use std::collections::BTreeSet;
+
+use tree_sitter_facade::{Node, QueryCursor};
+
+use crate::{tree_sitter, FormatterResult};
+
+/// How a query fares against a corpus of inputs, as reported by `query_coverage`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryCoverage {
+    /// Every pattern of the query, in the order in which they appear in it
+    pub patterns: Vec<PatternCoverage>,
+    /// The kinds of the nodes of the inputs that no capture of the query ever captured, sorted.
+    /// Anonymous kinds are quoted, as they are in queries.
+    pub untouched_kinds: Vec<String>,
+}
+
+/// The number of times a pattern of a query matched across a corpus of inputs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatternCoverage {
+    /// The line of the start of the pattern in the query, starting from 1
+    pub line: usize,
+    /// The number of matches of the pattern, across all inputs
+    pub matches: usize,
+}
+
+impl QueryCoverage {
+    /// The patterns of the query that did not match any input.
+    pub fn unmatched_patterns(&self) -> impl Iterator<Item = &PatternCoverage> {
+        self.patterns.iter().filter(|pattern| pattern.matches == 0)
+    }
+}
+
+/// Apply the query to each of the inputs, counting how often each of its patterns matches, and
+/// collecting the kinds of the nodes of the inputs that none of its captures ever captured.
+///
+/// Matches are counted regardless of the predicates that Topiary evaluates itself, such as
+/// `#single_line?`, so that a pattern counts as matched even if those predicates never held.
+///
+/// # Errors
+///
+/// If the query cannot be compiled, or an input cannot be parsed, a `FormatterError` will be
+/// returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use topiary::{query_coverage, Configuration};
+///
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let query = "(pair \":\" @append_space)\n(array \",\" @append_space)";
+/// let coverage = query_coverage(query, &grammar, &[r#"{"a":1}"#]).unwrap();
+///
+/// // There is no array in the input
+/// let unmatched: Vec<usize> = coverage.unmatched_patterns().map(|p| p.line).collect();
+/// assert_eq!(unmatched, [2]);
+/// assert!(coverage.untouched_kinds.contains(&"pair".to_string()));
+/// assert!(!coverage.untouched_kinds.contains(&"\":\"".to_string()));
+/// # }) // end tokio_test
+/// ```
+pub fn query_coverage(
+    query_content: &str,
+    grammar: &tree_sitter_facade::Language,
+    inputs: &[&str],
+) -> FormatterResult<QueryCoverage> {
+    let query = tree_sitter::compile_query(query_content, grammar)?.query;
+
+    let mut patterns: Vec<PatternCoverage> = (0..query.pattern_count())
+        .map(|index| PatternCoverage {
+            line: tree_sitter::pattern_line(&query, query_content, index),
+            matches: 0,
+        })
+        .collect();
+
+    let mut kinds: BTreeSet<String> = BTreeSet::new();
+    let mut captured_kinds: BTreeSet<String> = BTreeSet::new();
+
+    for input in inputs {
+        let (tree, _) = tree_sitter::parse(input, grammar, false, None)?;
+        let root = tree.root_node();
+        collect_kinds(&root, &mut kinds);
+
+        let mut cursor = QueryCursor::new();
+        for query_match in query.matches(&root, input.as_bytes(), &mut cursor) {
+            patterns[query_match.pattern_index() as usize].matches += 1;
+
+            for capture in query_match.captures() {
+                captured_kinds.insert(kind_of(&capture.node()));
+            }
+        }
+    }
+
+    Ok(QueryCoverage {
+        patterns,
+        untouched_kinds: kinds.difference(&captured_kinds).cloned().collect(),
+    })
+}
+
+// The kind of the node, quoted if it is anonymous
+fn kind_of(node: &Node) -> String {
+    if node.is_named() {
+        node.kind().to_string()
+    } else {
+        format!("{:?}", node.kind())
+    }
+}
+
+// Collect the kinds of the node and of all its descendants, except missing nodes, which are not
+// in the input
+fn collect_kinds(node: &Node, kinds: &mut BTreeSet<String>) {
+    if !node.is_missing() {
+        kinds.insert(kind_of(node));
+    }
+
+    let mut walker = node.walk();
+    for child in node.children(&mut walker) {
+        collect_kinds(&child, kinds);
+    }
+}
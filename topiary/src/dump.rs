@@ -0,0 +1,154 @@
+//! A textual dump of the atoms produced by the query, before and after post-processing, next to
+//! the text of the input that they come from. It is the low-level view between the trace of the
+//! query and the formatted output.
+
+use std::io;
+
+use crate::{
+    atom_collection::AtomCollection, prepare_atoms, query_atoms, read_input, tree_sitter, Atom,
+    FormatterError, FormatterOptions, FormatterResult, IoError,
+};
+
+/// The width of the column of the dump that holds the text of the input.
+const SOURCE_WIDTH: usize = 28;
+
+/// Write the atoms produced by the query for the input, first as the query produced them, then
+/// as they are after post-processing, i.e., once whitespace atoms have been merged, scoped atoms
+/// resolved and injected languages formatted. Each leaf atom is aligned with its position and its
+/// text in the input; the other atoms are listed between the leaves around which they were
+/// emitted. Empty atoms, which post-processing leaves in place of those it removes, are omitted.
+///
+/// Pre-processing hooks run before the input is parsed, so positions refer to their output.
+///
+/// # Errors
+///
+/// If the input cannot be read or parsed, the query cannot be applied, or the dump cannot be
+/// written, a `FormatterError` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{dump_atoms, Configuration, FormatterOptions};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+/// let options = FormatterOptions::new(&query, language, &grammar);
+///
+/// let mut output = Vec::new();
+/// dump_atoms(&mut "[1,\n2]".as_bytes(), &mut output, &options).unwrap();
+/// let dump = String::from_utf8(output).unwrap();
+///
+/// assert!(dump.starts_with("Atoms produced by the query:\n"));
+/// assert!(dump.contains("Atoms after post-processing:\n"));
+/// assert!(dump.lines().any(|line| line.starts_with("2:1") && line.ends_with("Leaf \"2\"")));
+/// # }) // end tokio_test
+/// ```
+pub fn dump_atoms(
+    input: &mut impl io::Read,
+    output: &mut impl io::Write,
+    options: &FormatterOptions,
+) -> FormatterResult<()> {
+    let content = read_input(input).map_err(|e| {
+        FormatterError::Io(IoError::Filesystem(
+            "Failed to read input contents".into(),
+            e,
+        ))
+    })?;
+    let (content, _) = options.pre_processed(&content)?;
+    let language = options.effective_language();
+
+    let (tree, _) = tree_sitter::parse(
+        &content,
+        options.grammar,
+        options.tolerate_parsing_errors,
+        None,
+    )?;
+
+    let mut atoms = query_atoms(&tree, &content, options)?;
+    let queried = atoms[..].to_vec();
+    prepare_atoms(&mut atoms, &language, options);
+
+    writeln!(output, "Atoms produced by the query:")?;
+    write_atoms(output, &queried, &atoms, &content)?;
+    writeln!(output)?;
+    writeln!(output, "Atoms after post-processing:")?;
+    write_atoms(output, &atoms[..], &atoms, &content)?;
+
+    Ok(())
+}
+
+/// Write the atoms, one per line, with the position and the text in the input of the leaves, as
+/// recorded in the collection.
+fn write_atoms(
+    output: &mut impl io::Write,
+    atoms: &[Atom],
+    collection: &AtomCollection,
+    content: &str,
+) -> FormatterResult<()> {
+    for atom in atoms {
+        let (position, source) = match atom {
+            Atom::Empty => continue,
+            Atom::Leaf { id, .. } => match collection.leaf_input_range(*id) {
+                Some(range) => (position_of(content, range.start), quoted(&content[range])),
+                None => (String::new(), String::new()),
+            },
+            _ => (String::new(), String::new()),
+        };
+
+        writeln!(
+            output,
+            "{position:<10}{source:<SOURCE_WIDTH$}  {}",
+            describe(atom)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The line and column of the byte offset in the content, starting from 1.
+fn position_of(content: &str, offset: usize) -> String {
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+    format!("{line}:{column}")
+}
+
+/// The text, quoted and escaped, shortened to fit in its column of the dump.
+fn quoted(text: &str) -> String {
+    let quoted = format!("{text:?}");
+
+    if quoted.chars().count() <= SOURCE_WIDTH {
+        quoted
+    } else {
+        let shortened: String = quoted.chars().take(SOURCE_WIDTH - 1).collect();
+        format!("{shortened}…")
+    }
+}
+
+/// A description of the atom, which is shorter than its `Debug` representation for leaves.
+fn describe(atom: &Atom) -> String {
+    match atom {
+        Atom::Leaf {
+            content,
+            single_line_no_indent,
+            injected,
+            ..
+        } => {
+            let mut description = format!("Leaf {content:?}");
+            if *single_line_no_indent {
+                description.push_str(" (single line, no indentation)");
+            }
+            if *injected {
+                description.push_str(" (reindented)");
+            }
+
+            description
+        }
+        atom => format!("{atom:?}"),
+    }
+}
@@ -0,0 +1,97 @@
+//! Reading inputs written in other encodings than UTF-8, such as Latin-1, UTF-16 or Shift_JIS, and
+//! writing their output back in the same encoding. Formatting itself is always done in UTF-8.
+
+use std::borrow::Cow;
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+use crate::{FormatterError, FormatterResult, IoError};
+
+/// The number of bytes at the start of a file that are looked at to tell whether it is binary, as
+/// Git does.
+pub(crate) const BINARY_CHECK_BYTES: usize = 8000;
+
+/// Whether the start of a file looks like that of a binary file, rather than of text: it has a NUL
+/// byte, which text has no use for, unless it starts with a UTF-16 byte order mark, as UTF-16
+/// text is full of them.
+pub(crate) fn looks_binary(start: &[u8]) -> bool {
+    let utf16 = matches!(Encoding::for_bom(start), Some((encoding, _)) if encoding != UTF_8);
+
+    !utf16 && start.contains(&0)
+}
+
+/// Decode the input, in the encoding that its byte order mark gives, if it starts with one, or
+/// else in the given encoding, or in UTF-8. The byte order mark is decoded along with the rest of
+/// the input, so that it is handled as that of a UTF-8 input. The encoding is returned along with
+/// the decoded input, which borrows UTF-8 inputs rather than copying them.
+///
+/// # Errors
+///
+/// If the input is not valid in its encoding, a `FormatterError::Io` will be returned.
+pub(crate) fn decode(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> FormatterResult<(Cow<'_, str>, &'static Encoding)> {
+    let encoding = match Encoding::for_bom(bytes) {
+        Some((encoding, _)) => encoding,
+        None => encoding.unwrap_or(UTF_8),
+    };
+
+    match encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+        Some(content) => Ok((content, encoding)),
+        None => Err(FormatterError::Io(IoError::Generic(
+            format!("Input is not valid {}", encoding.name()),
+            None,
+        ))),
+    }
+}
+
+/// Encode the output in the given encoding.
+///
+/// # Errors
+///
+/// If the output has characters that the encoding cannot represent, a `FormatterError::Io` will be
+/// returned, rather than replacing them.
+pub(crate) fn encode(text: &str, encoding: &'static Encoding) -> FormatterResult<Vec<u8>> {
+    // encoding_rs only decodes UTF-16, as the Encoding Standard does not encode it
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let bytes = text.encode_utf16().flat_map(|unit| {
+            if encoding == UTF_16LE {
+                unit.to_le_bytes()
+            } else {
+                unit.to_be_bytes()
+            }
+        });
+
+        return Ok(bytes.collect());
+    }
+
+    match encoding.encode(text) {
+        (_, _, true) => Err(FormatterError::Io(IoError::Generic(
+            format!(
+                "The output has characters that {} cannot represent",
+                encoding.name()
+            ),
+            None,
+        ))),
+        (bytes, _, false) => Ok(bytes.into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::looks_binary;
+
+    #[test]
+    fn binary_files_have_nul_bytes() {
+        assert!(looks_binary(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+        assert!(!looks_binary("[1, 2]\n".as_bytes()));
+
+        // UTF-16 text, with its byte order mark
+        let utf16: Vec<u8> = "\u{feff}[1]"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert!(!looks_binary(&utf16));
+    }
+}
@@ -1,11 +1,28 @@
 //! This module defines all errors that might be propagated out of the library,
 //! including all of the trait implementations one might expect for Errors.
 
-use std::{error::Error, fmt, io, ops::Deref, path::PathBuf, str, string};
+use std::{
+    error::Error,
+    fmt, io,
+    ops::{Deref, Range},
+    path::PathBuf,
+    str, string,
+    time::Duration,
+};
+
+#[cfg(feature = "error-serde")]
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 
 /// The various errors the formatter may return.
 #[derive(Debug)]
 pub enum FormatterError {
+    /// Formatting was aborted by its `CancellationToken`.
+    Cancelled,
+
+    /// Formatting took longer than the timeout set with `FormatterOptions::timeout`, and was
+    /// aborted.
+    Timeout(Duration),
+
     /// The input produced output that cannot be formatted, i.e. trying to format the
     /// output again produced an error. If this happened using our provided
     /// query files, it is a bug. Please log an issue.
@@ -13,18 +30,46 @@ pub enum FormatterError {
 
     /// The input produced output that isn't idempotent, i.e. formatting the
     /// output again made further changes. If this happened using our provided
-    /// query files, it is a bug. Please log an issue.
-    Idempotence,
+    /// query files, it is a bug. Please log an issue. The report shows where
+    /// the output of the two formatting passes first diverges.
+    Idempotence(String),
+
+    /// The output does not have the same tokens as the input, in the same order, as checked
+    /// when `FormatterOptions::verify_tokens` is set. If this happened using our provided query
+    /// files, it is a bug. Please log an issue. The report pinpoints the first token that was
+    /// lost, duplicated or reordered.
+    Tokens(String),
+
+    /// A comment of the input was dropped from the output, or duplicated, as checked when
+    /// `FormatterOptions::verify_comments` is set. If this happened using our provided query
+    /// files, it is a bug. Please log an issue. The report gives the text and the position of the
+    /// comment.
+    Comments(String),
+
+    /// A pre- or post-processing hook, registered in the `FormatterOptions`, failed.
+    Hook(String, Box<dyn Error>),
 
     /// An internal error occurred. This is a bug. Please log an issue.
     Internal(String, Option<Box<dyn Error>>),
 
+    /// A byte range, given to the formatter, is out of bounds of its input, or does not fall on
+    /// character boundaries.
+    InvalidRange(Range<usize>),
+
+    /// The Tree-sitter grammar of the language was generated for an ABI version that the
+    /// Tree-sitter runtime linked into Topiary does not support.
+    IncompatibleGrammar(tree_sitter_facade::LanguageError),
+
     /// Tree-sitter could not parse the input without errors.
     Parsing {
         start_line: u32,
         start_column: u32,
         end_line: u32,
         end_column: u32,
+        /// The first line of the input that has the error, with carets under the error
+        excerpt: String,
+        /// The kind of the nearest named node that contains the error, if there is one
+        context: Option<String>,
     },
 
     /// The query contains a pattern that had no match in the input file.
@@ -38,6 +83,10 @@ pub enum FormatterError {
     /// Could not detect the input language from the (filename, Option<extension>)
     LanguageDetection(PathBuf, Option<String>),
 
+    /// The extension of the file is shared by several languages, and the content of the file does
+    /// not tell which of them it is written in, given the (filename, candidate languages)
+    AmbiguousLanguage(PathBuf, Vec<String>),
+
     /// I/O-related errors
     Io(IoError),
 
@@ -45,6 +94,149 @@ pub enum FormatterError {
     UnsupportedLanguage(String),
 }
 
+/// The broad categories of `FormatterError`, for callers that need to branch on the kind of an
+/// error without inspecting its message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "error-serde",
+    derive(Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ErrorCategory {
+    /// A bug in Topiary, or in the query files it provides
+    Internal,
+    /// The input could not be parsed
+    Parsing,
+    /// The query is invalid, or does not match the input
+    Query,
+    /// The output is not idempotent, cannot be formatted again, or loses tokens or comments of
+    /// the input
+    Formatting,
+    /// The language could not be detected, or is not supported
+    Language,
+    /// A pre- or post-processing hook failed
+    Hook,
+    /// Formatting was cancelled, or timed out
+    Cancelled,
+    /// An I/O error
+    Io,
+    /// The formatter was called with invalid arguments
+    Usage,
+}
+
+impl FormatterError {
+    /// A stable code that identifies the kind of the error, e.g. `E010_PARSE`. Codes do not
+    /// change between releases, so they can be relied upon by tools, unlike messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Internal(_, _) => "E001_INTERNAL",
+            Self::Parsing { .. } => "E010_PARSE",
+            Self::Query(_, _) => "E020_QUERY",
+            Self::PatternDoesNotMatch(_) => "E021_PATTERN_DOES_NOT_MATCH",
+            Self::Idempotence(_) => "E030_IDEMPOTENCE",
+            Self::Formatting(_) => "E031_FORMATTING",
+            Self::Tokens(_) => "E032_TOKENS",
+            Self::Comments(_) => "E033_COMMENTS",
+            Self::LanguageDetection(_, _) => "E040_LANGUAGE_DETECTION",
+            Self::UnsupportedLanguage(_) => "E041_UNSUPPORTED_LANGUAGE",
+            Self::IncompatibleGrammar(_) => "E042_INCOMPATIBLE_GRAMMAR",
+            Self::AmbiguousLanguage(_, _) => "E043_AMBIGUOUS_LANGUAGE",
+            Self::Io(_) => "E050_IO",
+            Self::InvalidRange(_) => "E060_INVALID_RANGE",
+            Self::Hook(_, _) => "E080_HOOK",
+            Self::Cancelled => "E090_CANCELLED",
+            Self::Timeout(_) => "E091_TIMEOUT",
+        }
+    }
+
+    /// The category of the error.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Internal(_, _) => ErrorCategory::Internal,
+            Self::Parsing { .. } => ErrorCategory::Parsing,
+            Self::Query(_, _) | Self::PatternDoesNotMatch(_) => ErrorCategory::Query,
+            Self::Idempotence(_) | Self::Formatting(_) | Self::Tokens(_) | Self::Comments(_) => {
+                ErrorCategory::Formatting
+            }
+            Self::LanguageDetection(_, _)
+            | Self::AmbiguousLanguage(_, _)
+            | Self::UnsupportedLanguage(_)
+            | Self::IncompatibleGrammar(_) => ErrorCategory::Language,
+            Self::Io(_) => ErrorCategory::Io,
+            Self::InvalidRange(_) => ErrorCategory::Usage,
+            Self::Hook(_, _) => ErrorCategory::Hook,
+            Self::Cancelled | Self::Timeout(_) => ErrorCategory::Cancelled,
+        }
+    }
+
+    /// The exit code of the CLI for the error, which the C bindings return as their status too.
+    /// Like the codes of errors, exit codes do not change between releases.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Timeout(_) => 9,
+            Self::Formatting(_) | Self::Tokens(_) | Self::Comments(_) => 8,
+            Self::Idempotence(_) => 7,
+            Self::LanguageDetection(_, _)
+            | Self::AmbiguousLanguage(_, _)
+            | Self::UnsupportedLanguage(_) => 6,
+            Self::Parsing { .. } => 5,
+            Self::Query(_, _) => 4,
+            Self::Io(_) => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// The chain of the messages of the errors that caused the error, from its direct cause onwards.
+pub fn source_chain(error: &dyn Error) -> Vec<String> {
+    let mut sources = Vec::new();
+    let mut source = error.source();
+
+    while let Some(error) = source {
+        sources.push(error.to_string());
+        source = error.source();
+    }
+
+    sources
+}
+
+/// Errors are serialised as an object whose fields are stable, so that tools can rely on them,
+/// unlike on messages:
+///
+/// * `kind`, the category of the error, in snake case, e.g. `parsing`;
+/// * `code`, its code, e.g. `E010_PARSE`;
+/// * `message`, its message;
+/// * `span`, the lines and columns of the input, starting from 1, where a parsing error starts
+///   and ends, e.g. `{"start": {"line": 1, "column": 2}, "end": {"line": 1, "column": 3}}`, or
+///   `null` for other errors;
+/// * `sources`, the messages of the errors that caused it, from its direct cause onwards.
+#[cfg(feature = "error-serde")]
+impl Serialize for FormatterError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let span = match self {
+            Self::Parsing {
+                start_line,
+                start_column,
+                end_line,
+                end_column,
+                ..
+            } => Some(serde_json::json!({
+                "start": { "line": start_line, "column": start_column },
+                "end": { "line": end_line, "column": end_column },
+            })),
+            _ => None,
+        };
+
+        let mut error = serializer.serialize_struct("FormatterError", 5)?;
+        error.serialize_field("kind", &self.category())?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.serialize_field("span", &span)?;
+        error.serialize_field("sources", &source_chain(self))?;
+        error.end()
+    }
+}
+
 /// A subtype of `FormatterError::Io`
 #[derive(Debug)]
 pub enum IoError {
@@ -63,10 +255,43 @@ impl fmt::Display for FormatterError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let please_log_message = "It would be helpful if you logged this error at https://github.com/tweag/topiary/issues/new?assignees=&labels=type%3A+bug&template=bug_report.md";
         match self {
-            Self::Idempotence => {
+            Self::Cancelled => write!(f, "Formatting was cancelled"),
+
+            Self::Timeout(timeout) => write!(f, "Formatting timed out after {timeout:?}"),
+
+            Self::Idempotence(report) => {
+                write!(
+                    f,
+                    "The formatter did not produce the same result when invoked twice (idempotence check).\n{report}\n{please_log_message}"
+                )
+            }
+
+            Self::Tokens(report) => {
                 write!(
                     f,
-                    "The formatter did not produce the same result when invoked twice (idempotence check).\n{please_log_message}"
+                    "The formatter did not keep the tokens of the input, in order (token check).\n{report}\n{please_log_message}"
+                )
+            }
+
+            Self::Comments(report) => {
+                write!(
+                    f,
+                    "The formatter did not keep every comment of the input exactly once (comment check).\n{report}\n{please_log_message}"
+                )
+            }
+
+            Self::IncompatibleGrammar(e) => {
+                write!(
+                    f,
+                    "The Tree-sitter grammar of the language is incompatible with the Tree-sitter runtime of Topiary ({e}). Update the grammar, or build Topiary against a version of it that was generated for a compatible ABI."
+                )
+            }
+
+            Self::InvalidRange(range) => {
+                write!(
+                    f,
+                    "The range {}..{} is out of bounds of the input, or does not fall on character boundaries",
+                    range.start, range.end
                 )
             }
 
@@ -75,8 +300,18 @@ impl fmt::Display for FormatterError {
                 start_column,
                 end_line,
                 end_column,
+                excerpt,
+                context,
             } => {
-                write!(f, "Parsing error between line {start_line}, column {start_column} and line {end_line}, column {end_column}")
+                write!(f, "Parsing error between line {start_line}, column {start_column} and line {end_line}, column {end_column}")?;
+                if let Some(kind) = context {
+                    write!(f, ", in a node of kind {kind}")?;
+                }
+                if !excerpt.is_empty() {
+                    write!(f, "\n{excerpt}")?;
+                }
+
+                Ok(())
             }
 
             Self::LanguageDetection(filename, extension) => {
@@ -95,6 +330,14 @@ impl fmt::Display for FormatterError {
                 }
             }
 
+            Self::AmbiguousLanguage(filename, candidates) => write!(
+                f,
+                "Cannot tell the language of file '{}' from its extension, which is that of {}, \
+                 nor from its content. Try specifying language explicitly.",
+                filename.to_string_lossy(),
+                candidates.join(", ")
+            ),
+
             Self::Formatting(_err) => {
                 write!(
                     f,
@@ -109,7 +352,8 @@ impl fmt::Display for FormatterError {
                 )
             }
 
-            Self::Internal(message, _)
+            Self::Hook(message, _)
+            | Self::Internal(message, _)
             | Self::Query(message, _)
             | Self::Io(IoError::Filesystem(message, _) | IoError::Generic(message, _)) => {
                 write!(f, "{message}")
@@ -125,17 +369,25 @@ impl fmt::Display for FormatterError {
 impl Error for FormatterError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
-            Self::Idempotence
+            Self::Cancelled
+            | Self::Timeout(_)
+            | Self::Idempotence(_)
+            | Self::Tokens(_)
+            | Self::Comments(_)
+            | Self::InvalidRange(_)
             | Self::Parsing { .. }
             | Self::PatternDoesNotMatch(_)
             | Self::LanguageDetection(_, _)
+            | Self::AmbiguousLanguage(_, _)
             | Self::Io(IoError::Generic(_, None))
             | Self::UnsupportedLanguage(_) => None,
             Self::Internal(_, source) => source.as_ref().map(Deref::deref),
+            Self::Hook(_, source) => Some(source.as_ref()),
             Self::Query(_, source) => source.as_ref().map(|e| e as &dyn Error),
             Self::Io(IoError::Filesystem(_, source)) => Some(source),
             Self::Io(IoError::Generic(_, Some(source))) => Some(source.as_ref()),
             Self::Formatting(err) => Some(err),
+            Self::IncompatibleGrammar(source) => Some(source),
         }
     }
 }
@@ -224,3 +476,48 @@ impl From<toml::de::Error> for FormatterError {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ErrorCategory, FormatterError};
+
+    #[test]
+    fn codes_and_categories() {
+        let error = FormatterError::Parsing {
+            start_line: 1,
+            start_column: 1,
+            end_line: 1,
+            end_column: 2,
+            excerpt: String::new(),
+            context: None,
+        };
+        assert_eq!(error.code(), "E010_PARSE");
+        assert_eq!(error.category(), ErrorCategory::Parsing);
+
+        let error = FormatterError::Formatting(Box::new(error));
+        assert_eq!(error.code(), "E031_FORMATTING");
+        assert_eq!(error.category(), ErrorCategory::Formatting);
+    }
+
+    #[cfg(feature = "error-serde")]
+    #[test]
+    fn serialised_errors() {
+        let parsing = FormatterError::Parsing {
+            start_line: 1,
+            start_column: 2,
+            end_line: 1,
+            end_column: 3,
+            excerpt: String::new(),
+            context: None,
+        };
+        let error = serde_json::to_value(FormatterError::Formatting(Box::new(parsing))).unwrap();
+
+        assert_eq!(error["kind"], "formatting");
+        assert_eq!(error["code"], "E031_FORMATTING");
+        assert!(error["span"].is_null());
+        assert_eq!(
+            error["sources"],
+            serde_json::json!(["Parsing error between line 1, column 2 and line 1, column 3"])
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! An alternative to rendering the output as a string: the formatter can describe its output as
+//! a stream of events instead, so that, e.g., syntax highlighters or HTML renderers can lay out
+//! the output themselves, without parsing it again.
+
+use std::io;
+
+use crate::{
+    idempotence_check, indentation, prepare_atoms, pretty, query_atoms, read_input, tree_sitter,
+    trim_whitespace, Atom, FormatterError, FormatterOptions, FormatterResult, IoError,
+};
+
+/// A piece of the formatted output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event<'a> {
+    /// The content of a leaf node of the input, identified by its Tree-sitter node ID. It is
+    /// printed verbatim, and may span several lines (e.g., a multi-line string). The formatted
+    /// code of injected languages, and leaves captured by `@reindent`, which are indented, are
+    /// split into one event per line instead, separated by `Newline` events.
+    Leaf { content: &'a str, id: usize },
+    /// A literal string added by the query, such as a delimiter.
+    Literal(&'a str),
+    /// A single space.
+    Space,
+    /// A line break. The new line is indented by the last indentation level.
+    Newline,
+    /// The indentation level of the following lines changes. The size of each level is up to the
+    /// consumer; to render the formatted output, it is the indentation of the language.
+    IndentLevel(usize),
+}
+
+/// A consumer of the events of the formatted output. It is implemented by all closures of the
+/// right type.
+pub trait EventSink {
+    fn event(&mut self, event: Event) -> FormatterResult<()>;
+}
+
+impl<F> EventSink for F
+where
+    F: FnMut(Event) -> FormatterResult<()>,
+{
+    fn event(&mut self, event: Event) -> FormatterResult<()> {
+        self(event)
+    }
+}
+
+/// Format the input, describing the output as a stream of events, rather than writing it out.
+/// Rendering the events (each line break followed by the indentation of its level) gives the
+/// same output as `format`, except that post-processing hooks and line endings do not apply.
+///
+/// # Errors
+///
+/// If formatting fails for any reason, or the sink fails, a `FormatterError` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{format_to_events, Configuration, Event, FormatterOptions, FormatterResult};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+/// let options = FormatterOptions::new(&query, language, &grammar);
+///
+/// let mut html = String::new();
+/// let mut sink = |event: Event| -> FormatterResult<()> {
+///     match event {
+///         Event::Leaf { content, .. } => html += &format!("<b>{content}</b>"),
+///         Event::Literal(content) => html += content,
+///         Event::Space => html += " ",
+///         Event::Newline => html += "<br>",
+///         Event::IndentLevel(_) => {}
+///     }
+///     Ok(())
+/// };
+///
+/// format_to_events(&mut "[1,2]".as_bytes(), &mut sink, &options).unwrap();
+///
+/// assert_eq!(html, "<b>[</b> <b>1</b><b>,</b> <b>2</b> <b>]</b><br>");
+/// # }) // end tokio_test
+/// ```
+pub fn format_to_events(
+    input: &mut impl io::Read,
+    sink: &mut impl EventSink,
+    options: &FormatterOptions,
+) -> FormatterResult<()> {
+    let content = read_input(input).map_err(|e| {
+        FormatterError::Io(IoError::Filesystem(
+            "Failed to read input contents".into(),
+            e,
+        ))
+    })?;
+    let language = options.effective_language();
+    let (content, _) = options.pre_processed(&content)?;
+
+    let (tree, _) = tree_sitter::parse(
+        &content,
+        options.grammar,
+        options.tolerate_parsing_errors,
+        None,
+    )?;
+    let mut atoms = query_atoms(&tree, &content, options)?;
+    prepare_atoms(&mut atoms, &language, options);
+
+    if !options.skip_idempotence {
+        let rendered = trim_whitespace(&pretty::render(&atoms[..], indentation(&language))?);
+        idempotence_check(&options.post_processed(rendered)?, options)?;
+    }
+
+    emit_events(&atoms[..], &mut Trimmer::new(sink))
+}
+
+/// Describe the atoms, which must have been post-processed, as a stream of events.
+fn emit_events(atoms: &[Atom], trimmer: &mut Trimmer<impl EventSink>) -> FormatterResult<()> {
+    let mut indent_level: usize = 0;
+
+    for atom in atoms {
+        match atom {
+            Atom::Blankline => {
+                trimmer.newline(indent_level);
+                trimmer.newline(indent_level);
+            }
+
+            Atom::Empty => (),
+
+            Atom::Hardline => trimmer.newline(indent_level),
+
+            Atom::IndentEnd => {
+                if indent_level == 0 {
+                    return Err(FormatterError::Query(
+                        "Trying to close an unopened indentation block".into(),
+                        None,
+                    ));
+                }
+
+                indent_level -= 1;
+            }
+
+            Atom::IndentStart => indent_level += 1,
+
+            Atom::Leaf {
+                content,
+                id,
+                single_line_no_indent,
+                injected,
+            } => {
+                if *single_line_no_indent {
+                    trimmer.newline(0);
+                }
+
+                let content = content.trim_end_matches('\n');
+                if *injected {
+                    for (index, line) in content.split('\n').enumerate() {
+                        if index > 0 {
+                            trimmer.newline(indent_level);
+                        }
+                        trimmer.content(Event::Leaf {
+                            content: line,
+                            id: *id,
+                        })?;
+                    }
+                } else {
+                    trimmer.content(Event::Leaf { content, id: *id })?;
+                }
+            }
+
+            Atom::Literal(s) => trimmer.content(Event::Literal(s))?,
+
+            Atom::Space => trimmer.space(),
+
+            // All other atom kinds should have been post-processed at that point
+            other => {
+                return Err(FormatterError::Internal(
+                    format!("Found atom that should have been removed before rendering: {other:?}",),
+                    None,
+                ))
+            }
+        }
+    }
+
+    trimmer.sink.event(Event::Newline)
+}
+
+/// Drops the whitespace events that `trim_whitespace` would remove from the rendered output:
+/// spaces at the end of lines, and line breaks at the start and the end of the output. Only the
+/// whitespace since the last content is held back.
+struct Trimmer<'a, S: EventSink> {
+    sink: &'a mut S,
+    /// Whether any content was emitted
+    started: bool,
+    /// The line breaks since the last content
+    pending_newlines: usize,
+    /// The indentation level of the last of the pending line breaks
+    pending_level: usize,
+    /// The spaces since the last content, on the current line
+    pending_spaces: usize,
+    /// The indentation level that was last emitted
+    level: usize,
+}
+
+impl<'a, S: EventSink> Trimmer<'a, S> {
+    fn new(sink: &'a mut S) -> Self {
+        Self {
+            sink,
+            started: false,
+            pending_newlines: 0,
+            pending_level: 0,
+            pending_spaces: 0,
+            level: 0,
+        }
+    }
+
+    fn newline(&mut self, level: usize) {
+        if self.started {
+            self.pending_newlines += 1;
+            self.pending_level = level;
+        }
+        self.pending_spaces = 0;
+    }
+
+    fn space(&mut self) {
+        if self.started {
+            self.pending_spaces += 1;
+        }
+    }
+
+    fn content(&mut self, event: Event) -> FormatterResult<()> {
+        if matches!(event, Event::Leaf { content: "", .. } | Event::Literal("")) {
+            return Ok(());
+        }
+
+        if self.pending_newlines > 0 {
+            if self.pending_level != self.level {
+                self.level = self.pending_level;
+                self.sink.event(Event::IndentLevel(self.level))?;
+            }
+
+            for _ in 0..self.pending_newlines {
+                self.sink.event(Event::Newline)?;
+            }
+        }
+
+        for _ in 0..self.pending_spaces {
+            self.sink.event(Event::Space)?;
+        }
+
+        self.started = true;
+        self.pending_newlines = 0;
+        self.pending_spaces = 0;
+        self.sink.event(event)
+    }
+}
@@ -0,0 +1,200 @@
+//! Access to files, abstracted so that embedders (e.g., language servers, or build systems with
+//! in-memory overlays) can supply the contents of unsaved buffers and virtual query files, rather
+//! than have Topiary read them from disk.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// A source of files, from which Topiary reads query files and configuration.
+pub trait FileProvider {
+    /// Read the whole file at the given path, which must be valid UTF-8.
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    /// Whether there is a file at the given path.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Read at most `head` bytes at the start of the file at the given path and, if it is longer,
+    /// at most `tail` bytes at its end, which do not overlap them, e.g., to detect the language of
+    /// a large file without reading all of it. A character that is cut at either end may be left
+    /// out, or replaced as invalid UTF-8 is. By default, the whole file is read.
+    fn read_ends(&self, path: &Path, head: usize, tail: usize) -> io::Result<Ends> {
+        Ok(Ends::of(&self.read_to_string(path)?, head, tail))
+    }
+}
+
+/// The start and, if the file is longer, the end of a file, as given by `FileProvider::read_ends`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Ends {
+    pub head: String,
+    pub tail: Option<String>,
+}
+
+impl Ends {
+    /// The ends of the given contents, as `FileProvider::read_ends` reads them.
+    fn of(contents: &str, head: usize, tail: usize) -> Self {
+        let head_end = floor_char_boundary(contents, head);
+        let tail_start = ceil_char_boundary(contents, contents.len().saturating_sub(tail));
+
+        Self {
+            head: contents[..head_end].to_string(),
+            tail: (contents.len() > head).then(|| contents[tail_start.max(head_end)..].to_string()),
+        }
+    }
+}
+
+/// The largest character boundary of the string that is not past the given index.
+fn floor_char_boundary(string: &str, index: usize) -> usize {
+    (0..=index.min(string.len()))
+        .rev()
+        .find(|&index| string.is_char_boundary(index))
+        .unwrap_or(0)
+}
+
+/// The smallest character boundary of the string that is not before the given index.
+fn ceil_char_boundary(string: &str, index: usize) -> usize {
+    (index..string.len())
+        .find(|&index| string.is_char_boundary(index))
+        .unwrap_or(string.len())
+}
+
+/// The files of the operating system, read with `std::fs`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsFileProvider;
+
+impl FileProvider for OsFileProvider {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn read_ends(&self, path: &Path, head: usize, tail: usize) -> io::Result<Ends> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        let mut bytes = Vec::new();
+        (&mut file).take(head as u64).read_to_end(&mut bytes)?;
+        let head_contents = String::from_utf8_lossy(&bytes).into_owned();
+
+        let tail = if len > head as u64 {
+            bytes.clear();
+            file.seek(SeekFrom::Start(
+                len.saturating_sub(tail as u64).max(head as u64),
+            ))?;
+            file.read_to_end(&mut bytes)?;
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        } else {
+            None
+        };
+
+        Ok(Ends {
+            head: head_contents,
+            tail,
+        })
+    }
+}
+
+/// In-memory files, laid over those of another provider, which are used for the paths that have
+/// no file in memory.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use topiary::{FileProvider, OsFileProvider, OverlayFileProvider};
+///
+/// let mut files = OverlayFileProvider::new(OsFileProvider);
+/// files.insert("virtual/json.scm", "(object) @append_hardline");
+///
+/// let path = Path::new("virtual/json.scm");
+/// assert!(files.exists(path));
+/// assert_eq!(files.read_to_string(path).unwrap(), "(object) @append_hardline");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct OverlayFileProvider<P = OsFileProvider> {
+    files: HashMap<PathBuf, String>,
+    fallback: P,
+}
+
+impl<P> OverlayFileProvider<P> {
+    /// Create an overlay, with no file in memory yet, over the given provider.
+    pub fn new(fallback: P) -> Self {
+        Self {
+            files: HashMap::new(),
+            fallback,
+        }
+    }
+
+    /// Set the contents of the file at the given path, returning its previous contents in
+    /// memory, if any.
+    pub fn insert(
+        &mut self,
+        path: impl Into<PathBuf>,
+        contents: impl Into<String>,
+    ) -> Option<String> {
+        self.files.insert(path.into(), contents.into())
+    }
+
+    /// Remove the file at the given path from memory, so that it is read from the underlying
+    /// provider again. Returns its contents, if it was in memory.
+    pub fn remove(&mut self, path: &Path) -> Option<String> {
+        self.files.remove(path)
+    }
+}
+
+impl<P: FileProvider> FileProvider for OverlayFileProvider<P> {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self.files.get(path) {
+            Some(contents) => Ok(contents.clone()),
+            None => self.fallback.read_to_string(path),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path) || self.fallback.exists(path)
+    }
+
+    fn read_ends(&self, path: &Path, head: usize, tail: usize) -> io::Result<Ends> {
+        match self.files.get(path) {
+            Some(contents) => Ok(Ends::of(contents, head, tail)),
+            None => self.fallback.read_ends(path, head, tail),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Ends;
+
+    #[test]
+    fn ends_do_not_overlap_nor_cut_characters() {
+        assert_eq!(
+            Ends::of("abc", 4, 4),
+            Ends {
+                head: "abc".into(),
+                tail: None,
+            }
+        );
+        assert_eq!(
+            Ends::of("abcdef", 4, 4),
+            Ends {
+                head: "abcd".into(),
+                tail: Some("ef".into()),
+            }
+        );
+        // "é" takes two bytes
+        assert_eq!(
+            Ends::of("aébcdéf", 2, 2),
+            Ends {
+                head: "a".into(),
+                tail: Some("f".into()),
+            }
+        );
+    }
+}
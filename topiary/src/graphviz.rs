@@ -1,5 +1,6 @@
 //! GraphViz visualisation for our SyntaxTree representation.
-//! Named syntax nodes are elliptical; anonymous are rectangular.
+//! Named syntax nodes are elliptical; anonymous are rectangular. Their labels list the captures
+//! that were applied to them, and the atoms that were emitted before and after them.
 use std::{borrow::Cow, fmt, io};
 
 use crate::{tree_sitter::SyntaxNode, FormatterResult};
@@ -66,12 +67,20 @@ impl fmt::Display for SyntaxNode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let shape = if self.is_named { "ellipse" } else { "box" };
 
-        writeln!(
-            f,
-            "  {} [label=\"{}\", shape={shape}];",
-            self.id,
-            escape(&self.kind)
-        )?;
+        // The annotations of the node are on lines of their own, below its kind
+        let mut label = escape(&self.kind).into_owned();
+        if !self.captures.is_empty() {
+            let captures: Vec<String> = self.captures.iter().map(|c| format!("@{c}")).collect();
+            label += &format!("\\n{}", escape(&captures.join(" ")));
+        }
+        if !self.atoms_before.is_empty() {
+            label += &format!("\\nbefore: {}", escape(&self.atoms_before.join(", ")));
+        }
+        if !self.atoms_after.is_empty() {
+            label += &format!("\\nafter: {}", escape(&self.atoms_after.join(", ")));
+        }
+
+        writeln!(f, "  {} [label=\"{label}\", shape={shape}];", self.id)?;
 
         for child in &self.children {
             writeln!(f, "  {} -- {};", self.id, child.id)?;
@@ -0,0 +1,266 @@
+//! Expanding the `; include "file.scm"` directives of query files, so that large query files can
+//! be split into reusable pieces, and language variants can share a common core. Query files can
+//! also be layered over each other, so that style variants refine a common query file.
+
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{language::query_search_path, FileProvider, FormatterError, FormatterResult, IoError};
+
+/// A query file, with the files that it includes expanded in place.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExpandedQuery {
+    /// The text of the query, in which every include directive is followed by the text of the
+    /// file that it includes
+    pub content: String,
+    /// The file and the line in it, starting from 1, of every line of the content
+    origins: Vec<(PathBuf, usize)>,
+}
+
+impl ExpandedQuery {
+    /// The file, and the line in it, starting from 1, that the given line of the content, starting
+    /// from 1, comes from, e.g., to report errors in the file that has them.
+    pub fn origin(&self, line: usize) -> Option<(&Path, usize)> {
+        let (path, line) = self.origins.get(line.checked_sub(1)?)?;
+
+        Some((path, *line))
+    }
+}
+
+/// Read the query file at the given path from the provider, expanding its include directives.
+///
+/// An include directive is a comment of its own line, `; include "common.scm"`, so that a query
+/// file that has some is still a valid query. The included file is looked for next to the file
+/// that includes it, and then in the directories of the query files of the languages. Its text
+/// follows the directive, and its own directives are expanded too. A file that is included several
+/// times is only expanded where it is first included.
+///
+/// # Errors
+///
+/// If a file cannot be found or read, a `FormatterError::Io` will be returned. If a file includes
+/// itself, directly or not, a `FormatterError::Query` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use topiary::{expand_query, OsFileProvider, OverlayFileProvider};
+///
+/// let mut files = OverlayFileProvider::new(OsFileProvider);
+/// files.insert("queries/common.scm", "(comment) @leaf\n");
+/// files.insert("queries/ml.scm", "; include \"common.scm\"\n(string) @leaf\n");
+///
+/// let query = expand_query(Path::new("queries/ml.scm"), &files).unwrap();
+///
+/// assert_eq!(
+///     query.content,
+///     "; include \"common.scm\"\n(comment) @leaf\n(string) @leaf\n"
+/// );
+/// assert_eq!(query.origin(2), Some((Path::new("queries/common.scm"), 1)));
+/// assert_eq!(query.origin(3), Some((Path::new("queries/ml.scm"), 2)));
+/// ```
+pub fn expand_query(path: &Path, files: &dyn FileProvider) -> FormatterResult<ExpandedQuery> {
+    let mut query = ExpandedQuery {
+        content: String::new(),
+        origins: Vec::new(),
+    };
+
+    expand(
+        path,
+        files,
+        &mut Vec::new(),
+        &mut HashSet::new(),
+        &mut query,
+    )?;
+
+    Ok(query)
+}
+
+/// Read the query file at the given path from the provider, then the overlays, query files that
+/// are layered over it in order, each with its include directives expanded, as by `expand_query`.
+/// Overlays are looked for as included files are, next to the query file and then in the
+/// directories of the query files of the languages.
+///
+/// Each overlay is preceded by a layer directive, `; layer "compact.scm"`. The patterns that follow
+/// the n-th layer directive of a query have a priority of n by default, rather than 0, so that
+/// the whitespace they add between two leaves prevails over that of the earlier layers, unless a
+/// pattern sets another priority with `#priority!`.
+///
+/// # Errors
+///
+/// If a file cannot be found or read, a `FormatterError::Io` will be returned. If a file includes
+/// itself, directly or not, a `FormatterError::Query` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use topiary::{layer_queries, OsFileProvider, OverlayFileProvider};
+///
+/// let mut files = OverlayFileProvider::new(OsFileProvider);
+/// files.insert("queries/json.scm", "(pair \":\" @append_space)\n");
+/// files.insert("queries/compact.scm", "(pair \":\" @append_antispace)\n");
+///
+/// let query = layer_queries(Path::new("queries/json.scm"), &["compact.scm"], &files).unwrap();
+///
+/// assert_eq!(
+///     query.content,
+///     "(pair \":\" @append_space)\n; layer \"compact.scm\"\n(pair \":\" @append_antispace)\n"
+/// );
+/// assert_eq!(query.origin(3), Some((Path::new("queries/compact.scm"), 1)));
+/// ```
+pub fn layer_queries<S: AsRef<str>>(
+    path: &Path,
+    overlays: &[S],
+    files: &dyn FileProvider,
+) -> FormatterResult<ExpandedQuery> {
+    let mut query = expand_query(path, files)?;
+
+    for overlay in overlays {
+        let name = overlay.as_ref();
+        let overlay = resolve(name, path, files).ok_or_else(|| {
+            FormatterError::Io(IoError::Filesystem(
+                format!(
+                    "Could not find the query file {name}, layered over {}",
+                    path.display()
+                ),
+                io::Error::from(io::ErrorKind::NotFound),
+            ))
+        })?;
+
+        // The layer directive is not in the overlay, but it stands for it
+        query.content.push_str(&format!("; layer \"{name}\"\n"));
+        query.origins.push((overlay.clone(), 1));
+
+        let layer = expand_query(&overlay, files)?;
+        query.content.push_str(&layer.content);
+        query.origins.extend(layer.origins);
+    }
+
+    Ok(query)
+}
+
+/// Whether the line is a layer directive, which `layer_queries` puts before every overlay.
+pub(crate) fn is_layer_directive(line: &str) -> bool {
+    directive(line, "layer").is_some()
+}
+
+/// Append the lines of the file to the query, expanding its include directives. The stack holds
+/// the files that are being expanded, and the set those that have been.
+fn expand(
+    path: &Path,
+    files: &dyn FileProvider,
+    stack: &mut Vec<PathBuf>,
+    included: &mut HashSet<PathBuf>,
+    query: &mut ExpandedQuery,
+) -> FormatterResult<()> {
+    let content = files.read_to_string(path).map_err(|e| {
+        FormatterError::Io(IoError::Filesystem(
+            format!("Could not read query file {}", path.display()),
+            e,
+        ))
+    })?;
+
+    stack.push(path.to_path_buf());
+    included.insert(path.to_path_buf());
+
+    for (index, line) in content.lines().enumerate() {
+        query.content.push_str(line);
+        query.content.push('\n');
+        query.origins.push((path.to_path_buf(), index + 1));
+
+        if let Some(name) = directive(line, "include") {
+            let include = resolve(name, path, files).ok_or_else(|| {
+                FormatterError::Io(IoError::Filesystem(
+                    format!(
+                        "Could not find the query file {name}, included at {}:{}",
+                        path.display(),
+                        index + 1
+                    ),
+                    io::Error::from(io::ErrorKind::NotFound),
+                ))
+            })?;
+
+            if stack.contains(&include) {
+                return Err(FormatterError::Query(
+                    format!(
+                        "{} includes itself, through {}:{}",
+                        include.display(),
+                        path.display(),
+                        index + 1
+                    ),
+                    None,
+                ));
+            }
+
+            if !included.contains(&include) {
+                expand(&include, files, stack, included, query)?;
+            }
+        }
+    }
+
+    stack.pop();
+
+    Ok(())
+}
+
+/// The name of the file of the line, if it is a directive of the given kind, such as `include`.
+fn directive<'a>(line: &'a str, kind: &str) -> Option<&'a str> {
+    let name = line
+        .trim()
+        .strip_prefix(';')?
+        .trim_start()
+        .strip_prefix(kind)?
+        .trim();
+
+    name.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// The path of the included file, next to the file that includes it, or else in the directories of
+/// the query files of the languages.
+fn resolve(name: &str, including: &Path, files: &dyn FileProvider) -> Option<PathBuf> {
+    let next_to = including.parent().map(|dir| dir.join(name));
+
+    next_to
+        .into_iter()
+        .chain(query_search_path().into_iter().map(|dir| dir.join(name)))
+        .find(|path| files.exists(path))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::{expand_query, FormatterError, OsFileProvider, OverlayFileProvider};
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let mut files = OverlayFileProvider::new(OsFileProvider);
+        files.insert("q/a.scm", "; include \"b.scm\"\n");
+        files.insert("q/b.scm", "(string) @leaf\n; include \"a.scm\"\n");
+
+        match expand_query(Path::new("q/a.scm"), &files) {
+            Err(FormatterError::Query(message, None)) => {
+                assert!(message.ends_with("b.scm:2"), "{message}");
+            }
+            result => panic!("Expected a cycle, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let mut files = OverlayFileProvider::new(OsFileProvider);
+        files.insert("q/common.scm", "(comment) @leaf\n");
+        files.insert("q/a.scm", "; include \"common.scm\"\n");
+        files.insert("q/b.scm", "; include \"a.scm\"\n; include \"common.scm\"\n");
+
+        let query = expand_query(Path::new("q/b.scm"), &files).unwrap();
+
+        assert_eq!(query.content.matches("(comment) @leaf").count(), 1);
+        assert_eq!(query.origin(3), Some((Path::new("q/common.scm"), 1)));
+        assert_eq!(query.origin(4), Some((Path::new("q/b.scm"), 2)));
+    }
+}
@@ -0,0 +1,279 @@
+//! Incremental re-formatting, for long-lived tools (e.g., language servers) that repeatedly format
+//! the same document, with small edits in between. The syntax tree of the previous version of the
+//! document is kept around and edited, so Tree-sitter only has to re-parse the affected region.
+
+use std::ops::Range;
+
+use tree_sitter_facade::{InputEdit, Point, Tree};
+
+use crate::{
+    idempotence_check, render_atoms, tree_sitter, FormatterError, FormatterOptions,
+    FormatterResult, Language,
+};
+
+/// An edit to the input of a `Document`: the given byte range of the current input is replaced by
+/// the replacement text.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+/// Describes how the formatted output of a `Document` changed after an edit: the `replaced` byte
+/// range of the previous output corresponds to the `replacement` byte range of the new output.
+/// Everything outside of these ranges is unchanged.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutputEdit {
+    pub replaced: Range<usize>,
+    pub replacement: Range<usize>,
+}
+
+/// A formatted document, which retains its syntax tree so that it can be re-parsed incrementally
+/// after it has been edited.
+///
+/// Note that, while parsing is incremental, the query is still applied to the whole syntax tree:
+/// a change in one place may affect how another part of the document is formatted (e.g., if a
+/// node switches from single-line to multi-line).
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{Configuration, Document, TextEdit};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let mut document =
+///     Document::new(r#"{"a":1}"#.into(), &query, language, &grammar, false, false).unwrap();
+/// assert_eq!(document.output(), "{ \"a\": 1 }\n");
+///
+/// let edit = TextEdit { range: 5..6, replacement: "2".into() };
+/// let change = document
+///     .edit(&[edit], &query, language, &grammar, false, false)
+///     .unwrap();
+/// assert_eq!(document.output(), "{ \"a\": 2 }\n");
+/// assert_eq!(change.replacement, 7..8);
+/// # }) // end tokio_test
+/// ```
+pub struct Document {
+    input: String,
+    tree: Tree,
+    output: String,
+}
+
+impl Document {
+    /// Parse and format the given input.
+    ///
+    /// # Errors
+    ///
+    /// If formatting fails for any reason, a `FormatterError` will be returned.
+    pub fn new(
+        input: String,
+        query: &str,
+        language: &Language,
+        grammar: &tree_sitter_facade::Language,
+        skip_idempotence: bool,
+        tolerate_parsing_errors: bool,
+    ) -> FormatterResult<Self> {
+        let (tree, _) = tree_sitter::parse(&input, grammar, tolerate_parsing_errors, None)?;
+        let output = format_tree(
+            &tree,
+            &input,
+            query,
+            language,
+            grammar,
+            skip_idempotence,
+            tolerate_parsing_errors,
+        )?;
+
+        Ok(Self {
+            input,
+            tree,
+            output,
+        })
+    }
+
+    /// The current input of the document, with all edits applied.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// The formatted output of the current input.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Apply a sequence of edits to the input, then re-parse it incrementally and format it. Each
+    /// edit's range refers to the input as it stands after the preceding edits have been applied.
+    /// Returns the range of the output that changed.
+    ///
+    /// # Errors
+    ///
+    /// If an edit is out of bounds or formatting fails for any reason, a `FormatterError` will be
+    /// returned and the document is left unchanged.
+    pub fn edit(
+        &mut self,
+        edits: &[TextEdit],
+        query: &str,
+        language: &Language,
+        grammar: &tree_sitter_facade::Language,
+        skip_idempotence: bool,
+        tolerate_parsing_errors: bool,
+    ) -> FormatterResult<OutputEdit> {
+        let mut input = self.input.clone();
+        let mut tree = self.tree.clone();
+
+        for edit in edits {
+            tree.edit(&input_edit(&input, edit)?);
+            input.replace_range(edit.range.clone(), &edit.replacement);
+        }
+
+        log::info!("Re-parse input incrementally");
+        let (tree, _) = tree_sitter::parse(&input, grammar, tolerate_parsing_errors, Some(&tree))?;
+        let output = format_tree(
+            &tree,
+            &input,
+            query,
+            language,
+            grammar,
+            skip_idempotence,
+            tolerate_parsing_errors,
+        )?;
+
+        let change = output_edit(&self.output, &output);
+        *self = Self {
+            input,
+            tree,
+            output,
+        };
+
+        Ok(change)
+    }
+}
+
+/// Apply the query to a parsed syntax tree and render the result, checking for idempotence if
+/// requested.
+fn format_tree(
+    tree: &Tree,
+    input: &str,
+    query: &str,
+    language: &Language,
+    grammar: &tree_sitter_facade::Language,
+    skip_idempotence: bool,
+    tolerate_parsing_errors: bool,
+) -> FormatterResult<String> {
+    let options = FormatterOptions::new(query, language, grammar)
+        .tolerate_parsing_errors(tolerate_parsing_errors);
+
+    log::info!("Apply Tree-sitter query");
+    let atoms = tree_sitter::apply_query_to_tree(tree, input, query, grammar, false)?;
+    let output = render_atoms(atoms, language, &options)?;
+
+    if !skip_idempotence {
+        idempotence_check(&output, &options)?;
+    }
+
+    Ok(output)
+}
+
+/// Describe a `TextEdit` against the given input in the terms Tree-sitter requires.
+fn input_edit(input: &str, edit: &TextEdit) -> FormatterResult<InputEdit> {
+    let Range { start, end } = edit.range;
+
+    if input.get(start..end).is_none() {
+        return Err(FormatterError::InvalidRange(edit.range.clone()));
+    }
+
+    let new_prefix = [&input[..start], edit.replacement.as_str()].concat();
+
+    Ok(InputEdit::new(
+        start as u32,
+        end as u32,
+        new_prefix.len() as u32,
+        &point_at(input, start),
+        &point_at(input, end),
+        &point_at(&new_prefix, new_prefix.len()),
+    ))
+}
+
+/// The (0-based) Tree-sitter position of the given byte offset: its row and byte column.
+fn point_at(text: &str, offset: usize) -> Point {
+    let before = &text[..offset];
+    let row = before.matches('\n').count();
+    let column = before.len() - before.rfind('\n').map_or(0, |newline| newline + 1);
+
+    Point::new(row as u32, column as u32)
+}
+
+/// Compute the smallest change between two outputs, by trimming their common prefix and suffix.
+fn output_edit(old: &str, new: &str) -> OutputEdit {
+    // Equal bytes imply identical character boundaries in both strings, so it suffices to
+    // realign the prefix and suffix on the boundaries of the old output.
+    let mut prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(old, new)| old == new)
+        .count();
+    while !old.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+
+    let mut suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(old.len().min(new.len()) - prefix)
+        .take_while(|(old, new)| old == new)
+        .count();
+    while !old.is_char_boundary(old.len() - suffix) {
+        suffix -= 1;
+    }
+
+    OutputEdit {
+        replaced: prefix..old.len() - suffix,
+        replacement: prefix..new.len() - suffix,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{output_edit, OutputEdit};
+
+    #[test]
+    fn output_edit_replacement() {
+        assert_eq!(
+            output_edit("{ \"a\": 1 }\n", "{ \"a\": 23 }\n"),
+            OutputEdit {
+                replaced: 7..8,
+                replacement: 7..9,
+            }
+        );
+    }
+
+    #[test]
+    fn output_edit_unchanged() {
+        assert_eq!(
+            output_edit("foo", "foo"),
+            OutputEdit {
+                replaced: 3..3,
+                replacement: 3..3,
+            }
+        );
+    }
+
+    #[test]
+    fn output_edit_character_boundaries() {
+        // "é" and "ê" share their first byte, which must not be split off
+        assert_eq!(
+            output_edit("aé", "aê"),
+            OutputEdit {
+                replaced: 1..3,
+                replacement: 1..3,
+            }
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! The properties that formatting must have for every syntactically valid input, checked by the
+//! fuzz targets, and reusable in tests: it never panics, it never loses tokens, and it is
+//! idempotent.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+};
+
+use tree_sitter_facade::Node;
+
+use crate::{
+    format,
+    tokens::{tokens_outside, Token},
+    tree_sitter, FormatterError, FormatterOptions,
+};
+
+/// The size beyond which spliced inputs are not grown any further.
+const MAX_SPLICED_BYTES: usize = 1 << 16;
+
+/// A property of formatting that does not hold for an input, as found by `check_invariants`.
+#[derive(Debug)]
+pub enum InvariantViolation {
+    /// Formatting panicked, with the given message
+    Panic(String),
+    /// Formatting failed, e.g., because the input does not parse, or the output does not
+    Error(FormatterError),
+    /// Formatting the output again changes it, as described by the report
+    Idempotence(String),
+    /// A token of the input, i.e., the text of a named leaf node, is not in the output. The
+    /// position of the token in the input starts from 1.
+    LostToken {
+        text: String,
+        line: usize,
+        column: usize,
+    },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Panic(message) => write!(f, "Formatting panicked: {message}"),
+            Self::Error(e) => write!(f, "Formatting failed: {e}"),
+            Self::Idempotence(report) => write!(f, "Formatting is not idempotent. {report}"),
+            Self::LostToken { text, line, column } => write!(
+                f,
+                "The token {text:?}, at line {line}, column {column}, is not in the output"
+            ),
+        }
+    }
+}
+
+/// Format the input with the given options, and check that formatting does not panic, that it
+/// keeps all the tokens of the input, in order, and that it is idempotent, whether or not the
+/// options skip the idempotence check. The input should be syntactically valid.
+///
+/// Tokens are the texts of the named leaf nodes, e.g., identifiers, literals and comments, which,
+/// unlike anonymous nodes, a query can only delete if it lists their kind with `#deletable!`. The
+/// tokens in the nodes of such kinds are left out. Tokens are compared regardless of the whitespace
+/// that they contain, which is reformatted in comments or injected languages.
+///
+/// # Errors
+///
+/// The first property that does not hold is returned as an `InvariantViolation`.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{check_invariants, Configuration, FormatterOptions};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let options = FormatterOptions::new(&query, language, &grammar);
+/// check_invariants(r#"{"a": [1, 2], "b": null}"#, &options).unwrap();
+/// # }) // end tokio_test
+/// ```
+pub fn check_invariants(input: &str, options: &FormatterOptions) -> Result<(), InvariantViolation> {
+    let options = options.clone().skip_idempotence(false);
+
+    let mut output = Vec::new();
+    let formatted = panic::catch_unwind(AssertUnwindSafe(|| {
+        format(&mut input.as_bytes(), &mut output, &options)
+    }));
+
+    match formatted {
+        Ok(Ok(())) => {}
+        Ok(Err(FormatterError::Idempotence(report))) => {
+            return Err(InvariantViolation::Idempotence(report));
+        }
+        Ok(Err(e)) => return Err(InvariantViolation::Error(e)),
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            return Err(InvariantViolation::Panic(message));
+        }
+    }
+
+    let output = String::from_utf8(output)
+        .map_err(|e| InvariantViolation::Error(FormatterError::from(e)))?;
+
+    // The query may delete the nodes that it allows to be deleted
+    let deletable = tree_sitter::deletable_kinds(options.query, options.grammar)
+        .map_err(InvariantViolation::Error)?;
+    let input_tokens =
+        tokens_outside(input, &options, &deletable).map_err(InvariantViolation::Error)?;
+    let output_tokens =
+        tokens_outside(&output, &options, &deletable).map_err(InvariantViolation::Error)?;
+
+    // The tokens of the output are those of the input, in the same order
+    let mut output_tokens = output_tokens.into_iter().map(|token| token.text);
+    for Token { text, line, column } in input_tokens {
+        if output_tokens.next().as_ref() != Some(&text) {
+            return Err(InvariantViolation::LostToken { text, line, column });
+        }
+    }
+
+    Ok(())
+}
+
+/// Seed inputs, e.g., the samples of a language, whose syntax trees are spliced into new inputs
+/// that are syntactically valid, which random data almost never is. A node of an input is replaced
+/// by a node of the same kind from any of the seeds, and the splice is undone if the result does
+/// not parse without errors.
+///
+/// NOTE Inputs are not generated from the rules of the grammar, which are not available at run
+/// time: the node types of a grammar only tell the kinds of the children of its nodes, not their
+/// concrete syntax. Splicing only reaches the inputs that combine the nodes of the seeds.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{Configuration, FormatterOptions, SpliceSeeds};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+/// let options = FormatterOptions::new(&query, language, &grammar);
+///
+/// let seeds = SpliceSeeds::new(vec![r#"{"a": [1, 2]}"#.into(), "[true".into()], &options);
+///
+/// // The second seed does not parse, so the choice of seed wraps around to the first one
+/// assert_eq!(seeds.splice(&[1, 0], &options).unwrap(), r#"{"a": [1, 2]}"#);
+///
+/// // Whatever the nodes that are spliced, the input parses
+/// let input = seeds.splice(&[0, 0, 7, 0, 3, 0, 2, 0, 9, 0], &options).unwrap();
+/// assert!(topiary::check_invariants(&input, &options).is_ok());
+/// # }) // end tokio_test
+/// ```
+pub struct SpliceSeeds {
+    seeds: Vec<String>,
+    /// The seed and the byte range of every named node of the seeds, by kind
+    nodes: HashMap<String, Vec<(usize, Range<usize>)>>,
+}
+
+impl SpliceSeeds {
+    /// The seeds that parse without errors with the grammar of the options, along with their
+    /// nodes; the others are left out.
+    pub fn new(seeds: Vec<String>, options: &FormatterOptions) -> Self {
+        let seeds: Vec<String> = seeds
+            .into_iter()
+            .filter(|seed| named_nodes(seed, options).is_some())
+            .collect();
+
+        let mut nodes: HashMap<String, Vec<(usize, Range<usize>)>> = HashMap::new();
+        for (index, seed) in seeds.iter().enumerate() {
+            for (kind, range) in named_nodes(seed, options).unwrap_or_default() {
+                nodes.entry(kind).or_default().push((index, range));
+            }
+        }
+
+        Self { seeds, nodes }
+    }
+
+    /// Splice an input from the seeds, as the data tells: its first two bytes pick a seed, and
+    /// every four bytes that follow pick a node of the input, and a node of the same kind from the
+    /// seeds that replaces it. Returns `None` if there is no seed.
+    pub fn splice(&self, data: &[u8], options: &FormatterOptions) -> Option<String> {
+        let mut choices = data
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize);
+
+        let mut input = self.seeds.get(choices.next()? % self.seeds.len())?.clone();
+        let mut nodes = named_nodes(&input, options)?;
+
+        while let (Some(target), Some(replacement)) = (choices.next(), choices.next()) {
+            let (kind, range) = match nodes.get(target % nodes.len().max(1)) {
+                Some(node) => node.clone(),
+                None => break,
+            };
+            let (seed, other) = match self.nodes.get(&kind) {
+                Some(candidates) => &candidates[replacement % candidates.len()],
+                None => continue,
+            };
+
+            let mut spliced = input.clone();
+            spliced.replace_range(range, &self.seeds[*seed][other.clone()]);
+            if spliced.len() > MAX_SPLICED_BYTES {
+                continue;
+            }
+
+            if let Some(spliced_nodes) = named_nodes(&spliced, options) {
+                input = spliced;
+                nodes = spliced_nodes;
+            }
+        }
+
+        Some(input)
+    }
+}
+
+/// The kind and the byte range of every named node of the text, in document order, if it parses
+/// without errors.
+fn named_nodes(text: &str, options: &FormatterOptions) -> Option<Vec<(String, Range<usize>)>> {
+    let (tree, _) = tree_sitter::parse(text, options.grammar, false, None).ok()?;
+
+    let mut nodes = Vec::new();
+    collect_named_nodes(&tree.root_node(), &mut nodes);
+
+    Some(nodes)
+}
+
+fn collect_named_nodes(node: &Node, nodes: &mut Vec<(String, Range<usize>)>) {
+    let range = node.start_byte() as usize..node.end_byte() as usize;
+    if node.is_named() && !range.is_empty() {
+        nodes.push((node.kind().to_string(), range));
+    }
+
+    let mut walker = node.walk();
+    for child in node.children(&mut walker) {
+        collect_named_nodes(&child, nodes);
+    }
+}
+
+/// The entry point of the fuzz targets: an input is spliced from the seeds, as the data tells, it
+/// is formatted with the given options, and the invariants of formatting are checked.
+///
+/// # Panics
+///
+/// This function panics if an invariant does not hold, so that the fuzzer reports the data.
+pub fn fuzz_input(data: &[u8], seeds: &SpliceSeeds, options: &FormatterOptions) {
+    let input = match seeds.splice(data, options) {
+        Some(input) => input,
+        None => return,
+    };
+
+    if let Err(violation) = check_invariants(&input, options) {
+        panic!("{violation}\n{input}");
+    }
+}
@@ -0,0 +1,122 @@
+//! The intermediate representation of the formatter: the syntax tree of the input, along with the
+//! stream of atoms produced by the query. It lets query authors and external tools inspect why a
+//! particular space or line break ends up in the output, or post-process the atoms before they
+//! are rendered.
+
+use std::io;
+
+#[cfg(feature = "ir-serde")]
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use tree_sitter_facade::Tree;
+
+#[cfg(feature = "ir-serde")]
+use crate::SyntaxNode;
+use crate::{
+    indentation, prepare_atoms, pretty, query_atoms, read_input, tree_sitter, trim_whitespace,
+    Atom, FormatterError, FormatterOptions, FormatterResult, IoError,
+};
+
+/// The syntax tree of an input, and the atoms into which the query turned it.
+///
+/// The atoms are those that are rendered into the output: they have already been post-processed,
+/// so whitespace atoms have been merged and scoped atoms have been resolved.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{Atom, Configuration, FormatterOptions, IntermediateRepresentation};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+/// let options = FormatterOptions::new(&query, language, &grammar);
+///
+/// let ir = IntermediateRepresentation::new(&mut "[1,2]".as_bytes(), &options).unwrap();
+/// assert_eq!(ir.tree.root_node().kind(), "document");
+/// assert!(ir.atoms.contains(&Atom::Space));
+///
+/// assert_eq!(ir.render(&options).unwrap(), "[ 1, 2 ]\n");
+/// # }) // end tokio_test
+/// ```
+pub struct IntermediateRepresentation {
+    /// The Tree-sitter syntax tree of the input.
+    pub tree: Tree,
+    /// The atoms produced by the query, in output order.
+    pub atoms: Vec<Atom>,
+}
+
+impl IntermediateRepresentation {
+    /// Parse the input and apply the query to it, as given by the options.
+    ///
+    /// # Errors
+    ///
+    /// If the input cannot be parsed, or the query cannot be applied, a `FormatterError` will be
+    /// returned.
+    pub fn new(input: &mut impl io::Read, options: &FormatterOptions) -> FormatterResult<Self> {
+        let content = read_input(input).map_err(|e| {
+            FormatterError::Io(IoError::Filesystem(
+                "Failed to read input contents".into(),
+                e,
+            ))
+        })?;
+        let language = options.effective_language();
+
+        let (tree, _) = tree_sitter::parse(
+            &content,
+            options.grammar,
+            options.tolerate_parsing_errors,
+            None,
+        )?;
+
+        let mut atoms = query_atoms(&tree, &content, options)?;
+        prepare_atoms(&mut atoms, &language, options);
+
+        Ok(Self {
+            tree,
+            atoms: atoms.into_atoms(),
+        })
+    }
+
+    /// Render the atoms into the formatted output, as `format` would, with the indentation and
+    /// line endings given by the options. No idempotence check is performed.
+    ///
+    /// # Errors
+    ///
+    /// If rendering fails for any reason, a `FormatterError` will be returned.
+    pub fn render(&self, options: &FormatterOptions) -> FormatterResult<String> {
+        let language = options.effective_language();
+
+        log::info!("Pretty-print output");
+        let rendered = pretty::render(&self.atoms, indentation(&language))?;
+
+        Ok(options.line_ending.apply(&trim_whitespace(&rendered)))
+    }
+
+    /// Write the intermediate representation as JSON: an object with the syntax tree, in the
+    /// form of the JSON visualisation, and the list of atoms.
+    ///
+    /// # Errors
+    ///
+    /// If the intermediate representation cannot be written, a `FormatterError` will be returned.
+    #[cfg(feature = "ir-serde")]
+    pub fn write_json(&self, output: &mut impl io::Write) -> FormatterResult<()> {
+        serde_json::to_writer(output, self)?;
+        Ok(())
+    }
+}
+
+// The syntax tree is serialised in its simplified form, so it cannot be deserialised again
+#[cfg(feature = "ir-serde")]
+impl Serialize for IntermediateRepresentation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let root: SyntaxNode = self.tree.root_node().into();
+
+        let mut ir = serializer.serialize_struct("IntermediateRepresentation", 2)?;
+        ir.serialize_field("tree", &root)?;
+        ir.serialize_field("atoms", &self.atoms)?;
+        ir.end()
+    }
+}
@@ -1,11 +1,21 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::path::{Path, PathBuf};
 
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
-use crate::{Configuration, FormatterError, FormatterResult, IoError};
+use crate::{
+    layer_queries, modeline::modeline_language, Configuration, ExpandedQuery, FileProvider,
+    FormatterError, FormatterResult, IoError, OsFileProvider,
+};
+
+/// The number of bytes at the start of a file that are read to detect its language, for its
+/// shebang line and modelines, and that are parsed if its extension is shared by several languages.
+const DETECTION_HEAD_BYTES: usize = 1 << 16;
+
+/// The number of bytes at the end of a file that are read for its modelines.
+const DETECTION_TAIL_BYTES: usize = 1 << 12;
 
 /// A Language contains all the information Topiary requires to format that
 /// specific languages.
@@ -18,58 +28,339 @@ pub struct Language {
     /// A Set of the filetype extentions associated with this particular language.
     /// Enables Topiary to pick the right language given an input file
     pub extensions: HashSet<String>,
+    /// A Set of the file names associated with this particular language, for files that are
+    /// not known by their extension, e.g., "Dockerfile". They take precedence over extensions.
+    #[serde(default)]
+    pub filenames: HashSet<String>,
+    /// A Set of the interpreters of the scripts written in this language, e.g., "bash", for
+    /// scripts that are known neither by their file name nor by their extension. They are
+    /// detected by the interpreter of their shebang line, e.g., `#!/usr/bin/env bash`.
+    #[serde(default)]
+    pub interpreters: HashSet<String>,
     /// The indentation string used for that particular language. Defaults to "  "
     /// if not provided. Any string can be provided, but in most instances will be
     /// some whitespace: "  ", "    ", or "\t".
     pub indent: Option<String>,
+    /// The maximum width of lines. If it is provided, custom scopes that would
+    /// not fit in it on a single line are laid out on multiple lines.
+    pub line_width: Option<usize>,
+    /// The query files that are layered over the query file of the language, in order, e.g.,
+    /// "style-compact.scm", so that style variants refine it rather than duplicate it. They are
+    /// looked for next to the query file, and then in the directories of the query files.
+    #[serde(default)]
+    pub overlays: Vec<String>,
+    /// The kinds of the nodes that `format_enclosing_node` formats on their own, e.g.,
+    /// "let_declaration", so that editors can format the statement being typed. Top-level nodes are
+    /// formatted if no such node encloses the cursor.
+    #[serde(default)]
+    pub formattable_nodes: HashSet<String>,
+    /// Settings that query patterns can test with the `#config?` predicate, so that a single
+    /// query file can support several house styles, e.g., `style = "compact"`.
+    #[serde(default)]
+    pub settings: HashMap<String, String>,
 }
 
 impl Language {
-    /// Convenience alias to detect the Language from a Path-like value's extension.
+    /// Convenience alias to detect the Language from the Vim or Emacs modeline of a file, e.g.,
+    /// `vim: ft=ocaml` or `-*- mode: python -*-`, from a Path-like value's file name or extension,
+    /// or else from the interpreter of the shebang line of the file. An extension that is shared
+    /// by several languages gives the one whose grammar parses the file with the fewest errors.
     ///
     /// # Errors
     ///
-    /// If the file extension is not supported, a `FormatterError` will be returned.
+    /// If neither the file name, the file extension nor the interpreter is supported, or if the
+    /// extension is shared by languages that parse the file equally well, a `FormatterError` will
+    /// be returned.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(path = %path.as_ref().display()))
+    )]
     pub fn detect<P: AsRef<Path>>(path: P, config: &Configuration) -> FormatterResult<&Self> {
-        let pb = &path.as_ref().to_path_buf();
-        if let Some(extension) = pb.extension().map(|ext| ext.to_string_lossy()) {
+        Self::detect_in(path, &OsFileProvider, config)
+    }
+
+    /// Like `detect`, but reads the start and the end of the file, for its modelines, its shebang
+    /// line and, if its extension is shared, the content to parse, from the given provider, e.g.,
+    /// to detect the language of a file that is only in memory.
+    ///
+    /// # Errors
+    ///
+    /// If neither the file name, the file extension nor the interpreter is supported, or if the
+    /// extension is shared by languages that parse the file equally well, a `FormatterError` will
+    /// be returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use topiary::{Configuration, Language, OsFileProvider, OverlayFileProvider};
+    ///
+    /// let config = Configuration::parse_default_configuration().unwrap();
+    /// let mut files = OverlayFileProvider::new(OsFileProvider);
+    /// files.insert("scripts/build", "#!/usr/bin/env -S python3 -u\nprint('building')\n");
+    ///
+    /// let language = Language::detect_in("scripts/build", &files, &config).unwrap();
+    /// assert_eq!(language.name, "python");
+    ///
+    /// // An extension shared by several languages gives the one that parses the file best
+    /// let mut config = config;
+    /// for language in &mut config.language {
+    ///     if language.name == "json" || language.name == "toml" {
+    ///         language.extensions.insert("conf".into());
+    ///     }
+    /// }
+    /// files.insert("app.conf", "{\"servers\": [1, 2]}\n");
+    ///
+    /// let language = Language::detect_in("app.conf", &files, &config).unwrap();
+    /// assert_eq!(language.name, "json");
+    /// ```
+    pub fn detect_in<'config, P: AsRef<Path>>(
+        path: P,
+        files: &dyn FileProvider,
+        config: &'config Configuration,
+    ) -> FormatterResult<&'config Self> {
+        let path = path.as_ref();
+        let ends = files
+            .read_ends(path, DETECTION_HEAD_BYTES, DETECTION_TAIL_BYTES)
+            .unwrap_or_default();
+
+        // The authors of the file said what language it is written in
+        let modeline = modeline_language(&ends);
+        if let Some(lang) = modeline.and_then(|mode| Self::detect_mode(mode, config)) {
+            return Ok(lang);
+        }
+
+        Self::detect_by_name(path, &ends.head, config).or_else(|error| {
+            // The file name gives no usable extension
+            let interpreter = ends.head.lines().next().and_then(shebang_interpreter);
+
+            interpreter
+                .and_then(|interpreter| Self::detect_interpreter(interpreter, config))
+                .ok_or(error)
+        })
+    }
+
+    /// Detect the Language whose interpreters include the given one, e.g., "bash" or
+    /// "python3.11". Version numbers at the end of the interpreter need not be listed.
+    pub fn detect_interpreter<'config>(
+        interpreter: &str,
+        config: &'config Configuration,
+    ) -> Option<&'config Self> {
+        let unversioned = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+        config.language.iter().find(|lang| {
+            lang.interpreters.contains(interpreter) || lang.interpreters.contains(unversioned)
+        })
+    }
+
+    /// Detect the Language that a modeline gives, whose name, or one of whose extensions or
+    /// interpreters, is the mode of the modeline, e.g., `ocaml`, `sh` or `python`.
+    fn detect_mode<'config>(mode: &str, config: &'config Configuration) -> Option<&'config Self> {
+        let mode = mode.to_lowercase();
+
+        config.language.iter().find(|lang| {
+            lang.name == mode
+                || lang.extensions.contains(&mode)
+                || lang.interpreters.contains(&mode)
+        })
+    }
+
+    /// Detect the Language from the file name or extension of the path. An extension that is
+    /// shared by several languages gives the one whose grammar parses the start of the file, its
+    /// first `DETECTION_HEAD_BYTES`, with the fewest errors.
+    fn detect_by_name<'config>(
+        path: &Path,
+        content: &str,
+        config: &'config Configuration,
+    ) -> FormatterResult<&'config Self> {
+        let pb = &path.to_path_buf();
+        if let Some(filename) = pb.file_name().map(|name| name.to_string_lossy()) {
             for lang in &config.language {
-                if lang.extensions.contains::<String>(&extension.to_string()) {
+                if lang.filenames.contains::<String>(&filename.to_string()) {
                     return Ok(lang);
                 }
             }
-            return Err(FormatterError::LanguageDetection(
-                pb.clone(),
-                Some(extension.to_string()),
-            ));
+        }
+        if let Some(extension) = pb.extension().map(|ext| ext.to_string_lossy()) {
+            let candidates: Vec<&Self> = config
+                .language
+                .iter()
+                .filter(|lang| lang.extensions.contains::<String>(&extension.to_string()))
+                .collect();
+
+            return match candidates[..] {
+                [] => Err(FormatterError::LanguageDetection(
+                    pb.clone(),
+                    Some(extension.to_string()),
+                )),
+                [lang] => Ok(lang),
+                _ => Self::disambiguate(pb, content, candidates),
+            };
         }
         Err(FormatterError::LanguageDetection(pb.clone(), None))
     }
 
+    /// The candidate Language whose grammar parses the content with the fewest errors. Candidates
+    /// whose grammar is not available are left out.
+    ///
+    /// # Errors
+    ///
+    /// If several candidates parse the content equally well, a `FormatterError::AmbiguousLanguage`
+    /// will be returned, which lists them.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn disambiguate<'config>(
+        path: &Path,
+        content: &str,
+        candidates: Vec<&'config Self>,
+    ) -> FormatterResult<&'config Self> {
+        let mut scores: Vec<(usize, &Self)> = candidates
+            .iter()
+            .filter_map(|lang| {
+                let grammar = lang.static_grammar().ok()?;
+                let (tree, _) = crate::tree_sitter::parse(content, &grammar, true, None).ok()?;
+
+                Some((crate::tree_sitter::error_count(&tree.root_node()), *lang))
+            })
+            .collect();
+        scores.sort_by_key(|(errors, _)| *errors);
+
+        match scores[..] {
+            [(_, lang)] => Ok(lang),
+            [(best, lang), (second, _), ..] if best < second => Ok(lang),
+            [(best, _), ..] => Err(FormatterError::AmbiguousLanguage(
+                path.to_path_buf(),
+                scores
+                    .iter()
+                    .filter(|(errors, _)| *errors == best)
+                    .map(|(_, lang)| lang.name.clone())
+                    .collect(),
+            )),
+            [] => Err(FormatterError::AmbiguousLanguage(
+                path.to_path_buf(),
+                candidates.iter().map(|lang| lang.name.clone()).collect(),
+            )),
+        }
+    }
+
+    /// Grammars are loaded asynchronously in WebAssembly, so the candidates cannot be told apart.
+    #[cfg(target_arch = "wasm32")]
+    fn disambiguate<'config>(
+        path: &Path,
+        _content: &str,
+        candidates: Vec<&'config Self>,
+    ) -> FormatterResult<&'config Self> {
+        Err(FormatterError::AmbiguousLanguage(
+            path.to_path_buf(),
+            candidates.iter().map(|lang| lang.name.clone()).collect(),
+        ))
+    }
+
     /// Convenience alias to return the query file path for the Language.
     pub fn query_file(&self) -> FormatterResult<PathBuf> {
         self.try_into()
     }
 
+    /// Like `query_file`, but looks for the query file among the files of the given provider.
+    pub fn query_file_in(&self, files: &dyn FileProvider) -> FormatterResult<PathBuf> {
+        find_query_file(self, files)
+    }
+
+    /// Read the query file of the Language from the given provider, with the files that it
+    /// includes expanded in place, as by `expand_query`, and its overlays layered over it, as by
+    /// `layer_queries`.
+    ///
+    /// # Errors
+    ///
+    /// If the query file, an overlay, or a file that they include, cannot be found or read, or if
+    /// they include themselves, a `FormatterError` will be returned.
+    pub fn read_query(&self, files: &dyn FileProvider) -> FormatterResult<String> {
+        Ok(self.expand_query(files)?.content)
+    }
+
+    /// Like `read_query`, but keeps track of the file and the line that every line of the query
+    /// comes from.
+    ///
+    /// # Errors
+    ///
+    /// If the query file, an overlay, or a file that they include, cannot be found or read, or if
+    /// they include themselves, a `FormatterError` will be returned.
+    pub fn expand_query(&self, files: &dyn FileProvider) -> FormatterResult<ExpandedQuery> {
+        let query_path = self.query_file_in(files)?;
+
+        layer_queries(&query_path, &self.overlays, files)
+    }
+
     /// Convert a Language into a vector of supported Tree-sitter grammars, ordered by priority.
     ///
     /// Note that, currently, all grammars are statically linked. This will change once dynamic linking
-    /// is implemented (see Issue #4).
+    /// is implemented (see Issue #4). Each grammar is behind a `static-<language>` Cargo feature; if
+    /// that of the Language is disabled, it is unsupported.
     ///
     /// # Errors
     ///
     /// If the language is not supported, a `FormatterError` will be returned.
     #[cfg(not(target_arch = "wasm32"))]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(language = %self.name)))]
     pub async fn grammar(&self) -> FormatterResult<tree_sitter_facade::Language> {
+        self.static_grammar()
+    }
+
+    /// Synchronously load the statically linked Tree-sitter grammar of the Language. This is used
+    /// when formatting injected languages, where we cannot await.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn static_grammar(&self) -> FormatterResult<tree_sitter_facade::Language> {
         Ok(match self.name.as_str() {
+            #[cfg(feature = "static-bash")]
             "bash" => tree_sitter_bash::language(),
+            #[cfg(feature = "static-c")]
+            "c" => tree_sitter_c::language(),
+            #[cfg(feature = "static-css")]
+            "css" => tree_sitter_css::language(),
+            #[cfg(feature = "static-dockerfile")]
+            "dockerfile" => tree_sitter_dockerfile::language(),
+            #[cfg(feature = "static-elixir")]
+            "elixir" => tree_sitter_elixir::language(),
+            #[cfg(feature = "static-graphql")]
+            "graphql" => tree_sitter_graphql::language(),
+            #[cfg(feature = "static-haskell")]
+            "haskell" => tree_sitter_haskell::language(),
+            #[cfg(feature = "static-hcl")]
+            "hcl" => tree_sitter_hcl::language(),
+            #[cfg(feature = "static-html")]
+            "html" => tree_sitter_html::language(),
+            #[cfg(feature = "static-json")]
             "json" => tree_sitter_json::language(),
+            #[cfg(feature = "static-lua")]
+            "lua" => tree_sitter_lua::language(),
+            #[cfg(feature = "static-markdown")]
+            "markdown" => tree_sitter_md::language(),
+            #[cfg(feature = "static-nickel")]
             "nickel" => tree_sitter_nickel::language(),
+            #[cfg(feature = "static-ocaml")]
             "ocaml" => tree_sitter_ocaml::language_ocaml(),
+            #[cfg(feature = "static-ocaml")]
             "ocaml_interface" => tree_sitter_ocaml::language_ocaml_interface(),
+            #[cfg(feature = "static-php")]
+            "php" => tree_sitter_php::language(),
+            #[cfg(feature = "static-protobuf")]
+            "protobuf" => tree_sitter_proto::language(),
+            #[cfg(feature = "static-python")]
+            "python" => tree_sitter_python::language(),
+            #[cfg(feature = "static-rust")]
             "rust" => tree_sitter_rust::language(),
+            #[cfg(feature = "static-scss")]
+            "scss" => tree_sitter_scss::language(),
+            #[cfg(feature = "static-sql")]
+            "sql" => tree_sitter_sequel::language(),
+            #[cfg(feature = "static-toml")]
             "toml" => tree_sitter_toml::language(),
+            #[cfg(feature = "static-tree-sitter-query")]
             "tree_sitter_query" => tree_sitter_query::language(),
+            #[cfg(feature = "static-xml")]
+            "xml" => tree_sitter_xml::language_xml(),
+            #[cfg(feature = "static-yaml")]
+            "yaml" => tree_sitter_yaml::language(),
+            #[cfg(feature = "static-zig")]
+            "zig" => tree_sitter_zig::language(),
             name => return Err(FormatterError::UnsupportedLanguage(name.to_string())),
         }
         .into())
@@ -101,6 +392,25 @@ impl Language {
     }
 }
 
+/// The name of the interpreter of a shebang line, e.g., `python3` for `#!/usr/bin/python3`, or
+/// for `#!/usr/bin/env -S python3 -u`, where the interpreter is looked up by `env`.
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    fn file_name(path: &str) -> &str {
+        path.rsplit('/').next().unwrap_or(path)
+    }
+
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let interpreter = file_name(words.next()?);
+    if interpreter != "env" {
+        return Some(interpreter);
+    }
+
+    // The options of env, and the variables that it sets, come before the interpreter
+    words
+        .find(|word| !word.starts_with('-') && !word.contains('='))
+        .map(file_name)
+}
+
 /// Convert a Language into the canonical basename of its query file, under the most appropriate
 /// search path. We test 3 different locations for query files, in the following priority order,
 /// returning the first that exists:
@@ -117,53 +427,99 @@ impl TryFrom<&Language> for PathBuf {
     type Error = FormatterError;
 
     fn try_from(language: &Language) -> FormatterResult<Self> {
-        let basename = Self::from(match language.name.as_str() {
-            "bash" => "bash",
-            "json" => "json",
-            "nickel" => "nickel",
-            "ocaml" | "ocaml_interface" => "ocaml",
-            "rust" => "rust",
-            "toml" => "toml",
-            "tree_sitter_query" => "tree-sitter-query",
-            name => return Err(FormatterError::UnsupportedLanguage(name.to_string())),
-        })
-        .with_extension("scm");
-
-        #[rustfmt::skip]
-        let potentials: [Option<Self>; 4] = [
-            std::env::var("TOPIARY_LANGUAGE_DIR").map(Self::from).ok(),
-            option_env!("TOPIARY_LANGUAGE_DIR").map(Self::from),
-            Some(Self::from("./languages")),
-            Some(Self::from("../languages")),
-        ];
-
-        potentials
-            .into_iter()
-            .flatten()
-            .map(|path| path.join(&basename))
-            .find(|path| path.exists())
-            .ok_or_else(|| {
-                FormatterError::Io(IoError::Filesystem(
-                    "Language query file could not be found".into(),
-                    io::Error::from(io::ErrorKind::NotFound),
-                ))
-            })
+        find_query_file(language, &OsFileProvider)
     }
 }
 
-/// Topiary can format more languages than are actually "supported".
-/// This enum is an enumeration of those we (the maintainers) are comfortable in
-/// calling "supported".
-/// Any other entries in crate::Language are experimental and won't be
-/// exposed in the CLI. They can be accessed using --query language/foo.scm
-/// instead.
+/// The directories in which the query files of the languages are looked for, in order.
+pub(crate) fn query_search_path() -> Vec<PathBuf> {
+    #[rustfmt::skip]
+    let potentials: [Option<PathBuf>; 4] = [
+        std::env::var("TOPIARY_LANGUAGE_DIR").map(PathBuf::from).ok(),
+        option_env!("TOPIARY_LANGUAGE_DIR").map(PathBuf::from),
+        Some(PathBuf::from("./languages")),
+        Some(PathBuf::from("../languages")),
+    ];
+
+    potentials.into_iter().flatten().collect()
+}
+
+/// Find the query file of the language, as described above, among the files of the provider.
+fn find_query_file(language: &Language, files: &dyn FileProvider) -> FormatterResult<PathBuf> {
+    let basename = PathBuf::from(match language.name.as_str() {
+        "bash" => "bash",
+        "c" => "c",
+        "css" => "css",
+        "dockerfile" => "dockerfile",
+        "elixir" => "elixir",
+        "graphql" => "graphql",
+        "haskell" => "haskell",
+        "hcl" => "hcl",
+        "html" => "html",
+        "json" => "json",
+        "lua" => "lua",
+        "markdown" => "markdown",
+        "nickel" => "nickel",
+        "ocaml" | "ocaml_interface" => "ocaml",
+        "php" => "php",
+        "protobuf" => "protobuf",
+        "python" => "python",
+        "rust" => "rust",
+        "scss" => "scss",
+        "sql" => "sql",
+        "toml" => "toml",
+        "tree_sitter_query" => "tree-sitter-query",
+        "xml" => "xml",
+        "yaml" => "yaml",
+        "zig" => "zig",
+        name => return Err(FormatterError::UnsupportedLanguage(name.to_string())),
+    })
+    .with_extension("scm");
+
+    query_search_path()
+        .into_iter()
+        .map(|path| path.join(&basename))
+        .find(|path| files.exists(path))
+        .ok_or_else(|| {
+            FormatterError::Io(IoError::Filesystem(
+                "Language query file could not be found".into(),
+                io::Error::from(io::ErrorKind::NotFound),
+            ))
+        })
+}
+
+/// The languages of the built-in configuration, which can be chosen in the CLI.
+/// Topiary can format more languages than are actually "supported": only those
+/// that `is_supported` are the ones we (the maintainers) are comfortable in
+/// calling "supported". The others are experimental.
 #[derive(Clone, Copy, Debug, ValueEnum)]
 pub enum SupportedLanguage {
+    Bash,
+    C,
+    Css,
+    Dockerfile,
+    Elixir,
+    Graphql,
+    Haskell,
+    Hcl,
+    Html,
     Json,
+    Lua,
+    Markdown,
     Nickel,
     Ocaml,
     OcamlInterface,
+    Php,
+    Protobuf,
+    Python,
+    Rust,
+    Scss,
+    Sql,
     Toml,
+    TreeSitterQuery,
+    Xml,
+    Yaml,
+    Zig,
 }
 
 impl SupportedLanguage {
@@ -184,15 +540,44 @@ impl SupportedLanguage {
 
     pub fn name(&self) -> &str {
         match self {
+            SupportedLanguage::Bash => "bash",
+            SupportedLanguage::C => "c",
+            SupportedLanguage::Css => "css",
+            SupportedLanguage::Dockerfile => "dockerfile",
+            SupportedLanguage::Elixir => "elixir",
+            SupportedLanguage::Graphql => "graphql",
+            SupportedLanguage::Haskell => "haskell",
+            SupportedLanguage::Hcl => "hcl",
+            SupportedLanguage::Html => "html",
             SupportedLanguage::Json => "json",
+            SupportedLanguage::Lua => "lua",
+            SupportedLanguage::Markdown => "markdown",
             SupportedLanguage::Nickel => "nickel",
             SupportedLanguage::Ocaml => "ocaml",
             SupportedLanguage::OcamlInterface => "ocaml_interface",
+            SupportedLanguage::Php => "php",
+            SupportedLanguage::Protobuf => "protobuf",
+            SupportedLanguage::Python => "python",
+            SupportedLanguage::Rust => "rust",
+            SupportedLanguage::Scss => "scss",
+            SupportedLanguage::Sql => "sql",
             SupportedLanguage::Toml => "toml",
+            SupportedLanguage::TreeSitterQuery => "tree_sitter_query",
+            SupportedLanguage::Xml => "xml",
+            SupportedLanguage::Yaml => "yaml",
+            SupportedLanguage::Zig => "zig",
         }
     }
 
+    /// Whether the language of the given name is supported, rather than experimental.
     pub fn is_supported(name: &str) -> bool {
-        SupportedLanguage::from_str(name, true).is_ok()
+        matches!(
+            SupportedLanguage::from_str(name, true),
+            Ok(SupportedLanguage::Json
+                | SupportedLanguage::Nickel
+                | SupportedLanguage::Ocaml
+                | SupportedLanguage::OcamlInterface
+                | SupportedLanguage::Toml)
+        )
     }
 }
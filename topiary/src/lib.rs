@@ -10,25 +10,104 @@
 //! More details can be found on
 //! [GitHub](https://github.com/tweag/topiary).
 
-use std::io;
+use std::{cell::OnceCell, io, ops::Range, time::Duration};
 
 use itertools::Itertools;
 use pretty_assertions::StrComparison;
+#[cfg(feature = "ir-serde")]
+use serde::{Deserialize, Serialize};
+use tree_sitter_facade::Tree;
+use unicode_width::UnicodeWidthStr;
 
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+pub use crate::async_formatter::formatter_async;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::coverage::{query_coverage, PatternCoverage, QueryCoverage};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::lint::{lint_query, QueryLint, QueryLintKind};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::metrics::format_with_metrics;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::profile::{profile_query, PatternProfile, QueryProfile};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::query_diff::diff_queries;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::registry::{LanguageRegistry, LoadedLanguage};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::skeleton::skeleton_query;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::snapshot::{corpus_tests, snapshot_tests, SnapshotOutcome, SnapshotTest, Symlinks};
+use crate::{
+    atom_collection::AtomCollection,
+    metrics::{measure, CountingWriter},
+    options::{LineEndingWriter, BYTE_ORDER_MARK},
+};
 pub use crate::{
+    cancellation::CancellationToken,
+    captures::{closest_capture, find_capture, validate_query, Capture, UnknownCapture, CAPTURES},
     configuration::{default_configuration_toml, Configuration},
-    error::{FormatterError, IoError},
+    dump::dump_atoms,
+    error::{source_chain, ErrorCategory, FormatterError, IoError},
+    events::{format_to_events, Event, EventSink},
+    files::{Ends, FileProvider, OsFileProvider, OverlayFileProvider},
+    include::{expand_query, layer_queries, ExpandedQuery},
+    incremental::{Document, OutputEdit, TextEdit},
+    invariants::{check_invariants, fuzz_input, InvariantViolation, SpliceSeeds},
+    ir::IntermediateRepresentation,
     language::{Language, SupportedLanguage},
+    metrics::FormatMetrics,
+    offset::map_offsets,
+    options::{ByteOrderMark, FormatterOptions, Hook, LineEnding},
+    pragma::{skips_file, SKIP_FILE_PRAGMA},
+    range::{format_enclosing_node, format_range, NodeReplacement, RangeReplacement},
+    source_map::{format_with_source_map, LeafSpan, SourceMap},
     tree_sitter::{apply_query, SyntaxNode, Visualisation},
+    warning::Warning,
 };
+pub use encoding_rs::Encoding;
 
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+mod async_formatter;
 mod atom_collection;
+mod cancellation;
+mod captures;
 mod configuration;
+#[cfg(not(target_arch = "wasm32"))]
+mod coverage;
+mod dump;
+mod encoding;
 mod error;
+mod events;
+mod files;
 mod graphviz;
+mod include;
+mod incremental;
+mod invariants;
+mod ir;
 mod language;
+#[cfg(not(target_arch = "wasm32"))]
+mod lint;
+mod metrics;
+mod modeline;
+mod offset;
+mod options;
+mod pragma;
 mod pretty;
+#[cfg(not(target_arch = "wasm32"))]
+mod profile;
+#[cfg(not(target_arch = "wasm32"))]
+mod query_diff;
+mod range;
+#[cfg(not(target_arch = "wasm32"))]
+mod registry;
+#[cfg(not(target_arch = "wasm32"))]
+mod skeleton;
+#[cfg(not(target_arch = "wasm32"))]
+mod snapshot;
+mod source_map;
+mod tokens;
 mod tree_sitter;
+mod warning;
 
 #[doc(hidden)]
 pub mod test_utils;
@@ -37,7 +116,11 @@ pub mod test_utils;
 /// into atoms, and we add white-space atoms where appropriate. The final list
 /// of atoms is rendered to the output.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "ir-serde", derive(Deserialize, Serialize))]
 pub enum Atom {
+    /// Pads the line with spaces, so that what follows starts at the same column as what follows
+    /// the `Align` atoms of the same indentation level on the lines just before and after.
+    Align,
     /// We don't allow consecutive `Hardline`, but a `Blankline` will render two
     /// newlines to produce a blank line.
     Blankline,
@@ -60,6 +143,10 @@ pub enum Atom {
         id: usize,
         // marks the leaf to be printed on a single line, with no indentation
         single_line_no_indent: bool,
+        // marks the leaf's content as formatted in an injected language, or
+        // captured by @reindent, whose lines must be indented to the
+        // surrounding indentation level
+        injected: bool,
     },
     /// Represents a literal string, such as a semicolon.
     Literal(String),
@@ -103,6 +190,7 @@ pub enum Atom {
 /// Used in `Atom::ScopedConditional` to apply the containing Atoms only if
 /// the matched node spans a single line or multiple lines
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ir-serde", derive(Deserialize, Serialize))]
 pub enum ScopeCondition {
     /// The Atom is only applied if the matching node spans exactly one line
     SingleLineOnly,
@@ -126,7 +214,8 @@ pub enum Operation {
         /// and continues formatting instead of exiting with an error
         tolerate_parsing_errors: bool,
     },
-    /// Visualises the parsed file's tree-sitter tree
+    /// Visualises the parsed file's tree-sitter tree, annotated with the captures that the query
+    /// applied to every node and with the atoms that were emitted around it
     Visualise {
         /// Choose the type of visualation Topiary should ouput
         output_format: Visualisation,
@@ -135,6 +224,10 @@ pub enum Operation {
 
 /// The function that takes an input and formats, or visualises an output.
 ///
+/// The input is always read in full, as Tree-sitter parses whole documents. When the idempotence
+/// check is skipped, the formatted output is written to `output` as it is rendered, rather than
+/// being built up in memory first.
+///
 /// # Errors
 ///
 /// If formatting fails for any reason, a `FormatterError` will be returned.
@@ -194,33 +287,27 @@ pub fn formatter(
             skip_idempotence,
             tolerate_parsing_errors,
         } => {
-            // All the work related to tree-sitter and the query is done here
-            log::info!("Apply Tree-sitter query");
-            let mut atoms =
-                tree_sitter::apply_query(&content, query, grammar, tolerate_parsing_errors, false)?;
-
-            // Various post-processing of whitespace
-            atoms.post_process();
-
-            // Pretty-print atoms
-            log::info!("Pretty-print output");
-            let rendered = pretty::render(
-                &atoms[..],
-                // Default to "  " is the language has no indentation specified
-                language.indent.as_ref().map_or("  ", |v| v.as_str()),
-            )?;
-            let trimmed = trim_whitespace(&rendered);
-
-            if !skip_idempotence {
-                idempotence_check(&trimmed, query, language, grammar, tolerate_parsing_errors)?;
-            }
+            let options = FormatterOptions::new(query, language, grammar)
+                .skip_idempotence(skip_idempotence)
+                .tolerate_parsing_errors(tolerate_parsing_errors);
 
-            write!(output, "{trimmed}")?;
+            format_content(&content, output, &options, None)?;
         }
 
         Operation::Visualise { output_format } => {
-            let (tree, _) = tree_sitter::parse(&content, grammar, false)?;
-            let root: SyntaxNode = tree.root_node().into();
+            let (tree, _) = tree_sitter::parse(&content, grammar, false, None)?;
+            let mut root: SyntaxNode = tree.root_node().into();
+
+            // The tree is annotated with what the query does to it. It is still visualised when
+            // the query cannot be applied, as it is especially useful to write the query.
+            let options = FormatterOptions::new(query, language, grammar);
+            match query_atoms(&tree, &content, &options) {
+                Ok(mut atoms) => {
+                    prepare_atoms(&mut atoms, language, &options);
+                    root.annotate(&atoms);
+                }
+                Err(e) => log::warn!("The syntax tree is not annotated, as the query failed: {e}"),
+            }
 
             match output_format {
                 Visualisation::GraphViz => graphviz::write(output, &root)?,
@@ -232,6 +319,408 @@ pub fn formatter(
     Ok(())
 }
 
+/// Format the input with the given options. Unlike `formatter`, new options can be added to
+/// `FormatterOptions` without changing the signature of this function.
+///
+/// The input is always read in full, as Tree-sitter parses whole documents. When the idempotence
+/// check is skipped, the formatted output is written to `output` as it is rendered, rather than
+/// being built up in memory first.
+///
+/// The input is decoded from the encoding of the options, or that of its byte order mark, and the
+/// output is written in the same encoding. An input that has the `topiary: skip-file` pragma in
+/// one of its first lines, as `skips_file` tells, is written out as it is, without being parsed.
+///
+/// # Errors
+///
+/// If formatting fails for any reason, a `FormatterError` will be returned.
+pub fn format(
+    input: &mut impl io::Read,
+    output: &mut impl io::Write,
+    options: &FormatterOptions,
+) -> FormatterResult<()> {
+    format_encoded(&read_bytes(input)?, output, options, None)
+}
+
+/// Format the input, given as the bytes of its encoding, as `format` does. Unless pre-processing
+/// hooks rewrite it, or it is decoded from another encoding than UTF-8, the input is parsed and
+/// formatted where it is, without a copy of it being made, so that large inputs, e.g. mapped in
+/// memory, are not held in memory twice.
+///
+/// # Errors
+///
+/// If formatting fails for any reason, a `FormatterError` will be returned.
+pub fn format_bytes(
+    input: &[u8],
+    output: &mut impl io::Write,
+    options: &FormatterOptions,
+) -> FormatterResult<()> {
+    format_encoded(input, output, options, None)
+}
+
+/// Read the whole input, as bytes.
+fn read_bytes(input: &mut impl io::Read) -> FormatterResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes).map_err(|e| {
+        FormatterError::Io(IoError::Filesystem(
+            "Failed to read input contents".into(),
+            e,
+        ))
+    })?;
+
+    Ok(bytes)
+}
+
+/// Decode the input from its encoding, format it, and write the output in the same encoding.
+/// Outputs in other encodings than UTF-8 are held in memory, to be encoded in full.
+fn format_encoded(
+    input: &[u8],
+    output: &mut impl io::Write,
+    options: &FormatterOptions,
+    metrics: Option<&mut FormatMetrics>,
+) -> FormatterResult<()> {
+    let (content, encoding) = encoding::decode(input, options.encoding)?;
+
+    if skips_file(&content) {
+        log::info!("Leaving the input as it is, as it has a `{SKIP_FILE_PRAGMA}` pragma");
+        output.write_all(input)?;
+
+        return Ok(());
+    }
+
+    if encoding == encoding_rs::UTF_8 {
+        return format_content(&content, output, options, metrics);
+    }
+
+    let mut formatted = Vec::new();
+    format_content(&content, &mut formatted, options, metrics)?;
+    output.write_all(&encoding::encode(&String::from_utf8(formatted)?, encoding)?)?;
+
+    Ok(())
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        skip_all,
+        fields(language = %options.language.name, bytes = content.len())
+    )
+)]
+fn format_content(
+    content: &str,
+    output: &mut impl io::Write,
+    options: &FormatterOptions,
+    mut metrics: Option<&mut FormatMetrics>,
+) -> FormatterResult<()> {
+    // The timeout starts now, and its deadline is shared with the idempotence check
+    let mut timed_options;
+    let options = match options.timeout {
+        Some(timeout) => {
+            timed_options = options.clone();
+            let token = options.cancellation.clone().unwrap_or_default();
+            timed_options.cancellation = Some(token.with_timeout(timeout));
+            timed_options.timeout = None;
+            &timed_options
+        }
+        None => options,
+    };
+
+    // The query is compiled once, for both the input and the idempotence check
+    let compiled_query;
+    let mut shared_options;
+    let options = match options.compiled_query {
+        Some(_) => options,
+        None => {
+            compiled_query = measure(metrics.as_deref_mut().map(|m| &mut m.query_time), || {
+                tree_sitter::compile_query(options.query, options.grammar)
+            })?;
+            shared_options = options.clone();
+            shared_options.compiled_query = Some(&compiled_query);
+            &shared_options
+        }
+    };
+
+    let input = content;
+    let language = options.effective_language();
+    let (content, byte_order_mark) = options.pre_processed(input)?;
+    let byte_order_mark = if byte_order_mark { BYTE_ORDER_MARK } else { "" };
+    let token = options.cancellation.as_ref();
+    cancellation::check(token)?;
+
+    // All the work related to tree-sitter and the query is done here
+    let (tree, _) = measure(metrics.as_deref_mut().map(|m| &mut m.parse_time), || {
+        tree_sitter::parse(
+            &content,
+            options.grammar,
+            options.tolerate_parsing_errors,
+            None,
+        )
+    })?;
+    let mut atoms = measure(metrics.as_deref_mut().map(|m| &mut m.query_time), || {
+        query_atoms(&tree, &content, options)
+    })?;
+    measure(
+        metrics.as_deref_mut().map(|m| &mut m.post_processing_time),
+        || prepare_atoms(&mut atoms, &language, options),
+    );
+    cancellation::check(token)?;
+    // The warnings of post-processing are logged as they are found
+    if let Some(callback) = &options.on_warning {
+        for warning in &atoms.warnings {
+            callback(warning);
+        }
+    }
+
+    let mut output = CountingWriter::new(output);
+
+    // Over the memory budget, the output is not kept in memory to be checked
+    let over_budget = matches!(options.memory_budget, Some(budget) if input.len() > budget);
+    if over_budget
+        && !(options.skip_idempotence && !options.verify_tokens && !options.verify_comments)
+    {
+        options.warn(Warning::UncheckedOutput);
+    }
+    let skip_idempotence = options.skip_idempotence || over_budget;
+    let verify_tokens = options.verify_tokens && !over_budget;
+    let verify_comments = options.verify_comments && !over_budget;
+
+    if skip_idempotence
+        && !verify_tokens
+        && !verify_comments
+        && options.post_processing_hooks.is_empty()
+    {
+        // Without the idempotence check, the output need not be held in memory
+        write!(output, "{byte_order_mark}")?;
+        let mut output = LineEndingWriter::new(&mut output, options.line_ending);
+        measure(metrics.as_deref_mut().map(|m| &mut m.render_time), || {
+            render_prepared_atoms_to(&atoms, &language, &mut output)
+        })?;
+    } else {
+        let rendered = measure(metrics.as_deref_mut().map(|m| &mut m.render_time), || {
+            render_prepared_atoms(&atoms, &language)
+        })?;
+        if verify_tokens {
+            tokens::check_tokens(&content, &rendered, options)?;
+        }
+        if verify_comments {
+            tokens::check_comments(&content, &rendered, options)?;
+        }
+        let trimmed = options.post_processed(rendered)?;
+
+        if !skip_idempotence {
+            if input.strip_prefix(byte_order_mark) == Some(trimmed.as_str()) {
+                // Formatting is deterministic, so an output that is identical to the input is
+                // formatted into itself again: the second pass would only repeat the first one
+                log::info!("Skipping the idempotence check, as the input is already formatted");
+            } else {
+                idempotence_check(&trimmed, options)?;
+            }
+        }
+
+        write!(
+            output,
+            "{byte_order_mark}{}",
+            options.line_ending.apply(&trimmed)
+        )?;
+    }
+
+    if let Some(metrics) = metrics {
+        metrics.query_matches = atoms.match_count;
+        metrics.atoms = atoms[..].len();
+        metrics.output_bytes = output.count;
+        metrics.idempotence_checked = !skip_idempotence;
+    }
+
+    Ok(())
+}
+
+/// Apply the query of the options to the syntax tree of the content, reusing the compiled query
+/// if the options have one.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(bytes = content.len())))]
+fn query_atoms(
+    tree: &Tree,
+    content: &str,
+    options: &FormatterOptions,
+) -> FormatterResult<AtomCollection> {
+    log::info!("Apply Tree-sitter query");
+
+    let compiled_query;
+    let query = match options.compiled_query {
+        Some(query) => query,
+        None => {
+            compiled_query = tree_sitter::compile_query(options.query, options.grammar)?;
+            &compiled_query
+        }
+    };
+
+    tree_sitter::apply_compiled_query_to_tree(
+        tree,
+        content,
+        query,
+        options.query,
+        &options.language.settings,
+        options.cancellation.as_ref(),
+        options.trace_queries.as_ref(),
+    )
+}
+
+/// Post-process the atoms produced by the query, readying them to be pretty-printed. The regions
+/// of the input that are written in another language are formatted as the options tell.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+fn prepare_atoms(atoms: &mut AtomCollection, language: &Language, options: &FormatterOptions) {
+    // Format the regions of the input that are written in another language
+    let injections = Injections::new(options);
+    atoms.format_injections(|language, content| injections.format(language, content));
+
+    // Various post-processing of whitespace
+    atoms.post_process(language.line_width, indentation(language).width());
+}
+
+/// Post-process the atoms produced by the query, then pretty-print them into the final output.
+fn render_atoms(
+    mut atoms: AtomCollection,
+    language: &Language,
+    options: &FormatterOptions,
+) -> FormatterResult<String> {
+    prepare_atoms(&mut atoms, language, options);
+    render_prepared_atoms(&atoms, language)
+}
+
+/// Pretty-print atoms that have been post-processed by `prepare_atoms` into the final output.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+fn render_prepared_atoms(atoms: &AtomCollection, language: &Language) -> FormatterResult<String> {
+    // Pretty-print atoms
+    log::info!("Pretty-print output");
+    let rendered = pretty::render(&atoms[..], indentation(language))?;
+
+    Ok(trim_whitespace(&rendered))
+}
+
+/// Like `render_prepared_atoms`, but writes the final output directly into the given writer.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+fn render_prepared_atoms_to(
+    atoms: &AtomCollection,
+    language: &Language,
+    output: &mut impl io::Write,
+) -> FormatterResult<()> {
+    log::info!("Pretty-print output");
+    pretty::render_trimmed_to(&atoms[..], indentation(language), output)
+}
+
+/// Like `render_atoms`, but additionally returns where each leaf of the input ended up in the
+/// output, in order. Leaves that were deleted are omitted.
+fn render_atoms_with_spans(
+    mut atoms: AtomCollection,
+    language: &Language,
+    options: &FormatterOptions,
+) -> FormatterResult<(String, Vec<LeafSpan>)> {
+    prepare_atoms(&mut atoms, language, options);
+
+    log::info!("Pretty-print output");
+    let (rendered, leaves) = pretty::render_with_leaves(&atoms[..], indentation(language))?;
+
+    let mut output_ranges: Vec<Range<usize>> =
+        leaves.iter().map(|(_, range)| range.clone()).collect();
+    let trimmed = trim_whitespace_mapping_ranges(&rendered, &mut output_ranges);
+
+    let spans = leaves
+        .into_iter()
+        .zip(output_ranges)
+        .filter_map(|((id, _), output)| {
+            atoms
+                .leaf_input_range(id)
+                .map(|input| LeafSpan { input, output })
+        })
+        .collect();
+
+    Ok((trimmed, spans))
+}
+
+/// Format the input, like `Operation::Format`, additionally returning its syntax tree and where
+/// each of its leaves ended up in the output.
+fn format_with_spans(
+    content: &str,
+    options: &FormatterOptions,
+) -> FormatterResult<(Tree, String, Vec<LeafSpan>)> {
+    let language = options.effective_language();
+    let (tree, _) = tree_sitter::parse(
+        content,
+        options.grammar,
+        options.tolerate_parsing_errors,
+        None,
+    )?;
+
+    let atoms = query_atoms(&tree, content, options)?;
+    let (output, spans) = render_atoms_with_spans(atoms, &language, options)?;
+
+    if !options.skip_idempotence {
+        idempotence_check(&output, options)?;
+    }
+
+    Ok((tree, output, spans))
+}
+
+/// The indentation string of the language. Defaults to "  " if the language has no indentation
+/// specified.
+fn indentation(language: &Language) -> &str {
+    language.indent.as_deref().unwrap_or("  ")
+}
+
+/// The languages injected in an input, which are loaded once per input, from the registry of the
+/// options if any, or else from the built-in configuration, with the query files on disk.
+struct Injections<'a> {
+    registry: Option<&'a LanguageRegistry>,
+    // The registry of the built-in configuration, created for the first injected region
+    default_registry: OnceCell<LanguageRegistry>,
+    cancellation: Option<&'a CancellationToken>,
+    timeout: Option<Duration>,
+}
+
+impl<'a> Injections<'a> {
+    fn new(options: &'a FormatterOptions) -> Self {
+        Self {
+            registry: options.injections,
+            default_registry: OnceCell::new(),
+            cancellation: options.cancellation.as_ref(),
+            timeout: options.timeout,
+        }
+    }
+
+    /// Format the content of a region of the input, written in the given injected language, with
+    /// the query file of this language. Like the whole input, it is formatted until the token of
+    /// the options is cancelled, or their timeout is reached.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn format(&self, language_name: &str, content: &str) -> FormatterResult<String> {
+        let registry = match (self.registry, self.default_registry.get()) {
+            (Some(registry), _) | (None, Some(registry)) => registry,
+            (None, None) => {
+                let configuration = Configuration::parse_default_configuration()?;
+                self.default_registry
+                    .get_or_init(|| LanguageRegistry::new(configuration))
+            }
+        };
+        let loaded = registry.get_injected(language_name)?;
+
+        let mut options = loaded
+            .options()
+            // The idempotence of the injected region is covered by that of the whole input
+            .skip_idempotence(true)
+            .injections(registry);
+        options.cancellation = self.cancellation.cloned();
+        options.timeout = self.timeout;
+
+        let mut output = Vec::new();
+        format_content(content, &mut output, &options, None)?;
+
+        Ok(String::from_utf8(output)?)
+    }
+
+    // Grammars are loaded asynchronously in the browser, so they cannot be loaded mid-formatting
+    #[cfg(target_arch = "wasm32")]
+    fn format(&self, language_name: &str, _content: &str) -> FormatterResult<String> {
+        Err(FormatterError::UnsupportedLanguage(language_name.into()))
+    }
+}
+
 /// Simple helper function to read the full content of an io Read stream
 fn read_input(input: &mut dyn io::Read) -> Result<String, io::Error> {
     let mut content = String::new();
@@ -246,6 +735,52 @@ fn trim_whitespace(s: &str) -> String {
     format!("{}\n", s.lines().map(str::trim_end).join("\n").trim())
 }
 
+/// Trim whitespace exactly like `trim_whitespace`, additionally mapping the given byte ranges of
+/// the untrimmed string onto the trimmed one.
+fn trim_whitespace_mapping_ranges(s: &str, ranges: &mut [Range<usize>]) -> String {
+    // The byte ranges of the string that survive trimming the whitespace from the end of each
+    // line, in order
+    let mut kept: Vec<Range<usize>> = Vec::new();
+    let mut line_start = 0;
+    for line in s.split_inclusive('\n') {
+        kept.push(line_start..line_start + line.trim_end().len());
+        if line.ends_with('\n') {
+            kept.push(line_start + line.len() - 1..line_start + line.len());
+        }
+        line_start += line.len();
+    }
+
+    let lines_trimmed: String = kept.iter().map(|range| &s[range.clone()]).collect();
+    let leading = lines_trimmed.len() - lines_trimmed.trim_start().len();
+    let content = lines_trimmed.trim();
+
+    // The offset at which each of the kept ranges starts, once they are concatenated
+    let offsets: Vec<usize> = kept
+        .iter()
+        .scan(0, |offset, range| {
+            let start = *offset;
+            *offset += range.len();
+            Some(start)
+        })
+        .collect();
+
+    // Positions that fall into trimmed whitespace are moved forward, to the next kept byte
+    let map = |position: usize| {
+        let index = kept.partition_point(|range| range.end <= position);
+        let offset = kept.get(index).map_or(lines_trimmed.len(), |range| {
+            offsets[index] + position.saturating_sub(range.start)
+        });
+
+        offset.saturating_sub(leading).min(content.len())
+    };
+
+    for range in ranges.iter_mut() {
+        *range = map(range.start)..map(range.end);
+    }
+
+    format!("{content}\n")
+}
+
 /// Perform the idempotence check. Given the already formatted content of the
 /// file, formats the content again and checks if the two are identical.
 /// Result in: `Ok(())`` if the idempotence check succeeded (the content is
@@ -253,45 +788,42 @@ fn trim_whitespace(s: &str) -> String {
 ///
 /// # Errors
 ///
-/// `Err(FormatterError::Idempotence)` if the idempotence check failed
+/// `Err(FormatterError::Idempotence(...))` if the idempotence check failed
 /// `Err(FormatterError::Formatting(...))` if the formatting failed
-fn idempotence_check(
-    content: &str,
-    query: &str,
-    language: &Language,
-    grammar: &tree_sitter_facade::Language,
-    tolerate_parsing_errors: bool,
-) -> FormatterResult<()> {
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+fn idempotence_check(content: &str, options: &FormatterOptions) -> FormatterResult<()> {
     log::info!("Checking for idempotence ...");
 
-    let mut input = content.as_bytes();
     let mut output = io::BufWriter::new(Vec::new());
+    let mut options = options
+        .clone()
+        .skip_idempotence(true)
+        .line_ending(LineEnding::Lf);
+    // The captures were traced, and the tokens and comments checked, when formatting the input
+    options.trace_queries = None;
+    options.verify_tokens = false;
+    options.verify_comments = false;
+    // Formatting the output again repeats the warnings of formatting the input
+    options.on_warning = None;
 
-    formatter(
-        &mut input,
-        &mut output,
-        query,
-        language,
-        grammar,
-        Operation::Format {
-            skip_idempotence: true,
-            tolerate_parsing_errors,
-        },
-    )?;
+    format_content(content, &mut output, &options, None)?;
     let reformatted = String::from_utf8(output.into_inner()?)?;
     let res = if content == reformatted {
         Ok(())
     } else {
         log::error!("Failed idempotence check");
         log::error!("{}", StrComparison::new(content, &reformatted));
-        Err(FormatterError::Idempotence)
+        Err(FormatterError::Idempotence(idempotence_report(
+            content,
+            &reformatted,
+        )))
     };
 
     if let Err(err) = res {
         match err {
             // If topiary ran smoothly on its own output,
             // but produced a different output, it is a Idempotence error.
-            FormatterError::Idempotence => Err(FormatterError::Idempotence),
+            FormatterError::Idempotence(report) => Err(FormatterError::Idempotence(report)),
             // On the other hand, if it failed to run on its output,
             // it means that when formatting the code, topiary somehow broke it.
             // Hence it is a formatting error.
@@ -302,15 +834,63 @@ fn idempotence_check(
     }
 }
 
+/// Describe where the outputs of the first and second formatting passes diverge, showing the
+/// first differing line from both passes, along with a few lines of surrounding context.
+fn idempotence_report(first_pass: &str, second_pass: &str) -> String {
+    const CONTEXT_LINES: usize = 2;
+
+    let first_lines: Vec<&str> = first_pass.lines().collect();
+    let second_lines: Vec<&str> = second_pass.lines().collect();
+
+    // If one pass is a prefix of the other, they diverge where the shortest one ends
+    let divergence = first_lines
+        .iter()
+        .zip(&second_lines)
+        .position(|(first, second)| first != second)
+        .unwrap_or_else(|| first_lines.len().min(second_lines.len()));
+
+    let mut report = format!(
+        "The first and second passes diverge at line {}:\n",
+        divergence + 1
+    );
+
+    // The lines leading up to the divergence are identical in both passes
+    let context_start = divergence.saturating_sub(CONTEXT_LINES);
+    for (index, line) in first_lines[context_start..divergence].iter().enumerate() {
+        report += &format!("  {:>4} | {line}\n", context_start + index + 1);
+    }
+
+    for (marker, lines) in [("-", &first_lines), ("+", &second_lines)] {
+        let context_end = lines.len().min(divergence + CONTEXT_LINES + 1);
+
+        if divergence >= lines.len() {
+            report += &format!("{marker}      | <end of output>\n");
+        }
+
+        for (index, line) in lines[divergence.min(context_end)..context_end]
+            .iter()
+            .enumerate()
+        {
+            report += &format!("{marker} {:>4} | {line}\n", divergence + index + 1);
+        }
+    }
+
+    report + "(- first pass, + second pass)"
+}
+
 #[cfg(test)]
 mod tests {
-    use std::fs;
+    use std::{cell::RefCell, error::Error, fs, path::Path, rc::Rc, time::Duration};
 
     use test_log::test;
 
     use crate::{
-        configuration::Configuration, error::FormatterError, formatter,
-        test_utils::pretty_assert_eq, Operation,
+        check_invariants, configuration::Configuration, error::FormatterError, format,
+        format_bytes, format_enclosing_node, formatter, idempotence_report,
+        language::query_search_path, layer_queries, test_utils::pretty_assert_eq, trim_whitespace,
+        trim_whitespace_mapping_ranges, ByteOrderMark, Encoding, FormatterOptions,
+        InvariantViolation, LanguageRegistry, LineEnding, NodeReplacement, Operation,
+        OsFileProvider, OverlayFileProvider, Visualisation, Warning,
     };
 
     /// Attempt to parse invalid json, expecting a failure
@@ -337,8 +917,15 @@ mod tests {
             Err(FormatterError::Parsing {
                 start_line: 1,
                 end_line: 1,
+                excerpt,
+                context,
                 ..
-            }) => {}
+            }) => {
+                // The excerpt shows the line of the error, with carets under it
+                assert!(excerpt.contains("1 | {\"foo\":{\"bar\"}}\n"), "{excerpt}");
+                assert!(excerpt.ends_with('^'), "{excerpt}");
+                assert!(context.is_some());
+            }
             result => {
                 panic!("Expected a parsing error on line 1, but got {result:?}");
             }
@@ -375,4 +962,1042 @@ mod tests {
 
         pretty_assert_eq(expected, &formatted);
     }
+
+    #[test(tokio::test)]
+    async fn tolerate_parsing_errors_keeps_surrounding_whitespace() {
+        // The invalid "!!!" is left between the two pairs, on its own line
+        let mut input = "a = 1\n!!!\nb = 2\n".as_bytes();
+        let expected = "a = 1\n!!!\nb = 2\n";
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/toml.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("toml").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: true,
+                tolerate_parsing_errors: true,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn align_consecutive_lines() {
+        let mut input = "{\"a\": 1,\n\"bbb\": 2,\n\"cc\": 3}".as_bytes();
+        let expected = "{\n  \"a\"  : 1,\n  \"bbb\": 2,\n  \"cc\" : 3\n}\n";
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap() + "(pair \":\" @align)";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn verify_tokens_reports_lost_token() {
+        let mut input = "{\"a\": 1,\n\"b\": 2}".as_bytes();
+        let mut output = Vec::new();
+        let query = "(pair value: (number) @delete (#eq? @delete \"2\") (#deletable! \"number\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let options = FormatterOptions::new(query, language, &grammar)
+            .skip_idempotence(true)
+            .tolerate_parsing_errors(true)
+            .verify_tokens(true);
+
+        match format(&mut input, &mut output, &options) {
+            Err(FormatterError::Tokens(report)) => {
+                assert!(report.contains("\"2\", at line 2, column 6 of the input"));
+            }
+            result => panic!("Expected a token error, but got {result:?}"),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn invariants_allow_deleting_deletable_kinds() {
+        let input = "# one\na = 1\n";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("toml").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let query = "((comment) @delete (#deletable! \"comment\"))";
+        let options = FormatterOptions::new(query, language, &grammar);
+        check_invariants(input, &options).unwrap();
+
+        // Without #deletable!, the integer cannot be deleted
+        let query = "((integer) @delete)";
+        let options = FormatterOptions::new(query, language, &grammar);
+        match check_invariants(input, &options) {
+            Err(InvariantViolation::Error(FormatterError::Query(message, _))) => {
+                assert!(message.contains("listed by #deletable!"));
+            }
+            result => panic!("Expected a query error, but got {result:?}"),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn delete_named_nodes_listed_by_deletable() {
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("toml").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let query = "((comment) @delete (#deletable! \"comment\"))";
+        let options = FormatterOptions::new(query, language, &grammar).skip_idempotence(true);
+        let mut output = Vec::new();
+        format(&mut "# one\na = 1\n".as_bytes(), &mut output, &options).unwrap();
+        pretty_assert_eq("a=1\n", &String::from_utf8(output).unwrap());
+
+        // The comment kind is listed, but integers are not
+        let query = "((comment) @delete (#deletable! \"comment\")) ((integer) @delete)";
+        let options = FormatterOptions::new(query, language, &grammar).skip_idempotence(true);
+        match format(&mut "a = 1\n".as_bytes(), &mut Vec::new(), &options) {
+            Err(FormatterError::Query(message, None)) => {
+                assert!(message.starts_with(
+                    "@delete can only delete named integer nodes listed by #deletable!"
+                ));
+            }
+            result => panic!("Expected a query error, but got {result:?}"),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn verify_comments_reports_dropped_comment() {
+        let mut input = "# one\na = 1\n# two\nb = 2\n".as_bytes();
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/toml.scm").unwrap()
+            + "((comment) @delete (#eq? @delete \"# two\") (#deletable! \"comment\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("toml").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let options = FormatterOptions::new(&query, language, &grammar)
+            .skip_idempotence(true)
+            .verify_comments(true);
+
+        match format(&mut input, &mut output, &options) {
+            Err(FormatterError::Comments(report)) => {
+                assert!(report.contains("\"#two\", at line 3, column 1 of the input"));
+            }
+            result => panic!("Expected a comment error, but got {result:?}"),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn memory_budget_streams_output_without_checks() {
+        let mut input = "{\"a\": 1,\n\"b\": 2}".as_bytes();
+        let mut output = Vec::new();
+        let query = "(pair value: (number) @delete (#eq? @delete \"2\") (#deletable! \"number\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+        let warnings = Rc::new(RefCell::new(Vec::new()));
+        let reported = warnings.clone();
+
+        let options = FormatterOptions::new(query, language, &grammar)
+            .tolerate_parsing_errors(true)
+            .verify_tokens(true)
+            .line_ending(LineEnding::CrLf)
+            .memory_budget(8)
+            .on_warning(move |warning| reported.borrow_mut().push(warning.clone()));
+
+        // The lost token goes unnoticed, as the input is larger than the budget
+        format(&mut input, &mut output, &options).unwrap();
+        let formatted = String::from_utf8(output).unwrap();
+
+        assert!(!formatted.contains('2'));
+        assert!(!formatted.replace("\r\n", "").contains('\n'));
+        assert_eq!(*warnings.borrow(), vec![Warning::UncheckedOutput]);
+    }
+
+    #[test(tokio::test)]
+    async fn skip_file_pragma_leaves_input_as_is() {
+        // The input would not parse, but it is not formatted
+        let input = "// topiary: skip-file\r\n[1,\n   2";
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let options =
+            FormatterOptions::new(&query, language, &grammar).line_ending(LineEnding::CrLf);
+        format(&mut input.as_bytes(), &mut output, &options).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+    }
+
+    #[test(tokio::test)]
+    async fn timeout_aborts_formatting() {
+        let mut input = "[1,2]".as_bytes();
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let options = FormatterOptions::new(&query, language, &grammar).timeout(Duration::ZERO);
+
+        match format(&mut input, &mut output, &options) {
+            Err(FormatterError::Timeout(timeout)) => assert_eq!(timeout, Duration::ZERO),
+            result => panic!("Expected a timeout, but got {result:?}"),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn visualise_annotated_tree() {
+        let mut input = "{\"a\":1}".as_bytes();
+        let mut output = Vec::new();
+        let query = "(pair \":\" @append_space)";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            query,
+            language,
+            &grammar,
+            Operation::Visualise {
+                output_format: Visualisation::Json,
+            },
+        )
+        .unwrap();
+
+        fn find<'a>(node: &'a serde_json::Value, kind: &str) -> Option<&'a serde_json::Value> {
+            if node["kind"] == kind {
+                return Some(node);
+            }
+            node["children"]
+                .as_array()?
+                .iter()
+                .find_map(|child| find(child, kind))
+        }
+
+        let root: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        let colon = find(&root, ":").unwrap();
+        assert_eq!(colon["captures"], serde_json::json!(["append_space"]));
+        assert_eq!(colon["atoms_after"], serde_json::json!(["Space"]));
+
+        // The pair ends with the number, after which nothing is emitted
+        let pair = find(&root, "pair").unwrap();
+        assert_eq!(pair.get("captures"), None);
+        assert_eq!(pair.get("atoms_after"), None);
+    }
+
+    #[test(tokio::test)]
+    async fn format_injected_language() {
+        let mut input = "{\"a\": [1,\n2]}".as_bytes();
+        let expected = "{\n  \"a\": [\n    1,\n    2\n  ]\n}\n";
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap()
+            + "(pair value: (array) @injection (#injection_language! \"json\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn injections_use_the_registry_of_the_options() {
+        let mut input = "{\"a\": [1,2]}".as_bytes();
+        let expected = "{ \"a\": [1,2] }\n";
+
+        // The query of the injected language leaves it as it is
+        let mut files = OverlayFileProvider::new(OsFileProvider);
+        for dir in query_search_path() {
+            files.insert(dir.join("json.scm"), "");
+        }
+        let registry = LanguageRegistry::with_file_provider(
+            Configuration::parse_default_configuration().unwrap(),
+            files,
+        );
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap()
+            + "(pair value: (array) @injection (#injection_language! \"json\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+        let options = FormatterOptions::new(&query, language, &grammar).injections(&registry);
+
+        format(&mut input, &mut output, &options).unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn text_predicates() {
+        let mut input = "{\"a\":1,\"b\":2}".as_bytes();
+        let expected = "{ \"a\": 1,\n  \"b\": 2 }\n";
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap()
+            + "(pair key: (string) @_key @prepend_hardline"
+            + "  (#any-of? @_key \"\\\"b\\\"\" \"\\\"c\\\"\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                // The line break makes the object multi-line on the second pass
+                skip_idempotence: true,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn structural_predicates() {
+        let mut input = "[[1,2],[3],[4,5,6]]".as_bytes();
+        let expected = "[[ 1, 2 ], [3], [ 4, 5, 6 ]]\n";
+
+        let mut output = Vec::new();
+        // Only pad the brackets of short arrays with more than one element
+        let query = fs::read_to_string("../languages/json.scm")
+            .unwrap()
+            .replace(
+                "\"[\" @append_spaced_softline",
+                "\"[\" @append_empty_softline",
+            )
+            .replace(
+                "\"]\" @prepend_spaced_softline",
+                "\"]\" @prepend_empty_softline",
+            )
+            + "((array . \"[\" @append_space \"]\" @prepend_space .) @_array"
+            + "  (#not-child-count? @_array 1) (#text-length? @_array 12))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn missing_delimiter() {
+        // Only the last pair is missing a comma
+        let mut input = "{\"a\":1,\"b\":2}".as_bytes();
+        let expected = "{ \"a\": 1, \"b\": 2, }\n";
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap()
+            + "((pair) @append_missing_delimiter (#delimiter! \",\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                // The added commas do not parse as JSON
+                skip_idempotence: true,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn missing_delimiter_after_comment() {
+        // The comma of the first pair follows a comment, rather than the pair itself
+        let mut input = "{\"a\":1 /* one */,\"b\":2}".as_bytes();
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap()
+            + "((pair) @append_missing_delimiter (#delimiter! \",\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                // The added commas do not parse as JSON
+                skip_idempotence: true,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        // The comma of the first pair is kept, and only the last pair gets one
+        assert_eq!(formatted.matches(',').count(), 2);
+    }
+
+    #[test(tokio::test)]
+    async fn query_errors_point_at_the_query() {
+        let mut input = "[1]".as_bytes();
+
+        let mut output = Vec::new();
+        let query = "(array \"[\" @append_space)\n(number) @prepend_delimiter\n";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        match formatter(
+            &mut input,
+            &mut output,
+            query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: true,
+                tolerate_parsing_errors: false,
+            },
+        ) {
+            Err(FormatterError::Query(message, None)) => {
+                assert_eq!(
+                    message,
+                    "@prepend_delimiter requires a #delimiter! predicate, in the pattern at \
+                     line 2, column 10 of the query\n |\n\
+                     2 | (number) @prepend_delimiter\n |          ^"
+                );
+            }
+            result => panic!("Expected a query error, got {result:?}"),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn case_normalisation() {
+        let mut input = "[true,false]".as_bytes();
+        let expected = "[ TRUE, FALSE ]\n";
+
+        let mut output = Vec::new();
+        let query =
+            fs::read_to_string("../languages/json.scm").unwrap() + "[(true) (false)] @upper_case";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                // JSON keywords are case-sensitive
+                skip_idempotence: true,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn sort_nodes() {
+        let mut input = "[\"c\",\"a\",\"b\"]".as_bytes();
+        let expected = "[ \"a\", \"b\", \"c\" ]\n";
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap() + "(array (string) @sort)";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn scopes_wrap_at_line_width() {
+        let mut input = "[[1,2],[300000,400000]]".as_bytes();
+        let expected = "[\n  [1, 2],\n  [\n    300000,\n    400000\n  ]\n]\n";
+
+        let mut output = Vec::new();
+        let query = "(array \"[\" @begin_scope @append_empty_scoped_softline @append_indent_start
+                    \"]\" @end_scope @prepend_empty_scoped_softline @prepend_indent_end
+                    (#scope_id! \"array\"))
+                    (array \",\" @append_spaced_scoped_softline (#scope_id! \"array\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let mut language = configuration.get_language("json").unwrap().clone();
+        language.line_width = Some(12);
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            query,
+            &language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn inner_scopes_force_outer_scopes() {
+        // The line break after 4 makes the array around it multi-line, and hence the outer array,
+        // but not the array of 1 and 2
+        let mut input = "[[1,2],[3,4]]".as_bytes();
+        let expected = "[\n  [1, 2],\n  [\n    3,\n    4\n  ]\n]\n";
+
+        let mut output = Vec::new();
+        let query = "(array \"[\" @begin_scope @append_empty_scoped_softline @append_indent_start
+                    \"]\" @end_scope @prepend_empty_scoped_softline @prepend_indent_end
+                    (#scope_id! \"array\") (#forced_by_contents!))
+                    (array \",\" @append_spaced_scoped_softline (#scope_id! \"array\"))
+                    ((number) @append_hardline (#eq? @append_hardline \"4\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn line_breaks_only_force_marked_scopes() {
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let format_with = |marker: &str| {
+            let query = format!(
+                "(array \"[\" @begin_scope \"]\" @end_scope (#scope_id! \"array\") {marker})
+                (array \",\" @append_spaced_scoped_softline (#scope_id! \"array\"))
+                ((number) @append_hardline (#eq? @append_hardline \"1\"))"
+            );
+            let mut output = Vec::new();
+
+            formatter(
+                &mut "[1,2]".as_bytes(),
+                &mut output,
+                &query,
+                language,
+                &grammar,
+                Operation::Format {
+                    skip_idempotence: true,
+                    tolerate_parsing_errors: false,
+                },
+            )
+            .unwrap();
+
+            String::from_utf8(output).unwrap()
+        };
+
+        // The scope spans a single line of the input, so it is only multi-line if it is marked
+        pretty_assert_eq("[1\n, 2]\n", &format_with(""));
+        pretty_assert_eq("[1\n,\n2]\n", &format_with("(#forced_by_contents!)"));
+    }
+
+    #[test(tokio::test)]
+    async fn deleted_line_breaks_do_not_force_scopes() {
+        let mut input = "[1,[2,3],4]".as_bytes();
+        let expected = "[1, , 4]\n";
+
+        let mut output = Vec::new();
+        let query = "(document (array \"[\" @begin_scope \"]\" @end_scope (#scope_id! \"outer\")
+                      (#forced_by_contents!)))
+                    (document (array \",\" @append_spaced_scoped_softline (#scope_id! \"outer\")))
+                    (array (array (number) @append_hardline))
+                    (array (array) @delete (#deletable! \"array\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        // The output is not valid JSON, so it is not formatted again
+        formatter(
+            &mut input,
+            &mut output,
+            query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: true,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn config_predicate() {
+        let query = "(\",\" @append_space (#not-config? \"style\" \"compact\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let mut language = configuration.get_language("json").unwrap().clone();
+        let grammar = language.grammar().await.unwrap();
+
+        for (style, expected) in [("compact", "[1,2]\n"), ("wide", "[1, 2]\n")] {
+            language.settings.insert("style".into(), style.into());
+
+            let mut output = Vec::new();
+            formatter(
+                &mut "[1,2]".as_bytes(),
+                &mut output,
+                query,
+                &language,
+                &grammar,
+                Operation::Format {
+                    skip_idempotence: false,
+                    tolerate_parsing_errors: false,
+                },
+            )
+            .unwrap();
+
+            pretty_assert_eq(expected, &String::from_utf8(output).unwrap());
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn formatter_options() {
+        let mut input = "[[1,2],[300000,400000]]".as_bytes();
+        let expected = "[\r\n\t[1, 2],\r\n\t[\r\n\t\t300000,\r\n\t\t400000\r\n\t]\r\n]\r\n";
+
+        let mut output = Vec::new();
+        let query = "(array \"[\" @begin_scope @append_empty_scoped_softline @append_indent_start
+                    \"]\" @end_scope @prepend_empty_scoped_softline @prepend_indent_end
+                    (#scope_id! \"array\"))
+                    (array \",\" @append_spaced_scoped_softline (#scope_id! \"array\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let options = FormatterOptions::new(query, language, &grammar)
+            .indent("\t")
+            .line_width(12)
+            .line_ending(LineEnding::CrLf);
+
+        format(&mut input, &mut output, &options).unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn pre_and_post_processing_hooks() {
+        let mut input = "\u{feff}[1,2]".as_bytes();
+        let expected = "// Licensed under MIT\n[ 1, 2 ]\n";
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let strip_bom = |input: &str| -> Result<String, Box<dyn Error>> {
+            Ok(input.trim_start_matches('\u{feff}').to_string())
+        };
+        let add_header = |output: &str| -> Result<String, Box<dyn Error>> {
+            Ok(format!("// Licensed under MIT\n{output}"))
+        };
+
+        // The idempotence check would add the header once more
+        let options = FormatterOptions::new(&query, language, &grammar)
+            .skip_idempotence(true)
+            .pre_process(strip_bom)
+            .post_process(add_header);
+
+        format(&mut input, &mut output, &options).unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn byte_order_mark() {
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let options = FormatterOptions::new(&query, language, &grammar);
+        let mut output = Vec::new();
+        format(&mut "\u{feff}[1,2]".as_bytes(), &mut output, &options).unwrap();
+        pretty_assert_eq("\u{feff}[ 1, 2 ]\n", &String::from_utf8(output).unwrap());
+
+        let options = options.byte_order_mark(ByteOrderMark::Strip);
+        let mut output = Vec::new();
+        format(&mut "\u{feff}[1,2]".as_bytes(), &mut output, &options).unwrap();
+        pretty_assert_eq("[ 1, 2 ]\n", &String::from_utf8(output).unwrap());
+    }
+
+    #[test(tokio::test)]
+    async fn encodings() {
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        let options = FormatterOptions::new(&query, language, &grammar);
+        let latin1 = options
+            .clone()
+            .encoding(Encoding::for_label(b"latin1").unwrap());
+        let mut output = Vec::new();
+        format(&mut b"[\"caf\xe9\"]".as_slice(), &mut output, &latin1).unwrap();
+        assert_eq!(output, b"[ \"caf\xe9\" ]\n");
+
+        // UTF-16 is detected from the byte order mark, which is preserved
+        let utf16 =
+            |text: &str| -> Vec<u8> { text.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+        let mut output = Vec::new();
+        format(
+            &mut utf16("\u{feff}[1,2]").as_slice(),
+            &mut output,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(output, utf16("\u{feff}[ 1, 2 ]\n"));
+    }
+
+    #[test(tokio::test)]
+    async fn format_bytes_matches_format() {
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+        let options = FormatterOptions::new(&query, language, &grammar);
+
+        let input = "\u{feff}{\"a\":[1,2]}";
+        let mut expected = Vec::new();
+        format(&mut input.as_bytes(), &mut expected, &options).unwrap();
+        let mut output = Vec::new();
+        format_bytes(input.as_bytes(), &mut output, &options).unwrap();
+
+        assert_eq!(output, expected);
+    }
+
+    #[test(tokio::test)]
+    async fn priority_overrides_dominance() {
+        let mut input = "{\"a\":1}".as_bytes();
+        let expected = "{ \"a\":1 }\n";
+
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap()
+            + "(pair \":\" @append_hardline)"
+            + "(pair \":\" @append_empty_softline (#priority! 1))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(output).unwrap();
+        log::debug!("{}", formatted);
+
+        pretty_assert_eq(expected, &formatted);
+    }
+
+    #[test(tokio::test)]
+    async fn later_layers_override_whitespace() {
+        let mut input = "{\"a\":1}".as_bytes();
+        let expected = "{ \"a\":1 }\n";
+
+        let mut files = OverlayFileProvider::new(OsFileProvider);
+        files.insert(
+            "q/json.scm",
+            fs::read_to_string("../languages/json.scm").unwrap() + "(pair \":\" @append_hardline)",
+        );
+        files.insert("q/compact.scm", "(pair \":\" @append_empty_softline)");
+        let query = layer_queries(Path::new("q/json.scm"), &["compact.scm"], &files).unwrap();
+
+        let mut output = Vec::new();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        formatter(
+            &mut input,
+            &mut output,
+            &query.content,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .unwrap();
+
+        pretty_assert_eq(expected, &String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn trim_whitespace_maps_ranges() {
+        let untrimmed = "\n  foo  \n\tbar \r\n\n";
+        let mut ranges = [3..6, 10..13];
+        let trimmed = trim_whitespace_mapping_ranges(untrimmed, &mut ranges);
+
+        assert_eq!(trimmed, trim_whitespace(untrimmed));
+        assert_eq!(trimmed, "foo\n\tbar\n");
+        assert_eq!(ranges, [0..3, 5..8]);
+    }
+
+    #[test]
+    fn idempotence_report_shows_divergence() {
+        let first = "a\nb\nc\nd\ne\n";
+        let second = "a\nb\nc\nD\ne\n";
+        let expected = "The first and second passes diverge at line 4:
+     2 | b
+     3 | c
+-    4 | d
+-    5 | e
++    4 | D
++    5 | e
+(- first pass, + second pass)";
+
+        pretty_assert_eq(expected, &idempotence_report(first, second));
+    }
+
+    #[test]
+    fn idempotence_report_shows_end_of_output() {
+        let first = "a\n";
+        let second = "a\nb\n";
+        let expected = "The first and second passes diverge at line 2:
+     1 | a
+-      | <end of output>
++    2 | b
+(- first pass, + second pass)";
+
+        pretty_assert_eq(expected, &idempotence_report(first, second));
+    }
+
+    // The tests run on a current-thread runtime, which formatting must not block
+    #[cfg(feature = "async")]
+    #[test(tokio::test)]
+    async fn format_asynchronously() {
+        let mut input = "[1,2]".as_bytes();
+        let mut output = Vec::new();
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+
+        crate::formatter_async(
+            &mut input,
+            &mut output,
+            &query,
+            language,
+            &grammar,
+            Operation::Format {
+                skip_idempotence: false,
+                tolerate_parsing_errors: false,
+            },
+        )
+        .await
+        .unwrap();
+
+        pretty_assert_eq("[ 1, 2 ]\n", &String::from_utf8(output).unwrap());
+    }
+
+    #[test(tokio::test)]
+    async fn enclosing_node_rejects_invalid_cursors() {
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+        let options = FormatterOptions::new(&query, language, &grammar);
+
+        // Past the end of the input
+        match format_enclosing_node("{\"a\":1}", 8, &options) {
+            Err(FormatterError::InvalidRange(range)) => assert_eq!(range, 8..8),
+            result => panic!("Expected an invalid range, but got {result:?}"),
+        }
+
+        // Within the two bytes of the "é"
+        match format_enclosing_node("{\"é\":1}", 3, &options) {
+            Err(FormatterError::InvalidRange(range)) => assert_eq!(range, 3..3),
+            result => panic!("Expected an invalid range, but got {result:?}"),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn enclosing_node_defaults_to_top_level_node() {
+        let input = "{\"a\":1,\"b\":2}\n";
+        let query = fs::read_to_string("../languages/json.scm").unwrap();
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let mut language = configuration.get_language("json").unwrap().clone();
+        language.formattable_nodes.clear();
+        let grammar = language.grammar().await.unwrap();
+        let options = FormatterOptions::new(&query, &language, &grammar);
+
+        let mut output = Vec::new();
+        format(&mut input.as_bytes(), &mut output, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let formatted = format_enclosing_node(input, 5, &options).unwrap();
+        assert_eq!(formatted.replaced, 0..input.trim_end().len());
+        pretty_assert_eq(output.trim_end(), &formatted.replacement);
+    }
+
+    #[test(tokio::test)]
+    async fn enclosing_node_with_deleted_leaves() {
+        let input = "{\"a\":1}";
+        let query = "((pair) @delete (#deletable! \"pair\"))";
+        let configuration = Configuration::parse_default_configuration().unwrap();
+        let language = configuration.get_language("json").unwrap();
+        let grammar = language.grammar().await.unwrap();
+        let options = FormatterOptions::new(query, language, &grammar);
+
+        // The cursor follows the pair, all the leaves of which are deleted
+        let formatted = format_enclosing_node(input, 6, &options).unwrap();
+        assert_eq!(
+            formatted,
+            NodeReplacement {
+                replaced: 1..6,
+                replacement: String::new(),
+                cursor: 1,
+            }
+        );
+    }
 }
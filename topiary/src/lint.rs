@@ -0,0 +1,320 @@
+//! Static checks of query files, which catch the most common mistakes of query authors before
+//! anything is formatted.
+
+use std::{collections::HashMap, fmt};
+
+use tree_sitter_facade::Query;
+
+use crate::{captures, tree_sitter, FormatterError, FormatterResult};
+
+/// Pairs of captures that undo each other when they are applied to the same node.
+const CONFLICTING_CAPTURES: &[(&str, &str)] = &[
+    ("append_antispace", "append_space"),
+    ("append_indent_end", "append_indent_start"),
+    ("prepend_antispace", "prepend_space"),
+    ("prepend_indent_end", "prepend_indent_start"),
+];
+
+/// A likely mistake in a query, found by `lint_query`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryLint {
+    /// The line of the mistake in the query, starting from 1
+    pub line: usize,
+    /// The column of the mistake, in characters, starting from 1
+    pub column: usize,
+    /// What the mistake is
+    pub kind: QueryLintKind,
+}
+
+/// The kinds of mistakes that `lint_query` finds.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum QueryLintKind {
+    /// A capture whose name Topiary does not understand, without the leading `@`
+    UnknownCapture(String),
+    /// Two captures of the same node that undo each other, or one that is never applied as the
+    /// other is `@do_nothing`, without their leading `@`
+    ConflictingCaptures(String, String),
+    /// A pattern that is identical to the one at the given line of the query, and is redundant
+    DuplicatePattern(usize),
+}
+
+impl fmt::Display for QueryLintKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownCapture(name) => {
+                write!(
+                    f,
+                    "@{name} is not a valid capture name{}",
+                    captures::did_you_mean(name)
+                )
+            }
+            Self::ConflictingCaptures(first, second) if first == "do_nothing" => {
+                write!(
+                    f,
+                    "@{second} is never applied, as the node is captured by @do_nothing"
+                )
+            }
+            Self::ConflictingCaptures(first, second) => {
+                write!(f, "@{first} and @{second} undo each other on the same node")
+            }
+            Self::DuplicatePattern(line) => {
+                write!(f, "This pattern is a duplicate of the one at line {line}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for QueryLint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}, at line {}, column {}",
+            self.kind, self.line, self.column
+        )
+    }
+}
+
+/// Check the query content for the given grammar, without formatting anything. Returns the
+/// likely mistakes in the query, in the order in which they appear in it: captures whose name
+/// Topiary does not understand, captures of the same node that undo each other, and patterns that
+/// duplicate earlier ones.
+///
+/// Patterns that can never match, because they use node kinds or fields that the grammar does not
+/// have, already prevent the query from being parsed.
+///
+/// # Errors
+///
+/// If the query content cannot be parsed by the grammar, a `FormatterError::Query` will be
+/// returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use topiary::{lint_query, Configuration, QueryLintKind};
+///
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let query = concat!(
+///     "\",\" @append_space @append_antispace\n",
+///     "(pair) @append_hardline\n",
+///     "(pair) @append_hardline\n",
+/// );
+/// let lints: Vec<QueryLintKind> = lint_query(query, &grammar)
+///     .unwrap()
+///     .into_iter()
+///     .map(|lint| lint.kind)
+///     .collect();
+///
+/// assert_eq!(
+///     lints,
+///     [
+///         QueryLintKind::ConflictingCaptures("append_antispace".into(), "append_space".into()),
+///         QueryLintKind::DuplicatePattern(2),
+///     ]
+/// );
+/// # }) // end tokio_test
+/// ```
+pub fn lint_query(
+    query_content: &str,
+    grammar: &tree_sitter_facade::Language,
+) -> FormatterResult<Vec<QueryLint>> {
+    let query = Query::new(grammar, query_content)
+        .map_err(|e| FormatterError::Query("Error parsing query file".into(), Some(e)))?;
+
+    let capture_names = query.capture_names();
+    let mut lints: Vec<QueryLint> = captures::unknown_captures(query_content, &capture_names)
+        .into_iter()
+        .map(|capture| QueryLint {
+            line: capture.line,
+            column: capture.column,
+            kind: QueryLintKind::UnknownCapture(capture.name),
+        })
+        .collect();
+
+    lints.extend(conflicting_captures(query_content));
+    lints.extend(duplicate_patterns(&query, query_content));
+    lints.sort_by_key(|lint| (lint.line, lint.column));
+
+    Ok(lints)
+}
+
+/// A capture in the query content, along with its position.
+struct PositionedCapture {
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+/// Find the captures that apply to the same node as an earlier one, i.e., that only whitespace
+/// separates from it, and that conflict with it.
+fn conflicting_captures(query_content: &str) -> Vec<QueryLint> {
+    let mut lints = Vec::new();
+
+    for group in capture_groups(query_content) {
+        for (index, capture) in group.iter().enumerate() {
+            for earlier in &group[..index] {
+                let mut pair = [earlier.name.as_str(), capture.name.as_str()];
+                pair.sort_unstable();
+
+                let conflicting = CONFLICTING_CAPTURES.contains(&(pair[0], pair[1]))
+                    || (earlier.name == "do_nothing") != (capture.name == "do_nothing");
+
+                if conflicting {
+                    // @do_nothing comes first, whichever of the two is written first
+                    let (first, second) = if capture.name == "do_nothing" {
+                        (capture.name.clone(), earlier.name.clone())
+                    } else if earlier.name == "do_nothing" {
+                        (earlier.name.clone(), capture.name.clone())
+                    } else {
+                        (pair[0].to_string(), pair[1].to_string())
+                    };
+
+                    lints.push(QueryLint {
+                        line: capture.line,
+                        column: capture.column,
+                        kind: QueryLintKind::ConflictingCaptures(first, second),
+                    });
+                }
+            }
+        }
+    }
+
+    lints
+}
+
+/// Group the captures of the query content that apply to the same node, i.e., that follow each
+/// other with only whitespace, or comments, between them. Strings are skipped, and captures whose
+/// name starts with an underscore, which only serve as arguments to predicates, are left out.
+fn capture_groups(query_content: &str) -> Vec<Vec<PositionedCapture>> {
+    let mut groups: Vec<Vec<PositionedCapture>> = Vec::new();
+    // Whether the next capture applies to the same node as the last one
+    let mut adjacent = false;
+    // Strings can span several lines
+    let mut in_string = false;
+
+    for (line_index, line) in query_content.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if in_string => i += 1,
+                '"' => {
+                    in_string = !in_string;
+                    adjacent = false;
+                }
+                _ if in_string => {}
+                ';' => break,
+                '@' => {
+                    let name: String = chars[i + 1..]
+                        .iter()
+                        .take_while(|c| c.is_alphanumeric() || "_-.?!".contains(**c))
+                        .collect();
+                    let length = name.chars().count();
+
+                    if !name.starts_with('_') {
+                        let capture = PositionedCapture {
+                            name,
+                            line: line_index + 1,
+                            column: i + 1,
+                        };
+
+                        match groups.last_mut() {
+                            Some(group) if adjacent => group.push(capture),
+                            _ => groups.push(vec![capture]),
+                        }
+                        adjacent = true;
+                    }
+
+                    i += length;
+                }
+                c if c.is_whitespace() => {}
+                _ => adjacent = false,
+            }
+
+            i += 1;
+        }
+    }
+
+    groups
+}
+
+/// Find the patterns of the query that are identical to an earlier one, up to whitespace and
+/// comments.
+fn duplicate_patterns(query: &Query, query_content: &str) -> Vec<QueryLint> {
+    let pattern_count = query.pattern_count();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut lints = Vec::new();
+
+    for index in 0..pattern_count {
+        let start = query.start_byte_for_pattern(index);
+        let end = if index == pattern_count - 1 {
+            query_content.len()
+        } else {
+            query.start_byte_for_pattern(index + 1)
+        };
+
+        let line = tree_sitter::pattern_line(query, query_content, index);
+        let pattern = normalise(&query_content[start..end]);
+
+        match seen.get(&pattern) {
+            Some(&earlier) => lints.push(QueryLint {
+                line,
+                column: query_content[..start]
+                    .chars()
+                    .rev()
+                    .take_while(|&c| c != '\n')
+                    .count()
+                    + 1,
+                kind: QueryLintKind::DuplicatePattern(earlier),
+            }),
+            None => {
+                seen.insert(pattern, line);
+            }
+        }
+    }
+
+    lints
+}
+
+/// The text of a pattern without its comments, and with all whitespace outside of strings
+/// collapsed into single spaces.
+fn normalise(pattern: &str) -> String {
+    let mut normalised = String::new();
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut escaped = false;
+
+    for c in pattern.chars() {
+        if in_comment {
+            in_comment = c != '\n';
+            continue;
+        }
+
+        if in_string {
+            normalised.push(c);
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+            continue;
+        }
+
+        match c {
+            ';' => in_comment = true,
+            '"' => {
+                in_string = true;
+                normalised.push(c);
+            }
+            c if c.is_whitespace() => {
+                if !normalised.is_empty() && !normalised.ends_with(' ') {
+                    normalised.push(' ');
+                }
+            }
+            c => normalised.push(c),
+        }
+    }
+
+    normalised.trim_end().to_string()
+}
@@ -0,0 +1,111 @@
+//! Measurements of the work done by the formatter, for services that embed Topiary and export
+//! them to their monitoring systems.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{format_encoded, read_bytes, FormatterOptions, FormatterResult};
+
+/// Measurements of a single run of the formatter.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FormatMetrics {
+    /// The time spent parsing the input
+    pub parse_time: Duration,
+    /// The time spent compiling the query, unless it was compiled beforehand, matching it against
+    /// the syntax tree, and turning it into atoms
+    pub query_time: Duration,
+    /// The number of matches of the query in the syntax tree
+    pub query_matches: usize,
+    /// The number of atoms produced by the query, after post-processing
+    pub atoms: usize,
+    /// The time spent post-processing the atoms, including the formatting of injected languages
+    pub post_processing_time: Duration,
+    /// The time spent rendering the atoms into the output, before the idempotence check
+    pub render_time: Duration,
+    /// The size of the formatted output, in bytes
+    pub output_bytes: usize,
+    /// Whether the idempotence check ran
+    pub idempotence_checked: bool,
+}
+
+/// Like `format`, but also returns measurements of the run. They are only taken when requested,
+/// as reading the clock is not free. This is not available on WebAssembly, whose standard library
+/// has no clock.
+///
+/// # Errors
+///
+/// If formatting fails for any reason, a `FormatterError` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{format_with_metrics, Configuration, FormatterOptions};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+/// let options = FormatterOptions::new(&query, language, &grammar);
+///
+/// let mut output = Vec::new();
+/// let metrics = format_with_metrics(&mut "[1,2]".as_bytes(), &mut output, &options).unwrap();
+///
+/// assert_eq!(metrics.output_bytes, output.len());
+/// assert!(metrics.idempotence_checked);
+/// # }) // end tokio_test
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+pub fn format_with_metrics(
+    input: &mut impl io::Read,
+    output: &mut impl io::Write,
+    options: &FormatterOptions,
+) -> FormatterResult<FormatMetrics> {
+    let mut metrics = FormatMetrics::default();
+    format_encoded(&read_bytes(input)?, output, options, Some(&mut metrics))?;
+
+    Ok(metrics)
+}
+
+/// Run a step of formatting, adding how long it took to the duration, if one is given.
+pub(crate) fn measure<T>(duration: Option<&mut Duration>, step: impl FnOnce() -> T) -> T {
+    match duration {
+        Some(duration) => {
+            let start = Instant::now();
+            let result = step();
+            *duration += start.elapsed();
+
+            result
+        }
+        None => step(),
+    }
+}
+
+/// A writer that counts the bytes written through it.
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    pub(crate) count: usize,
+}
+
+impl<W> CountingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: io::Write> io::Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,112 @@
+//! Reading the language of a file from the modelines of Vim, such as `vim: ft=ocaml`, and Emacs,
+//! such as `-*- mode: nix -*-`, that its authors left near its top or bottom for their editors.
+
+use crate::Ends;
+
+/// The number of lines at the top, and at the bottom, of a file that are searched for modelines,
+/// as Vim does by default.
+const MODELINE_LINES: usize = 5;
+
+/// The language that a modeline near the top or the bottom of a file gives, e.g., `ocaml`, as it
+/// is written there, searching the ends of the file that were read. Modelines at the top come
+/// first.
+pub(crate) fn modeline_language(ends: &Ends) -> Option<&str> {
+    // Unless the end of the file was read apart, its first lines are not its last lines
+    let (bottom, skipped) = match &ends.tail {
+        Some(tail) => (tail, 0),
+        None => (&ends.head, MODELINE_LINES),
+    };
+    let lines: Vec<&str> = bottom.lines().collect();
+    let bottom = lines.len().saturating_sub(MODELINE_LINES).max(skipped);
+
+    ends.head
+        .lines()
+        .take(MODELINE_LINES)
+        .chain(lines.iter().skip(bottom).copied())
+        .find_map(|line| emacs_mode(line).or_else(|| vim_filetype(line)))
+}
+
+/// The mode of an Emacs modeline, either `-*- mode: nix; -*-`, among other variables, or just
+/// `-*- nix -*-`.
+fn emacs_mode(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (variables, _) = rest.split_once("-*-")?;
+
+    if !variables.contains(':') {
+        return Some(variables.trim()).filter(|mode| !mode.is_empty());
+    }
+
+    variables.split(';').find_map(|variable| {
+        let (name, value) = variable.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("mode")
+            .then_some(value.trim())
+    })
+}
+
+/// The file type of a Vim modeline, e.g., `vim: ft=ocaml` or `vim: set filetype=ocaml :`. Compound
+/// file types, such as `ocaml.doc`, give their first part.
+fn vim_filetype(line: &str) -> Option<&str> {
+    let start = ["vim:", " vi:", " ex:", "\tvi:", "\tex:"]
+        .iter()
+        .filter_map(|marker| line.find(marker).map(|index| index + marker.len()))
+        .min()?;
+
+    line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| match option.split_once('=') {
+            Some(("ft" | "filetype" | "syn" | "syntax", value)) => value.split('.').next(),
+            _ => None,
+        })
+        .filter(|filetype| !filetype.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::modeline_language;
+    use crate::Ends;
+
+    /// The language of the modeline of the file whose ends are given.
+    fn mode(head: &str, tail: Option<&str>) -> Option<String> {
+        let ends = Ends {
+            head: head.to_string(),
+            tail: tail.map(str::to_string),
+        };
+        modeline_language(&ends).map(str::to_string)
+    }
+
+    #[test]
+    fn modelines_give_languages() {
+        assert_eq!(
+            mode("# vim: ft=ocaml\nlet x = 1\n", None).as_deref(),
+            Some("ocaml")
+        );
+        assert_eq!(
+            mode(
+                "#!/bin/sh\n# -*- mode: sh; indent-tabs-mode: nil -*-\n",
+                None
+            )
+            .as_deref(),
+            Some("sh")
+        );
+        assert_eq!(mode("; -*- scheme -*-\n", None).as_deref(), Some("scheme"));
+
+        let bottom = format!("{}/* vim: set filetype=c.doxygen : */\n", "x\n".repeat(20));
+        assert_eq!(mode(&bottom, None).as_deref(), Some("c"));
+
+        // Modelines in the middle of the file, and lines that merely mention Vim, are ignored
+        let middle = format!("{}# vim: ft=ocaml\n{}", "x\n".repeat(10), "x\n".repeat(10));
+        assert_eq!(mode(&middle, None), None);
+        assert_eq!(mode("# Configure vim: see the docs\n", None), None);
+    }
+
+    #[test]
+    fn modelines_at_the_end_of_large_files() {
+        // The last lines of the head are in the middle of the file
+        let head = "x\n".repeat(10) + "# vim: ft=ocaml\n";
+        assert_eq!(mode(&head, Some("x\nx\n")), None);
+
+        let tail = "x\n".repeat(10) + "# vim: ft=ocaml\n";
+        assert_eq!(mode("x\n", Some(&tail)).as_deref(), Some("ocaml"));
+    }
+}
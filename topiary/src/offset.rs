@@ -0,0 +1,120 @@
+//! Mapping positions of the input to the formatted output, so that editors can keep the user's
+//! cursor and selections in place after formatting.
+
+use crate::{
+    format_with_spans, FormatterError, FormatterOptions, FormatterResult, Language, LeafSpan,
+};
+
+/// Format the input, additionally mapping each of the given byte offsets of the input to the
+/// corresponding byte offset of the formatted output. Returns the output and the mapped offsets,
+/// in the same order as they were given.
+///
+/// An offset within a leaf node is kept at the same position relative to the start of the leaf.
+/// An offset that immediately follows a leaf stays immediately after it; any other offset that
+/// falls into whitespace, or into content deleted by formatting, moves to the start of the next
+/// leaf.
+///
+/// # Errors
+///
+/// If an offset is out of bounds or does not fall on a character boundary, or formatting fails
+/// for any reason, a `FormatterError` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{map_offsets, Configuration};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// // The cursor is on the value of the pair
+/// let (output, offsets) =
+///     map_offsets(r#"{"a":1}"#, &[5], &query, language, &grammar, false, false).unwrap();
+///
+/// assert_eq!(output, "{ \"a\": 1 }\n");
+/// assert_eq!(offsets, [7]);
+/// # }) // end tokio_test
+/// ```
+pub fn map_offsets(
+    input: &str,
+    offsets: &[usize],
+    query: &str,
+    language: &Language,
+    grammar: &tree_sitter_facade::Language,
+    skip_idempotence: bool,
+    tolerate_parsing_errors: bool,
+) -> FormatterResult<(String, Vec<usize>)> {
+    if let Some(&offset) = offsets
+        .iter()
+        .find(|&&offset| !input.is_char_boundary(offset))
+    {
+        return Err(FormatterError::InvalidRange(offset..offset));
+    }
+
+    let options = FormatterOptions::new(query, language, grammar)
+        .skip_idempotence(skip_idempotence)
+        .tolerate_parsing_errors(tolerate_parsing_errors);
+    let (_, output, spans) = format_with_spans(input, &options)?;
+
+    let mapped = offsets
+        .iter()
+        .map(|&offset| map_offset(offset, &spans, &output))
+        .collect();
+
+    Ok((output, mapped))
+}
+
+/// Map a single offset of the input through the given leaf spans, which are in input order.
+pub(crate) fn map_offset(offset: usize, spans: &[LeafSpan], output: &str) -> usize {
+    // The first leaf that ends at or after the offset
+    let index = spans.partition_point(|span| span.input.end < offset);
+
+    let mut mapped = match spans.get(index) {
+        Some(span) if span.input.end == offset => span.output.end,
+        Some(span) if span.input.start <= offset => {
+            (span.output.start + offset - span.input.start).min(span.output.end)
+        }
+        Some(span) => span.output.start,
+        None => output.len(),
+    };
+
+    // The content of a leaf may have changed (e.g., if it is in an injected language)
+    while !output.is_char_boundary(mapped) {
+        mapped -= 1;
+    }
+
+    mapped
+}
+
+#[cfg(test)]
+mod test {
+    use super::map_offset;
+    use crate::LeafSpan;
+
+    #[test]
+    fn offsets_follow_leaves() {
+        // "foo  bar" formatted as "foo bar\n"
+        let spans = [
+            LeafSpan {
+                input: 0..3,
+                output: 0..3,
+            },
+            LeafSpan {
+                input: 5..8,
+                output: 4..7,
+            },
+        ];
+        let output = "foo bar\n";
+
+        let mapped: Vec<usize> = [0, 1, 3, 4, 5, 7, 8]
+            .iter()
+            .map(|&offset| map_offset(offset, &spans, output))
+            .collect();
+
+        assert_eq!(mapped, [0, 1, 3, 4, 4, 6, 7]);
+    }
+}
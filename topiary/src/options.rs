@@ -0,0 +1,408 @@
+//! The options of the formatter, gathered in a builder so that new options can be added without
+//! breaking the users of the library.
+
+use std::{borrow::Cow, error::Error, io, ops::RangeInclusive, rc::Rc, time::Duration};
+
+use encoding_rs::Encoding;
+
+use crate::{
+    tree_sitter::CompiledQuery, CancellationToken, FormatterError, FormatterResult, Language,
+    LanguageRegistry, Warning,
+};
+
+/// A transformation of text, run by the formatter before parsing its input or after rendering its
+/// output (e.g., to strip a byte order mark, or to insert a license header). It is implemented by
+/// all functions and closures of the right type.
+pub trait Hook {
+    fn run(&self, text: &str) -> Result<String, Box<dyn Error>>;
+}
+
+impl<F> Hook for F
+where
+    F: Fn(&str) -> Result<String, Box<dyn Error>>,
+{
+    fn run(&self, text: &str) -> Result<String, Box<dyn Error>> {
+        self(text)
+    }
+}
+
+/// The line endings of the formatted output.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LineEnding {
+    /// Line feeds, as used on Unix-like systems
+    #[default]
+    Lf,
+    /// Carriage returns followed by line feeds, as used on Windows
+    CrLf,
+}
+
+impl LineEnding {
+    /// Convert the line feeds of the given text to these line endings.
+    pub(crate) fn apply(self, text: &str) -> String {
+        match self {
+            Self::Lf => text.to_string(),
+            Self::CrLf => text.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// What the formatter does with the byte order mark that starts its input, if any. The mark is
+/// never parsed, as Tree-sitter would take it for part of the first token.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ByteOrderMark {
+    /// Start the output with a byte order mark if the input starts with one
+    #[default]
+    Preserve,
+    /// Leave the byte order mark out of the output
+    Strip,
+}
+
+/// A UTF-8 byte order mark.
+pub(crate) const BYTE_ORDER_MARK: &str = "\u{feff}";
+
+/// A writer that converts the line feeds written through it to the given line endings, so that
+/// the output can be streamed whatever its line endings.
+pub(crate) struct LineEndingWriter<W> {
+    inner: W,
+    line_ending: LineEnding,
+}
+
+impl<W> LineEndingWriter<W> {
+    pub(crate) fn new(inner: W, line_ending: LineEnding) -> Self {
+        Self { inner, line_ending }
+    }
+}
+
+impl<W: io::Write> io::Write for LineEndingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.line_ending {
+            LineEnding::Lf => self.inner.write(buf),
+            LineEnding::CrLf => {
+                for (index, line) in buf.split(|&byte| byte == b'\n').enumerate() {
+                    if index > 0 {
+                        self.inner.write_all(b"\r\n")?;
+                    }
+                    self.inner.write_all(line)?;
+                }
+
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The options used by `format`. They are created from the query, language and grammar to
+/// format with, and can then be adjusted one by one.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{format, Configuration, FormatterOptions, LineEnding};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let options = FormatterOptions::new(&query, language, &grammar)
+///     .indent("    ")
+///     .line_ending(LineEnding::CrLf);
+///
+/// let mut output = Vec::new();
+/// format(&mut "[1,2]".as_bytes(), &mut output, &options).unwrap();
+///
+/// assert_eq!(String::from_utf8(output).unwrap(), "[ 1, 2 ]\r\n");
+/// # }) // end tokio_test
+/// ```
+#[derive(Clone)]
+pub struct FormatterOptions<'a> {
+    pub(crate) query: &'a str,
+    /// The query, compiled beforehand (e.g., by a `LanguageRegistry`)
+    pub(crate) compiled_query: Option<&'a CompiledQuery>,
+    pub(crate) language: &'a Language,
+    pub(crate) grammar: &'a tree_sitter_facade::Language,
+    pub(crate) skip_idempotence: bool,
+    pub(crate) tolerate_parsing_errors: bool,
+    indent: Option<String>,
+    line_width: Option<usize>,
+    pub(crate) line_ending: LineEnding,
+    pub(crate) byte_order_mark: ByteOrderMark,
+    pub(crate) encoding: Option<&'static Encoding>,
+    pre_processing_hooks: Vec<Rc<dyn Hook>>,
+    pub(crate) post_processing_hooks: Vec<Rc<dyn Hook>>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) trace_queries: Option<RangeInclusive<usize>>,
+    pub(crate) verify_tokens: bool,
+    pub(crate) verify_comments: bool,
+    pub(crate) memory_budget: Option<usize>,
+    pub(crate) on_warning: Option<Rc<dyn Fn(&Warning)>>,
+    pub(crate) injections: Option<&'a LanguageRegistry>,
+}
+
+impl<'a> FormatterOptions<'a> {
+    /// The default options for formatting with the given query, language and grammar: the
+    /// idempotence check is performed, parsing errors are not tolerated, and the indentation and
+    /// line width are those of the language configuration.
+    pub fn new(
+        query: &'a str,
+        language: &'a Language,
+        grammar: &'a tree_sitter_facade::Language,
+    ) -> Self {
+        Self {
+            query,
+            compiled_query: None,
+            language,
+            grammar,
+            skip_idempotence: false,
+            tolerate_parsing_errors: false,
+            indent: None,
+            line_width: None,
+            line_ending: LineEnding::default(),
+            byte_order_mark: ByteOrderMark::default(),
+            encoding: None,
+            pre_processing_hooks: Vec::new(),
+            post_processing_hooks: Vec::new(),
+            cancellation: None,
+            timeout: None,
+            trace_queries: None,
+            verify_tokens: false,
+            verify_comments: false,
+            memory_budget: None,
+            on_warning: None,
+            injections: None,
+        }
+    }
+
+    /// Format with another query.
+    pub fn query(mut self, query: &'a str) -> Self {
+        self.query = query;
+        self.compiled_query = None;
+        self
+    }
+
+    /// Format as another language, with its grammar.
+    pub fn language(
+        mut self,
+        language: &'a Language,
+        grammar: &'a tree_sitter_facade::Language,
+    ) -> Self {
+        self.language = language;
+        self.grammar = grammar;
+        self.compiled_query = None;
+        self
+    }
+
+    /// If true, skips the idempotence check (where we format twice, succeeding only if the
+    /// intermediate and final result are identical).
+    pub fn skip_idempotence(mut self, skip_idempotence: bool) -> Self {
+        self.skip_idempotence = skip_idempotence;
+        self
+    }
+
+    /// If true, Topiary will consider an ERROR as it does a leaf node, and continues formatting
+    /// instead of exiting with an error.
+    pub fn tolerate_parsing_errors(mut self, tolerate_parsing_errors: bool) -> Self {
+        self.tolerate_parsing_errors = tolerate_parsing_errors;
+        self
+    }
+
+    /// Indent with the given string, rather than with the indentation of the language.
+    pub fn indent(mut self, indent: impl Into<String>) -> Self {
+        self.indent = Some(indent.into());
+        self
+    }
+
+    /// Lay out custom scopes within the given line width, rather than that of the language.
+    pub fn line_width(mut self, line_width: usize) -> Self {
+        self.line_width = Some(line_width);
+        self
+    }
+
+    /// End the lines of the output with the given line endings.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Preserve or strip the byte order mark that starts the input, if any, once it has been
+    /// pre-processed. The default is to preserve it.
+    pub fn byte_order_mark(mut self, byte_order_mark: ByteOrderMark) -> Self {
+        self.byte_order_mark = byte_order_mark;
+        self
+    }
+
+    /// Read the input of `format` in the given encoding, as found by `Encoding::for_label`, rather
+    /// than in UTF-8, and write its output in the same encoding. Inputs that start with a byte
+    /// order mark are read in the encoding that it gives, whatever this option.
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// Run the given hook on the input, before it is parsed. Hooks run in the order in which they
+    /// are added.
+    pub fn pre_process(mut self, hook: impl Hook + 'static) -> Self {
+        self.pre_processing_hooks.push(Rc::new(hook));
+        self
+    }
+
+    /// Run the given hook on the output, before the idempotence check. Hooks run in the order in
+    /// which they are added. As the idempotence check formats the output again, with the same
+    /// hooks, they should be idempotent themselves.
+    pub fn post_process(mut self, hook: impl Hook + 'static) -> Self {
+        self.post_processing_hooks.push(Rc::new(hook));
+        self
+    }
+
+    /// Abort formatting, with `FormatterError::Cancelled`, once the given token is cancelled.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Abort formatting, with `FormatterError::Timeout`, once it has taken longer than the given
+    /// time, including the idempotence check. Like cancellation, the timeout is checked between
+    /// the stages of formatting and while the query is applied, so formatting can overrun it by
+    /// the time that parsing, or another stage, takes.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Trace the captures that the query applies to the nodes that overlap the given range of
+    /// lines of the input, starting from 1 (e.g., `1..=usize::MAX` for the whole input). Every node
+    /// is logged with the `topiary::trace` target, at the info level, along with the captures
+    /// applied to it, their lines in the query, and the atoms they produced.
+    pub fn trace_queries(mut self, lines: RangeInclusive<usize>) -> Self {
+        self.trace_queries = Some(lines);
+        self
+    }
+
+    /// Check that the output has the same tokens as the input, i.e., the texts of its named leaf
+    /// nodes, up to their whitespace, in the same order. Formatting fails with
+    /// `FormatterError::Tokens` at the first token that was lost, duplicated or reordered. The
+    /// check runs before the post-processing hooks, and also when the idempotence check is
+    /// skipped.
+    pub fn verify_tokens(mut self, verify_tokens: bool) -> Self {
+        self.verify_tokens = verify_tokens;
+        self
+    }
+
+    /// Check that every comment of the input, i.e., every named node whose kind mentions
+    /// comments, appears exactly once in the output, up to its whitespace, wherever it was moved
+    /// to. Formatting fails with `FormatterError::Comments`, giving the text and the position of
+    /// the first comment that was dropped or duplicated. Like the token check, it runs before the
+    /// post-processing hooks, and also when the idempotence check is skipped.
+    pub fn verify_comments(mut self, verify_comments: bool) -> Self {
+        self.verify_comments = verify_comments;
+        self
+    }
+
+    /// Bound the memory that formatting holds on to beyond the input and its syntax tree. Inputs
+    /// larger than the given number of bytes, whose size is taken as an estimate of that of their
+    /// output, are formatted without holding their output in memory: it is streamed to the
+    /// writer as it is rendered, and the idempotence check, the token and comment checks, which
+    /// need a copy of it, are skipped with a warning. Post-processing hooks need the whole output,
+    /// so they still hold it in memory.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Call the given function with every warning, i.e., every problem that the formatter works
+    /// around without failing, such as injected code that could not be formatted, or scopes of
+    /// the query that do not match up. Warnings are logged regardless.
+    pub fn on_warning(mut self, callback: impl Fn(&Warning) + 'static) -> Self {
+        self.on_warning = Some(Rc::new(callback));
+        self
+    }
+
+    /// Format the regions of the input that are written in another language with the languages
+    /// of the given registry, i.e., with its configuration and its query files, rather than with
+    /// the built-in configuration and the query files on disk. Either way, every injected
+    /// language is loaded only once per input, or once for all inputs in the case of a registry.
+    pub fn injections(mut self, registry: &'a LanguageRegistry) -> Self {
+        self.injections = Some(registry);
+        self
+    }
+
+    /// Log the warning, and pass it to the callback, if any.
+    pub(crate) fn warn(&self, warning: Warning) {
+        log::warn!("{warning}");
+        if let Some(callback) = &self.on_warning {
+            callback(&warning);
+        }
+    }
+
+    /// Run the pre-processing hooks on the input, then take off the byte order mark that starts
+    /// it, if any. It is returned along with whether the output should start with one. Without
+    /// hooks, the input is borrowed rather than copied.
+    pub(crate) fn pre_processed<'b>(
+        &self,
+        input: &'b str,
+    ) -> FormatterResult<(Cow<'b, str>, bool)> {
+        let input = if self.pre_processing_hooks.is_empty() {
+            Cow::Borrowed(input)
+        } else {
+            let message = "A pre-processing hook failed";
+            Cow::Owned(run_hooks(
+                &self.pre_processing_hooks,
+                input.into(),
+                message,
+            )?)
+        };
+
+        let has_mark = input.starts_with(BYTE_ORDER_MARK);
+        let content = match input {
+            Cow::Borrowed(input) if has_mark => Cow::Borrowed(&input[BYTE_ORDER_MARK.len()..]),
+            Cow::Owned(mut input) if has_mark => {
+                input.drain(..BYTE_ORDER_MARK.len());
+                Cow::Owned(input)
+            }
+            input => input,
+        };
+
+        Ok((
+            content,
+            has_mark && self.byte_order_mark == ByteOrderMark::Preserve,
+        ))
+    }
+
+    /// Run the post-processing hooks on the output.
+    pub(crate) fn post_processed(&self, output: String) -> FormatterResult<String> {
+        run_hooks(
+            &self.post_processing_hooks,
+            output,
+            "A post-processing hook failed",
+        )
+    }
+
+    /// The language configuration, with the indentation and line width overridden.
+    pub(crate) fn effective_language(&self) -> Language {
+        let mut language = self.language.clone();
+
+        if let Some(indent) = &self.indent {
+            language.indent = Some(indent.clone());
+        }
+
+        if let Some(line_width) = self.line_width {
+            language.line_width = Some(line_width);
+        }
+
+        language
+    }
+}
+
+fn run_hooks(hooks: &[Rc<dyn Hook>], text: String, message: &str) -> FormatterResult<String> {
+    hooks.iter().try_fold(text, |text, hook| {
+        hook.run(&text)
+            .map_err(|e| FormatterError::Hook(message.into(), e))
+    })
+}
@@ -0,0 +1,26 @@
+//! The `topiary: skip-file` pragma, with which a file, e.g., a generated one, opts out of being
+//! formatted, wherever it is formatted from.
+
+/// The text of the pragma, which is usually written in a comment.
+pub const SKIP_FILE_PRAGMA: &str = "topiary: skip-file";
+
+/// The number of lines at the start of a file in which the pragma is looked for.
+const PRAGMA_LINES: usize = 5;
+
+/// Whether the content has the `topiary: skip-file` pragma in one of its first five lines, in
+/// which case `format` and `format_bytes` leave it as it is.
+///
+/// # Examples
+///
+/// ```
+/// use topiary::skips_file;
+///
+/// assert!(skips_file("#!/bin/sh\n# Generated, topiary: skip-file\necho hi\n"));
+/// assert!(!skips_file("echo hi\n"));
+/// ```
+pub fn skips_file(content: &str) -> bool {
+    content
+        .lines()
+        .take(PRAGMA_LINES)
+        .any(|line| line.contains(SKIP_FILE_PRAGMA))
+}
@@ -2,10 +2,25 @@
 //! module is reponsible for rendering the slice of Atoms back into a displayable
 //! format.
 
-use std::fmt::Write;
+use std::{
+    fmt::{self, Write},
+    io,
+    ops::Range,
+};
 
 use crate::{Atom, FormatterError, FormatterResult};
 
+/// A destination for rendered output, which knows how many bytes were written to it.
+trait Output: Write {
+    fn position(&self) -> usize;
+}
+
+impl Output for String {
+    fn position(&self) -> usize {
+        self.len()
+    }
+}
+
 /// Renders a slice of Atoms into an owned string.
 /// The indent &str is used when an `Atom::IdentStart` is encountered.
 /// Any string is accepted, but you will probably want to specify something
@@ -16,6 +31,61 @@ use crate::{Atom, FormatterError, FormatterResult};
 /// If an unexpected Atom is encountered, a `FormatterError::Internal` is returned.
 pub fn render(atoms: &[Atom], indent: &str) -> FormatterResult<String> {
     let mut buffer = String::new();
+    render_inner(atoms, indent, &mut buffer, None)?;
+
+    Ok(buffer)
+}
+
+/// Renders a slice of Atoms directly into a writer, trimming whitespace on the fly
+/// exactly as `trim_whitespace` does. Unlike `render`, the rendered output is never
+/// held in memory in its entirety.
+///
+/// # Errors
+///
+/// If an unexpected Atom is encountered, a `FormatterError::Internal` is returned.
+/// If writing fails, a `FormatterError::Io` is returned.
+pub fn render_trimmed_to(
+    atoms: &[Atom],
+    indent: &str,
+    output: &mut impl io::Write,
+) -> FormatterResult<()> {
+    let mut trimmer = Trimmer::new(output);
+    let rendered = render_inner(atoms, indent, &mut trimmer, None);
+
+    // Writing errors are hidden behind `fmt::Error`, so we surface them first
+    if let Some(e) = trimmer.error.take() {
+        return Err(e.into());
+    }
+    rendered?;
+
+    trimmer.finish()?;
+    Ok(())
+}
+
+/// Renders a slice of Atoms into an owned string, like `render`, additionally
+/// returning the ID of every rendered leaf, along with the byte range it
+/// occupies in the rendered string, in order.
+///
+/// # Errors
+///
+/// If an unexpected Atom is encountered, a `FormatterError::Internal` is returned.
+pub fn render_with_leaves(
+    atoms: &[Atom],
+    indent: &str,
+) -> FormatterResult<(String, Vec<(usize, Range<usize>)>)> {
+    let mut buffer = String::new();
+    let mut leaves = Vec::new();
+    render_inner(atoms, indent, &mut buffer, Some(&mut leaves))?;
+
+    Ok((buffer, leaves))
+}
+
+fn render_inner(
+    atoms: &[Atom],
+    indent: &str,
+    buffer: &mut impl Output,
+    mut leaves: Option<&mut Vec<(usize, Range<usize>)>>,
+) -> FormatterResult<()> {
     let mut indent_level: usize = 0;
 
     for atom in atoms {
@@ -41,15 +111,30 @@ pub fn render(atoms: &[Atom], indent: &str) -> FormatterResult<String> {
 
             Atom::Leaf {
                 content,
+                id,
                 single_line_no_indent,
-                ..
+                injected,
             } => {
                 if *single_line_no_indent {
                     // The line break after the content has been previously added
                     // as a `Hardline` in the atom stream.
                     writeln!(buffer)?;
                 }
-                write!(buffer, "{}", content.trim_end_matches('\n'))?;
+
+                let start = buffer.position();
+                let content = content.trim_end_matches('\n');
+                if *injected {
+                    // Splice the formatted code of the injected language, or the
+                    // reindented leaf, in at the current indentation level
+                    let line_break = format!("\n{}", indent.repeat(indent_level));
+                    write!(buffer, "{}", content.replace('\n', &line_break))?;
+                } else {
+                    write!(buffer, "{content}")?;
+                }
+
+                if let Some(leaves) = leaves.as_mut() {
+                    leaves.push((*id, start..buffer.position()));
+                }
             }
 
             Atom::Literal(s) => write!(buffer, "{s}")?,
@@ -66,5 +151,127 @@ pub fn render(atoms: &[Atom], indent: &str) -> FormatterResult<String> {
         };
     }
 
-    Ok(buffer)
+    Ok(())
+}
+
+/// Writes rendered output through to an `io::Write`, trimming whitespace from the end
+/// of each line, as well as from the start and the end of the output, which is then
+/// terminated by a single new line. Only the whitespace that may need to be written
+/// once more content follows is buffered.
+struct Trimmer<'a, W: io::Write> {
+    output: &'a mut W,
+    /// Whether any content other than whitespace was written
+    started: bool,
+    /// The line breaks since the last content
+    pending_newlines: usize,
+    /// The whitespace since the last content, on the current line
+    pending_whitespace: String,
+    /// The number of bytes that were received
+    position: usize,
+    /// The first error that occurred when writing to the output
+    error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write> Trimmer<'a, W> {
+    fn new(output: &'a mut W) -> Self {
+        Self {
+            output,
+            started: false,
+            pending_newlines: 0,
+            pending_whitespace: String::new(),
+            position: 0,
+            error: None,
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        writeln!(self.output)
+    }
+
+    fn write_content(&mut self, content: &str) -> io::Result<()> {
+        if self.started {
+            for _ in 0..self.pending_newlines {
+                writeln!(self.output)?;
+            }
+            self.output.write_all(self.pending_whitespace.as_bytes())?;
+        }
+
+        self.started = true;
+        self.pending_newlines = 0;
+        self.pending_whitespace.clear();
+        self.output.write_all(content.as_bytes())
+    }
+}
+
+impl<W: io::Write> Write for Trimmer<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.position += s.len();
+
+        // The start of the current run of content, which is written out in one go
+        let mut content_start = None;
+
+        for (index, c) in s.char_indices() {
+            if !c.is_whitespace() {
+                content_start.get_or_insert(index);
+                continue;
+            }
+
+            if let Some(start) = content_start.take() {
+                if let Err(e) = self.write_content(&s[start..index]) {
+                    self.error = Some(e);
+                    return Err(fmt::Error);
+                }
+            }
+
+            if c == '\n' {
+                self.pending_whitespace.clear();
+                if self.started {
+                    self.pending_newlines += 1;
+                }
+            } else if self.started {
+                self.pending_whitespace.push(c);
+            }
+        }
+
+        if let Some(start) = content_start {
+            if let Err(e) = self.write_content(&s[start..]) {
+                self.error = Some(e);
+                return Err(fmt::Error);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: io::Write> Output for Trimmer<'_, W> {
+    fn position(&self) -> usize {
+        self.position
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Write;
+
+    use super::Trimmer;
+    use crate::trim_whitespace;
+
+    #[test]
+    fn trimmer_trims_like_trim_whitespace() {
+        let untrimmed = "\n \n  foo  \n\n\tbar \r\n  baz\n\n  ";
+
+        let mut output = Vec::new();
+        let mut trimmer = Trimmer::new(&mut output);
+        // Split the input, to check that whitespace is handled across writes
+        for chunk in ["\n \n  fo", "o  \n", "\n\tbar \r", "\n  baz\n\n  "] {
+            trimmer.write_str(chunk).unwrap();
+        }
+        trimmer.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            trim_whitespace(untrimmed)
+        );
+    }
 }
@@ -0,0 +1,115 @@
+//! Measuring how long each pattern of a query takes to match, so that query maintainers can find
+//! the patterns, often catch-all ones, that make formatting large inputs slow.
+
+use std::time::{Duration, Instant};
+
+use tree_sitter_facade::QueryCursor;
+
+use crate::{tree_sitter, FormatterResult};
+
+/// The cost of each pattern of a query over a corpus of inputs, as measured by `profile_query`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryProfile {
+    /// Every pattern of the query, in the order in which they appear in it
+    pub patterns: Vec<PatternProfile>,
+}
+
+/// The cost of a pattern of a query over a corpus of inputs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatternProfile {
+    /// The line of the start of the pattern in the query, starting from 1
+    pub line: usize,
+    /// The number of matches of the pattern, across all inputs
+    pub matches: usize,
+    /// The time spent matching the pattern, across all inputs
+    pub time: Duration,
+}
+
+impl QueryProfile {
+    /// The patterns of the query, from the slowest to the fastest.
+    pub fn slowest(&self) -> Vec<&PatternProfile> {
+        let mut patterns: Vec<&PatternProfile> = self.patterns.iter().collect();
+        patterns.sort_by(|a, b| b.time.cmp(&a.time));
+
+        patterns
+    }
+}
+
+/// Match each pattern of the query against each of the inputs, counting its matches and
+/// measuring the time that they take. Tree-sitter matches all the patterns of a query at once,
+/// without telling how long each of them took, so every pattern is matched on its own: the times
+/// add up to more than that of matching the whole query, but they show which patterns are
+/// expensive.
+///
+/// Matches are counted regardless of the predicates that Topiary evaluates itself, such as
+/// `#single_line?`, and the time that Topiary takes to apply the captures is not included.
+///
+/// # Errors
+///
+/// If the query cannot be compiled, or an input cannot be parsed, a `FormatterError` will be
+/// returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use topiary::{profile_query, Configuration};
+///
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let query = "(pair \":\" @append_space)\n(_) @allow_blank_line_before";
+/// let profile = profile_query(query, &grammar, &[r#"{"a":1,"b":2}"#]).unwrap();
+///
+/// let matches: Vec<usize> = profile.patterns.iter().map(|p| p.matches).collect();
+/// assert_eq!(matches[0], 2);
+/// assert!(matches[1] > matches[0]);
+/// # }) // end tokio_test
+/// ```
+pub fn profile_query(
+    query_content: &str,
+    grammar: &tree_sitter_facade::Language,
+    inputs: &[&str],
+) -> FormatterResult<QueryProfile> {
+    let query = tree_sitter::compile_query(query_content, grammar)?.query;
+    let pattern_count = query.pattern_count();
+
+    let trees = inputs
+        .iter()
+        .map(|input| Ok((tree_sitter::parse(input, grammar, false, None)?.0, input)))
+        .collect::<FormatterResult<Vec<_>>>()?;
+
+    let mut patterns = Vec::new();
+
+    for index in 0..pattern_count {
+        let start = query.start_byte_for_pattern(index);
+        let end = if index == pattern_count - 1 {
+            query_content.len()
+        } else {
+            query.start_byte_for_pattern(index + 1)
+        };
+
+        // The pattern is compiled on its own, which is not measured
+        let pattern = tree_sitter::compile_query(&query_content[start..end], grammar)?.query;
+
+        let mut profile = PatternProfile {
+            line: tree_sitter::pattern_line(&query, query_content, index),
+            matches: 0,
+            time: Duration::ZERO,
+        };
+
+        for (tree, input) in &trees {
+            let mut cursor = QueryCursor::new();
+            let started = Instant::now();
+            profile.matches += pattern
+                .matches(&tree.root_node(), input.as_bytes(), &mut cursor)
+                .count();
+            profile.time += started.elapsed();
+        }
+
+        patterns.push(profile);
+    }
+
+    Ok(QueryProfile { patterns })
+}
@@ -0,0 +1,48 @@
+//! Comparing the outputs of two versions of a query over a corpus of inputs, so that the
+//! maintainers of a query can see which inputs a change to it affects, and how.
+
+use crate::{format, snapshot::diff, FormatterOptions, FormatterResult};
+
+/// Format the input with the options of the old version of a query, then with those of the new
+/// one, which usually only differ in their query. Returns the differences between the two
+/// outputs, line by line, from the old output to the new one, or `None` if they are the same.
+///
+/// # Errors
+///
+/// If formatting fails with either of the options, for any reason, a `FormatterError` will be
+/// returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use topiary::{diff_queries, Configuration, FormatterOptions};
+///
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let old = FormatterOptions::new("(pair \":\" @append_space)", language, &grammar);
+/// let new = old.clone().query("(pair \":\" @prepend_space @append_space)");
+///
+/// assert!(diff_queries("[1]", &old, &new).unwrap().is_none());
+/// assert!(diff_queries(r#"{"a":1}"#, &old, &new).unwrap().is_some());
+/// # }) // end tokio_test
+/// ```
+pub fn diff_queries(
+    input: &str,
+    old: &FormatterOptions,
+    new: &FormatterOptions,
+) -> FormatterResult<Option<String>> {
+    let formatted = |options: &FormatterOptions| -> FormatterResult<String> {
+        let mut output = Vec::new();
+        format(&mut input.as_bytes(), &mut output, options)?;
+
+        Ok(String::from_utf8(output)?)
+    };
+
+    let old_output = formatted(old)?;
+    let new_output = formatted(new)?;
+
+    Ok((old_output != new_output).then(|| diff(&old_output, &new_output)))
+}
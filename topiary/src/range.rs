@@ -0,0 +1,243 @@
+//! Range formatting, for editors and language servers that only want to reformat a selection of
+//! the input, or the node around the cursor as the user types, leaving the rest of it untouched.
+
+use std::ops::Range;
+
+use tree_sitter_facade::Node;
+
+use crate::{
+    format_with_spans, offset::map_offset, FormatterError, FormatterOptions, FormatterResult,
+    Language, LeafSpan,
+};
+
+/// The result of range formatting: the `replaced` byte range of the input should be replaced by
+/// the `replacement` text.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RangeReplacement {
+    pub replaced: Range<usize>,
+    pub replacement: String,
+}
+
+/// The result of formatting the node around the cursor: the `replaced` byte range of the input
+/// should be replaced by the `replacement` text, after which the cursor should be moved to the
+/// byte offset `cursor`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NodeReplacement {
+    pub replaced: Range<usize>,
+    pub replacement: String,
+    pub cursor: usize,
+}
+
+/// Format the given byte range of the input. The range is first expanded to the nearest complete
+/// syntax nodes that enclose it, which becomes the replaced range. Its replacement is taken from
+/// the formatted output of the whole input, so it is indented as it would be in context. The
+/// whitespace surrounding the replaced range is left as it is.
+///
+/// # Errors
+///
+/// If the range is out of bounds, or formatting fails for any reason, a `FormatterError` will be
+/// returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{format_range, Configuration};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// // Select part of the first pair
+/// let input = r#"{"a":1,  "b":2}"#;
+/// let replacement = format_range(input, 3..5, &query, language, &grammar, false, false).unwrap();
+///
+/// assert_eq!(replacement.replaced, 1..6);
+/// assert_eq!(replacement.replacement, r#""a": 1"#);
+/// # }) // end tokio_test
+/// ```
+pub fn format_range(
+    input: &str,
+    range: Range<usize>,
+    query: &str,
+    language: &Language,
+    grammar: &tree_sitter_facade::Language,
+    skip_idempotence: bool,
+    tolerate_parsing_errors: bool,
+) -> FormatterResult<RangeReplacement> {
+    if input.get(range.clone()).is_none() {
+        return Err(FormatterError::InvalidRange(range));
+    }
+
+    let options = FormatterOptions::new(query, language, grammar)
+        .skip_idempotence(skip_idempotence)
+        .tolerate_parsing_errors(tolerate_parsing_errors);
+    let (tree, output, spans) = format_with_spans(input, &options)?;
+
+    let replaced = enclosing_nodes(tree.root_node(), &range);
+    let replacement = output_range(&replaced, &spans)
+        .map_or_else(String::new, |within| output[within].to_string());
+
+    Ok(RangeReplacement {
+        replaced,
+        replacement,
+    })
+}
+
+/// Format the smallest node that encloses the cursor, a byte offset of the input, and whose kind
+/// is one of the `formattable_nodes` of the language, e.g., the statement in which the user just
+/// typed a semicolon or a new line. If the language lists none of them, or none encloses the
+/// cursor, the top-level node that encloses it is formatted. As with `format_range`, the
+/// replacement is taken from the formatted output of the whole input, and the replaced range is
+/// that of the node, without its surrounding whitespace. The new cursor is where the cursor ends
+/// up once the replacement is made, following the leaf that it was in, or after.
+///
+/// # Errors
+///
+/// If the cursor is out of bounds, or does not fall on a character boundary, or formatting fails
+/// for any reason, a `FormatterError` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{format_enclosing_node, Configuration, FormatterOptions};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// // The cursor follows the value of the second pair, which is formatted on its own
+/// let input = "{\n  \"a\": 1,\n  \"b\":2\n}\n";
+/// let options = FormatterOptions::new(&query, language, &grammar);
+/// let formatted = format_enclosing_node(input, 19, &options).unwrap();
+///
+/// assert_eq!(formatted.replaced, 14..19);
+/// assert_eq!(formatted.replacement, "\"b\": 2");
+/// assert_eq!(formatted.cursor, 20);
+/// # }) // end tokio_test
+/// ```
+pub fn format_enclosing_node(
+    input: &str,
+    cursor: usize,
+    options: &FormatterOptions,
+) -> FormatterResult<NodeReplacement> {
+    if !input.is_char_boundary(cursor) {
+        return Err(FormatterError::InvalidRange(cursor..cursor));
+    }
+
+    let (tree, output, spans) = format_with_spans(input, options)?;
+
+    let replaced = formattable_node(tree.root_node(), cursor, options.language);
+
+    let (replacement, cursor) = match output_range(&replaced, &spans) {
+        Some(within) => {
+            let mapped = map_offset(cursor, &spans, &output).clamp(within.start, within.end);
+            (
+                output[within.clone()].to_string(),
+                replaced.start + mapped - within.start,
+            )
+        }
+        // All the leaves of the node were deleted by formatting
+        None => (String::new(), replaced.start),
+    };
+
+    Ok(NodeReplacement {
+        replaced,
+        replacement,
+        cursor,
+    })
+}
+
+/// The byte range of the output that the leaves within the given range of the input were
+/// formatted into, if any of them was kept.
+fn output_range(replaced: &Range<usize>, spans: &[LeafSpan]) -> Option<Range<usize>> {
+    let mut within = spans
+        .iter()
+        .filter(|span| replaced.start <= span.input.start && span.input.end <= replaced.end);
+    let first = within.next()?;
+    let last = within.last().unwrap_or(first);
+
+    Some(first.output.start..last.output.end)
+}
+
+/// The byte range of the smallest formattable node of the language that encloses the cursor, or
+/// else of the top-level node that does. A cursor that immediately follows a node, as after typing
+/// its last character, is enclosed by it.
+fn formattable_node(root: Node, cursor: usize, language: &Language) -> Range<usize> {
+    let node_range = |node: &Node| node.start_byte() as usize..node.end_byte() as usize;
+
+    // The nodes that enclose the cursor, from the root down
+    let mut path = vec![root];
+    loop {
+        let node = &path[path.len() - 1];
+        let child = node.children(&mut node.walk()).find(|child| {
+            let child = node_range(child);
+            !child.is_empty() && child.start <= cursor && cursor <= child.end
+        });
+
+        match child {
+            Some(child) => path.push(child),
+            None => break,
+        }
+    }
+
+    let formattable = path.iter().rev().find(|node| {
+        let kind = node.kind();
+        language
+            .formattable_nodes
+            .iter()
+            .any(|formattable| formattable.as_str() == kind)
+    });
+
+    match formattable.or(path.get(1)) {
+        Some(node) => node_range(node),
+        None => node_range(&path[0]),
+    }
+}
+
+/// The byte range of the smallest sequence of sibling nodes that encloses the given range. An
+/// empty range selects the character that follows it.
+fn enclosing_nodes(root: Node, range: &Range<usize>) -> Range<usize> {
+    let target = if range.is_empty() {
+        range.start..range.start + 1
+    } else {
+        range.clone()
+    };
+
+    let node_range = |node: &Node| node.start_byte() as usize..node.end_byte() as usize;
+
+    let mut node = root;
+    loop {
+        let overlapping: Vec<Node> = node
+            .children(&mut node.walk())
+            .filter(|child| {
+                let child = node_range(child);
+                !child.is_empty() && child.start < target.end && target.start < child.end
+            })
+            .collect();
+
+        match overlapping.as_slice() {
+            // The range only covers whitespace between the children
+            [] => return node_range(&node),
+
+            // Descend into the only child that encloses the range, if it can be narrowed further
+            [child]
+                if child.child_count() > 0
+                    && child.start_byte() as usize <= target.start
+                    && target.end <= child.end_byte() as usize =>
+            {
+                node = child.clone();
+            }
+
+            [first, ..] => {
+                let last = overlapping.last().unwrap_or(first);
+                return first.start_byte() as usize..last.end_byte() as usize;
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! A registry of loaded languages, for long-lived and multi-threaded embedders that format many
+//! inputs: each grammar is loaded, and each query compiled, only once.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    tree_sitter::{self, CompiledQuery},
+    Configuration, FileProvider, FormatterError, FormatterOptions, FormatterResult, Language,
+    OsFileProvider,
+};
+
+/// A language along with its grammar and its compiled query, ready to format with.
+pub struct LoadedLanguage {
+    pub language: Language,
+    pub grammar: tree_sitter_facade::Language,
+    /// The content of the query file
+    pub query: String,
+    compiled_query: CompiledQuery,
+}
+
+impl LoadedLanguage {
+    /// The default options for formatting with this language, which reuse its compiled query.
+    pub fn options(&self) -> FormatterOptions {
+        let mut options = FormatterOptions::new(&self.query, &self.language, &self.grammar);
+        options.compiled_query = Some(&self.compiled_query);
+        options
+    }
+}
+
+/// Loads the languages of a configuration on first use, and shares them afterwards. The registry
+/// can be shared across threads, e.g., behind an `Arc`.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use topiary::{format, Configuration, LanguageRegistry};
+///
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let registry = LanguageRegistry::new(config);
+///
+/// let json = registry.get("json").await.unwrap();
+///
+/// let mut output = Vec::new();
+/// format(&mut "[1,2]".as_bytes(), &mut output, &json.options()).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "[ 1, 2 ]\n");
+/// # }) // end tokio_test
+/// ```
+pub struct LanguageRegistry {
+    configuration: Configuration,
+    files: Box<dyn FileProvider + Send + Sync>,
+    loaded: Mutex<HashMap<String, Arc<LoadedLanguage>>>,
+}
+
+impl LanguageRegistry {
+    /// Create an empty registry of the languages of the given configuration, whose query files
+    /// are read from disk.
+    pub fn new(configuration: Configuration) -> Self {
+        Self::with_file_provider(configuration, OsFileProvider)
+    }
+
+    /// Like `new`, but the query files are read from the given provider (e.g., to use the
+    /// unsaved query files of an editor).
+    pub fn with_file_provider(
+        configuration: Configuration,
+        files: impl FileProvider + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            configuration,
+            files: Box::new(files),
+            loaded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the language of the given name, loading its grammar and compiling its query file if
+    /// this is the first time it is requested.
+    ///
+    /// # Errors
+    ///
+    /// If the language is unknown, or its grammar or query cannot be loaded, a `FormatterError`
+    /// will be returned.
+    pub async fn get(&self, name: &str) -> FormatterResult<Arc<LoadedLanguage>> {
+        if let Some(loaded) = self.lock()?.get(name) {
+            return Ok(loaded.clone());
+        }
+
+        let language = self.configuration.get_language(name)?;
+        let query = language.read_query(self.files.as_ref())?;
+
+        log::info!("Load the grammar and query of {name}");
+        let grammar = language.grammar().await?;
+
+        self.insert(name, language, grammar, query)
+    }
+
+    /// Like `get`, but for a language that is injected in an input while it is formatted, where
+    /// grammars cannot be awaited: the grammar is the one built into Topiary. As code blocks often
+    /// name their language by its file extension instead, e.g., "py", languages are also found by
+    /// their extensions.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn get_injected(&self, name: &str) -> FormatterResult<Arc<LoadedLanguage>> {
+        if let Some(loaded) = self.lock()?.get(name) {
+            return Ok(loaded.clone());
+        }
+
+        let language = self.configuration.get_language(name).or_else(|error| {
+            self.configuration
+                .language
+                .iter()
+                .find(|language| language.extensions.contains(name))
+                .ok_or(error)
+        })?;
+        let query = language.read_query(self.files.as_ref())?;
+
+        log::info!("Load the grammar and query of the injected language {name}");
+        let grammar = language.static_grammar()?;
+
+        self.insert(name, language, grammar, query)
+    }
+
+    fn insert(
+        &self,
+        name: &str,
+        language: &Language,
+        grammar: tree_sitter_facade::Language,
+        query: String,
+    ) -> FormatterResult<Arc<LoadedLanguage>> {
+        let compiled_query = tree_sitter::compile_query(&query, &grammar)?;
+
+        let loaded = Arc::new(LoadedLanguage {
+            language: language.clone(),
+            grammar,
+            query,
+            compiled_query,
+        });
+
+        // If another thread loaded the language in the meantime, keep its copy
+        Ok(self
+            .lock()?
+            .entry(name.to_string())
+            .or_insert(loaded)
+            .clone())
+    }
+
+    fn lock(&self) -> FormatterResult<MutexGuard<HashMap<String, Arc<LoadedLanguage>>>> {
+        self.loaded.lock().map_err(|_| {
+            FormatterError::Internal("The language registry has been poisoned".into(), None)
+        })
+    }
+}
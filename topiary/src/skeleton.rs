@@ -0,0 +1,147 @@
+//! Generating a starter query file for a language, from the node kinds of its grammar, so that
+//! adding a language does not start from an empty file.
+
+use std::collections::BTreeSet;
+
+/// The punctuation that the skeleton formats, along with the captures that it applies to it. They
+/// are educated guesses, which suit most C-like languages.
+const PUNCTUATION: &[(&[&str], &str)] = &[
+    (&[","], "@append_spaced_softline"),
+    (&[";"], "@append_hardline"),
+    (&[":"], "@append_space"),
+    (
+        &[
+            "=", "==", "!=", "<=", ">=", "+=", "-=", "->", "=>", "&&", "||",
+        ],
+        "@prepend_space @append_space",
+    ),
+    (&["(", "["], "@append_antispace"),
+    (&[")", "]"], "@prepend_antispace"),
+    (
+        &["{"],
+        "@prepend_space @append_spaced_softline @append_indent_start",
+    ),
+    (&["}"], "@prepend_indent_end @prepend_spaced_softline"),
+];
+
+/// Generate a starter query file for the language of the given name, from the node kinds of its
+/// grammar. The query declares the nodes whose kind mentions strings as leaves, keeps the line
+/// breaks around comments from the input, spaces the common punctuation and the keywords that the
+/// grammar has, and ends with a TODO for each of the other named nodes, whose layout is left to
+/// the query author.
+///
+/// The grammar does not tell which nodes are strings or comments, nor what its punctuation means:
+/// the query is a starting point, to be checked against the formatting of actual inputs.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use topiary::{skeleton_query, validate_query, Configuration};
+///
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let query = skeleton_query("json", &grammar);
+///
+/// assert!(query.contains("(string)"));
+/// assert!(query.contains("; TODO (object)"));
+/// assert!(validate_query(&query, &grammar).unwrap().is_empty());
+/// # }) // end tokio_test
+/// ```
+pub fn skeleton_query(language_name: &str, grammar: &tree_sitter_facade::Language) -> String {
+    let mut named: BTreeSet<&str> = BTreeSet::new();
+    let mut anonymous: BTreeSet<&str> = BTreeSet::new();
+
+    for id in 0..grammar.node_kind_count() {
+        let id = id as u16;
+        if !grammar.node_kind_is_visible(id) {
+            continue;
+        }
+
+        if let Some(kind) = grammar.node_kind_for_id(id) {
+            if grammar.node_kind_is_named(id) {
+                named.insert(kind);
+            } else {
+                anonymous.insert(kind);
+            }
+        }
+    }
+
+    let strings: Vec<String> = named
+        .iter()
+        .filter(|kind| kind.contains("string"))
+        .map(|kind| format!("({kind})"))
+        .collect();
+    let comments: Vec<String> = named
+        .iter()
+        .filter(|kind| kind.contains("comment"))
+        .map(|kind| format!("({kind})"))
+        .collect();
+    let keywords: Vec<String> = anonymous
+        .iter()
+        .filter(|kind| kind.chars().all(|c| c.is_ascii_alphabetic() || c == '_'))
+        .map(|kind| format!("\"{kind}\""))
+        .collect();
+
+    let mut lines = vec![
+        format!("; A starter query for {language_name}, generated from the node kinds of its"),
+        "; grammar. Its rules are educated guesses, to be checked against the formatting".into(),
+        "; of actual inputs, and the nodes listed at the end still need rules of their own.".into(),
+        String::new(),
+    ];
+
+    push_rule(
+        &mut lines,
+        "Strings are kept as they are in the input",
+        &strings,
+        "@leaf",
+    );
+    push_rule(
+        &mut lines,
+        "Comments keep the line breaks around them from the input",
+        &comments,
+        "@allow_blank_line_before @prepend_input_softline @append_input_softline",
+    );
+    push_rule(
+        &mut lines,
+        "Keywords are separated from what follows them",
+        &keywords,
+        "@append_space",
+    );
+
+    for (kinds, captures) in PUNCTUATION {
+        let kinds: Vec<String> = kinds
+            .iter()
+            .filter(|kind| anonymous.contains(*kind))
+            .map(|kind| format!("\"{kind}\""))
+            .collect();
+
+        push_rule(&mut lines, "Punctuation", &kinds, captures);
+    }
+
+    lines.push("; TODO Decide the layout of the other named nodes".into());
+    lines.extend(
+        named
+            .iter()
+            .filter(|kind| !kind.contains("string") && !kind.contains("comment"))
+            .map(|kind| format!("; TODO ({kind})")),
+    );
+
+    lines.join("\n") + "\n"
+}
+
+/// Add a rule that applies the captures to all the patterns, unless there are none, preceded by
+/// a comment.
+fn push_rule(lines: &mut Vec<String>, comment: &str, patterns: &[String], captures: &str) {
+    if patterns.is_empty() {
+        return;
+    }
+
+    lines.push(format!("; {comment}"));
+    lines.push("[".into());
+    lines.extend(patterns.iter().map(|pattern| format!("  {pattern}")));
+    lines.push(format!("] {captures}"));
+    lines.push(String::new());
+}
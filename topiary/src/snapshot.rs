@@ -0,0 +1,270 @@
+//! Snapshot tests of query files: inputs that are formatted and compared with the output that is
+//! expected of them, as Topiary tests its own queries. Query maintainers can use them to test
+//! their queries without building a harness of their own.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use prettydiff::text::{diff_lines, ContextConfig};
+
+use crate::{
+    encoding::{looks_binary, BINARY_CHECK_BYTES},
+    format, Configuration, FormatterError, FormatterOptions, FormatterResult, IoError, Language,
+    OsFileProvider,
+};
+
+/// An input file, along with the file that holds the output that is expected of formatting it.
+#[derive(Clone, Debug)]
+pub struct SnapshotTest<'a> {
+    /// The input file
+    pub input: PathBuf,
+    /// The file that holds the expected output, which may not exist yet
+    pub expected: PathBuf,
+    /// The language of the input, detected from its name
+    pub language: &'a Language,
+}
+
+/// How the directory walks of `snapshot_tests` and `corpus_tests` treat symbolic links.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum Symlinks {
+    /// Follow symbolic links to files and directories. Every directory is visited once, so that
+    /// links that point back to a directory being walked cannot make the walk loop.
+    #[default]
+    Follow,
+    /// Skip symbolic links, with a warning, e.g. so that a symlinked vendor tree is not tested
+    Skip,
+}
+
+/// The outcome of running a snapshot test.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SnapshotOutcome {
+    /// The input was formatted into the expected output
+    Passed,
+    /// The input was not formatted into the expected output. The diff goes from the expected
+    /// output to the actual one.
+    Mismatch(String),
+    /// There is no expected output for the input
+    Missing,
+    /// The expected output was missing or different, and was overwritten by the actual one
+    Blessed,
+}
+
+/// List the snapshot tests in the given directory, which holds the inputs in its `input`
+/// subdirectory, and their expected outputs under the same names in its `expected`
+/// subdirectory. The tests are sorted by the name of their input. Inputs whose language cannot
+/// be detected from their name, in the given configuration, are skipped, as are the inputs that
+/// look like binary files, with a warning. Inputs that are symbolic links are treated as given.
+///
+/// # Errors
+///
+/// If the `input` subdirectory cannot be read, a `FormatterError::Io` will be returned.
+pub fn snapshot_tests<'a>(
+    dir: &Path,
+    configuration: &'a Configuration,
+    symlinks: Symlinks,
+) -> FormatterResult<Vec<SnapshotTest<'a>>> {
+    let input_dir = dir.join("input");
+    let expected_dir = dir.join("expected");
+
+    let read_error = |e| {
+        FormatterError::Io(IoError::Filesystem(
+            format!("Could not read the inputs in {}", input_dir.display()),
+            e,
+        ))
+    };
+
+    let mut inputs = Vec::new();
+    for entry in fs::read_dir(&input_dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        if !path.is_file() || skipped_symlink(&path, symlinks).map_err(read_error)? {
+            continue;
+        }
+
+        if is_binary(&path).map_err(read_error)? {
+            log::warn!(
+                "Skipping {}, which looks like a binary file",
+                path.display()
+            );
+        } else {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+
+    Ok(inputs
+        .into_iter()
+        .filter_map(|input| {
+            let language = Language::detect(&input, configuration).ok()?;
+            let expected = expected_dir.join(input.file_name()?);
+
+            Some(SnapshotTest {
+                input,
+                expected,
+                language,
+            })
+        })
+        .collect())
+}
+
+/// List the snapshot tests of a corpus: every directory under the given one, including itself,
+/// that has an `input` subdirectory holds snapshot tests, as listed by `snapshot_tests`. A corpus
+/// can thus keep the tests of each language in a directory of its own. The tests are sorted by
+/// the path of their input. Symbolic links are treated as given, and every directory is walked
+/// once, whichever links lead to it.
+///
+/// # Errors
+///
+/// If a directory of the corpus cannot be read, a `FormatterError::Io` will be returned.
+pub fn corpus_tests<'a>(
+    dir: &Path,
+    configuration: &'a Configuration,
+    symlinks: Symlinks,
+) -> FormatterResult<Vec<SnapshotTest<'a>>> {
+    let mut tests = Vec::new();
+    let mut visited = HashSet::new();
+    collect_corpus_tests(dir, configuration, symlinks, &mut visited, &mut tests)?;
+    tests.sort_by(|a, b| a.input.cmp(&b.input));
+
+    Ok(tests)
+}
+
+fn collect_corpus_tests<'a>(
+    dir: &Path,
+    configuration: &'a Configuration,
+    symlinks: Symlinks,
+    visited: &mut HashSet<PathBuf>,
+    tests: &mut Vec<SnapshotTest<'a>>,
+) -> FormatterResult<()> {
+    let read_error = |e| {
+        FormatterError::Io(IoError::Filesystem(
+            format!("Could not read the corpus in {}", dir.display()),
+            e,
+        ))
+    };
+
+    // Directories are known by their canonical path, which resolves the links to them
+    if !visited.insert(dir.canonicalize().map_err(read_error)?) {
+        log::warn!("Skipping {}, which has already been walked", dir.display());
+        return Ok(());
+    }
+
+    if dir.join("input").is_dir() {
+        tests.extend(snapshot_tests(dir, configuration, symlinks)?);
+    }
+
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        let is_snapshot_dir = path.ends_with("input") || path.ends_with("expected");
+
+        if path.is_dir()
+            && !is_snapshot_dir
+            && !skipped_symlink(&path, symlinks).map_err(read_error)?
+        {
+            collect_corpus_tests(&path, configuration, symlinks, visited, tests)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the path is a symbolic link that is skipped, with a warning.
+fn skipped_symlink(path: &Path, symlinks: Symlinks) -> io::Result<bool> {
+    let skipped = symlinks == Symlinks::Skip && fs::symlink_metadata(path)?.is_symlink();
+    if skipped {
+        log::warn!("Skipping {}, which is a symbolic link", path.display());
+    }
+
+    Ok(skipped)
+}
+
+impl SnapshotTest<'_> {
+    /// Format the input with the query of its language, checking idempotence, and compare the
+    /// output with the expected one. Parsing errors are tolerated, so that inputs can show how
+    /// they are formatted. With `bless`, an expected output that is missing or different is
+    /// overwritten by the actual one.
+    ///
+    /// # Errors
+    ///
+    /// If the files cannot be read or written, or formatting fails for any reason, including the
+    /// idempotence check, a `FormatterError` will be returned.
+    pub async fn run(&self, bless: bool) -> FormatterResult<SnapshotOutcome> {
+        let read = |path: &Path| {
+            fs::read_to_string(path).map_err(|e| {
+                FormatterError::Io(IoError::Filesystem(
+                    format!("Could not read {}", path.display()),
+                    e,
+                ))
+            })
+        };
+
+        let query = self.language.read_query(&OsFileProvider)?;
+        let grammar = self.language.grammar().await?;
+        let options =
+            FormatterOptions::new(&query, self.language, &grammar).tolerate_parsing_errors(true);
+
+        let mut output = Vec::new();
+        format(&mut read(&self.input)?.as_bytes(), &mut output, &options)?;
+        let formatted = String::from_utf8(output)?;
+
+        let expected = if self.expected.exists() {
+            Some(read(&self.expected)?)
+        } else {
+            None
+        };
+
+        let outcome = match expected {
+            Some(expected) if expected == formatted => return Ok(SnapshotOutcome::Passed),
+            Some(expected) => SnapshotOutcome::Mismatch(diff(&expected, &formatted)),
+            None => SnapshotOutcome::Missing,
+        };
+
+        if !bless {
+            return Ok(outcome);
+        }
+
+        if let Some(parent) = self.expected.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                FormatterError::Io(IoError::Filesystem(
+                    format!("Could not create {}", parent.display()),
+                    e,
+                ))
+            })?;
+        }
+        fs::write(&self.expected, formatted).map_err(|e| {
+            FormatterError::Io(IoError::Filesystem(
+                format!("Could not write {}", self.expected.display()),
+                e,
+            ))
+        })?;
+
+        Ok(SnapshotOutcome::Blessed)
+    }
+}
+
+/// Whether the start of the file looks like that of a binary file.
+fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut start = Vec::new();
+    fs::File::open(path)?
+        .take(BINARY_CHECK_BYTES as u64)
+        .read_to_end(&mut start)?;
+
+    Ok(looks_binary(&start))
+}
+
+/// The differences between two texts, line by line, with a few lines of context.
+pub(crate) fn diff(expected: &str, actual: &str) -> String {
+    diff_lines(expected, actual)
+        .format_with_context(
+            Some(ContextConfig {
+                context_size: 2,
+                skipping_marker: "...",
+            }),
+            true,
+        )
+        .to_string()
+}
@@ -0,0 +1,78 @@
+//! Source maps, which relate the formatted output back to the input it was produced from.
+
+use std::{io, ops::Range};
+
+use serde::Serialize;
+
+use crate::{format_with_spans, FormatterOptions, FormatterResult, Language};
+
+/// A leaf of the syntax tree, along with its byte range in the input and in the formatted output.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct LeafSpan {
+    pub input: Range<usize>,
+    pub output: Range<usize>,
+}
+
+/// Describes where each leaf of the input ended up in the formatted output, in order. Leaves that
+/// were deleted by formatting have no span.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct SourceMap {
+    pub leaves: Vec<LeafSpan>,
+}
+
+impl SourceMap {
+    /// Write the source map as JSON, in which each leaf span has the form
+    /// `{"input":{"start":0,"end":3},"output":{"start":0,"end":3}}`.
+    ///
+    /// # Errors
+    ///
+    /// If the source map cannot be written, a `FormatterError` will be returned.
+    pub fn write_json(&self, output: &mut impl io::Write) -> FormatterResult<()> {
+        serde_json::to_writer(output, self)?;
+        Ok(())
+    }
+}
+
+/// Format the input, additionally returning a source map of the output.
+///
+/// # Errors
+///
+/// If formatting fails for any reason, a `FormatterError` will be returned.
+///
+/// # Examples
+///
+/// ```
+/// # tokio_test::block_on(async {
+/// use std::fs;
+/// use topiary::{format_with_source_map, Configuration, LeafSpan};
+///
+/// let query = fs::read_to_string("../languages/json.scm").expect("query file");
+/// let config = Configuration::parse_default_configuration().unwrap();
+/// let language = config.get_language("json").unwrap();
+/// let grammar = language.grammar().await.expect("grammar");
+///
+/// let (output, source_map) =
+///     format_with_source_map("[1,2]", &query, language, &grammar, false, false).unwrap();
+///
+/// assert_eq!(output, "[ 1, 2 ]\n");
+/// assert_eq!(
+///     source_map.leaves[3],
+///     LeafSpan { input: 3..4, output: 5..6 }
+/// );
+/// # }) // end tokio_test
+/// ```
+pub fn format_with_source_map(
+    input: &str,
+    query: &str,
+    language: &Language,
+    grammar: &tree_sitter_facade::Language,
+    skip_idempotence: bool,
+    tolerate_parsing_errors: bool,
+) -> FormatterResult<(String, SourceMap)> {
+    let options = FormatterOptions::new(query, language, grammar)
+        .skip_idempotence(skip_idempotence)
+        .tolerate_parsing_errors(tolerate_parsing_errors);
+    let (_, output, leaves) = format_with_spans(input, &options)?;
+
+    Ok((output, SourceMap { leaves }))
+}
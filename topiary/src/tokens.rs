@@ -0,0 +1,227 @@
+//! Checking that formatting keeps the tokens of its input, in order, and its comments. A query
+//! that deletes or duplicates a node breaks this long before the idempotence check notices
+//! anything, if it ever does.
+
+use std::collections::{HashMap, HashSet};
+
+use tree_sitter_facade::Node;
+
+use crate::{tree_sitter, FormatterError, FormatterOptions, FormatterResult};
+
+/// A token of a text, i.e., the text of a named leaf node, without its whitespace, which comments
+/// and injected languages have reformatted.
+pub(crate) struct Token {
+    pub(crate) text: String,
+    /// The line of the token in the text, starting from 1
+    pub(crate) line: usize,
+    /// The column of the token, in characters, starting from 1
+    pub(crate) column: usize,
+}
+
+/// The tokens of the text, as parsed with the grammar of the options. Anonymous nodes are left
+/// out, as queries can delete them, or add them as delimiters.
+pub(crate) fn tokens(text: &str, options: &FormatterOptions) -> FormatterResult<Vec<Token>> {
+    tokens_outside(text, options, &HashSet::new())
+}
+
+/// The tokens of the text, like `tokens`, except those in the nodes of the given kinds, e.g.,
+/// those that the query may delete.
+pub(crate) fn tokens_outside(
+    text: &str,
+    options: &FormatterOptions,
+    kinds: &HashSet<String>,
+) -> FormatterResult<Vec<Token>> {
+    let (tree, _) =
+        tree_sitter::parse(text, options.grammar, options.tolerate_parsing_errors, None)?;
+
+    let mut tokens = Vec::new();
+    collect_tokens(&tree.root_node(), text, kinds, &mut tokens)?;
+
+    Ok(tokens)
+}
+
+fn collect_tokens(
+    node: &Node,
+    text: &str,
+    skipped: &HashSet<String>,
+    tokens: &mut Vec<Token>,
+) -> FormatterResult<()> {
+    if node.is_named() && skipped.contains(&node.kind().to_string()) {
+        return Ok(());
+    }
+
+    if node.child_count() == 0 {
+        if node.is_named() && node.end_byte() > node.start_byte() {
+            let content = node.utf8_text(text.as_bytes())?;
+            let position = node.start_position();
+
+            tokens.push(Token {
+                text: content.split_whitespace().collect(),
+                line: position.row() as usize + 1,
+                column: position.column() as usize + 1,
+            });
+        }
+
+        return Ok(());
+    }
+
+    let mut walker = node.walk();
+    for child in node.children(&mut walker) {
+        collect_tokens(&child, text, skipped, tokens)?;
+    }
+
+    Ok(())
+}
+
+/// Check that the output has the same tokens as the input, in the same order.
+///
+/// # Errors
+///
+/// If the tokens differ, a `FormatterError::Tokens` will be returned, whose report pinpoints the
+/// first token that was lost, duplicated or reordered. If the output cannot be parsed, a
+/// `FormatterError::Formatting` will be returned.
+pub(crate) fn check_tokens(
+    input: &str,
+    output: &str,
+    options: &FormatterOptions,
+) -> FormatterResult<()> {
+    log::info!("Checking that the tokens of the input are kept ...");
+
+    let input_tokens = tokens(input, options)?;
+    let output_tokens =
+        tokens(output, options).map_err(|e| FormatterError::Formatting(Box::new(e)))?;
+
+    match token_report(&input_tokens, &output_tokens) {
+        Some(report) => Err(FormatterError::Tokens(report)),
+        None => Ok(()),
+    }
+}
+
+/// Describe the first difference between the tokens of the input and those of the output, if
+/// there is any.
+fn token_report(input: &[Token], output: &[Token]) -> Option<String> {
+    let index = (0..input.len().max(output.len())).find(|&index| {
+        input.get(index).map(|token| &token.text) != output.get(index).map(|token| &token.text)
+    })?;
+
+    let occurs = |tokens: &[Token], text: &str| tokens.iter().any(|token| token.text == text);
+
+    let report = match (input.get(index), output.get(index)) {
+        (Some(expected), Some(_)) if !occurs(&output[index..], &expected.text) => lost(expected),
+        (Some(_), Some(found))
+            if occurs(&input[..index], &found.text) && !occurs(&input[index..], &found.text) =>
+        {
+            duplicated(found)
+        }
+        (Some(expected), Some(found)) => format!(
+            "The token {:?}, at line {}, column {} of the input, is out of order in the output, \
+             where {:?} comes first, at line {}, column {}",
+            expected.text, expected.line, expected.column, found.text, found.line, found.column
+        ),
+        (Some(expected), None) => lost(expected),
+        (None, Some(found)) => duplicated(found),
+        (None, None) => unreachable!("the tokens differ at this index"),
+    };
+
+    Some(report)
+}
+
+/// The comments of the text, as parsed with the grammar of the options, i.e., the named nodes
+/// whose kind mentions comments, such as `comment` or `line_comment`, without their whitespace.
+fn comments(text: &str, options: &FormatterOptions) -> FormatterResult<Vec<Token>> {
+    let (tree, _) =
+        tree_sitter::parse(text, options.grammar, options.tolerate_parsing_errors, None)?;
+
+    let mut comments = Vec::new();
+    collect_comments(&tree.root_node(), text, &mut comments)?;
+
+    Ok(comments)
+}
+
+fn collect_comments(node: &Node, text: &str, comments: &mut Vec<Token>) -> FormatterResult<()> {
+    if node.is_named() && node.kind().contains("comment") {
+        let content = node.utf8_text(text.as_bytes())?;
+        let position = node.start_position();
+
+        comments.push(Token {
+            text: content.split_whitespace().collect(),
+            line: position.row() as usize + 1,
+            column: position.column() as usize + 1,
+        });
+
+        return Ok(());
+    }
+
+    let mut walker = node.walk();
+    for child in node.children(&mut walker) {
+        collect_comments(&child, text, comments)?;
+    }
+
+    Ok(())
+}
+
+/// Check that every comment of the input appears exactly once in the output, wherever it was
+/// moved to.
+///
+/// # Errors
+///
+/// If a comment was dropped or duplicated, a `FormatterError::Comments` will be returned, whose
+/// report gives the text and the position of the first such comment. If the output cannot be
+/// parsed, a `FormatterError::Formatting` will be returned.
+pub(crate) fn check_comments(
+    input: &str,
+    output: &str,
+    options: &FormatterOptions,
+) -> FormatterResult<()> {
+    log::info!("Checking that the comments of the input are kept ...");
+
+    let input_comments = comments(input, options)?;
+    let output_comments =
+        comments(output, options).map_err(|e| FormatterError::Formatting(Box::new(e)))?;
+
+    // How many times each comment is expected to appear in the rest of the output
+    let mut expected: HashMap<&str, usize> = HashMap::new();
+    for comment in &input_comments {
+        *expected.entry(comment.text.as_str()).or_default() += 1;
+    }
+
+    for comment in &output_comments {
+        match expected.get_mut(comment.text.as_str()) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => {
+                return Err(FormatterError::Comments(format!(
+                    "The comment {:?}, at line {}, column {} of the output, is not in the input, \
+                     or is duplicated",
+                    comment.text, comment.line, comment.column
+                )));
+            }
+        }
+    }
+
+    // The comments that remain expected were dropped
+    let dropped = input_comments
+        .iter()
+        .find(|comment| expected[comment.text.as_str()] > 0);
+
+    match dropped {
+        Some(comment) => Err(FormatterError::Comments(format!(
+            "The comment {:?}, at line {}, column {} of the input, is dropped from the output",
+            comment.text, comment.line, comment.column
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn lost(token: &Token) -> String {
+    format!(
+        "The token {:?}, at line {}, column {} of the input, is lost in the output",
+        token.text, token.line, token.column
+    )
+}
+
+fn duplicated(token: &Token) -> String {
+    format!(
+        "The token {:?}, at line {}, column {} of the output, duplicates a token of the input",
+        token.text, token.line, token.column
+    )
+}
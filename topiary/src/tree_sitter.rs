@@ -1,14 +1,23 @@
-use std::collections::HashSet;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    mem,
+    ops::RangeInclusive,
+};
 
+use regex::Regex;
 use serde::Serialize;
 use tree_sitter_facade::{
     Node, Parser, Point, Query, QueryCapture, QueryCursor, QueryPredicate, Tree,
 };
+use unicode_width::UnicodeWidthChar;
 
 use crate::{
     atom_collection::{AtomCollection, QueryPredicates},
+    cancellation::{self, CancellationToken},
+    captures,
     error::FormatterError,
-    FormatterResult,
+    include, Atom, FormatterResult,
 };
 
 /// Supported visualisation formats
@@ -48,6 +57,15 @@ pub struct SyntaxNode {
     start: Position,
     end: Position,
 
+    // The captures that were applied to the node, and the atoms that were emitted before and
+    // after it, once the query has been applied by `annotate`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub captures: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub atoms_before: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub atoms_after: Vec<String>,
+
     pub children: Vec<SyntaxNode>,
 }
 
@@ -67,11 +85,76 @@ impl From<Node<'_>> for SyntaxNode {
             start: node.start_position().into(),
             end: node.end_position().into(),
 
+            captures: Vec::new(),
+            atoms_before: Vec::new(),
+            atoms_after: Vec::new(),
+
             children,
         }
     }
 }
 
+impl SyntaxNode {
+    /// Annotates the tree with the captures that were applied to every node, and with the atoms
+    /// that were emitted around it. The atoms are expected to have been post-processed already,
+    /// so that they are those that are rendered. The atoms around a node that is not a leaf are
+    /// those before its first leaf and after its last one.
+    pub(crate) fn annotate(&mut self, atoms: &AtomCollection) {
+        // The atoms between consecutive leaves are both after the first and before the second
+        let mut gaps: HashMap<usize, (Vec<String>, Vec<String>)> = HashMap::new();
+        let mut gap: Vec<String> = Vec::new();
+        let mut previous_leaf: Option<usize> = None;
+
+        for atom in &atoms[..] {
+            match atom {
+                Atom::Leaf { id, .. } => {
+                    if let Some(previous) = previous_leaf {
+                        gaps.entry(previous).or_default().1 = gap.clone();
+                    }
+                    gaps.entry(*id).or_default().0 = mem::take(&mut gap);
+                    previous_leaf = Some(*id);
+                }
+                Atom::Empty => {}
+                atom => gap.push(format!("{atom:?}")),
+            }
+        }
+
+        if let Some(previous) = previous_leaf {
+            gaps.entry(previous).or_default().1 = gap;
+        }
+
+        self.annotate_inner(atoms, &mut gaps);
+    }
+
+    // Returns whether any leaf was found in the subtree of the node
+    fn annotate_inner(
+        &mut self,
+        atoms: &AtomCollection,
+        gaps: &mut HashMap<usize, (Vec<String>, Vec<String>)>,
+    ) -> bool {
+        self.captures = atoms.captures_of(self.id).to_vec();
+
+        if let Some((before, after)) = gaps.remove(&self.id) {
+            self.atoms_before = before;
+            self.atoms_after = after;
+            return true;
+        }
+
+        // Every child is annotated, even after the leaves have been found
+        let with_leaves: Vec<usize> = (0..self.children.len())
+            .filter(|&index| self.children[index].annotate_inner(atoms, gaps))
+            .collect();
+
+        if let (Some(&first), Some(&last)) = (with_leaves.first(), with_leaves.last()) {
+            self.atoms_before = self.children[first].atoms_before.clone();
+            self.atoms_after = self.children[last].atoms_after.clone();
+            true
+        } else {
+            false
+        }
+    }
+}
+
 #[derive(Debug)]
 // A struct to statically store the public fields of query match results,
 // to avoid running queries twice.
@@ -97,18 +180,214 @@ pub fn apply_query(
     tolerate_parsing_errors: bool,
     should_check_input_exhaustivity: bool,
 ) -> FormatterResult<AtomCollection> {
-    let (tree, grammar) = parse(input_content, grammar, tolerate_parsing_errors)?;
-    let root = tree.root_node();
-    let source = input_content.as_bytes();
+    let (tree, grammar) = parse(input_content, grammar, tolerate_parsing_errors, None)?;
+    apply_query_to_tree(
+        &tree,
+        input_content,
+        query_content,
+        grammar,
+        should_check_input_exhaustivity,
+    )
+}
+
+/// Applies a query to an already parsed syntax tree of the input content and returns a
+/// collection of atoms.
+///
+/// # Errors
+///
+/// This function can return an error if:
+/// - The query content cannot be parsed by the grammar.
+/// - The input exhaustivity check fails.
+/// - A found predicate could not be parsed or is malformed.
+/// - A unknown capture name was encountered in the query.
+pub fn apply_query_to_tree(
+    tree: &Tree,
+    input_content: &str,
+    query_content: &str,
+    grammar: &tree_sitter_facade::Language,
+    should_check_input_exhaustivity: bool,
+) -> FormatterResult<AtomCollection> {
+    let query = compile_query(query_content, grammar)?;
+    let atoms = apply_compiled_query_to_tree(
+        tree,
+        input_content,
+        &query,
+        query_content,
+        &HashMap::new(),
+        None,
+        None,
+    )?;
+
+    if should_check_input_exhaustivity {
+        check_input_exhaustivity(
+            atoms.match_count,
+            &query.query,
+            query_content,
+            grammar,
+            &tree.root_node(),
+            input_content.as_bytes(),
+        )?;
+    }
+
+    Ok(atoms)
+}
+
+/// The kinds of the named nodes that the patterns of the query allow `@delete` to delete, with
+/// `#deletable!`.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn deletable_kinds(
+    query_content: &str,
+    grammar: &tree_sitter_facade::Language,
+) -> FormatterResult<HashSet<String>> {
+    let query = Query::new(grammar, query_content)
+        .map_err(|e| FormatterError::Query("Error parsing query file".into(), Some(e)))?;
+
+    let mut kinds = HashSet::new();
+    for pattern_index in 0..query.pattern_count() {
+        for predicate in query.general_predicates(pattern_index as u32) {
+            if &*predicate.operator() == "deletable!" {
+                kinds.extend(predicate.args());
+            }
+        }
+    }
+
+    Ok(kinds)
+}
+
+// The patterns of a query cannot be counted in the browser
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn deletable_kinds(
+    _query_content: &str,
+    _grammar: &tree_sitter_facade::Language,
+) -> FormatterResult<HashSet<String>> {
+    Ok(HashSet::new())
+}
+
+/// A query compiled by `compile_query`, along with the regular expressions of its `#match?`
+/// predicates, which are compiled once rather than for every match.
+pub struct CompiledQuery {
+    pub(crate) query: Query,
+    // The regular expressions of the #match? predicates, by their source
+    regexes: HashMap<String, Regex>,
+}
+
+/// Compiles the query content for the given grammar, so that it can be applied many times.
+///
+/// # Errors
+///
+/// This function returns an error if the query content cannot be parsed by the grammar, if it
+/// contains a capture name that Topiary does not understand, or if the regular expression of one
+/// of its `#match?` predicates is invalid.
+pub fn compile_query(
+    query_content: &str,
+    grammar: &tree_sitter_facade::Language,
+) -> FormatterResult<CompiledQuery> {
     let query = Query::new(grammar, query_content)
         .map_err(|e| FormatterError::Query("Error parsing query file".into(), Some(e)))?;
 
+    let capture_names = query.capture_names();
+    if let Some(name) = capture_names
+        .iter()
+        .find(|name| !captures::is_valid_capture_name(name))
+    {
+        let message = match captures::unknown_captures(query_content, &capture_names).first() {
+            Some(capture) => format!(
+                "{capture}\n{}",
+                snippet(query_content, capture.line, capture.column, 1)
+            ),
+            None => format!(
+                "@{name} is not a valid capture name{}",
+                captures::did_you_mean(name)
+            ),
+        };
+
+        return Err(FormatterError::Query(message, None));
+    }
+
+    Ok(CompiledQuery {
+        regexes: match_regexes(&query, query_content)?,
+        query,
+    })
+}
+
+/// The regular expressions of the `#match?` predicates of the query, by their source.
+#[cfg(not(target_arch = "wasm32"))]
+fn match_regexes(query: &Query, query_content: &str) -> FormatterResult<HashMap<String, Regex>> {
+    let mut regexes = HashMap::new();
+
+    for pattern_index in 0..query.pattern_count() as u32 {
+        for predicate in query.general_predicates(pattern_index) {
+            let operator = format!("#{}", predicate.operator());
+            if operator != "#match?" && operator != "#not-match?" {
+                continue;
+            }
+
+            if let Some(source) = predicate.args().get(1) {
+                if !regexes.contains_key(source) {
+                    let regex = compile_regex(&operator, source).map_err(|e| {
+                        pattern_error(e, query, query_content, pattern_index, Some(&operator))
+                    })?;
+                    regexes.insert(source.clone(), regex);
+                }
+            }
+        }
+    }
+
+    Ok(regexes)
+}
+
+// The patterns of a query cannot be counted in the browser, so the regular expressions are
+// compiled when they are needed
+#[cfg(target_arch = "wasm32")]
+fn match_regexes(_query: &Query, _query_content: &str) -> FormatterResult<HashMap<String, Regex>> {
+    Ok(HashMap::new())
+}
+
+fn compile_regex(operator: &str, source: &str) -> FormatterResult<Regex> {
+    Regex::new(source).map_err(|e| {
+        FormatterError::Query(
+            format!("Invalid regular expression in {operator}: {e}"),
+            None,
+        )
+    })
+}
+
+/// Like `apply_query_to_tree`, but with a query that was compiled from the query content
+/// beforehand, by `compile_query`, and that can be cancelled with a token. The input
+/// exhaustivity is not checked. The `#config?` predicates of the query test the given settings
+/// of the language.
+///
+/// The captures applied to the nodes that overlap the given range of lines of the input, starting
+/// from 1, are traced: they are logged with the `topiary::trace` target, every node along with the
+/// captures applied to it, their lines in the query, and the atoms they produced.
+///
+/// # Errors
+///
+/// This function can return an error if:
+/// - The token is cancelled.
+/// - A found predicate could not be parsed or is malformed.
+/// - A unknown capture name was encountered in the query.
+pub fn apply_compiled_query_to_tree(
+    tree: &Tree,
+    input_content: &str,
+    compiled_query: &CompiledQuery,
+    query_content: &str,
+    settings: &HashMap<String, String>,
+    token: Option<&CancellationToken>,
+    trace_lines: Option<&RangeInclusive<usize>>,
+) -> FormatterResult<AtomCollection> {
+    let root = tree.root_node();
+    let source = input_content.as_bytes();
+    let query = &compiled_query.query;
+
     // Match queries
     let mut cursor = QueryCursor::new();
     let mut matches: Vec<LocalQueryMatch> = Vec::new();
     let capture_names = query.capture_names();
 
     for query_match in query.matches(&root, source, &mut cursor) {
+        cancellation::check(token)?;
+
         let local_captures: Vec<QueryCapture> = query_match.captures().collect();
 
         matches.push(LocalQueryMatch {
@@ -117,24 +396,13 @@ pub fn apply_query(
         });
     }
 
-    if should_check_input_exhaustivity {
-        let ref_match_count = matches.len();
-        check_input_exhaustivity(
-            ref_match_count,
-            &query,
-            query_content,
-            grammar,
-            &root,
-            source,
-        )?;
-    }
-
     // Find the ids of all tree-sitter nodes that were identified as a leaf
     // We want to avoid recursing into them in the collect_leafs function.
     let specified_leaf_nodes: HashSet<usize> = collect_leaf_ids(&matches, &capture_names);
 
     // The Flattening: collects all terminal nodes of the tree-sitter tree in a Vec
     let mut atoms = AtomCollection::collect_leafs(&root, source, specified_leaf_nodes)?;
+    atoms.match_count = matches.len();
 
     log::debug!("List of atoms before formatting: {atoms:?}");
 
@@ -147,15 +415,60 @@ pub fn apply_query(
     // means we want to append a hardline at
     // the end, but we don't know if we get a line_comment capture or not.
 
-    for m in matches {
+    // The traced captures, with a description of their node, keyed by the position of the node
+    let mut traces = BTreeMap::new();
+    let layers = layer_starts(query_content);
+
+    'matches: for m in matches {
         log::debug!("Processing match: {m:?}");
+        cancellation::check(token)?;
 
-        let mut predicates = QueryPredicates::default();
+        let pattern_index = m.pattern_index;
+        // The patterns of later layers take precedence over those of earlier ones by default
+        let mut predicates = QueryPredicates {
+            priority: pattern_layer(query, &layers, pattern_index),
+            ..QueryPredicates::default()
+        };
+
+        // Errors of the query are located at the capture or the predicate that they are about
+        let in_pattern = |name: Option<String>| {
+            move |e: FormatterError| {
+                pattern_error(e, query, query_content, pattern_index, name.as_deref())
+            }
+        };
 
-        for p in query.general_predicates(m.pattern_index) {
-            predicates = handle_predicate(&p, &predicates)?;
+        for p in query.general_predicates(pattern_index) {
+            let operator = Some(format!("#{}", p.operator()));
+
+            match match_predicate(
+                &p,
+                &m,
+                &capture_names,
+                source,
+                settings,
+                &compiled_query.regexes,
+            )
+            .map_err(in_pattern(operator.clone()))?
+            {
+                Some(true) => {}
+                Some(false) => {
+                    log::debug!("Skipping match, as #{} does not hold", p.operator());
+                    continue 'matches;
+                }
+                None => {
+                    predicates = handle_predicate(&p, &predicates).map_err(in_pattern(operator))?
+                }
+            }
+        }
+        check_predicates(&predicates).map_err(in_pattern(None))?;
+
+        // The injected language may be named by a capture, rather than a
+        // string, e.g., by the info string of a Markdown code block.
+        if let Some(language) = &predicates.injection_language {
+            if let Some(node) = captured_node(&m, &capture_names, language) {
+                predicates.injection_language = Some(node.utf8_text(source)?.trim().to_string());
+            }
         }
-        check_predicates(&predicates)?;
 
         // If any capture is a do_nothing, then do nothing.
         if m.captures
@@ -168,75 +481,321 @@ pub fn apply_query(
 
         for c in m.captures {
             let name = c.name(&capture_names);
-            atoms.resolve_capture(&name, &c.node(), &predicates)?;
+
+            // Captures whose name starts with an underscore only serve as
+            // arguments to predicates
+            if name.starts_with('_') {
+                continue;
+            }
+
+            let node = c.node();
+            match trace_lines {
+                Some(lines) if overlaps_lines(&node, lines) => {
+                    let traced = atoms
+                        .trace_capture(&name, &node, &predicates, source)
+                        .map_err(in_pattern(Some(format!("@{name}"))))?;
+                    let location = pattern_location(query, query_content, pattern_index);
+
+                    traces
+                        .entry((node.start_byte(), Reverse(node.end_byte()), node.id()))
+                        .or_insert_with(|| (describe_node(&node), Vec::new()))
+                        .1
+                        .push(format!("@{name}, from {location} of the query: {traced:?}"));
+                }
+                _ => atoms
+                    .resolve_capture(&name, &node, &predicates, source)
+                    .map_err(in_pattern(Some(format!("@{name}"))))?,
+            }
+        }
+    }
+
+    // The traced nodes are logged in the order in which they appear in the input, parents first
+    for (node, captures) in traces.into_values() {
+        log::info!(target: "topiary::trace", "{node}");
+        for capture in captures {
+            log::info!(target: "topiary::trace", "  {capture}");
         }
     }
 
+    // Sort the nodes captured by @sort, along with the atoms they received.
+    atoms.sort(&root, source)?;
+
     // Now apply all atoms in prepend and append to the leaf nodes.
     atoms.apply_prepends_and_appends();
 
     Ok(atoms)
 }
 
+// Whether the node overlaps the given range of lines, starting from 1
+fn overlaps_lines(node: &Node, lines: &RangeInclusive<usize>) -> bool {
+    let start = node.start_position().row() as usize + 1;
+    let end = node.end_position().row() as usize + 1;
+
+    start <= *lines.end() && *lines.start() <= end
+}
+
+// The kind of the node, along with its position in the input, starting from 1
+fn describe_node(node: &Node) -> String {
+    let position: Position = node.start_position().into();
+    format!("{} at {}:{}", node.kind(), position.row, position.column)
+}
+
+/// The byte offsets of the layer directives of the query content, which `layer_queries` puts
+/// before every overlay, in order.
+fn layer_starts(query_content: &str) -> Vec<usize> {
+    let mut offset = 0;
+    let mut starts = Vec::new();
+
+    for line in query_content.split_inclusive('\n') {
+        if include::is_layer_directive(line) {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+
+    starts
+}
+
+/// The layer of the pattern with the given index, i.e., the number of layer directives before it.
+#[cfg(not(target_arch = "wasm32"))]
+fn pattern_layer(query: &Query, layer_starts: &[usize], pattern_index: u32) -> i32 {
+    let start = query.start_byte_for_pattern(pattern_index as usize);
+    layer_starts.partition_point(|&offset| offset < start) as i32
+}
+
+// The positions of patterns are not available in the browser, so layers are not told apart
+#[cfg(target_arch = "wasm32")]
+fn pattern_layer(_query: &Query, _layer_starts: &[usize], _pattern_index: u32) -> i32 {
+    0
+}
+
+/// The line of the start of the pattern with the given index in the query, starting from 1.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn pattern_line(query: &Query, query_content: &str, pattern_index: usize) -> usize {
+    let start = query.start_byte_for_pattern(pattern_index);
+    query_content[..start].matches('\n').count() + 1
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pattern_location(query: &Query, query_content: &str, pattern_index: u32) -> String {
+    format!(
+        "line {}",
+        pattern_line(query, query_content, pattern_index as usize)
+    )
+}
+
+// The positions of patterns are not available in the browser
+#[cfg(target_arch = "wasm32")]
+fn pattern_location(_query: &Query, _query_content: &str, pattern_index: u32) -> String {
+    format!("pattern {}", pattern_index + 1)
+}
+
+/// Give the error of the query, raised by the pattern with the given index, the position in the
+/// query of the capture or the predicate of the given name, such as `@append_space` or
+/// `#delimiter!`, or else of the pattern, along with the line of the query that has it. Errors
+/// that tree-sitter raised already have a position, and are left as they are.
+#[cfg(not(target_arch = "wasm32"))]
+fn pattern_error(
+    error: FormatterError,
+    query: &Query,
+    query_content: &str,
+    pattern_index: u32,
+    name: Option<&str>,
+) -> FormatterError {
+    let message = match error {
+        FormatterError::Query(message, None) => message,
+        error => return error,
+    };
+
+    let index = pattern_index as usize;
+    let start = query.start_byte_for_pattern(index);
+    let end = if index + 1 < query.pattern_count() {
+        query.start_byte_for_pattern(index + 1)
+    } else {
+        query_content.len()
+    };
+
+    let offset = match name.and_then(|name| find_name(&query_content[start..end], name)) {
+        Some(offset) => start + offset,
+        None => start,
+    };
+    let before = &query_content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+    FormatterError::Query(
+        format!(
+            "{message}, in the pattern at line {line}, column {column} of the query\n{}",
+            snippet(query_content, line, column, 1)
+        ),
+        None,
+    )
+}
+
+#[cfg(target_arch = "wasm32")]
+fn pattern_error(
+    error: FormatterError,
+    _query: &Query,
+    _query_content: &str,
+    pattern_index: u32,
+    _name: Option<&str>,
+) -> FormatterError {
+    match error {
+        FormatterError::Query(message, None) => FormatterError::Query(
+            format!("{message}, in pattern {} of the query", pattern_index + 1),
+            None,
+        ),
+        error => error,
+    }
+}
+
+// The byte offset of the given name of a capture or a predicate in the text of a pattern, where
+// it is not the start of a longer name
+#[cfg(not(target_arch = "wasm32"))]
+fn find_name(pattern: &str, name: &str) -> Option<usize> {
+    pattern
+        .match_indices(name)
+        .map(|(offset, _)| offset)
+        .find(|offset| {
+            pattern[offset + name.len()..]
+                .chars()
+                .next()
+                .map_or(true, |c| !(c.is_alphanumeric() || "_-.!?".contains(c)))
+        })
+}
+
+// The line of the text, starting from 1, with carets under the given number of columns from the
+// given column, starting from 1
+fn snippet(text: &str, line: usize, column: usize, length: usize) -> String {
+    let text = text.lines().nth(line - 1).unwrap_or_default();
+    let gutter = " ".repeat(line.to_string().len());
+
+    format!(
+        "{gutter} |\n{line} | {text}\n{gutter} | {}{}",
+        " ".repeat(column - 1),
+        "^".repeat(length.max(1))
+    )
+}
+
 // A single "language" can correspond to multiple grammars.
 // For instance, we have separate grammars for interfaces and implementation in OCaml.
 // When the proper grammar cannot be inferred from the extension of the input file,
 // this function tries to parse the data with every possible grammar.
 // It returns the syntax tree of the first grammar that succeeds, along with said grammar,
 // or the last error if all grammars fail.
+// If the syntax tree of a previous version of the content is given, which must have been edited
+// to reflect the changes since, it is used to re-parse the content incrementally.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(bytes = content.len())))]
 pub fn parse<'a>(
     content: &str,
     grammar: &'a tree_sitter_facade::Language,
     tolerate_parsing_errors: bool,
+    old_tree: Option<&Tree>,
 ) -> FormatterResult<(Tree, &'a tree_sitter_facade::Language)> {
     let mut parser = Parser::new()?;
-    parser.set_language(grammar).map_err(|_| {
-        FormatterError::Internal("Could not apply Tree-sitter grammar".into(), None)
-    })?;
+    parser
+        .set_language(grammar)
+        .map_err(FormatterError::IncompatibleGrammar)?;
 
     let tree = parser
-        .parse(content, None)?
+        .parse(content, old_tree)?
         .ok_or_else(|| FormatterError::Internal("Could not parse input".into(), None))?;
 
     // Fail parsing if we don't get a complete syntax tree.
     if !tolerate_parsing_errors {
-        check_for_error_nodes(&tree.root_node())?;
+        check_for_error_nodes(&tree.root_node(), content)?;
     }
 
     Ok((tree, grammar))
 }
 
-fn check_for_error_nodes(node: &Node) -> FormatterResult<()> {
+fn check_for_error_nodes(node: &Node, content: &str) -> FormatterResult<()> {
     if node.kind() == "ERROR" {
         let start = node.start_position();
         let end = node.end_position();
 
         // Report 1-based lines and columns.
+        let start_line = start.row() + 1;
+        let start_column = display_column(content, start.row(), start.column()) + 1;
+        let end_line = end.row() + 1;
+        let end_column = display_column(content, end.row(), end.column()) + 1;
+
+        // The excerpt is the first line of the error, whose end may be on the next lines
+        let line_end = match content.split('\n').nth(start.row() as usize) {
+            Some(line) => display_column(content, start.row(), line.len() as u32) + 1,
+            None => start_column,
+        };
+        let length = if end_line == start_line {
+            end_column - start_column
+        } else {
+            line_end - start_column
+        };
+
+        let mut ancestor = node.parent();
+        while let Some(node) = ancestor.as_ref().filter(|node| !node.is_named()) {
+            ancestor = node.parent();
+        }
+
         return Err(FormatterError::Parsing {
-            start_line: start.row() + 1,
-            start_column: start.column() + 1,
-            end_line: end.row() + 1,
-            end_column: end.column() + 1,
+            start_line,
+            start_column,
+            end_line,
+            end_column,
+            excerpt: snippet(
+                content,
+                start_line as usize,
+                start_column as usize,
+                length as usize,
+            ),
+            context: ancestor.map(|node| node.kind().to_string()),
         });
     }
 
     for child in node.children(&mut node.walk()) {
-        check_for_error_nodes(&child)?;
+        check_for_error_nodes(&child, content)?;
     }
 
     Ok(())
 }
 
+/// The number of error and missing nodes under the node, e.g., to tell how well a grammar parses
+/// an input.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn error_count(node: &Node) -> usize {
+    let own = usize::from(node.is_error() || node.is_missing());
+
+    own + node
+        .children(&mut node.walk())
+        .map(|child| error_count(&child))
+        .sum::<usize>()
+}
+
+/// Converts a Tree-sitter column, which is a byte offset into its line, into a 0-based display
+/// column. Wide characters (e.g., CJK or emoji) count for two columns and combining characters
+/// for none, so that the reported column matches what users see in their terminal or editor.
+/// Control characters, such as tabs, count for a single column.
+fn display_column(content: &str, row: u32, byte_column: u32) -> u32 {
+    content
+        .split('\n')
+        .nth(row as usize)
+        .and_then(|line| line.get(..byte_column as usize))
+        .map_or(byte_column, |prefix| {
+            prefix.chars().map(|c| c.width().unwrap_or(1) as u32).sum()
+        })
+}
+
 /// Collects the IDs of all leaf nodes in a set of query matches.
 ///
 /// This function takes a slice of `LocalQueryMatch` and a slice of capture names,
 /// and returns a `HashSet` of node IDs that are matched by the "leaf" capture name.
+/// Nodes captured by "injection" are leaves too, as they are formatted separately.
 fn collect_leaf_ids(matches: &[LocalQueryMatch], capture_names: &[String]) -> HashSet<usize> {
     let mut ids = HashSet::new();
 
     for m in matches {
         for c in &m.captures {
-            if c.name(capture_names) == "leaf" {
+            let name = c.name(capture_names);
+            if name == "leaf" || name == "injection" {
                 ids.insert(c.node().id());
             }
         }
@@ -284,6 +843,39 @@ fn handle_predicate(
             scope_id: Some(arg),
             ..predicates.clone()
         })
+    } else if "injection_language!" == operator {
+        let arg =
+            predicate.args().into_iter().next().ok_or_else(|| {
+                FormatterError::Query(format!("{operator} needs an argument"), None)
+            })?;
+        Ok(QueryPredicates {
+            injection_language: Some(arg),
+            ..predicates.clone()
+        })
+    } else if "deletable!" == operator {
+        let args = predicate.args();
+        if args.is_empty() {
+            return Err(FormatterError::Query(
+                format!("{operator} needs an argument"),
+                None,
+            ));
+        }
+        Ok(QueryPredicates {
+            deletable: args.into_iter().collect(),
+            ..predicates.clone()
+        })
+    } else if "priority!" == operator {
+        let arg =
+            predicate.args().into_iter().next().ok_or_else(|| {
+                FormatterError::Query(format!("{operator} needs an argument"), None)
+            })?;
+        let priority = arg.parse().map_err(|_| {
+            FormatterError::Query(format!("{operator} needs an integer argument"), None)
+        })?;
+        Ok(QueryPredicates {
+            priority,
+            ..predicates.clone()
+        })
     } else if "single_line_only!" == operator {
         Ok(QueryPredicates {
             single_line_only: true,
@@ -294,6 +886,11 @@ fn handle_predicate(
             multi_line_only: true,
             ..predicates.clone()
         })
+    } else if "forced_by_contents!" == operator {
+        Ok(QueryPredicates {
+            forced_by_contents: true,
+            ..predicates.clone()
+        })
     } else if "single_line_scope_only!" == operator {
         let arg =
             predicate.args().into_iter().next().ok_or_else(|| {
@@ -317,6 +914,141 @@ fn handle_predicate(
     }
 }
 
+/// Evaluates the predicates that test the nodes of a query match, rather than configure Topiary.
+/// Returns `None` if the predicate is not one of these. They are:
+///
+/// * The standard Tree-sitter text predicates, `#eq?`, `#match?` and `#any-of?`. On some
+///   platforms, Tree-sitter already filters matches on some of these, in which case they never
+///   reach this function.
+/// * Topiary's structural predicates: `#single_line?`, which holds if the node occupied a single
+///   line of the input, `#child-count? n`, which holds if the node has exactly `n` named children,
+///   `#text-length? n`, which holds if the text of the node is at most `n` characters long, and
+///   `#whitespace-before?`, which holds if the node is preceded by whitespace in the input.
+/// * `#config? name value`, which holds if the setting of the language of the given name has the
+///   given value, and tests the configuration rather than a node.
+///
+/// All of these can be negated with a `#not-` prefix. As in Tree-sitter, a predicate on an
+/// optional capture that did not match anything holds, whether it is the first or the second
+/// capture of an `#eq?` predicate.
+///
+/// The second argument of an `#eq?` predicate is a capture if it names one of the query, and the
+/// regular expressions of `#match?` predicates are those compiled with the query, if any.
+///
+/// # Errors
+///
+/// This function will return an error if the predicate has the wrong number of arguments, if the
+/// regular expression of a `#match?` predicate is invalid, or if a numeric argument is not a
+/// number.
+fn match_predicate(
+    predicate: &QueryPredicate,
+    query_match: &LocalQueryMatch,
+    capture_names: &[String],
+    source: &[u8],
+    settings: &HashMap<String, String>,
+    regexes: &HashMap<String, Regex>,
+) -> FormatterResult<Option<bool>> {
+    let operator = &*predicate.operator();
+    let (negated, positive) = match operator.strip_prefix("not-") {
+        Some(positive) => (true, positive),
+        None => (false, operator),
+    };
+
+    // The number of arguments that follow the capture, or `None` if there can be any number of
+    // them, but at least one
+    let arity = match positive {
+        "single_line?" | "whitespace-before?" => Some(0),
+        "eq?" | "match?" | "child-count?" | "text-length?" | "config?" => Some(1),
+        "any-of?" => None,
+        _ => return Ok(None),
+    };
+
+    let args = predicate.args();
+    let (capture, values) = match args.split_first() {
+        Some((capture, values))
+            if arity.map_or(!values.is_empty(), |arity| values.len() == arity) =>
+        {
+            (capture, values)
+        }
+        _ => {
+            return Err(FormatterError::Query(
+                format!("#{operator} has the wrong number of arguments"),
+                None,
+            ))
+        }
+    };
+
+    // The first argument of #config? is the name of a setting, rather than a capture
+    if positive == "config?" {
+        let holds = settings.get(capture.as_str()) == Some(&values[0]);
+        return Ok(Some(holds != negated));
+    }
+
+    let node = match captured_node(query_match, capture_names, capture) {
+        Some(node) => node,
+        None => return Ok(Some(true)),
+    };
+    let text = node.utf8_text(source)?;
+
+    let number = |value: &str| -> FormatterResult<usize> {
+        value.parse().map_err(|_| {
+            FormatterError::Query(format!("#{operator} needs a numeric argument"), None)
+        })
+    };
+
+    let holds = match positive {
+        // The second argument is either another capture, or a string
+        "eq?" => {
+            // The arguments of general predicates do not tell captures from strings, so an
+            // argument that names a capture of the query is one
+            let other = Some(values[0].trim_start_matches('@'))
+                .filter(|name| capture_names.iter().any(|capture| capture == name));
+
+            match other {
+                Some(name) => match captured_node(query_match, capture_names, name) {
+                    Some(other) => text == other.utf8_text(source)?,
+                    None => return Ok(Some(true)),
+                },
+                None => text == values[0],
+            }
+        }
+        "match?" => match regexes.get(&values[0]) {
+            Some(regex) => regex.is_match(text),
+            None => compile_regex(&format!("#{operator}"), &values[0])?.is_match(text),
+        },
+        "any-of?" => values.iter().any(|value| text == value.as_str()),
+        "single_line?" => node.start_position().row() == node.end_position().row(),
+        "whitespace-before?" => {
+            let start = node.start_byte() as usize;
+            start > 0 && source[start - 1].is_ascii_whitespace()
+        }
+        "child-count?" => {
+            let named_children = node
+                .children(&mut node.walk())
+                .filter(Node::is_named)
+                .count();
+            named_children == number(&values[0])?
+        }
+        _ => text.chars().count() <= number(&values[0])?,
+    };
+
+    Ok(Some(holds != negated))
+}
+
+/// The node captured under the given name in a query match, if any.
+fn captured_node<'tree>(
+    query_match: &LocalQueryMatch<'tree>,
+    capture_names: &[String],
+    name: &str,
+) -> Option<Node<'tree>> {
+    let name = name.trim_start_matches('@');
+
+    query_match
+        .captures
+        .iter()
+        .find(|c| c.name(capture_names) == name)
+        .map(|c| c.node())
+}
+
 /// Checks the validity of the query predicates.
 ///
 /// This function ensures that the query predicates do not contain more than one
@@ -409,3 +1141,24 @@ fn check_input_exhaustivity(
 ) -> FormatterResult<()> {
     unimplemented!();
 }
+
+#[cfg(test)]
+mod test {
+    use super::display_column;
+
+    #[test]
+    fn display_column_ascii() {
+        assert_eq!(display_column("foo bar", 0, 4), 4);
+        assert_eq!(display_column("foo\nbar baz", 1, 4), 4);
+    }
+
+    #[test]
+    fn display_column_unicode() {
+        // Each of these CJK characters is three bytes long and two columns wide
+        assert_eq!(display_column("\"日本\": 1", 0, 8), 6);
+        // The combining acute accent is two bytes long and zero columns wide
+        assert_eq!(display_column("e\u{301}x", 0, 3), 1);
+        // A tab counts as a single column
+        assert_eq!(display_column("\tx", 0, 1), 1);
+    }
+}
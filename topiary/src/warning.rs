@@ -0,0 +1,70 @@
+//! Warnings, which report what the formatter did not do, or did not check, without failing: the
+//! output is still written, but it may not be what the query intended.
+
+use std::fmt;
+
+#[cfg(feature = "error-serde")]
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+/// A problem that the formatter worked around. Warnings are logged, and passed to the callback
+/// given by `FormatterOptions::on_warning`, so that tools (such as the CLI) can report them, or
+/// treat them as errors.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Warning {
+    /// The input is larger than the memory budget, so its output was not checked for
+    /// idempotence, nor for lost tokens or comments, as requested
+    UncheckedOutput,
+
+    /// The code of an injected language could not be formatted, and was left as it is
+    InjectionNotFormatted {
+        /// The name of the injected language
+        language: String,
+        /// Why the code could not be formatted
+        reason: String,
+    },
+
+    /// The scopes of the query do not match up: a scope was closed without being opened, or
+    /// left opened, or a scoped atom was found outside of its scope
+    UnbalancedScopes(String),
+}
+
+impl Warning {
+    /// A stable identifier of the kind of warning, such as `W010_UNCHECKED_OUTPUT`. Unlike
+    /// messages, it can be relied upon by tools that process warnings.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UncheckedOutput => "W010_UNCHECKED_OUTPUT",
+            Self::InjectionNotFormatted { .. } => "W020_INJECTION_NOT_FORMATTED",
+            Self::UnbalancedScopes(_) => "W030_UNBALANCED_SCOPES",
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UncheckedOutput => write!(
+                f,
+                "The input is larger than the memory budget: the output is not checked for \
+                 idempotence, nor for lost tokens or comments"
+            ),
+            Self::InjectionNotFormatted { language, reason } => write!(
+                f,
+                "Could not format injected {language} code, leaving it as is: {reason}"
+            ),
+            Self::UnbalancedScopes(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// Warnings are serialised like errors, with `warning` as their kind.
+#[cfg(feature = "error-serde")]
+impl Serialize for Warning {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Warning", 3)?;
+        state.serialize_field("kind", "warning")?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
@@ -6,60 +6,25 @@ use log::info;
 use test_log::test;
 
 use topiary::{
-    apply_query, formatter, test_utils::pretty_assert_eq, Configuration, FormatterError, Language,
-    Operation,
+    apply_query, formatter, snapshot_tests, test_utils::pretty_assert_eq, Configuration,
+    FormatterError, Language, Operation, SnapshotOutcome, Symlinks,
 };
 
 #[test(tokio::test)]
 async fn input_output_tester() {
-    let input_dir = fs::read_dir("tests/samples/input").unwrap();
-    let expected_dir = Path::new("tests/samples/expected");
     let config = Configuration::parse_default_configuration().unwrap();
-    let extensions = config.known_extensions();
-
-    for file in input_dir {
-        let file = file.unwrap();
-        if let Some(ext) = file.path().extension().map(|ext| ext.to_string_lossy()) {
-            if !extensions.contains(ext.as_ref()) {
-                continue;
-            }
-
-            let language = Language::detect(file.path(), &config).unwrap();
-
-            let expected_path = expected_dir.join(file.file_name());
-            let expected = fs::read_to_string(expected_path).unwrap();
-
-            let mut input = BufReader::new(fs::File::open(file.path()).unwrap());
-            let mut output = Vec::new();
-            let query = fs::read_to_string(language.query_file().unwrap()).unwrap();
-
-            let grammar = language.grammar().await.unwrap();
-
-            info!(
-                "Formatting file {} as {}.",
-                file.path().display(),
-                language.name,
-            );
-
-            info!("Formatting {}", file.path().display());
-
-            formatter(
-                &mut input,
-                &mut output,
-                &query,
-                language,
-                &grammar,
-                Operation::Format {
-                    skip_idempotence: false,
-                    tolerate_parsing_errors: true,
-                },
-            )
-            .unwrap();
-
-            let formatted = String::from_utf8(output).unwrap();
-            log::debug!("{}", formatted);
 
-            pretty_assert_eq(&expected, &formatted);
+    for test in snapshot_tests(Path::new("tests/samples"), &config, Symlinks::Follow).unwrap() {
+        info!(
+            "Formatting file {} as {}.",
+            test.input.display(),
+            test.language.name,
+        );
+
+        match test.run(false).await.unwrap() {
+            SnapshotOutcome::Passed => {}
+            SnapshotOutcome::Mismatch(diff) => panic!("\n{diff}"),
+            outcome => panic!("{}: {outcome:?}", test.input.display()),
         }
     }
 }
@@ -0,0 +1,71 @@
+/* A small example */
+#include <stdio.h>
+#include "point.h"
+
+#define MAX_POINTS 16
+#define MAX(a, b) ((a) > (b) ? (a) : (b))
+
+#if MAX_POINTS > 8
+#define MANY_POINTS
+#endif
+
+typedef unsigned long size_type;
+
+struct point {
+  int x;
+  int y; // In pixels
+};
+
+enum color {
+  RED,
+  GREEN,
+  BLUE
+};
+
+static const char *names[] = {"red", "green", "blue"};
+
+#ifdef DEBUG
+static int verbose = 1;
+#else
+static int verbose = 0;
+#endif
+
+int distance(struct point *a, struct point *b);
+
+int main(int argc, char **argv)
+{
+  struct point p = {.x = 1, .y = 2};
+  int total = 0;
+
+  for (int i = 0; i < argc; i++) {
+    total += i * 2;
+  }
+
+  for (total = 0; total < MAX_POINTS; total++) {
+    if (total > 3 && verbose) {
+      break;
+    } else if (total == 2) {
+      continue;
+    } else {
+      total = total ? total : 1;
+    }
+  }
+
+  while (total > 0) {
+    total--;
+  }
+
+  do {
+    total++;
+  } while (total < 10);
+
+  switch (argc) {
+    case 1:
+      printf("%s\n", names[RED]);
+      break;
+    default:
+      return (int)p.x;
+  }
+
+  return 0;
+}
@@ -0,0 +1,56 @@
+defmodule Example.Greeter do
+  @moduledoc """
+  Greets people.
+  """
+
+  use GenServer
+
+  @default_options [greeting: "Hello", punctuation: "!"]
+
+  def greet(name, options \\ []) do
+    options = Keyword.merge(@default_options, options)
+    "#{options[:greeting]}, #{name}#{options[:punctuation]}"
+  end
+
+  # Pipelines that do not fit on a line have one step per line
+  def shout(names) when is_list(names) do
+    names
+    |> Enum.map(&String.upcase/1)
+    |> Enum.reject(&(&1 == ""))
+    |> Enum.map(fn name -> greet(name, punctuation: "!!!") end)
+    |> Enum.join(" ")
+  end
+
+  def short(names), do: names |> Enum.take(2) |> Enum.join(", ")
+
+  def classify(value) do
+    case value do
+      {:ok, result} -> result
+      {:error, reason} ->
+        Logger.warning("Failed: #{inspect(reason)}")
+        nil
+      _ -> :unknown
+    end
+  end
+
+  def check(n) do
+    if not is_nil(n) and n in 1..10 do
+      :small
+    else
+      :large
+    end
+  end
+
+  def safe(fun) do
+    fun.()
+  rescue
+    error -> {:error, error}
+  end
+
+  def options do
+    [
+      name: "greeter",
+      timeout: 5_000
+    ]
+  end
+end
@@ -0,0 +1,40 @@
+# Example Terraform configuration
+terraform {
+  required_version = ">= 1.0"
+}
+
+resource "aws_instance" "web" {
+  ami           = "ami-0c55b159cbfafe1f0"
+  instance_type = var.instance_type
+  count         = var.enabled ? 1 : 0
+
+  tags = {
+    Name        = "${var.name}-web"
+    Environment = var.environment
+  }
+
+  security_groups = ["default", "web"]
+  user_data       = <<-EOT
+    #!/bin/bash
+    echo "Hello"
+  EOT
+
+  lifecycle {
+    create_before_destroy = true
+  }
+}
+
+variable "enabled" {}
+
+locals {
+  ports = [
+    80,
+    443,
+  ]
+  limits = { cpu = 2, memory = 512 * 2 }
+}
+
+output "address" {
+  value       = aws_instance.web[0].public_ip
+  description = "The public address" # Used by the DNS records
+}
@@ -0,0 +1,59 @@
+-- A sample module
+local M = {}
+
+local defaults = { size = 10, name = "box" }
+local empty = {}
+local opts = {
+  verbose = true,
+  level = 2;
+  nested = { 1, 2, 3 },
+}
+
+local help = [[
+Usage:   box   [options]
+]]
+
+--[[ A long
+     comment ]]
+function M.area(w, h)
+  return w * h
+end
+
+local function clamp(x, lo, hi)
+  if x < lo then
+    return lo
+  elseif x > hi then
+    return hi
+  else
+    return x
+  end
+end
+
+for i = 1, 3 do
+  print(i)
+end
+
+for k, v in pairs(opts) do
+  if not v then
+    print(k .. " is off")
+  end
+end
+
+while false do
+end
+
+repeat
+  local n = clamp(5, 1, 3)
+until n >= 3 or true
+
+do
+  local inspect = require "inspect" -- pretty printer
+  local s = builder:new():add(1)
+    :add(2)
+    :build()
+  local cb = function(x)
+    return x + 1
+  end
+end
+
+return M
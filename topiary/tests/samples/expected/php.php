@@ -0,0 +1,61 @@
+<?php
+
+namespace App\Models;
+
+use App\Contracts\Shape;
+
+class Rectangle extends Base implements Shape
+{
+    private $width;
+    public $height = 0;
+
+    public function __construct($width, $height)
+    {
+        $this->width = $width;
+        $this->height = $height;
+    }
+
+    public function area()
+    {
+        return $this->width * $this->height;
+    }
+
+    public function scaled(
+        $factor,
+        $round
+    ) {
+        // Scale both sides
+        return new Rectangle($this->width * $factor, $this->height * $factor);
+    }
+}
+
+function describe(Shape $shape): string
+{
+    if ($shape->area() > 100) {
+        return "large";
+    } elseif ($shape->area() > 10) {
+        return 'medium';
+    } else {
+        return 'small';
+    }
+}
+
+foreach ($shapes as $key => $shape) {
+    echo describe($shape);
+}
+
+$sizes = [1, 2, 3];
+$names = [
+    'small',
+    'large'
+];
+
+for ($i = 0; $i < 3; $i++) {
+    while (false) {}
+}
+?>
+<ul>
+  <li>Item</li>
+</ul>
+<?php
+echo count($names);
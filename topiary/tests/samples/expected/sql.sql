@@ -0,0 +1,14 @@
+-- Users with large orders
+SELECT id, name AS n
+FROM users u
+JOIN orders o ON u.id = o.user_id
+WHERE u.age > 18 AND o.total >= 100
+ORDER BY name;
+
+SELECT count(*)
+FROM (
+  SELECT id
+  FROM users
+  WHERE active = 1
+) AS active_users
+GROUP BY id;
@@ -1506,3 +1506,13 @@
   .
   ["," ";"]* @do_nothing
 )
+
+; Predicates that span several lines have one parameter per line.
+(
+  (value_name) @_name @leaf
+  (#any-of? @_name
+    "__FILE__"
+    "__LINE__"
+    "__MODULE__"
+  )
+)
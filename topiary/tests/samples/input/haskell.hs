@@ -0,0 +1,16 @@
+module Main (main) where
+
+import Data.List (sort)
+
+-- | Sort and print.
+main :: IO ()
+main = do putStrLn "Sorting"
+          let xs = [3,1,2]
+              ys = sort xs
+          print ys
+
+
+classify :: Int->String
+classify n = case n of 0 -> "zero"
+                       _ -> label
+  where label = "many"
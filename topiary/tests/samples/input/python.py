@@ -0,0 +1,58 @@
+"""A module docstring."""
+
+import os
+import sys, re
+from collections import OrderedDict as OD
+
+# A comment on its own line
+CONSTANT   =  42  # A trailing comment
+count: int=0
+x = 1; y = 2
+
+
+def greet(name,greeting = "Hello",*args,**kwargs) :
+    message=greeting+", "+name
+    if len(message)>10 and not kwargs :
+        return message[: 10]
+    elif name in ["Alice" ,"Bob"]:
+        pass
+    else :
+        message = message.upper( )
+    return message
+
+class Greeter ( object ):
+    """A docstring
+       that spans lines."""
+
+    def __init__(self, names: list = [], loud: bool=False) -> None:
+        self.names = names
+        self.loud=loud
+
+    @property
+    def count(self) -> int:
+        return len( self.names )
+
+    async def wait(self, delay: float):
+        await sleep(delay)
+
+for i in range(3): log(i)
+
+while True:
+    try:
+        x = {"a":1, "b" : [1,2,3]}
+    except KeyError as e:
+        raise ValueError("bad" "news") from e
+    finally:
+        break
+
+result = [ x*2 for x in range( 10 ) if x%2==0 ]
+values = (1,
+    2,
+      3,
+)
+single = (1 , )
+f = lambda a , b : a if a > b else b
+x += -1
+with open("file") as handle:
+    data = handle.read()
+assert x is not None, "x is unset"
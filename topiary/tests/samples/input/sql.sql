@@ -0,0 +1,5 @@
+-- Users with large orders
+select id, name as n from users u join orders o on u.id = o.user_id where u.age > 18 and o.total >= 100 order by name;
+
+
+select count(*) from (select id from users where active = 1) as active_users group by id;
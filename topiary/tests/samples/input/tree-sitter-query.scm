@@ -1507,3 +1507,10 @@
   .
   [ "," ";" ]* @do_nothing
 )
+
+; Predicates that span several lines have one parameter per line.
+(
+  (value_name) @_name @leaf
+  (#any-of? @_name "__FILE__"
+      "__LINE__" "__MODULE__")
+)
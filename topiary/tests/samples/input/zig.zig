@@ -0,0 +1,20 @@
+const std = @import("std");
+
+// A point in the plane.
+const Point = struct {
+x: i32,
+    y: i32 = 0,
+};
+
+pub fn main() void {
+    const p = Point{.x=1,.y=2};
+    const kind = switch (p.x) {
+        0 => "origin",
+          1, 2 => "near",
+        else => "far",
+    };
+    comptime {
+    std.debug.assert(@sizeOf(Point) == 8);
+    }
+    std.debug.print("{s} {d}\n", .{ kind, p.y+1 });
+}